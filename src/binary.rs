@@ -0,0 +1,1396 @@
+// Chunked binary transfers over web messages.
+//
+// A transfer is a `start` frame with the size, chunk count and SHA-256 of
+// the payload, followed by base64 `chunk` frames in order. The receiver acks
+// every chunk, and the sender keeps at most `window` chunks unacknowledged.
+// The receiver ends the transfer with a `complete` frame, with an error if
+// the payload is not what `start` announced; the sender can `cancel` it.
+//
+// Large payloads from the host can instead be announced with a `blob` frame,
+// whose URL the page fetches through a `WebResourceRequested` handler. Blob
+// URLs end in a random id, and are only served to allowed origins.
+
+use crate::origin::{MessageDirection, OriginAllowlist};
+use crate::rpc::Transport;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Field that marks a web message as a binary transfer frame.
+pub const FRAME_TAG: &str = "$binary";
+
+/// A binary transfer protocol message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "$binary", rename_all = "lowercase")]
+pub enum Frame {
+    Start {
+        id: u64,
+        size: u64,
+        chunks: u64,
+        /// Base64 SHA-256 of the whole payload.
+        sha256: String,
+        #[serde(default)]
+        meta: Value,
+    },
+    Chunk {
+        id: u64,
+        seq: u64,
+        /// Base64.
+        data: String,
+    },
+    Ack {
+        id: u64,
+        seq: u64,
+    },
+    Complete {
+        id: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Cancel {
+        id: u64,
+    },
+    Blob {
+        id: u64,
+        url: String,
+        size: u64,
+        sha256: String,
+        #[serde(default)]
+        meta: Value,
+    },
+}
+
+impl Frame {
+    /// Parse a web message, or `None` if it is not a frame.
+    pub fn parse(message: &str) -> Option<Result<Self, serde_json::Error>> {
+        let value: Value = serde_json::from_str(message).ok()?;
+        value.get(FRAME_TAG)?;
+        Some(serde_json::from_value(value))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("frames always serialize")
+    }
+}
+
+/// Base64 SHA-256 of a payload, as in `start` frames.
+pub fn payload_digest(data: &[u8]) -> String {
+    base64::encode(Sha256::digest(data))
+}
+
+/// Split a payload into base64 chunk frames.
+pub fn chunk_frames(id: u64, data: &[u8], chunk_size: usize) -> impl Iterator<Item = Frame> + '_ {
+    data.chunks(chunk_size)
+        .enumerate()
+        .map(move |(seq, chunk)| Frame::Chunk {
+            id,
+            seq: seq as u64,
+            data: base64::encode(chunk),
+        })
+}
+
+fn chunk_count(size: usize, chunk_size: usize) -> u64 {
+    let full = (size / chunk_size) as u64;
+    match size % chunk_size {
+        0 => full,
+        _ => full + 1,
+    }
+}
+
+/// Why a transfer failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    /// The sender cancelled the transfer.
+    Cancelled,
+    /// The payload is larger than allowed.
+    TooLarge { size: u64, max: u64 },
+    /// The received size or digest does not match the announced one.
+    Integrity,
+    /// Too many incoming transfers, or bytes, are in progress.
+    Busy,
+    /// Unexpected or malformed frames.
+    Protocol(String),
+    /// The other side reported an error.
+    Remote(String),
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::Cancelled => f.write_str("transfer cancelled"),
+            TransferError::TooLarge { size, max } => {
+                write!(f, "payload of {} bytes exceeds the limit of {}", size, max)
+            }
+            TransferError::Integrity => f.write_str("payload does not match its digest"),
+            TransferError::Busy => f.write_str("too many transfers in progress"),
+            TransferError::Protocol(message) => write!(f, "protocol error: {}", message),
+            TransferError::Remote(message) => write!(f, "remote error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+/// Progress of a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub id: u64,
+    /// Bytes acknowledged by the receiver, or received.
+    pub transferred: u64,
+    pub total: u64,
+}
+
+type ProgressCallback = Box<dyn Fn(Progress)>;
+type CompleteCallback = Box<dyn FnOnce(Result<(), TransferError>)>;
+
+struct Outgoing {
+    data: Vec<u8>,
+    chunks: u64,
+    next_seq: u64,
+    acked: u64,
+    // Blob URL, for blob transfers.
+    blob: Option<String>,
+    on_progress: Option<ProgressCallback>,
+    on_complete: Option<CompleteCallback>,
+}
+
+struct Incoming {
+    size: u64,
+    chunks: u64,
+    sha256: String,
+    meta: Value,
+    data: Vec<u8>,
+    next_seq: u64,
+}
+
+struct Inner {
+    transport: Rc<dyn Transport>,
+    chunk_size: Cell<usize>,
+    window: Cell<u64>,
+    max_size: Cell<u64>,
+    max_incoming: Cell<usize>,
+    max_incoming_size: Cell<u64>,
+    blobs: RefCell<Option<(u64, BlobStore)>>,
+    next_id: Cell<u64>,
+    outgoing: RefCell<HashMap<u64, Outgoing>>,
+    incoming: RefCell<HashMap<u64, Incoming>>,
+    on_payload: RefCell<Option<Rc<dyn Fn(Value, Vec<u8>)>>>,
+    on_progress: RefCell<Option<Rc<dyn Fn(Progress)>>>,
+    on_error: RefCell<Option<Rc<dyn Fn(u64, TransferError)>>>,
+}
+
+/// Sends and receives binary payloads over a `Transport`.
+///
+/// Feed every received web message to `receive`; it returns `false` for
+/// messages that are not frames, so they can be handled by something else.
+#[derive(Clone)]
+pub struct BinaryChannel {
+    inner: Rc<Inner>,
+}
+
+impl fmt::Debug for BinaryChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BinaryChannel")
+            .field("chunk_size", &self.inner.chunk_size.get())
+            .field("window", &self.inner.window.get())
+            .field("outgoing", &self.inner.outgoing.borrow().len())
+            .field("incoming", &self.inner.incoming.borrow().len())
+            .finish()
+    }
+}
+
+impl BinaryChannel {
+    /// A channel with 48 KiB chunks, a window of 4 chunks, a 64 MiB limit
+    /// on received payloads, and at most 4 incoming transfers of 128 MiB
+    /// together at a time.
+    pub fn new(transport: Rc<dyn Transport>) -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                transport,
+                chunk_size: Cell::new(48 * 1024),
+                window: Cell::new(4),
+                max_size: Cell::new(64 * 1024 * 1024),
+                max_incoming: Cell::new(4),
+                max_incoming_size: Cell::new(128 * 1024 * 1024),
+                blobs: RefCell::new(None),
+                next_id: Cell::new(1),
+                outgoing: RefCell::default(),
+                incoming: RefCell::default(),
+                on_payload: RefCell::new(None),
+                on_progress: RefCell::new(None),
+                on_error: RefCell::new(None),
+            }),
+        }
+    }
+
+    /// Size of the chunks sent, before base64 encoding.
+    pub fn with_chunk_size(self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must not be zero");
+        self.inner.chunk_size.set(chunk_size);
+        self
+    }
+
+    /// Number of chunks sent before waiting for acks.
+    pub fn with_window(self, window: u64) -> Self {
+        assert!(window > 0, "window must not be zero");
+        self.inner.window.set(window);
+        self
+    }
+
+    /// Largest payload accepted from the other side.
+    pub fn with_max_size(self, max_size: u64) -> Self {
+        self.inner.max_size.set(max_size);
+        self
+    }
+
+    /// Most incoming transfers in progress at a time, and most bytes they
+    /// may announce together. Transfers beyond that fail with `Busy`.
+    pub fn with_max_incoming(self, transfers: usize, size: u64) -> Self {
+        self.inner.max_incoming.set(transfers);
+        self.inner.max_incoming_size.set(size);
+        self
+    }
+
+    /// Send payloads of at least `threshold` bytes through blob URLs served
+    /// from `store` rather than in chunks.
+    pub fn with_blob_store(self, threshold: u64, store: BlobStore) -> Self {
+        *self.inner.blobs.borrow_mut() = Some((threshold, store));
+        self
+    }
+
+    /// Called with the metadata and bytes of every received payload.
+    pub fn on_payload(&self, handler: impl Fn(Value, Vec<u8>) + 'static) {
+        *self.inner.on_payload.borrow_mut() = Some(Rc::new(handler));
+    }
+
+    /// Called as chunks of incoming payloads arrive.
+    pub fn on_progress(&self, handler: impl Fn(Progress) + 'static) {
+        *self.inner.on_progress.borrow_mut() = Some(Rc::new(handler));
+    }
+
+    /// Called when an incoming transfer fails.
+    pub fn on_error(&self, handler: impl Fn(u64, TransferError) + 'static) {
+        *self.inner.on_error.borrow_mut() = Some(Rc::new(handler));
+    }
+
+    /// Send a payload with JSON metadata. Returns the transfer id.
+    ///
+    /// `on_progress` is called as chunks are acknowledged, and `on_complete`
+    /// once the receiver verified the payload, or the transfer failed.
+    pub fn send(
+        &self,
+        data: Vec<u8>,
+        meta: Value,
+        on_progress: Option<Box<dyn Fn(Progress)>>,
+        on_complete: impl FnOnce(Result<(), TransferError>) + 'static,
+    ) -> u64 {
+        let id = self.inner.next_id.get();
+        self.inner.next_id.set(id + 1);
+        let sha256 = payload_digest(&data);
+        let size = data.len() as u64;
+
+        let blob = match *self.inner.blobs.borrow() {
+            Some((threshold, ref store)) if size >= threshold => {
+                Some(store.insert(data.clone(), "application/octet-stream"))
+            }
+            _ => None,
+        };
+        let chunks = if blob.is_some() {
+            0
+        } else {
+            chunk_count(data.len(), self.inner.chunk_size.get())
+        };
+        let frame = match blob {
+            Some(ref url) => Frame::Blob {
+                id,
+                url: url.clone(),
+                size,
+                sha256,
+                meta,
+            },
+            None => Frame::Start {
+                id,
+                size,
+                chunks,
+                sha256,
+                meta,
+            },
+        };
+        self.inner.outgoing.borrow_mut().insert(
+            id,
+            Outgoing {
+                // The store has its own copy.
+                data: if blob.is_some() { Vec::new() } else { data },
+                chunks,
+                next_seq: 0,
+                acked: 0,
+                blob,
+                on_progress,
+                on_complete: Some(Box::new(on_complete)),
+            },
+        );
+        self.send_frame(&frame);
+        self.pump(id);
+        id
+    }
+
+    /// Cancel an outgoing transfer. Returns whether it was in progress.
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.finish_outgoing(id, Err(TransferError::Cancelled)) {
+            true => {
+                self.send_frame(&Frame::Cancel { id });
+                true
+            }
+            false => false,
+        }
+    }
+
+    /// Fail all transfers in progress, e.g. because the page is gone.
+    pub fn close(&self) {
+        self.inner.incoming.borrow_mut().clear();
+        let ids: Vec<u64> = self.inner.outgoing.borrow().keys().cloned().collect();
+        for id in ids {
+            self.finish_outgoing(id, Err(TransferError::Cancelled));
+        }
+    }
+
+    /// Number of outgoing transfers in progress.
+    pub fn pending_count(&self) -> usize {
+        self.inner.outgoing.borrow().len()
+    }
+
+    /// Handle a received web message. Returns `false` if it is not a frame.
+    pub fn receive(&self, message: &str) -> bool {
+        match Frame::parse(message) {
+            None => false,
+            Some(Ok(frame)) => {
+                self.handle(frame);
+                true
+            }
+            Some(Err(e)) => {
+                self.report(0, TransferError::Protocol(e.to_string()));
+                true
+            }
+        }
+    }
+
+    fn handle(&self, frame: Frame) {
+        match frame {
+            Frame::Start {
+                id,
+                size,
+                chunks,
+                sha256,
+                meta,
+            } => self.start_incoming(id, size, chunks, sha256, meta),
+            Frame::Chunk { id, seq, data } => self.chunk_incoming(id, seq, &data),
+            Frame::Cancel { id } => {
+                if self.inner.incoming.borrow_mut().remove(&id).is_some() {
+                    self.report(id, TransferError::Cancelled);
+                }
+            }
+            Frame::Ack { id, seq } => {
+                let progress = {
+                    let mut outgoing = self.inner.outgoing.borrow_mut();
+                    let transfer = match outgoing.get_mut(&id) {
+                        Some(transfer) => transfer,
+                        None => return,
+                    };
+                    // Acks are cumulative, and never for unsent chunks.
+                    if seq >= transfer.next_seq || seq < transfer.acked {
+                        return;
+                    }
+                    transfer.acked = seq + 1;
+                    let chunk_size = self.inner.chunk_size.get() as u64;
+                    let total = transfer.data.len() as u64;
+                    Progress {
+                        id,
+                        transferred: (transfer.acked * chunk_size).min(total),
+                        total,
+                    }
+                };
+                // Call outside of the borrow, so that the callback can send.
+                self.outgoing_progress(id, progress);
+                self.pump(id);
+            }
+            Frame::Complete { id, error } => {
+                let result = match error {
+                    None => Ok(()),
+                    Some(error) => Err(TransferError::Remote(error)),
+                };
+                self.finish_outgoing(id, result);
+            }
+            Frame::Blob { id, .. } => {
+                // Blob URLs are served by the host; the page has none.
+                self.send_frame(&Frame::Complete {
+                    id,
+                    error: Some("blob frames are not supported by the host".into()),
+                });
+            }
+        }
+    }
+
+    fn start_incoming(&self, id: u64, size: u64, chunks: u64, sha256: String, meta: Value) {
+        let max = self.inner.max_size.get();
+        if size > max {
+            return self.fail_incoming(id, TransferError::TooLarge { size, max });
+        }
+        let (duplicate, busy) = {
+            let incoming = self.inner.incoming.borrow();
+            let reserved = incoming.values().map(|t| t.size).sum::<u64>();
+            (
+                incoming.contains_key(&id),
+                incoming.len() >= self.inner.max_incoming.get()
+                    || reserved.saturating_add(size) > self.inner.max_incoming_size.get(),
+            )
+        };
+        if duplicate {
+            return self.fail_incoming(id, TransferError::Protocol("duplicate transfer id".into()));
+        }
+        if busy {
+            return self.fail_incoming(id, TransferError::Busy);
+        }
+        // The size is only announced: grow the buffer as chunks arrive.
+        self.inner.incoming.borrow_mut().insert(
+            id,
+            Incoming {
+                size,
+                chunks,
+                sha256,
+                meta,
+                data: Vec::new(),
+                next_seq: 0,
+            },
+        );
+        if chunks == 0 {
+            self.finish_incoming(id);
+        }
+    }
+
+    fn chunk_incoming(&self, id: u64, seq: u64, data: &str) {
+        let result = {
+            let mut incoming = self.inner.incoming.borrow_mut();
+            let transfer = match incoming.get_mut(&id) {
+                Some(transfer) => transfer,
+                // Cancelled or failed already.
+                None => return,
+            };
+            match base64::decode(data) {
+                _ if seq != transfer.next_seq => Err(TransferError::Protocol(format!(
+                    "expected chunk {}, got {}",
+                    transfer.next_seq, seq
+                ))),
+                Err(e) => Err(TransferError::Protocol(e.to_string())),
+                Ok(ref bytes)
+                    if transfer.data.len() as u64 + bytes.len() as u64 > transfer.size =>
+                {
+                    Err(TransferError::Integrity)
+                }
+                Ok(bytes) => {
+                    transfer.data.extend_from_slice(&bytes);
+                    transfer.next_seq += 1;
+                    Ok((
+                        transfer.next_seq == transfer.chunks,
+                        Progress {
+                            id,
+                            transferred: transfer.data.len() as u64,
+                            total: transfer.size,
+                        },
+                    ))
+                }
+            }
+        };
+        match result {
+            Ok((last, progress)) => {
+                self.send_frame(&Frame::Ack { id, seq });
+                let on_progress = self.inner.on_progress.borrow().clone();
+                if let Some(on_progress) = on_progress {
+                    on_progress(progress);
+                }
+                if last {
+                    self.finish_incoming(id);
+                }
+            }
+            Err(e) => self.fail_incoming(id, e),
+        }
+    }
+
+    fn finish_incoming(&self, id: u64) {
+        let transfer = match self.inner.incoming.borrow_mut().remove(&id) {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        if transfer.data.len() as u64 != transfer.size
+            || payload_digest(&transfer.data) != transfer.sha256
+        {
+            return self.fail_incoming(id, TransferError::Integrity);
+        }
+        self.send_frame(&Frame::Complete { id, error: None });
+        let on_payload = self.inner.on_payload.borrow().clone();
+        if let Some(on_payload) = on_payload {
+            on_payload(transfer.meta, transfer.data);
+        }
+    }
+
+    fn fail_incoming(&self, id: u64, error: TransferError) {
+        self.inner.incoming.borrow_mut().remove(&id);
+        self.send_frame(&Frame::Complete {
+            id,
+            error: Some(error.to_string()),
+        });
+        self.report(id, error);
+    }
+
+    fn report(&self, id: u64, error: TransferError) {
+        let on_error = self.inner.on_error.borrow().clone();
+        if let Some(on_error) = on_error {
+            on_error(id, error);
+        }
+    }
+
+    // Send chunks while the window allows.
+    fn pump(&self, id: u64) {
+        loop {
+            let frame = {
+                let mut outgoing = self.inner.outgoing.borrow_mut();
+                let transfer = match outgoing.get_mut(&id) {
+                    Some(transfer) => transfer,
+                    None => return,
+                };
+                if transfer.next_seq >= transfer.chunks
+                    || transfer.next_seq >= transfer.acked + self.inner.window.get()
+                {
+                    return;
+                }
+                let chunk_size = self.inner.chunk_size.get();
+                let start = transfer.next_seq as usize * chunk_size;
+                let end = (start + chunk_size).min(transfer.data.len());
+                let frame = Frame::Chunk {
+                    id,
+                    seq: transfer.next_seq,
+                    data: base64::encode(&transfer.data[start..end]),
+                };
+                transfer.next_seq += 1;
+                frame
+            };
+            self.send_frame(&frame);
+        }
+    }
+
+    fn outgoing_progress(&self, id: u64, progress: Progress) {
+        // Take the callback out, so that it can call into the channel.
+        let on_progress = match self.inner.outgoing.borrow_mut().get_mut(&id) {
+            Some(transfer) => transfer.on_progress.take(),
+            None => None,
+        };
+        if let Some(on_progress) = on_progress {
+            on_progress(progress);
+            if let Some(transfer) = self.inner.outgoing.borrow_mut().get_mut(&id) {
+                transfer.on_progress = Some(on_progress);
+            }
+        }
+    }
+
+    fn finish_outgoing(&self, id: u64, result: Result<(), TransferError>) -> bool {
+        let transfer = match self.inner.outgoing.borrow_mut().remove(&id) {
+            Some(transfer) => transfer,
+            None => return false,
+        };
+        if let Some(ref url) = transfer.blob {
+            if let Some((_, ref store)) = *self.inner.blobs.borrow() {
+                store.remove(url);
+            }
+        }
+        if let Some(on_complete) = transfer.on_complete {
+            on_complete(result);
+        }
+        true
+    }
+
+    fn send_frame(&self, frame: &Frame) {
+        self.inner.transport.send(&frame.to_json());
+    }
+
+    /// JavaScript defining the page side as `window[global]`.
+    ///
+    /// It provides `send(data, meta, {onProgress})` for `ArrayBuffer`s,
+    /// typed arrays and `Blob`s, returning a promise of the transfer's
+    /// completion, `onPayload(fn(meta, Uint8Array))` and `handle(message)`,
+    /// which returns whether a message was a frame. Frames are handled
+    /// automatically unless `autoListen` is `false`.
+    pub fn script(&self, global: &str, auto_listen: bool) -> String {
+        BINARY_SCRIPT
+            .replace("$GLOBAL", &Value::String(global.into()).to_string())
+            .replace("$CHUNK_SIZE", &self.inner.chunk_size.get().to_string())
+            .replace("$WINDOW", &self.inner.window.get().to_string())
+            .replace("$AUTO_LISTEN", if auto_listen { "true" } else { "false" })
+    }
+}
+
+const BINARY_SCRIPT: &str = r#"(function () {
+  var webview = window.chrome && window.chrome.webview;
+  var CHUNK_SIZE = $CHUNK_SIZE;
+  var WINDOW = $WINDOW;
+  var TAG = "$binary";
+  var nextId = 1;
+  var outgoing = {};
+  var incoming = {};
+  var payloadHandler = null;
+
+  function post(frame) {
+    webview.postMessage(frame);
+  }
+
+  function frame(kind, fields) {
+    fields[TAG] = kind;
+    return fields;
+  }
+
+  function toBase64(bytes) {
+    var s = "";
+    for (var i = 0; i < bytes.length; i += 0x8000) {
+      s += String.fromCharCode.apply(null, bytes.subarray(i, i + 0x8000));
+    }
+    return btoa(s);
+  }
+
+  function fromBase64(data) {
+    var s = atob(data);
+    var bytes = new Uint8Array(s.length);
+    for (var i = 0; i < s.length; i++) {
+      bytes[i] = s.charCodeAt(i);
+    }
+    return bytes;
+  }
+
+  function digest(bytes) {
+    return crypto.subtle.digest("SHA-256", bytes).then(function (hash) {
+      return toBase64(new Uint8Array(hash));
+    });
+  }
+
+  function toBytes(data) {
+    if (typeof Blob !== "undefined" && data instanceof Blob) {
+      return data.arrayBuffer().then(toBytes);
+    }
+    if (ArrayBuffer.isView(data)) {
+      return Promise.resolve(new Uint8Array(data.buffer, data.byteOffset, data.byteLength));
+    }
+    return Promise.resolve(new Uint8Array(data));
+  }
+
+  function pump(t) {
+    while (t.next < t.chunks && t.next < t.acked + WINDOW) {
+      var seq = t.next++;
+      var chunk = t.bytes.subarray(seq * CHUNK_SIZE, (seq + 1) * CHUNK_SIZE);
+      post(frame("chunk", { id: t.id, seq: seq, data: toBase64(chunk) }));
+    }
+  }
+
+  function deliver(t, bytes) {
+    return digest(bytes).then(function (hash) {
+      if (bytes.length !== t.size || hash !== t.sha256) {
+        throw new Error("payload does not match its digest");
+      }
+      post(frame("complete", { id: t.id }));
+      if (payloadHandler) {
+        payloadHandler(t.meta, bytes);
+      }
+    });
+  }
+
+  function fail(id, e) {
+    delete incoming[id];
+    post(frame("complete", { id: id, error: String((e && e.message) || e) }));
+  }
+
+  function finish(t) {
+    delete incoming[t.id];
+    var bytes = new Uint8Array(t.received);
+    var offset = 0;
+    t.parts.forEach(function (part) {
+      bytes.set(part, offset);
+      offset += part.length;
+    });
+    deliver(t, bytes).catch(function (e) {
+      fail(t.id, e);
+    });
+  }
+
+  function handle(message) {
+    if (!message || typeof message !== "object" || typeof message[TAG] !== "string") {
+      return false;
+    }
+    var id = message.id;
+    var t;
+    switch (message[TAG]) {
+      case "start":
+        t = { id: id, size: message.size, chunks: message.chunks, sha256: message.sha256,
+              meta: message.meta, parts: [], received: 0, next: 0 };
+        incoming[id] = t;
+        if (t.chunks === 0) {
+          finish(t);
+        }
+        break;
+      case "chunk":
+        t = incoming[id];
+        if (!t) {
+          break;
+        }
+        if (message.seq !== t.next) {
+          fail(id, "expected chunk " + t.next + ", got " + message.seq);
+          break;
+        }
+        var part = fromBase64(message.data);
+        t.parts.push(part);
+        t.received += part.length;
+        t.next++;
+        post(frame("ack", { id: id, seq: message.seq }));
+        if (t.next === t.chunks) {
+          finish(t);
+        }
+        break;
+      case "blob":
+        t = { id: id, size: message.size, sha256: message.sha256, meta: message.meta };
+        fetch(message.url)
+          .then(function (response) {
+            return response.arrayBuffer();
+          })
+          .then(function (buffer) {
+            return deliver(t, new Uint8Array(buffer));
+          })
+          .catch(function (e) {
+            fail(id, e);
+          });
+        break;
+      case "cancel":
+        delete incoming[id];
+        break;
+      case "ack":
+        t = outgoing[id];
+        if (t && message.seq >= t.acked && message.seq < t.next) {
+          t.acked = message.seq + 1;
+          if (t.onProgress) {
+            t.onProgress({ id: id, transferred: Math.min(t.acked * CHUNK_SIZE, t.bytes.length),
+                           total: t.bytes.length });
+          }
+          pump(t);
+        }
+        break;
+      case "complete":
+        t = outgoing[id];
+        if (t) {
+          delete outgoing[id];
+          if (message.error) {
+            t.reject(new Error(message.error));
+          } else {
+            t.resolve();
+          }
+        }
+        break;
+    }
+    return true;
+  }
+
+  window[$GLOBAL] = {
+    send: function (data, meta, options) {
+      return toBytes(data).then(function (bytes) {
+        return digest(bytes).then(function (hash) {
+          return new Promise(function (resolve, reject) {
+            var id = nextId++;
+            var t = { id: id, bytes: bytes, chunks: Math.ceil(bytes.length / CHUNK_SIZE),
+                      next: 0, acked: 0, resolve: resolve, reject: reject,
+                      onProgress: options && options.onProgress };
+            outgoing[id] = t;
+            post(frame("start", { id: id, size: bytes.length, chunks: t.chunks, sha256: hash,
+                                  meta: meta === undefined ? null : meta }));
+            pump(t);
+          });
+        });
+      });
+    },
+    onPayload: function (f) {
+      payloadHandler = f;
+    },
+    handle: handle,
+  };
+
+  if (webview && $AUTO_LISTEN) {
+    webview.addEventListener("message", function (event) {
+      handle(event.data);
+    });
+  }
+})();
+"#;
+
+/// Payloads served to the page through URLs, see
+/// `BinaryChannel::with_blob_store`.
+///
+/// Blob URLs end in a random 128-bit id, so that documents can't guess the
+/// URLs of blobs they were not sent. Clones share the same blobs.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    base_url: Rc<str>,
+    blobs: Rc<RefCell<HashMap<String, (String, Rc<[u8]>)>>>,
+}
+
+impl BlobStore {
+    /// A store serving blobs below `base_url`, e.g.
+    /// `"https://blobs.example/"`. The host should not be a real one.
+    pub fn new(base_url: &str) -> Self {
+        let mut base_url = base_url.to_string();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        Self {
+            base_url: base_url.into(),
+            blobs: Rc::default(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Add a blob, returning its URL.
+    pub fn insert(&self, data: Vec<u8>, content_type: &str) -> String {
+        let url = format!("{}{}", self.base_url, random_id());
+        self.blobs
+            .borrow_mut()
+            .insert(url.clone(), (content_type.into(), data.into()));
+        url
+    }
+
+    /// The content type and data of a blob. The query and fragment of the
+    /// URL are ignored.
+    pub fn get(&self, url: &str) -> Option<(String, Rc<[u8]>)> {
+        let url = &url[..url.find(&['?', '#'][..]).unwrap_or(url.len())];
+        self.blobs.borrow().get(url).cloned()
+    }
+
+    pub fn remove(&self, url: &str) -> bool {
+        self.blobs.borrow_mut().remove(url).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blobs.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blobs.borrow().is_empty()
+    }
+}
+
+// 128 random bits, as hex.
+fn random_id() -> String {
+    let mut bytes = [0u8; 16];
+    fill_random(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(windows)]
+fn fill_random(buf: &mut [u8]) {
+    use winapi::um::ntsecapi::RtlGenRandom;
+    let ok = unsafe { RtlGenRandom(buf.as_mut_ptr() as _, buf.len() as _) };
+    assert!(ok != 0, "RtlGenRandom failed");
+}
+
+#[cfg(not(windows))]
+fn fill_random(buf: &mut [u8]) {
+    use std::io::Read;
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(buf))
+        .expect("reading /dev/urandom");
+}
+
+#[cfg(windows)]
+impl BinaryChannel {
+    /// A channel talking to page JavaScript through web messages.
+    ///
+    /// The page side is installed as `window[global]` for new documents,
+    /// with its chunk size and window. Transfers in progress fail when a new
+    /// document starts loading, like with `RpcEndpoint::attach`. The channel
+    /// and the webview reference each other until the returned tokens are
+    /// removed, the second one with `remove_content_loading`.
+    pub fn attach(
+        webview: &crate::WebView,
+        global: &str,
+        configure: impl FnOnce(Self) -> Self,
+    ) -> crate::Result<(
+        Self,
+        crate::EventRegistrationToken,
+        crate::EventRegistrationToken,
+    )> {
+        Self::attach_impl(webview, global, None, configure)
+    }
+
+    /// Like `attach`, but only exchange frames with documents from these
+    /// origins.
+    pub fn attach_with_origins(
+        webview: &crate::WebView,
+        global: &str,
+        origins: OriginAllowlist,
+        configure: impl FnOnce(Self) -> Self,
+    ) -> crate::Result<(
+        Self,
+        crate::EventRegistrationToken,
+        crate::EventRegistrationToken,
+    )> {
+        Self::attach_impl(webview, global, Some(origins), configure)
+    }
+
+    fn attach_impl(
+        webview: &crate::WebView,
+        global: &str,
+        origins: Option<OriginAllowlist>,
+        configure: impl FnOnce(Self) -> Self,
+    ) -> crate::Result<(
+        Self,
+        crate::EventRegistrationToken,
+        crate::EventRegistrationToken,
+    )> {
+        let mut transport = crate::rpc::WebViewTransport::new(webview.clone());
+        if let Some(ref origins) = origins {
+            transport = transport.with_origins(origins.clone());
+        }
+        let channel = configure(Self::new(Rc::new(transport)));
+        webview
+            .add_script_to_execute_on_document_created(&channel.script(global, true), |_| Ok(()))?;
+        let c = channel.clone();
+        let message_token = webview.add_web_message_received(move |_, args| {
+            if let Some(ref origins) = origins {
+                if !origins.check(&args.get_source()?, MessageDirection::Incoming) {
+                    return Ok(());
+                }
+            }
+            c.receive(&args.get_web_message_as_json()?);
+            Ok(())
+        })?;
+        let c = channel.clone();
+        // The current document is gone.
+        let content_loading_token = webview.add_content_loading(move |_, _| {
+            c.close();
+            Ok(())
+        })?;
+        Ok((channel, message_token, content_loading_token))
+    }
+}
+
+// The `Origin` of a blob request, if it may read the blob. The blob URL is
+// cross-origin for every document, so requests without one, e.g. from an
+// `<img>`, are not served.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn allowed_origin(origins: &OriginAllowlist, origin: Option<String>) -> Option<String> {
+    // Never split a header.
+    let origin = origin.filter(|o| !o.is_empty() && o.bytes().all(|b| b.is_ascii_graphic()))?;
+    if origins.check(&origin, MessageDirection::Outgoing) {
+        Some(origin)
+    } else {
+        None
+    }
+}
+
+// Response headers of a blob for an allowed origin.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn blob_headers(content_type: Option<&str>, origin: &str) -> String {
+    let mut headers = String::new();
+    if let Some(content_type) = content_type {
+        headers.push_str(&format!("Content-Type: {}\r\n", content_type));
+    }
+    headers.push_str(&format!(
+        "Access-Control-Allow-Origin: {}\r\nVary: Origin",
+        origin
+    ));
+    headers
+}
+
+#[cfg(windows)]
+impl BlobStore {
+    /// Serve the blobs to a `WebView`, for documents from `origins`. Other
+    /// requests for blob URLs get a 403 response.
+    pub fn attach(
+        &self,
+        env: &crate::Environment,
+        webview: &crate::WebView,
+        origins: OriginAllowlist,
+    ) -> crate::Result<crate::EventRegistrationToken> {
+        webview.add_web_resource_requested_filter(
+            &format!("{}*", self.base_url),
+            crate::WebResourceContext::All,
+        )?;
+        let store = self.clone();
+        let env = env.clone();
+        webview.add_web_resource_requested(move |_, args| {
+            let request = args.get_request()?;
+            let uri = request.get_uri()?;
+            if !uri.starts_with(&*store.base_url) {
+                return Ok(());
+            }
+            // `get_header` fails if the header is not present.
+            let origin = request.get_headers()?.get_header("Origin").ok();
+            let response = match (allowed_origin(&origins, origin), store.get(&uri)) {
+                (None, _) => env.create_web_resource_response(
+                    crate::Stream::from_bytes(&[]),
+                    403,
+                    "Forbidden",
+                    "",
+                )?,
+                (Some(origin), Some((content_type, data))) => env.create_web_resource_response(
+                    crate::Stream::from_bytes(&data),
+                    200,
+                    "OK",
+                    &blob_headers(Some(&content_type), &origin),
+                )?,
+                (Some(origin), None) => env.create_web_resource_response(
+                    crate::Stream::from_bytes(&[]),
+                    404,
+                    "Not Found",
+                    &blob_headers(None, &origin),
+                )?,
+            };
+            args.put_response(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::QueueTransport;
+
+    fn channel(chunk_size: usize, window: u64) -> (Rc<QueueTransport>, BinaryChannel) {
+        let queue = Rc::new(QueueTransport::default());
+        let channel = BinaryChannel::new(Rc::clone(&queue) as Rc<dyn Transport>)
+            .with_chunk_size(chunk_size)
+            .with_window(window);
+        (queue, channel)
+    }
+
+    fn frames(queue: &QueueTransport) -> Vec<Frame> {
+        queue
+            .take()
+            .into_iter()
+            .map(|m| Frame::parse(&m).unwrap().unwrap())
+            .collect()
+    }
+
+    // Deliver frames between two channels until both are idle.
+    fn run(qa: &QueueTransport, a: &BinaryChannel, qb: &QueueTransport, b: &BinaryChannel) {
+        loop {
+            let fa = qa.take();
+            let fb = qb.take();
+            if fa.is_empty() && fb.is_empty() {
+                break;
+            }
+            for m in fa {
+                assert!(b.receive(&m));
+            }
+            for m in fb {
+                assert!(a.receive(&m));
+            }
+        }
+    }
+
+    #[test]
+    fn test_framing() {
+        let frame = Frame::Start {
+            id: 1,
+            size: 3,
+            chunks: 1,
+            sha256: payload_digest(b"abc"),
+            meta: serde_json::json!({"name": "a.bin"}),
+        };
+        let json = frame.to_json();
+        assert!(json.starts_with(r#"{"$binary":"start","id":1,"size":3"#));
+        assert_eq!(Frame::parse(&json).unwrap().unwrap(), frame);
+        assert_eq!(
+            payload_digest(b"abc"),
+            "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+        );
+
+        assert!(Frame::parse(r#"{"type": "other"}"#).is_none());
+        assert!(Frame::parse("not json").is_none());
+        assert!(Frame::parse(r#"{"$binary": "bogus"}"#).unwrap().is_err());
+
+        let chunks: Vec<Frame> = chunk_frames(7, b"hello", 2).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            chunks[2],
+            Frame::Chunk {
+                id: 7,
+                seq: 2,
+                data: base64::encode(b"o"),
+            }
+        );
+        assert_eq!(chunk_count(0, 2), 0);
+        assert_eq!(chunk_count(4, 2), 2);
+        assert_eq!(chunk_count(5, 2), 3);
+    }
+
+    #[test]
+    fn test_transfer() {
+        let (qa, a) = channel(3, 2);
+        let (qb, b) = channel(3, 2);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let r = Rc::clone(&received);
+        b.on_payload(move |meta, data| r.borrow_mut().push((meta, data)));
+        let progress = Rc::new(RefCell::new(Vec::new()));
+        let p = Rc::clone(&progress);
+        let result = Rc::new(RefCell::new(None));
+        let r = Rc::clone(&result);
+
+        let data: Vec<u8> = (0..10).collect();
+        a.send(
+            data.clone(),
+            serde_json::json!("meta"),
+            Some(Box::new(move |progress| {
+                p.borrow_mut().push(progress.transferred)
+            })),
+            move |result| *r.borrow_mut() = Some(result),
+        );
+        assert_eq!(a.pending_count(), 1);
+        run(&qa, &a, &qb, &b);
+
+        assert_eq!(*result.borrow(), Some(Ok(())));
+        assert_eq!(*received.borrow(), vec![(serde_json::json!("meta"), data)]);
+        assert_eq!(*progress.borrow(), vec![3, 6, 9, 10]);
+        assert_eq!(a.pending_count(), 0);
+
+        // Empty payloads.
+        a.send(Vec::new(), Value::Null, None, |r| r.unwrap());
+        run(&qa, &a, &qb, &b);
+        assert_eq!(received.borrow()[1].1, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_back_pressure() {
+        let (qa, a) = channel(2, 2);
+        a.send(vec![0; 10], Value::Null, None, |_| {});
+        let sent = frames(&qa);
+        // Start and a window of two chunks.
+        assert_eq!(sent.len(), 3);
+        assert!(matches!(sent[2], Frame::Chunk { seq: 1, .. }));
+
+        // Acks for unsent chunks are ignored.
+        a.receive(&Frame::Ack { id: 1, seq: 4 }.to_json());
+        assert!(frames(&qa).is_empty());
+        a.receive(&Frame::Ack { id: 1, seq: 0 }.to_json());
+        let sent = frames(&qa);
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(sent[0], Frame::Chunk { seq: 2, .. }));
+        // A cumulative ack opens the window further.
+        a.receive(&Frame::Ack { id: 1, seq: 2 }.to_json());
+        assert_eq!(frames(&qa).len(), 2);
+    }
+
+    #[test]
+    fn test_integrity() {
+        let (q, channel) = channel(4, 4);
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let e = Rc::clone(&errors);
+        channel.on_error(move |id, error| e.borrow_mut().push((id, error)));
+        channel.on_payload(|_, _| panic!("corrupt payload delivered"));
+
+        let start = |id, sha256| Frame::Start {
+            id,
+            size: 4,
+            chunks: 1,
+            sha256,
+            meta: Value::Null,
+        };
+        let chunk = |id, seq, data: &[u8]| Frame::Chunk {
+            id,
+            seq,
+            data: base64::encode(data),
+        };
+
+        // Wrong digest.
+        channel.receive(&start(1, payload_digest(b"abcd")).to_json());
+        channel.receive(&chunk(1, 0, b"abce").to_json());
+        // Out of order.
+        channel.receive(&start(2, payload_digest(b"abcd")).to_json());
+        channel.receive(&chunk(2, 1, b"abcd").to_json());
+        // Too much data.
+        channel.receive(&start(3, payload_digest(b"abcd")).to_json());
+        channel.receive(&chunk(3, 0, b"abcde").to_json());
+
+        let errors = errors.borrow();
+        assert_eq!(errors[0], (1, TransferError::Integrity));
+        assert!(matches!(errors[1], (2, TransferError::Protocol(_))));
+        assert_eq!(errors[2], (3, TransferError::Integrity));
+        let completes: Vec<Frame> = frames(&q)
+            .into_iter()
+            .filter(|f| matches!(f, Frame::Complete { .. }))
+            .collect();
+        assert_eq!(completes.len(), 3);
+        assert!(completes
+            .iter()
+            .all(|f| matches!(f, Frame::Complete { error: Some(_), .. })));
+    }
+
+    #[test]
+    fn test_limits_and_cancel() {
+        let (qa, a) = channel(2, 1);
+        let (qb, b) = channel(2, 1);
+        let b = b.with_max_size(4);
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let e = Rc::clone(&errors);
+        b.on_error(move |_, error| e.borrow_mut().push(error));
+
+        let result = Rc::new(RefCell::new(None));
+        let r = Rc::clone(&result);
+        a.send(vec![0; 5], Value::Null, None, move |res| {
+            *r.borrow_mut() = Some(res)
+        });
+        run(&qa, &a, &qb, &b);
+        assert!(matches!(
+            *result.borrow(),
+            Some(Err(TransferError::Remote(_)))
+        ));
+        assert_eq!(
+            errors.borrow()[0],
+            TransferError::TooLarge { size: 5, max: 4 }
+        );
+
+        let r = Rc::clone(&result);
+        let id = a.send(vec![0; 4], Value::Null, None, move |res| {
+            *r.borrow_mut() = Some(res)
+        });
+        assert!(a.cancel(id));
+        assert!(!a.cancel(id));
+        assert_eq!(*result.borrow(), Some(Err(TransferError::Cancelled)));
+        run(&qa, &a, &qb, &b);
+        assert_eq!(errors.borrow()[1], TransferError::Cancelled);
+
+        assert!(!a.receive(r#"{"type": "not a frame"}"#));
+
+        let r = Rc::clone(&result);
+        a.send(vec![0; 4], Value::Null, None, move |res| {
+            *r.borrow_mut() = Some(res)
+        });
+        *result.borrow_mut() = None;
+        a.close();
+        assert_eq!(*result.borrow(), Some(Err(TransferError::Cancelled)));
+        assert_eq!(a.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_incoming_limits() {
+        let (q, b) = channel(2, 1);
+        let b = b.with_max_size(u64::MAX).with_max_incoming(2, 6);
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let e = Rc::clone(&errors);
+        b.on_error(move |id, error| e.borrow_mut().push((id, error)));
+        let start = |id, size| {
+            let frame = Frame::Start {
+                id,
+                size,
+                chunks: 1,
+                sha256: String::new(),
+                meta: Value::Null,
+            };
+            b.receive(&frame.to_json());
+        };
+
+        // Announced sizes are not allocated up front.
+        start(1, u64::MAX);
+        start(2, 4);
+        start(3, 2);
+        start(4, 1);
+        assert_eq!(
+            *errors.borrow(),
+            vec![(1, TransferError::Busy), (4, TransferError::Busy)]
+        );
+        start(5, 1);
+        assert_eq!(errors.borrow()[2], (5, TransferError::Busy));
+        assert_eq!(frames(&q).len(), 3);
+
+        // Ended transfers make room.
+        b.receive(&Frame::Cancel { id: 2 }.to_json());
+        start(6, 1);
+        assert_eq!(errors.borrow().len(), 4);
+        assert_eq!(errors.borrow()[3], (2, TransferError::Cancelled));
+    }
+
+    #[test]
+    fn test_blob_store() {
+        let store = BlobStore::new("https://blobs.example");
+        let (q, channel) = channel(2, 1);
+        let channel = channel.with_blob_store(8, store.clone());
+
+        let result = Rc::new(RefCell::new(None));
+        let r = Rc::clone(&result);
+        channel.send(vec![7; 8], Value::Null, None, move |res| {
+            *r.borrow_mut() = Some(res)
+        });
+        let url = match frames(&q).pop().unwrap() {
+            Frame::Blob { id, url, size, .. } => {
+                assert_eq!((id, size), (1, 8));
+                url
+            }
+            f => panic!("{:?}", f),
+        };
+        let id = &url["https://blobs.example/".len()..];
+        assert_eq!(id.len(), 32);
+        assert!(id.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(
+            store.insert(Vec::new(), "text/plain"),
+            store.insert(Vec::new(), "text/plain")
+        );
+        let (content_type, data) = store.get(&format!("{}?x#y", url)).unwrap();
+        assert_eq!(content_type, "application/octet-stream");
+        assert_eq!(&data[..], &[7; 8][..]);
+
+        channel.receive(&Frame::Complete { id: 1, error: None }.to_json());
+        assert_eq!(*result.borrow(), Some(Ok(())));
+        assert_eq!(store.len(), 2);
+
+        // Small payloads are still chunked.
+        channel.send(vec![1], Value::Null, None, |_| {});
+        assert!(matches!(frames(&q)[0], Frame::Start { .. }));
+    }
+
+    #[test]
+    fn test_blob_headers() {
+        let rejected = Rc::new(RefCell::new(Vec::new()));
+        let r = Rc::clone(&rejected);
+        let origins = OriginAllowlist::new()
+            .with_rule("https://app.example".parse().unwrap())
+            .with_rejection_handler(move |uri, _| r.borrow_mut().push(uri.to_string()));
+        let allowed = |origin: Option<&str>| allowed_origin(&origins, origin.map(String::from));
+        assert_eq!(
+            allowed(Some("https://app.example")).as_deref(),
+            Some("https://app.example")
+        );
+        assert_eq!(allowed(Some("https://evil.example")), None);
+        assert_eq!(allowed(Some("null")), None);
+        assert_eq!(allowed(None), None);
+        // Never split a header.
+        assert_eq!(allowed(Some("https://app.example\r\nX: y")), None);
+        assert_eq!(*rejected.borrow(), vec!["https://evil.example", "null"]);
+
+        assert_eq!(
+            blob_headers(Some("image/png"), "https://app.example"),
+            "Content-Type: image/png\r\n\
+             Access-Control-Allow-Origin: https://app.example\r\nVary: Origin"
+        );
+        assert_eq!(
+            blob_headers(None, "https://app.example"),
+            "Access-Control-Allow-Origin: https://app.example\r\nVary: Origin"
+        );
+    }
+
+    #[test]
+    fn test_script() {
+        let (_, channel) = channel(1024, 8);
+        let script = channel.script("binary", false);
+        assert!(script.contains("var CHUNK_SIZE = 1024;"));
+        assert!(script.contains("var WINDOW = 8;"));
+        assert!(script.contains(r#"window["binary"] = {"#));
+        assert!(script.contains("if (webview && false)"));
+    }
+}
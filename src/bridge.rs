@@ -0,0 +1,479 @@
+// Typed JSON messages between the host and the page.
+
+use crate::js::to_js_literal;
+use crate::origin::OriginAllowlist;
+use crate::typescript::{TsBindings, TypeScript};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// Why an incoming message could not be dispatched.
+#[derive(Debug)]
+pub enum BridgeError {
+    /// The message is not valid JSON, or does not match `In`.
+    Malformed {
+        message: String,
+        error: serde_json::Error,
+    },
+    /// The message is not an object with a string tag field.
+    MissingTag { message: String },
+    /// No handler is registered for the tag.
+    UnknownTag { tag: String, message: String },
+    /// A reply could not be serialized.
+    Encode(serde_json::Error),
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BridgeError::Malformed { message, error } => {
+                write!(f, "malformed message {}: {}", message, error)
+            }
+            BridgeError::MissingTag { message } => write!(f, "message without tag: {}", message),
+            BridgeError::UnknownTag { tag, message } => {
+                write!(f, "no handler for tag {:?}: {}", tag, message)
+            }
+            BridgeError::Encode(error) => write!(f, "failed to encode reply: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for BridgeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BridgeError::Malformed { error, .. } | BridgeError::Encode(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+type Handler<In, Out> = Rc<dyn Fn(In) -> Option<Out>>;
+
+/// Dispatches JSON web messages to typed handlers by a tag field.
+///
+/// `In` is typically an enum with `#[serde(tag = "type")]`, so that the tag
+/// both selects the handler and the variant. Handlers may return a reply,
+/// which is posted back to the page. Messages that can't be dispatched are
+/// reported to the error handler.
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use webview2::MessageBridge;
+///
+/// #[derive(Deserialize)]
+/// #[serde(tag = "type")]
+/// enum In {
+///     Add { a: i32, b: i32 },
+/// }
+///
+/// #[derive(Serialize)]
+/// #[serde(tag = "type")]
+/// enum Out {
+///     Sum { value: i32 },
+/// }
+///
+/// let bridge = MessageBridge::<In, Out>::new().with_handler("Add", |m| match m {
+///     In::Add { a, b } => Some(Out::Sum { value: a + b }),
+/// });
+/// let reply = bridge.receive(r#"{"type": "Add", "a": 1, "b": 2}"#);
+/// assert_eq!(reply.as_deref(), Some(r#"{"type":"Sum","value":3}"#));
+/// ```
+pub struct MessageBridge<In, Out> {
+    tag: String,
+    global: String,
+    handlers: HashMap<String, Handler<In, Out>>,
+    fallback: Option<Handler<In, Out>>,
+    on_error: Rc<dyn Fn(BridgeError)>,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    origins: Option<OriginAllowlist>,
+    _marker: PhantomData<fn(In) -> Out>,
+}
+
+impl<In, Out> Clone for MessageBridge<In, Out> {
+    fn clone(&self) -> Self {
+        Self {
+            tag: self.tag.clone(),
+            global: self.global.clone(),
+            handlers: self.handlers.clone(),
+            fallback: self.fallback.clone(),
+            on_error: self.on_error.clone(),
+            origins: self.origins.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<In, Out> fmt::Debug for MessageBridge<In, Out> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tags: Vec<&String> = self.handlers.keys().collect();
+        tags.sort();
+        f.debug_struct("MessageBridge")
+            .field("tag", &self.tag)
+            .field("global", &self.global)
+            .field("handlers", &tags)
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+impl<In, Out> Default for MessageBridge<In, Out>
+where
+    In: DeserializeOwned + 'static,
+    Out: Serialize + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<In, Out> MessageBridge<In, Out>
+where
+    In: DeserializeOwned + 'static,
+    Out: Serialize + 'static,
+{
+    /// A bridge using the `"type"` tag field and the `hostBridge` global in
+    /// the page. Errors are ignored until an error handler is set.
+    pub fn new() -> Self {
+        Self {
+            tag: "type".into(),
+            global: "hostBridge".into(),
+            handlers: HashMap::new(),
+            fallback: None,
+            on_error: Rc::new(|_| {}),
+            origins: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Name of the field messages are dispatched by.
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tag = tag.into();
+        self
+    }
+
+    /// Name of the global object the JavaScript shim defines.
+    pub fn with_global(mut self, global: &str) -> Self {
+        self.global = global.into();
+        self
+    }
+
+    /// Handle messages with the given tag.
+    pub fn with_handler(
+        mut self,
+        tag: &str,
+        handler: impl Fn(In) -> Option<Out> + 'static,
+    ) -> Self {
+        self.handlers.insert(tag.into(), Rc::new(handler));
+        self
+    }
+
+    /// Handle messages with tags that have no handler.
+    pub fn with_fallback(mut self, handler: impl Fn(In) -> Option<Out> + 'static) -> Self {
+        self.fallback = Some(Rc::new(handler));
+        self
+    }
+
+    /// Called for messages that can't be dispatched.
+    pub fn with_error_handler(mut self, on_error: impl Fn(BridgeError) + 'static) -> Self {
+        self.on_error = Rc::new(on_error);
+        self
+    }
+
+    /// Only exchange messages with documents from these origins: messages
+    /// from other origins are dropped, and nothing is posted to them.
+    pub fn with_origins(mut self, origins: OriginAllowlist) -> Self {
+        self.origins = Some(origins);
+        self
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn encode(&self, message: &Out) -> serde_json::Result<String> {
+        serde_json::to_string(message)
+    }
+
+    /// Decode a message, returning its tag and value.
+    pub fn decode(&self, message: &str) -> Result<(String, In), BridgeError> {
+        let (tag, value) = self.parse(message)?;
+        Ok((tag, from_value(message, value)?))
+    }
+
+    /// Dispatch a JSON message, returning the encoded reply if any.
+    ///
+    /// Errors are reported to the error handler.
+    pub fn receive(&self, message: &str) -> Option<String> {
+        match self.dispatch(message) {
+            Ok(reply) => reply,
+            Err(e) => {
+                (self.on_error)(e);
+                None
+            }
+        }
+    }
+
+    fn parse(&self, message: &str) -> Result<(String, Value), BridgeError> {
+        let value: Value =
+            serde_json::from_str(message).map_err(|error| BridgeError::Malformed {
+                message: message.into(),
+                error,
+            })?;
+        match value.get(&self.tag) {
+            Some(Value::String(tag)) => Ok((tag.clone(), value)),
+            _ => Err(BridgeError::MissingTag {
+                message: message.into(),
+            }),
+        }
+    }
+
+    fn dispatch(&self, message: &str) -> Result<Option<String>, BridgeError> {
+        // Look up the handler before decoding, so that unknown tags are not
+        // reported as malformed messages.
+        let (tag, value) = self.parse(message)?;
+        let handler = match self.handlers.get(&tag).or(self.fallback.as_ref()) {
+            Some(handler) => handler,
+            None => {
+                return Err(BridgeError::UnknownTag {
+                    tag,
+                    message: message.into(),
+                })
+            }
+        };
+        match handler(from_value(message, value)?) {
+            Some(reply) => self.encode(&reply).map(Some).map_err(BridgeError::Encode),
+            None => Ok(None),
+        }
+    }
+
+    /// JavaScript defining the page side of the bridge.
+    ///
+    /// It defines `post(message)`, `on(tag, callback)` and `off(tag)` on the
+    /// global object; messages from the host are dispatched by the same tag
+    /// field. Register it with `add_script_to_execute_on_document_created`.
+    pub fn script(&self) -> String {
+        let global = to_js_literal(&self.global).expect("strings always serialize");
+        let tag = to_js_literal(&self.tag).expect("strings always serialize");
+        // In one pass, so that placeholders in the values are kept as is.
+        let mut script = String::with_capacity(BRIDGE_SCRIPT.len() + global.len() + tag.len());
+        let mut rest = BRIDGE_SCRIPT;
+        while let Some(i) = rest.find('$') {
+            script.push_str(&rest[..i]);
+            rest = &rest[i..];
+            let (value, placeholder) = if rest.starts_with("$GLOBAL") {
+                (&global[..], "$GLOBAL")
+            } else if rest.starts_with("$TAG") {
+                (&tag[..], "$TAG")
+            } else {
+                ("$", "$")
+            };
+            script.push_str(value);
+            rest = &rest[placeholder.len()..];
+        }
+        script.push_str(rest);
+        script
+    }
+}
+
+impl<In, Out> MessageBridge<In, Out>
+where
+    In: DeserializeOwned + TypeScript + 'static,
+    Out: Serialize + TypeScript + 'static,
+{
+    /// A `.d.ts` file declaring the message types and the global object
+    /// defined by `script`.
+    pub fn typescript_declarations(&self) -> String {
+        let mut bindings = TsBindings::new();
+        bindings.set_messages::<In, Out>();
+        bindings.to_declarations(&self.global)
+    }
+}
+
+fn from_value<In: DeserializeOwned>(message: &str, value: Value) -> Result<In, BridgeError> {
+    serde_json::from_value(value).map_err(|error| BridgeError::Malformed {
+        message: message.into(),
+        error,
+    })
+}
+
+const BRIDGE_SCRIPT: &str = r#"(function () {
+  var tag = $TAG;
+  var handlers = Object.create(null);
+  var webview = window.chrome && window.chrome.webview;
+  window[$GLOBAL] = {
+    post: function (message) {
+      webview.postMessage(message);
+    },
+    on: function (type, callback) {
+      handlers[type] = callback;
+    },
+    off: function (type) {
+      delete handlers[type];
+    },
+  };
+  if (webview) {
+    webview.addEventListener("message", function (event) {
+      var message = event.data;
+      var handler = message && handlers[message[tag]];
+      if (handler) {
+        handler(message);
+      }
+    });
+  }
+})();
+"#;
+
+#[cfg(windows)]
+use crate::origin::MessageDirection;
+
+#[cfg(windows)]
+impl<In, Out> MessageBridge<In, Out>
+where
+    In: DeserializeOwned + 'static,
+    Out: Serialize + 'static,
+{
+    /// Dispatch the web messages of a `WebView`, and post replies back.
+    ///
+    /// This does not inject the shim, see `script`.
+    pub fn attach(&self, webview: &crate::WebView) -> crate::Result<crate::EventRegistrationToken> {
+        let bridge = self.clone();
+        webview.add_web_message_received(move |webview, args| {
+            if let Some(ref origins) = bridge.origins {
+                let source = args.get_source()?;
+                if !origins.check(&source, MessageDirection::Incoming) {
+                    return Ok(());
+                }
+            }
+            let message = args.get_web_message_as_json()?;
+            if let Some(reply) = bridge.receive(&message) {
+                bridge.post_json(&webview, &reply)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn post_json(&self, webview: &crate::WebView, json: &str) -> crate::Result<()> {
+        match self.origins {
+            Some(ref origins) => origins.post_web_message_as_json(webview, json).map(|_| ()),
+            None => webview.post_web_message_as_json(json),
+        }
+    }
+
+    /// Post a message to the page. With `with_origins`, the message is
+    /// dropped if the current document is not trusted.
+    pub fn post(&self, webview: &crate::WebView, message: &Out) -> crate::Result<()> {
+        let json = match self.encode(message) {
+            Ok(json) => json,
+            Err(e) => {
+                (self.on_error)(BridgeError::Encode(e));
+                return Err(crate::Error::new(winapi::shared::winerror::E_INVALIDARG));
+            }
+        };
+        self.post_json(webview, &json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::cell::RefCell;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(tag = "type", rename_all = "camelCase")]
+    enum In {
+        Add { a: i32, b: i32 },
+        Log { text: String },
+        Other,
+    }
+
+    #[derive(Debug, PartialEq, Serialize)]
+    #[serde(tag = "type", rename_all = "camelCase")]
+    enum Out {
+        Sum { value: i32 },
+    }
+
+    fn bridge(errors: Rc<RefCell<Vec<String>>>) -> MessageBridge<In, Out> {
+        MessageBridge::new()
+            .with_handler("add", |m| match m {
+                In::Add { a, b } => Some(Out::Sum { value: a + b }),
+                _ => None,
+            })
+            .with_handler("log", |_| None)
+            .with_error_handler(move |e| errors.borrow_mut().push(e.to_string()))
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let errors = Rc::default();
+        let bridge = bridge(Rc::clone(&errors));
+        assert_eq!(
+            bridge
+                .receive(r#"{"type": "add", "a": 2, "b": 3}"#)
+                .as_deref(),
+            Some(r#"{"type":"sum","value":5}"#)
+        );
+        assert_eq!(bridge.receive(r#"{"type": "log", "text": "hi"}"#), None);
+        assert!(errors.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let bridge = bridge(Rc::clone(&errors));
+        assert_eq!(bridge.receive("not json"), None);
+        assert_eq!(bridge.receive(r#""add""#), None);
+        assert_eq!(bridge.receive(r#"{"type": 1}"#), None);
+        assert_eq!(bridge.receive(r#"{"type": "other"}"#), None);
+        assert_eq!(bridge.receive(r#"{"type": "add", "a": "x"}"#), None);
+
+        let errors = errors.borrow();
+        assert_eq!(errors.len(), 5);
+        assert!(errors[0].starts_with("malformed message not json"));
+        assert!(errors[1].starts_with("message without tag"));
+        assert!(errors[2].starts_with("message without tag"));
+        assert!(errors[3].starts_with("no handler for tag \"other\""));
+        assert!(errors[4].starts_with("malformed message"));
+    }
+
+    #[test]
+    fn test_fallback_and_tag() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen2 = Rc::clone(&seen);
+        let bridge = MessageBridge::<Value, Out>::new()
+            .with_tag("kind")
+            .with_fallback(move |m| {
+                seen2.borrow_mut().push(m);
+                None
+            });
+        bridge.receive(r#"{"kind": "anything"}"#);
+        assert_eq!(seen.borrow()[0]["kind"], "anything");
+
+        let (tag, _) = bridge.decode(r#"{"kind": "x"}"#).unwrap();
+        assert_eq!(tag, "x");
+        assert!(bridge.decode(r#"{"type": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn test_script() {
+        let bridge = MessageBridge::<In, Out>::new()
+            .with_tag("k\"ind")
+            .with_global("app");
+        let script = bridge.script();
+        assert!(script.contains(r#"var tag = "k\"ind";"#));
+        assert!(script.contains(r#"window["app"] = {"#));
+        assert!(script.contains("Object.create(null)"));
+
+        // Values are not searched for the other placeholder.
+        let script = MessageBridge::<In, Out>::new()
+            .with_tag("$GLOBAL")
+            .with_global("$TAG")
+            .script();
+        assert!(script.contains(r#"var tag = "$GLOBAL";"#));
+        assert!(script.contains(r#"window["$TAG"] = {"#));
+    }
+}
@@ -0,0 +1,529 @@
+// `Accept-Encoding` negotiation and compression for served assets.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// A content coding for `Content-Encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl ContentEncoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentEncoding::Identity => "identity",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Brotli => "br",
+        }
+    }
+
+    /// File extension of precompressed variants, e.g. `app.js.br`.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            ContentEncoding::Identity => None,
+            ContentEncoding::Gzip => Some("gz"),
+            ContentEncoding::Brotli => Some("br"),
+        }
+    }
+
+    fn matches(self, coding: &str) -> bool {
+        coding.eq_ignore_ascii_case(self.as_str())
+            || (self == ContentEncoding::Gzip && coding.eq_ignore_ascii_case("x-gzip"))
+    }
+}
+
+/// Choose an encoding from `available` for an `Accept-Encoding` header value.
+///
+/// The encoding with the highest quality value wins. Ties are broken by the
+/// order of `available`, so list the preferred encodings first. `identity`
+/// is acceptable unless it is explicitly refused (with `identity;q=0`, or
+/// `*;q=0` without an `identity` entry). Returns `None` if nothing in
+/// `available` is acceptable.
+pub fn negotiate(accept_encoding: &str, available: &[ContentEncoding]) -> Option<ContentEncoding> {
+    let entries: Vec<(&str, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let coding = parts.next()?.trim();
+            if coding.is_empty() {
+                return None;
+            }
+            let mut q = 1.0;
+            for param in parts {
+                let mut kv = param.splitn(2, '=');
+                let key = kv.next().unwrap_or("").trim();
+                if key.eq_ignore_ascii_case("q") {
+                    q = kv
+                        .next()
+                        .and_then(|v| v.trim().parse::<f32>().ok())
+                        .filter(|q| (0.0..=1.0).contains(q))
+                        .unwrap_or(0.0);
+                }
+            }
+            Some((coding, q))
+        })
+        .collect();
+
+    let quality = |encoding: ContentEncoding| -> f32 {
+        if let Some(&(_, q)) = entries.iter().find(|(c, _)| encoding.matches(c)) {
+            return q;
+        }
+        if let Some(&(_, q)) = entries.iter().find(|(c, _)| *c == "*") {
+            return q;
+        }
+        // Not mentioned: only identity is implicitly acceptable.
+        if encoding == ContentEncoding::Identity {
+            // Lowest non-zero quality, so that anything listed is preferred.
+            f32::MIN_POSITIVE
+        } else {
+            0.0
+        }
+    };
+
+    let mut best: Option<(ContentEncoding, f32)> = None;
+    for &encoding in available {
+        let q = quality(encoding);
+        let better = match best {
+            Some((_, best_q)) => q > best_q,
+            None => true,
+        };
+        if q > 0.0 && better {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// A negotiated asset body.
+#[derive(Debug, Clone)]
+pub struct CompressedAsset {
+    pub encoding: ContentEncoding,
+    pub body: Rc<[u8]>,
+}
+
+impl CompressedAsset {
+    /// Response headers for `create_web_resource_response`.
+    ///
+    /// `Vary: Accept-Encoding` is always included, because the same URL may
+    /// be answered with a different encoding for another request.
+    pub fn headers(&self, content_type: &str) -> String {
+        let mut headers = format!("Content-Type: {}\r\n", content_type);
+        if self.encoding != ContentEncoding::Identity {
+            headers.push_str(&format!("Content-Encoding: {}\r\n", self.encoding.as_str()));
+        }
+        headers.push_str("Vary: Accept-Encoding");
+        headers
+    }
+}
+
+type CacheKey = (PathBuf, ContentEncoding);
+
+// Compressed outputs, evicted oldest first when over the limit.
+#[derive(Debug, Default)]
+struct Cache {
+    bodies: HashMap<CacheKey, Rc<[u8]>>,
+    order: VecDeque<CacheKey>,
+    size: usize,
+}
+
+impl Cache {
+    fn insert(&mut self, key: CacheKey, body: Rc<[u8]>, limit: usize) {
+        if body.len() > limit {
+            return;
+        }
+        while self.size + body.len() > limit {
+            let oldest = match self.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if let Some(evicted) = self.bodies.remove(&oldest) {
+                self.size -= evicted.len();
+            }
+        }
+        self.size += body.len();
+        self.order.push_back(key.clone());
+        if let Some(replaced) = self.bodies.insert(key, body) {
+            self.size -= replaced.len();
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = Cache::default();
+    }
+}
+
+/// Serves files in the best encoding the browser accepts.
+///
+/// Precompressed variants next to a file (`app.js.br`, `app.js.gz`) are used
+/// if present. Otherwise files are compressed on the fly (unless disabled),
+/// and the compressed output is cached in memory, up to a size limit.
+#[derive(Debug)]
+pub struct AssetCompressor {
+    use_precompressed: bool,
+    compress_on_the_fly: bool,
+    min_size: usize,
+    encodings: Vec<ContentEncoding>,
+    cache_limit: usize,
+    cache: RefCell<Cache>,
+}
+
+impl Default for AssetCompressor {
+    fn default() -> Self {
+        Self {
+            use_precompressed: true,
+            compress_on_the_fly: true,
+            min_size: 1024,
+            encodings: vec![
+                ContentEncoding::Brotli,
+                ContentEncoding::Gzip,
+                ContentEncoding::Identity,
+            ],
+            cache_limit: 32 * 1024 * 1024,
+            cache: RefCell::default(),
+        }
+    }
+}
+
+impl AssetCompressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn with_precompressed(mut self, use_precompressed: bool) -> Self {
+        self.use_precompressed = use_precompressed;
+        self
+    }
+
+    #[inline]
+    pub fn with_compress_on_the_fly(mut self, compress: bool) -> Self {
+        self.compress_on_the_fly = compress;
+        self
+    }
+
+    /// Files smaller than this are not compressed on the fly. Defaults to
+    /// 1024 bytes.
+    #[inline]
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Encodings to offer, in order of preference. Defaults to brotli, gzip,
+    /// identity.
+    #[inline]
+    pub fn with_encodings(mut self, encodings: Vec<ContentEncoding>) -> Self {
+        self.encodings = encodings;
+        self
+    }
+
+    /// The total size of cached compressed outputs. The oldest are dropped
+    /// to stay below it. Defaults to 32 MiB.
+    #[inline]
+    pub fn with_cache_limit(mut self, bytes: usize) -> Self {
+        self.cache_limit = bytes;
+        self
+    }
+
+    /// The total size of cached compressed outputs.
+    pub fn cache_size(&self) -> usize {
+        self.cache.borrow().size
+    }
+
+    /// Drop all cached compressed outputs, e.g. after files changed.
+    pub fn clear_cache(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    /// Load `path` in the best encoding for `accept_encoding`.
+    ///
+    /// Falls back to the next acceptable encoding if a compressed variant is
+    /// not available. Returns `None` if no acceptable encoding is available,
+    /// e.g. for `identity;q=0`, which should be answered with 406.
+    pub fn load(&self, path: &Path, accept_encoding: &str) -> io::Result<Option<CompressedAsset>> {
+        let mut candidates = self.encodings.clone();
+        while let Some(encoding) = negotiate(accept_encoding, &candidates) {
+            if encoding == ContentEncoding::Identity {
+                return Ok(Some(CompressedAsset {
+                    encoding,
+                    body: fs::read(path)?.into(),
+                }));
+            }
+            if let Some(body) = self.load_encoded(path, encoding)? {
+                return Ok(Some(CompressedAsset { encoding, body }));
+            }
+            candidates.retain(|&e| e != encoding);
+        }
+        Ok(None)
+    }
+
+    fn load_encoded(&self, path: &Path, encoding: ContentEncoding) -> io::Result<Option<Rc<[u8]>>> {
+        let key = (path.to_path_buf(), encoding);
+        if let Some(body) = self.cache.borrow().bodies.get(&key) {
+            return Ok(Some(body.clone()));
+        }
+
+        let body: Rc<[u8]> = match self.read_precompressed(path, encoding)? {
+            Some(body) => body.into(),
+            None if self.compress_on_the_fly => {
+                let content = fs::read(path)?;
+                if content.len() < self.min_size {
+                    return Ok(None);
+                }
+                compress(&content, encoding)?.into()
+            }
+            None => return Ok(None),
+        };
+        self.cache
+            .borrow_mut()
+            .insert(key, body.clone(), self.cache_limit);
+        Ok(Some(body))
+    }
+
+    fn read_precompressed(
+        &self,
+        path: &Path,
+        encoding: ContentEncoding,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let extension = match encoding.extension() {
+            Some(extension) if self.use_precompressed => extension,
+            _ => return Ok(None),
+        };
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(".");
+        variant.push(extension);
+        match fs::read(&variant) {
+            Ok(body) => Ok(Some(body)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Compress `content` with `encoding`.
+pub fn compress(content: &[u8], encoding: ContentEncoding) -> io::Result<Vec<u8>> {
+    match encoding {
+        ContentEncoding::Identity => Ok(content.to_vec()),
+        ContentEncoding::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(content)?;
+            encoder.finish()
+        }
+        ContentEncoding::Brotli => {
+            let mut output = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, 9, 22);
+                encoder.write_all(content)?;
+            }
+            Ok(output)
+        }
+    }
+}
+
+#[cfg(windows)]
+impl AssetCompressor {
+    /// Create a response for `request` with the content of `path`, or a 406
+    /// response if no acceptable encoding is available.
+    pub fn respond(
+        &self,
+        env: &crate::Environment,
+        request: &crate::WebResourceRequest,
+        path: &Path,
+        content_type: &str,
+    ) -> crate::Result<crate::WebResourceResponse> {
+        // `get_header` fails if the header is not present.
+        let accept_encoding = request
+            .get_headers()?
+            .get_header("Accept-Encoding")
+            .unwrap_or_default();
+        let asset = match self.load(path, &accept_encoding)? {
+            Some(asset) => asset,
+            None => {
+                return env.create_web_resource_response(
+                    crate::Stream::from_bytes(b""),
+                    406,
+                    "Not Acceptable",
+                    "Vary: Accept-Encoding",
+                )
+            }
+        };
+        env.create_web_resource_response(
+            crate::Stream::from_bytes(&asset.body),
+            200,
+            "OK",
+            &asset.headers(content_type),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use ContentEncoding::*;
+
+    const ALL: &[ContentEncoding] = &[Brotli, Gzip, Identity];
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br", ALL), Some(Brotli));
+        assert_eq!(negotiate("gzip, deflate", ALL), Some(Gzip));
+        assert_eq!(negotiate("", ALL), Some(Identity));
+        assert_eq!(negotiate("deflate", ALL), Some(Identity));
+        assert_eq!(negotiate("br;q=0.5, gzip;q=0.8", ALL), Some(Gzip));
+        assert_eq!(negotiate("BR, GZIP", ALL), Some(Brotli));
+        assert_eq!(negotiate("x-gzip", ALL), Some(Gzip));
+        assert_eq!(negotiate("*", ALL), Some(Brotli));
+        assert_eq!(negotiate("*;q=0.5, br;q=0", ALL), Some(Gzip));
+        assert_eq!(negotiate("gzip;q=0, identity", ALL), Some(Identity));
+        assert_eq!(negotiate("gzip , br ; q=1.0", &[Gzip, Brotli]), Some(Gzip));
+        assert_eq!(negotiate("br;q=bogus", ALL), Some(Identity));
+    }
+
+    #[test]
+    fn test_negotiate_refused() {
+        assert_eq!(negotiate("identity;q=0", ALL), None);
+        assert_eq!(negotiate("*;q=0", ALL), None);
+        assert_eq!(negotiate("*;q=0, identity", ALL), Some(Identity));
+        assert_eq!(negotiate("gzip;q=0", &[Gzip]), None);
+        assert_eq!(negotiate("gzip", &[]), None);
+    }
+
+    #[test]
+    fn test_headers() {
+        let asset = CompressedAsset {
+            encoding: Gzip,
+            body: Rc::from(&b""[..]),
+        };
+        assert_eq!(
+            asset.headers("text/javascript"),
+            "Content-Type: text/javascript\r\nContent-Encoding: gzip\r\nVary: Accept-Encoding"
+        );
+        let asset = CompressedAsset {
+            encoding: Identity,
+            body: Rc::from(&b""[..]),
+        };
+        assert_eq!(
+            asset.headers("text/html"),
+            "Content-Type: text/html\r\nVary: Accept-Encoding"
+        );
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "webview2-compression-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_compress_on_the_fly() {
+        let dir = temp_dir("fly");
+        let path = dir.join("app.js");
+        let content = "console.log('hello');\n".repeat(100);
+        fs::write(&path, &content).unwrap();
+
+        let compressor = AssetCompressor::new();
+        let asset = compressor.load(&path, "gzip").unwrap().unwrap();
+        assert_eq!(asset.encoding, Gzip);
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&asset.body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, content);
+
+        let asset = compressor.load(&path, "br").unwrap().unwrap();
+        assert_eq!(asset.encoding, Brotli);
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&asset.body[..], 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, content);
+
+        // Served from the cache, even after the file changed.
+        fs::write(&path, "changed").unwrap();
+        let cached = compressor.load(&path, "br").unwrap().unwrap();
+        assert!(Rc::ptr_eq(&cached.body, &asset.body));
+        compressor.clear_cache();
+        assert_eq!(
+            compressor.load(&path, "br").unwrap().unwrap().encoding,
+            Identity
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_cache_limit() {
+        let dir = temp_dir("limit");
+        let (a, b) = (dir.join("a.js"), dir.join("b.js"));
+        fs::write(&a, "let a = 1;\n".repeat(200)).unwrap();
+        fs::write(&b, "let b = 2;\n".repeat(200)).unwrap();
+
+        let compressor = AssetCompressor::new();
+        let a_len = compressor.load(&a, "gzip").unwrap().unwrap().body.len();
+        let b_len = compressor.load(&b, "gzip").unwrap().unwrap().body.len();
+        assert_eq!(compressor.cache_size(), a_len + b_len);
+
+        // Only room for one: `a` is evicted.
+        let compressor = AssetCompressor::new().with_cache_limit(a_len.max(b_len));
+        let first = compressor.load(&a, "gzip").unwrap().unwrap();
+        compressor.load(&b, "gzip").unwrap().unwrap();
+        assert_eq!(compressor.cache_size(), b_len);
+        let again = compressor.load(&a, "gzip").unwrap().unwrap();
+        assert!(!Rc::ptr_eq(&first.body, &again.body));
+        assert_eq!(compressor.cache_size(), a_len);
+
+        // Too large to cache at all.
+        let compressor = AssetCompressor::new().with_cache_limit(10);
+        compressor.load(&a, "gzip").unwrap().unwrap();
+        assert_eq!(compressor.cache_size(), 0);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_precompressed() {
+        let dir = temp_dir("pre");
+        let path = dir.join("app.js");
+        fs::write(&path, "x").unwrap();
+        fs::write(dir.join("app.js.gz"), "gzipped").unwrap();
+
+        let compressor = AssetCompressor::new();
+        // No `.br` variant and too small to compress, so gzip is used.
+        let asset = compressor.load(&path, "br, gzip").unwrap().unwrap();
+        assert_eq!(asset.encoding, Gzip);
+        assert_eq!(&asset.body[..], b"gzipped");
+
+        let asset = compressor.load(&path, "deflate").unwrap().unwrap();
+        assert_eq!(asset.encoding, Identity);
+        assert_eq!(&asset.body[..], b"x");
+
+        let compressor = AssetCompressor::new().with_precompressed(false);
+        assert_eq!(
+            compressor.load(&path, "gzip").unwrap().unwrap().encoding,
+            Identity
+        );
+
+        // Nothing acceptable: 406.
+        assert!(compressor.load(&path, "identity;q=0").unwrap().is_none());
+        assert!(compressor
+            .load(&path, "gzip, identity;q=0")
+            .unwrap()
+            .is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,338 @@
+// Content Security Policy builder.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Name of the header a `ContentSecurityPolicy` is sent in.
+pub const CSP_HEADER: &str = "Content-Security-Policy";
+
+/// A CSP directive.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Directive {
+    DefaultSrc,
+    ScriptSrc,
+    StyleSrc,
+    ImgSrc,
+    ConnectSrc,
+    FontSrc,
+    ObjectSrc,
+    MediaSrc,
+    FrameSrc,
+    ChildSrc,
+    WorkerSrc,
+    ManifestSrc,
+    BaseUri,
+    FormAction,
+    FrameAncestors,
+    /// Takes no sources.
+    UpgradeInsecureRequests,
+    /// Any other directive, e.g. `"script-src-elem"`.
+    Other(String),
+}
+
+impl Directive {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Directive::DefaultSrc => "default-src",
+            Directive::ScriptSrc => "script-src",
+            Directive::StyleSrc => "style-src",
+            Directive::ImgSrc => "img-src",
+            Directive::ConnectSrc => "connect-src",
+            Directive::FontSrc => "font-src",
+            Directive::ObjectSrc => "object-src",
+            Directive::MediaSrc => "media-src",
+            Directive::FrameSrc => "frame-src",
+            Directive::ChildSrc => "child-src",
+            Directive::WorkerSrc => "worker-src",
+            Directive::ManifestSrc => "manifest-src",
+            Directive::BaseUri => "base-uri",
+            Directive::FormAction => "form-action",
+            Directive::FrameAncestors => "frame-ancestors",
+            Directive::UpgradeInsecureRequests => "upgrade-insecure-requests",
+            Directive::Other(name) => name,
+        }
+    }
+}
+
+/// A CSP source expression.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Source {
+    /// `'none'`
+    None,
+    /// `'self'`
+    SelfOrigin,
+    /// `'unsafe-inline'`
+    UnsafeInline,
+    /// `'unsafe-eval'`
+    UnsafeEval,
+    /// `'strict-dynamic'`
+    StrictDynamic,
+    /// A scheme without the colon, e.g. `"https"` or `"data"`.
+    Scheme(String),
+    /// A host source, e.g. `"*.example.com"` or `"https://example.com:8080/js/"`.
+    Host(String),
+    /// `'nonce-<value>'`. The value should be freshly generated, base64
+    /// encoded random bytes for every response.
+    Nonce(String),
+    /// `'sha256-<value>'`, with the base64 encoded digest.
+    Sha256(String),
+}
+
+impl Source {
+    /// Hash source for an inline script or style.
+    ///
+    /// The digest is computed over the UTF-8 encoding of `content`, which
+    /// must match the element content exactly, including whitespace.
+    pub fn sha256_of(content: &str) -> Self {
+        Source::Sha256(base64::encode(Sha256::digest(content.as_bytes())))
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::None => f.write_str("'none'"),
+            Source::SelfOrigin => f.write_str("'self'"),
+            Source::UnsafeInline => f.write_str("'unsafe-inline'"),
+            Source::UnsafeEval => f.write_str("'unsafe-eval'"),
+            Source::StrictDynamic => f.write_str("'strict-dynamic'"),
+            Source::Scheme(scheme) => {
+                write_escaped(f, scheme)?;
+                f.write_str(":")
+            }
+            Source::Host(host) => write_escaped(f, host),
+            Source::Nonce(nonce) => {
+                f.write_str("'nonce-")?;
+                write_escaped(f, nonce)?;
+                f.write_str("'")
+            }
+            Source::Sha256(digest) => {
+                f.write_str("'sha256-")?;
+                write_escaped(f, digest)?;
+                f.write_str("'")
+            }
+        }
+    }
+}
+
+// Percent-encode characters that would end a source expression, a directive,
+// the policy or the header line.
+fn write_escaped(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    for c in s.chars() {
+        match c {
+            ';' | ',' | '\'' | '%' => write!(f, "%{:02X}", c as u32)?,
+            c if c.is_whitespace() || c.is_control() => {
+                let mut buf = [0u8; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    write!(f, "%{:02X}", b)?;
+                }
+            }
+            c => write!(f, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+/// A builder for `Content-Security-Policy` header values.
+///
+/// ```
+/// use webview2::{ContentSecurityPolicy, Directive, Source};
+///
+/// let script = "window.chrome.webview.postMessage('ready');";
+/// let csp = ContentSecurityPolicy::new()
+///     .with_sources(Directive::DefaultSrc, vec![Source::SelfOrigin])
+///     .with_source(Directive::ImgSrc, Source::Scheme("data".into()))
+///     .with_script_hash(script);
+/// assert!(csp.to_string().starts_with("default-src 'self'; img-src data:; script-src 'sha256-"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(Directive, Vec<Source>)>,
+}
+
+impl ContentSecurityPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A strict starting point: everything from the page's own origin, and
+    /// no plugins, framing or base URI changes.
+    pub fn strict() -> Self {
+        Self::new()
+            .with_source(Directive::DefaultSrc, Source::SelfOrigin)
+            .with_source(Directive::ObjectSrc, Source::None)
+            .with_source(Directive::BaseUri, Source::None)
+            .with_source(Directive::FrameAncestors, Source::None)
+    }
+
+    /// Add a directive without sources (if it is not already present).
+    pub fn with_directive(mut self, directive: Directive) -> Self {
+        self.entry(directive);
+        self
+    }
+
+    /// Add a source to a directive.
+    ///
+    /// Adding a source removes `'none'` from the directive, and adding
+    /// `'none'` removes every other source.
+    pub fn with_source(mut self, directive: Directive, source: Source) -> Self {
+        let sources = self.entry(directive);
+        if source == Source::None {
+            sources.clear();
+        } else {
+            sources.retain(|s| *s != Source::None);
+        }
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+        self
+    }
+
+    pub fn with_sources(
+        self,
+        directive: Directive,
+        sources: impl IntoIterator<Item = Source>,
+    ) -> Self {
+        sources.into_iter().fold(self, |csp, source| {
+            csp.with_source(directive.clone(), source)
+        })
+    }
+
+    /// Allow an inline script by its SHA-256 hash, e.g. a script registered
+    /// with `add_script_to_execute_on_document_created` that is also
+    /// embedded in the page.
+    pub fn with_script_hash(self, script: &str) -> Self {
+        self.with_source(Directive::ScriptSrc, Source::sha256_of(script))
+    }
+
+    /// Allow an inline style by its SHA-256 hash.
+    pub fn with_style_hash(self, style: &str) -> Self {
+        self.with_source(Directive::StyleSrc, Source::sha256_of(style))
+    }
+
+    /// Allow scripts carrying `nonce="<nonce>"`.
+    pub fn with_script_nonce(self, nonce: &str) -> Self {
+        self.with_source(Directive::ScriptSrc, Source::Nonce(nonce.into()))
+    }
+
+    /// Sources of a directive, if present.
+    pub fn sources(&self, directive: &Directive) -> Option<&[Source]> {
+        self.directives
+            .iter()
+            .find(|(d, _)| d == directive)
+            .map(|(_, s)| &s[..])
+    }
+
+    /// The policy as a header line for `create_web_resource_response`.
+    pub fn to_header_line(&self) -> String {
+        format!("{}: {}", CSP_HEADER, self)
+    }
+
+    fn entry(&mut self, directive: Directive) -> &mut Vec<Source> {
+        let i = match self.directives.iter().position(|(d, _)| *d == directive) {
+            Some(i) => i,
+            None => {
+                self.directives.push((directive, Vec::new()));
+                self.directives.len() - 1
+            }
+        };
+        &mut self.directives[i].1
+    }
+}
+
+impl fmt::Display for ContentSecurityPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (directive, sources)) in self.directives.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write_escaped(f, directive.as_str())?;
+            for source in sources {
+                write!(f, " {}", source)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl ContentSecurityPolicy {
+    /// Append the policy to the headers of a response, e.g. one intercepted
+    /// in a `WebResourceRequested` handler.
+    pub fn apply_to_response(&self, response: &crate::WebResourceResponse) -> crate::Result<()> {
+        response
+            .get_headers()?
+            .append_header(CSP_HEADER, &self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        let csp = ContentSecurityPolicy::strict()
+            .with_sources(
+                Directive::ScriptSrc,
+                vec![
+                    Source::SelfOrigin,
+                    Source::Host("https://cdn.example.com".into()),
+                ],
+            )
+            .with_script_nonce("abc123")
+            .with_source(Directive::ImgSrc, Source::Scheme("data".into()))
+            .with_directive(Directive::UpgradeInsecureRequests);
+        assert_eq!(
+            csp.to_string(),
+            "default-src 'self'; object-src 'none'; base-uri 'none'; frame-ancestors 'none'; \
+             script-src 'self' https://cdn.example.com 'nonce-abc123'; img-src data:; \
+             upgrade-insecure-requests"
+        );
+        assert!(csp
+            .to_header_line()
+            .starts_with("Content-Security-Policy: default-src"));
+    }
+
+    #[test]
+    fn test_none_and_duplicates() {
+        let csp = ContentSecurityPolicy::new()
+            .with_source(Directive::ObjectSrc, Source::None)
+            .with_source(Directive::ObjectSrc, Source::SelfOrigin)
+            .with_source(Directive::ObjectSrc, Source::SelfOrigin);
+        assert_eq!(csp.to_string(), "object-src 'self'");
+
+        let csp = csp.with_source(Directive::ObjectSrc, Source::None);
+        assert_eq!(
+            csp.sources(&Directive::ObjectSrc),
+            Some(&[Source::None][..])
+        );
+        assert_eq!(csp.sources(&Directive::ImgSrc), None);
+    }
+
+    #[test]
+    fn test_escaping() {
+        let csp = ContentSecurityPolicy::new().with_source(
+            Directive::ConnectSrc,
+            Source::Host("example.com; script-src *\r\nX: y".into()),
+        );
+        assert_eq!(
+            csp.to_string(),
+            "connect-src example.com%3B%20script-src%20*%0D%0AX:%20y"
+        );
+    }
+
+    #[test]
+    fn test_hash() {
+        // Known value from the CSP specification examples.
+        assert_eq!(
+            Source::sha256_of("alert('Hello, world.');"),
+            Source::Sha256("qznLcsROx4GACP2dm0UCKCzCG+HiZ1guq6ZZDob/Tng=".into())
+        );
+        let csp = ContentSecurityPolicy::new().with_style_hash("");
+        assert_eq!(
+            csp.to_string(),
+            "style-src 'sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU='"
+        );
+    }
+}
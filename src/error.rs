@@ -0,0 +1,498 @@
+// The error type, and decoding of `HRESULT`s.
+//
+// This does not depend on Windows APIs: the symbolic names and descriptions
+// of common `HRESULT`s and Win32 error codes are in tables here.
+
+use std::fmt;
+use std::io;
+use std::string::FromUtf16Error;
+use widestring::NulError;
+
+const E_FAIL: i32 = 0x8000_4005_u32 as i32;
+const E_INVALIDARG: i32 = 0x8007_0057_u32 as i32;
+const FACILITY_WIN32: u32 = 0x8007_0000;
+const ERROR_FILE_NOT_FOUND: u32 = 2;
+const ERROR_NO_UNICODE_TRANSLATION: u32 = 1113;
+
+// `HRESULT`s that are not just Win32 error codes, or that are better known by
+// their own names.
+static HRESULTS: &[(u32, &str, &str)] = &[
+    (0x8000_4001, "E_NOTIMPL", "Not implemented."),
+    (0x8000_4002, "E_NOINTERFACE", "No such interface supported."),
+    (0x8000_4003, "E_POINTER", "Invalid pointer."),
+    (0x8000_4004, "E_ABORT", "Operation aborted."),
+    (0x8000_4005, "E_FAIL", "Unspecified error."),
+    (0x8000_FFFF, "E_UNEXPECTED", "Catastrophic failure."),
+    (
+        0x8000_000B,
+        "E_BOUNDS",
+        "The operation attempted to access data outside the valid range.",
+    ),
+    (
+        0x8000_000E,
+        "E_ILLEGAL_METHOD_CALL",
+        "A method was called at an unexpected time.",
+    ),
+    (0x8007_0005, "E_ACCESSDENIED", "Access is denied."),
+    (0x8007_0006, "E_HANDLE", "The handle is invalid."),
+    (
+        0x8007_000E,
+        "E_OUTOFMEMORY",
+        "Not enough memory resources are available to complete this operation.",
+    ),
+    (
+        0x8007_0057,
+        "E_INVALIDARG",
+        "One or more arguments are invalid.",
+    ),
+    (
+        0x8001_0106,
+        "RPC_E_CHANGED_MODE",
+        "Cannot change thread mode after it is set.",
+    ),
+    (
+        0x8001_010E,
+        "RPC_E_WRONG_THREAD",
+        "The application called an interface that was marshalled for a different thread.",
+    ),
+    (0x8004_0154, "REGDB_E_CLASSNOTREG", "Class not registered."),
+    (
+        0x8004_01F0,
+        "CO_E_NOTINITIALIZED",
+        "CoInitialize has not been called.",
+    ),
+];
+
+static WIN32_ERRORS: &[(u32, &str, &str)] = &[
+    (
+        2,
+        "ERROR_FILE_NOT_FOUND",
+        "The system cannot find the file specified.",
+    ),
+    (
+        3,
+        "ERROR_PATH_NOT_FOUND",
+        "The system cannot find the path specified.",
+    ),
+    (5, "ERROR_ACCESS_DENIED", "Access is denied."),
+    (6, "ERROR_INVALID_HANDLE", "The handle is invalid."),
+    (
+        8,
+        "ERROR_NOT_ENOUGH_MEMORY",
+        "Not enough memory resources are available to process this command.",
+    ),
+    (
+        32,
+        "ERROR_SHARING_VIOLATION",
+        "The process cannot access the file because it is being used by another process.",
+    ),
+    (50, "ERROR_NOT_SUPPORTED", "The request is not supported."),
+    (87, "ERROR_INVALID_PARAMETER", "The parameter is incorrect."),
+    (
+        112,
+        "ERROR_DISK_FULL",
+        "There is not enough space on the disk.",
+    ),
+    (
+        122,
+        "ERROR_INSUFFICIENT_BUFFER",
+        "The data area passed to a system call is too small.",
+    ),
+    (
+        126,
+        "ERROR_MOD_NOT_FOUND",
+        "The specified module could not be found.",
+    ),
+    (
+        127,
+        "ERROR_PROC_NOT_FOUND",
+        "The specified procedure could not be found.",
+    ),
+    (
+        183,
+        "ERROR_ALREADY_EXISTS",
+        "Cannot create a file when that file already exists.",
+    ),
+    (
+        193,
+        "ERROR_BAD_EXE_FORMAT",
+        "The file is not a valid Win32 application.",
+    ),
+    (
+        1113,
+        "ERROR_NO_UNICODE_TRANSLATION",
+        "No mapping for the Unicode character exists in the target multi-byte code page.",
+    ),
+    (1168, "ERROR_NOT_FOUND", "Element not found."),
+    (
+        1223,
+        "ERROR_CANCELLED",
+        "The operation was canceled by the user.",
+    ),
+    (1359, "ERROR_INTERNAL_ERROR", "An internal error occurred."),
+    (
+        1400,
+        "ERROR_INVALID_WINDOW_HANDLE",
+        "Invalid window handle.",
+    ),
+    (
+        1460,
+        "ERROR_TIMEOUT",
+        "This operation returned because the timeout period expired.",
+    ),
+    (
+        5023,
+        "ERROR_INVALID_STATE",
+        "The group or resource is not in the correct state to perform the requested operation.",
+    ),
+];
+
+/// Symbolic name and description of an `HRESULT` or Win32 error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HResultInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+fn lookup(table: &[(u32, &'static str, &'static str)], code: u32) -> Option<HResultInfo> {
+    table
+        .iter()
+        .find(|&&(c, _, _)| c == code)
+        .map(|&(_, name, description)| HResultInfo { name, description })
+}
+
+/// Look up a Win32 error code, e.g. `2` is `ERROR_FILE_NOT_FOUND`.
+pub fn describe_win32_error(code: u32) -> Option<HResultInfo> {
+    lookup(WIN32_ERRORS, code)
+}
+
+/// Look up an `HRESULT`. Those wrapping Win32 error codes are described by
+/// the Win32 code, unless they have their own name, like `E_INVALIDARG`.
+pub fn describe_hresult(hresult: i32) -> Option<HResultInfo> {
+    let hresult = hresult as u32;
+    lookup(HRESULTS, hresult).or_else(|| {
+        if hresult & 0xffff_0000 == FACILITY_WIN32 {
+            describe_win32_error(hresult & 0xffff)
+        } else {
+            None
+        }
+    })
+}
+
+/// `HRESULT_FROM_WIN32`.
+pub fn hresult_from_win32(code: u32) -> i32 {
+    if code as i32 <= 0 {
+        code as i32
+    } else {
+        ((code & 0xffff) | FACILITY_WIN32) as i32
+    }
+}
+
+/// What kind of error an `Error` is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A string passed to WebView2 contains a nul character, at this
+    /// position in UTF-16 code units.
+    InvalidString { nul_position: usize },
+    /// A string from WebView2 is not valid UTF-16.
+    NotUtf16,
+    /// No WebView2 runtime is installed.
+    RuntimeMissing,
+    /// The installed runtime is older than the target compatible browser
+    /// version.
+    VersionMismatch { installed: String, required: String },
+    /// Any other failure, see the `HRESULT`.
+    HResult,
+}
+
+/// WebView2 Error.
+///
+/// Has a kind, the `HRESULT` that is returned to or was returned by
+/// WebView2, the name of the operation that failed, if known, and labels
+/// added by `context`.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    hresult: i32,
+    operation: Option<&'static str>,
+    // Outermost first.
+    context: Vec<String>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// An error of kind `HResult`.
+    pub fn new(hresult: i32) -> Self {
+        Self::with_kind(ErrorKind::HResult, hresult)
+    }
+
+    pub fn with_kind(kind: ErrorKind, hresult: i32) -> Self {
+        Self {
+            kind,
+            hresult,
+            operation: None,
+            context: Vec::new(),
+        }
+    }
+
+    /// Set the name of the operation that failed.
+    pub fn with_operation(mut self, operation: &'static str) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+
+    /// Add a label saying what was being done when the error happened,
+    /// outside of those already added.
+    pub fn with_context(mut self, label: impl Into<String>) -> Self {
+        self.context.insert(0, label.into());
+        self
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn hresult(&self) -> i32 {
+        self.hresult
+    }
+
+    pub fn operation(&self) -> Option<&'static str> {
+        self.operation
+    }
+
+    /// Labels added by `with_context`, outermost first.
+    pub fn context(&self) -> &[String] {
+        &self.context
+    }
+
+    /// Symbolic name and description of the `HRESULT`, if known.
+    pub fn info(&self) -> Option<HResultInfo> {
+        describe_hresult(self.hresult)
+    }
+
+    /// An error creating an environment. `installed` is the version of the
+    /// runtime that could be found, `required` the target compatible browser
+    /// version.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) fn environment(hresult: i32, installed: Option<String>, required: &str) -> Self {
+        let kind = if hresult != hresult_from_win32(ERROR_FILE_NOT_FOUND) {
+            ErrorKind::HResult
+        } else {
+            match installed {
+                None => ErrorKind::RuntimeMissing,
+                Some(installed) if version_less(&installed, required) => {
+                    ErrorKind::VersionMismatch {
+                        installed,
+                        required: required.to_string(),
+                    }
+                }
+                Some(_) => ErrorKind::HResult,
+            }
+        };
+        Self::with_kind(kind, hresult)
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    #[allow(clippy::io_other_error)] // `io::Error::other` is too new.
+    pub(crate) fn into_io_error(self) -> io::Error {
+        if self.kind == ErrorKind::HResult && self.hresult as u32 & 0xffff_0000 == FACILITY_WIN32 {
+            io::Error::from_raw_os_error(self.hresult & 0xffff)
+        } else {
+            io::Error::new(io::ErrorKind::Other, self)
+        }
+    }
+}
+
+// Compare the numeric parts of versions like `86.0.622.0 canary`.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn version_less(a: &str, b: &str) -> bool {
+    fn parts(v: &str) -> Vec<u64> {
+        v.split_whitespace()
+            .next()
+            .unwrap_or("")
+            .split('.')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect()
+    }
+    parts(a) < parts(b)
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::InvalidString { nul_position } => write!(
+                f,
+                "string contains a nul character at position {}",
+                nul_position
+            ),
+            ErrorKind::NotUtf16 => f.write_str("string is not valid UTF-16"),
+            ErrorKind::RuntimeMissing => f.write_str("WebView2 runtime not found"),
+            ErrorKind::VersionMismatch {
+                installed,
+                required,
+            } => write!(
+                f,
+                "WebView2 runtime {} is older than the required {}",
+                installed, required
+            ),
+            ErrorKind::HResult => f.write_str("webview2 error"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(operation) = self.operation {
+            write!(f, "{}: ", operation)?;
+        }
+        match self.info() {
+            Some(info) => write!(
+                f,
+                "{}, {} ({:#X}): {}",
+                self.kind, info.name, self.hresult as u32, info.description
+            ),
+            None => write!(f, "{}, HRESULT {:#X}", self.kind, self.hresult as u32),
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Error")
+            .field("kind", &self.kind)
+            .field("hresult", &format_args!("{:#X}", self.hresult as u32))
+            .field("operation", &self.operation)
+            .field("context", &self.context)
+            .finish()
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<NulError<u16>> for Error {
+    fn from(e: NulError<u16>) -> Error {
+        Error::with_kind(
+            ErrorKind::InvalidString {
+                nul_position: e.nul_position(),
+            },
+            E_INVALIDARG,
+        )
+    }
+}
+
+impl From<FromUtf16Error> for Error {
+    fn from(_: FromUtf16Error) -> Error {
+        Error::with_kind(
+            ErrorKind::NotUtf16,
+            hresult_from_win32(ERROR_NO_UNICODE_TRANSLATION),
+        )
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        match e.raw_os_error() {
+            Some(e) => Error::new(hresult_from_win32(e as u32)),
+            _ => Error::new(E_FAIL),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use widestring::U16CString;
+
+    #[test]
+    fn test_describe() {
+        let invalid_arg = describe_hresult(E_INVALIDARG).unwrap();
+        assert_eq!(invalid_arg.name, "E_INVALIDARG");
+        assert_eq!(
+            describe_hresult(0x8000_FFFF_u32 as i32).unwrap().name,
+            "E_UNEXPECTED"
+        );
+        // Win32 codes.
+        assert_eq!(
+            describe_hresult(hresult_from_win32(2)),
+            describe_win32_error(ERROR_FILE_NOT_FOUND)
+        );
+        assert_eq!(
+            describe_hresult(0x8007_0020_u32 as i32).unwrap().name,
+            "ERROR_SHARING_VIOLATION"
+        );
+        assert_eq!(describe_hresult(0x8007_3039_u32 as i32), None);
+        assert_eq!(describe_hresult(0x8123_4567_u32 as i32), None);
+        assert_eq!(describe_hresult(0), None);
+
+        assert_eq!(hresult_from_win32(0), 0);
+        assert_eq!(hresult_from_win32(87), E_INVALIDARG);
+        assert_eq!(hresult_from_win32(E_FAIL as u32), E_FAIL);
+    }
+
+    #[test]
+    fn test_display() {
+        let e = Error::new(E_INVALIDARG).with_operation("put_zoom_factor");
+        assert_eq!(
+            e.to_string(),
+            "put_zoom_factor: webview2 error, E_INVALIDARG (0x80070057): One or more arguments are invalid."
+        );
+        assert_eq!(
+            Error::new(0x8123_4567_u32 as i32).to_string(),
+            "webview2 error, HRESULT 0x81234567"
+        );
+        assert_eq!(
+            format!("{:?}", e),
+            r#"Error { kind: HResult, hresult: 0x80070057, operation: Some("put_zoom_factor"), context: [] }"#
+        );
+        let e = e.with_context("inner").with_context("outer");
+        assert_eq!(e.context(), ["outer", "inner"]);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let e: Error = U16CString::from_str("a\0b").unwrap_err().into();
+        assert_eq!(e.kind(), &ErrorKind::InvalidString { nul_position: 1 });
+        assert_eq!(e.hresult(), E_INVALIDARG);
+
+        let e: Error = String::from_utf16(&[0xd800]).unwrap_err().into();
+        assert_eq!(e.kind(), &ErrorKind::NotUtf16);
+        assert_eq!(e.info().unwrap().name, "ERROR_NO_UNICODE_TRANSLATION");
+        assert!(e.to_string().starts_with("string is not valid UTF-16, "));
+
+        let e: Error = io::Error::from_raw_os_error(5).into();
+        assert_eq!(e.info().unwrap().name, "E_ACCESSDENIED");
+        assert_eq!(e.into_io_error().raw_os_error(), Some(5));
+        let e: Error = io::Error::new(io::ErrorKind::InvalidData, "x").into();
+        assert_eq!(e.hresult(), E_FAIL);
+        assert_eq!(e.into_io_error().raw_os_error(), None);
+    }
+
+    #[test]
+    fn test_environment() {
+        let not_found = hresult_from_win32(ERROR_FILE_NOT_FOUND);
+        let e = Error::environment(not_found, None, "86.0.622");
+        assert_eq!(e.kind(), &ErrorKind::RuntimeMissing);
+        assert_eq!(e.hresult(), not_found);
+
+        let e = Error::environment(not_found, Some("85.0.564.40".into()), "86.0.622");
+        assert_eq!(
+            e.kind(),
+            &ErrorKind::VersionMismatch {
+                installed: "85.0.564.40".into(),
+                required: "86.0.622".into(),
+            }
+        );
+        assert_eq!(
+            e.with_operation("CreateCoreWebView2EnvironmentWithOptions")
+                .to_string(),
+            "CreateCoreWebView2EnvironmentWithOptions: WebView2 runtime 85.0.564.40 is older than the required 86.0.622, \
+             ERROR_FILE_NOT_FOUND (0x80070002): The system cannot find the file specified."
+        );
+
+        let e = Error::environment(not_found, Some("86.0.622.0 canary".into()), "86.0.622");
+        assert_eq!(e.kind(), &ErrorKind::HResult);
+        let e = Error::environment(E_FAIL, None, "86.0.622");
+        assert_eq!(e.kind(), &ErrorKind::HResult);
+    }
+}
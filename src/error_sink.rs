@@ -0,0 +1,452 @@
+// Reporting errors and panics of handlers.
+//
+// Callbacks run through `run_handler`, which records the event being
+// handled, catches panics and reports the errors a handler returns to the
+// browser. Both go to the `ErrorSink` of the environment the handler was
+// registered with, if any: the environment, and the controllers and
+// webviews created from it, carry the sink and clone it into each handler.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
+
+/// What went wrong in a handler.
+#[derive(Debug)]
+pub enum Failure {
+    /// The handler returned an error.
+    Error(Box<dyn Error + Send + Sync>),
+    /// The handler panicked, with this message.
+    Panic(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Error(e) => e.fmt(f),
+            Failure::Panic(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+/// A handler error or panic.
+#[derive(Debug)]
+pub struct HandlerError {
+    /// The handler interface, e.g.
+    /// `"ICoreWebView2NavigationStartingEventHandler"`.
+    pub event: &'static str,
+    /// Outermost first: labels of `context` calls the error was returned
+    /// or the panic unwound through, then the sources of the error.
+    pub context: Vec<String>,
+    pub failure: Failure,
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed", self.event)?;
+        for label in &self.context {
+            write!(f, ": {}", label)?;
+        }
+        write!(f, ": {}", self.failure)
+    }
+}
+
+/// What an `ErrorSink` does with errors.
+#[derive(Clone)]
+pub enum ErrorPolicy {
+    /// Print the error and abort the process.
+    Abort,
+    /// Print the error to stderr.
+    Log,
+    /// Send the error to a channel, or print it if the receiver is gone.
+    Channel(Sender<HandlerError>),
+    /// Call a function.
+    Callback(Rc<dyn Fn(HandlerError)>),
+}
+
+impl fmt::Debug for ErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorPolicy::Abort => "Abort",
+            ErrorPolicy::Log => "Log",
+            ErrorPolicy::Channel(_) => "Channel",
+            ErrorPolicy::Callback(_) => "Callback",
+        })
+    }
+}
+
+/// Where handler errors go, see `EnvironmentBuilder::with_error_sink`.
+#[derive(Debug, Clone)]
+pub struct ErrorSink {
+    policy: ErrorPolicy,
+    catch_panics: bool,
+}
+
+// `const` initializers need a newer compiler than the crate supports.
+thread_local! {
+    // Handlers being run, innermost last.
+    #[allow(clippy::missing_const_for_thread_local)]
+    static EVENTS: RefCell<Vec<&'static str>> = RefCell::new(Vec::new());
+    // Labels of the `context` calls a panic unwound through, innermost
+    // first.
+    #[allow(clippy::missing_const_for_thread_local)]
+    static CONTEXT: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+impl ErrorSink {
+    /// A sink that does not catch panics: they still abort the process.
+    pub fn new(policy: ErrorPolicy) -> Self {
+        Self {
+            policy,
+            catch_panics: false,
+        }
+    }
+
+    /// Report panics instead of aborting. The browser is told that the
+    /// handler failed.
+    pub fn with_catch_panics(mut self, catch_panics: bool) -> Self {
+        self.catch_panics = catch_panics;
+        self
+    }
+
+    pub fn policy(&self) -> &ErrorPolicy {
+        &self.policy
+    }
+
+    pub fn catches_panics(&self) -> bool {
+        self.catch_panics
+    }
+
+    /// Handle an error according to the policy.
+    pub fn report(&self, error: HandlerError) {
+        match self.policy {
+            ErrorPolicy::Abort => {
+                eprintln!("webview2: {}. Aborting.", error);
+                std::process::abort()
+            }
+            ErrorPolicy::Log => eprintln!("webview2: {}", error),
+            ErrorPolicy::Channel(ref sender) => {
+                if let Err(e) = sender.send(error) {
+                    eprintln!("webview2: {}", e.0);
+                }
+            }
+            ErrorPolicy::Callback(ref callback) => callback(error),
+        }
+    }
+}
+
+/// Run `f`, and if it fails, add `label` to the context of the error, see
+/// `Error::with_context`. If it panics in a handler, `label` is reported
+/// along with the panic.
+///
+/// ```
+/// # use webview2::{context, Error};
+/// let r: webview2::Result<()> = context("loading settings", || Err(Error::new(-1)));
+/// assert_eq!(r.unwrap_err().context(), ["loading settings"]);
+/// ```
+pub fn context<T>(
+    label: impl Into<String>,
+    f: impl FnOnce() -> crate::Result<T>,
+) -> crate::Result<T> {
+    let mut guard = ContextGuard(Some(label.into()));
+    let result = f();
+    let label = guard.0.take().expect("only taken here");
+    result.map_err(|e| e.with_context(label))
+}
+
+// Records the label of a `context` call if a panic unwinds through it.
+struct ContextGuard(Option<String>);
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if let Some(label) = self.0.take() {
+            if std::thread::panicking() && !EVENTS.with(|events| events.borrow().is_empty()) {
+                CONTEXT.with(|context| context.borrow_mut().push(label));
+            }
+        }
+    }
+}
+
+struct EventGuard;
+
+impl Drop for EventGuard {
+    fn drop(&mut self) {
+        let outermost = EVENTS.with(|events| {
+            let mut events = events.borrow_mut();
+            events.pop();
+            events.is_empty()
+        });
+        if outermost {
+            // Context of panics that were not reported.
+            CONTEXT.with(|context| context.borrow_mut().clear());
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<Any>".to_string()
+    }
+}
+
+/// What a handler returns to the browser.
+#[doc(hidden)]
+pub trait HandlerOutput {
+    /// Report a failure, while the handler is still running.
+    fn report(&self, error_sink: &ErrorSink);
+}
+
+// An `HRESULT`, which is not reported.
+impl HandlerOutput for i32 {
+    fn report(&self, _: &ErrorSink) {}
+}
+
+impl HandlerOutput for crate::Result<()> {
+    fn report(&self, error_sink: &ErrorSink) {
+        if let Err(ref e) = *self {
+            report_error(error_sink, e.clone());
+        }
+    }
+}
+
+/// Run a handler for `event`, reporting the error it returns to
+/// `error_sink`. Returns `None` if it panicked and the sink caught the
+/// panic; aborts the process if it panicked otherwise.
+#[doc(hidden)]
+pub fn run_handler<R: HandlerOutput>(
+    event: &'static str,
+    error_sink: Option<&ErrorSink>,
+    f: impl FnOnce() -> R,
+) -> Option<R> {
+    EVENTS.with(|events| events.borrow_mut().push(event));
+    let _guard = EventGuard;
+    let f = move || {
+        let output = f();
+        if let Some(error_sink) = error_sink {
+            output.report(error_sink);
+        }
+        output
+    };
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(r) => Some(r),
+        Err(payload) => {
+            let message = panic_message(&*payload);
+            match error_sink {
+                Some(sink) if sink.catch_panics => {
+                    let context =
+                        CONTEXT.with(|context| context.borrow_mut().drain(..).rev().collect());
+                    sink.report(HandlerError {
+                        event,
+                        context,
+                        failure: Failure::Panic(message),
+                    });
+                    None
+                }
+                _ => {
+                    eprintln!(
+                        "webview2: panic in callback function: {}. Aborting because it's UB to unwind across FFI boundaries.",
+                        message
+                    );
+                    std::process::abort()
+                }
+            }
+        }
+    }
+}
+
+/// Report an error of the handler being run.
+pub(crate) fn report_error<E: Error + Send + Sync + 'static>(sink: &ErrorSink, error: E) {
+    let event = match EVENTS.with(|events| events.borrow().last().cloned()) {
+        Some(event) => event,
+        // Not in a handler.
+        None => return,
+    };
+    let mut context = match (&error as &(dyn Error + 'static)).downcast_ref::<crate::Error>() {
+        Some(e) => e.context().to_vec(),
+        None => Vec::new(),
+    };
+    let mut source = error.source();
+    while let Some(e) = source {
+        context.push(e.to_string());
+        source = e.source();
+    }
+    sink.report(HandlerError {
+        event,
+        context,
+        failure: Failure::Error(Box::new(error)),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[derive(Debug)]
+    struct Synthetic(&'static str, Option<Box<Synthetic>>);
+
+    impl fmt::Display for Synthetic {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl Error for Synthetic {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            self.1.as_ref().map(|e| &**e as &(dyn Error + 'static))
+        }
+    }
+
+    // What a handler returning an error goes through.
+    fn failing_handler(sink: &ErrorSink, error: Synthetic) -> impl FnOnce() -> i32 + '_ {
+        move || {
+            report_error(sink, error);
+            -1
+        }
+    }
+
+    fn channel_sink(catch_panics: bool) -> (ErrorSink, mpsc::Receiver<HandlerError>) {
+        let (tx, rx) = mpsc::channel();
+        let sink = ErrorSink::new(ErrorPolicy::Channel(tx)).with_catch_panics(catch_panics);
+        (sink, rx)
+    }
+
+    #[test]
+    fn test_errors() {
+        let (sink, rx) = channel_sink(false);
+
+        let error = Synthetic("top", Some(Box::new(Synthetic("cause", None))));
+        assert_eq!(
+            run_handler(
+                "NavigationStarting",
+                Some(&sink),
+                failing_handler(&sink, error)
+            ),
+            Some(-1)
+        );
+        let reported = rx.try_recv().unwrap();
+        assert_eq!(reported.event, "NavigationStarting");
+        assert_eq!(reported.context, vec!["cause"]);
+        assert_eq!(
+            reported.to_string(),
+            "NavigationStarting failed: cause: top"
+        );
+
+        // Nested handlers report their own event.
+        run_handler("Outer", Some(&sink), || {
+            run_handler(
+                "Inner",
+                Some(&sink),
+                failing_handler(&sink, Synthetic("e", None)),
+            )
+            .unwrap()
+        });
+        assert_eq!(rx.try_recv().unwrap().event, "Inner");
+
+        // Nothing is reported outside of handlers.
+        report_error(&sink, Synthetic("ignored", None));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_context() {
+        let (sink, rx) = channel_sink(false);
+        let result = run_handler("WebMessageReceived", Some(&sink), || {
+            context("handling", || {
+                context("decoding", || Err::<(), _>(crate::Error::new(-1)))
+            })
+        });
+        let error = crate::Error::new(-1)
+            .with_context("decoding")
+            .with_context("handling");
+        assert_eq!(result, Some(Err(error)));
+        let reported = rx.try_recv().unwrap();
+        assert_eq!(reported.context, vec!["handling", "decoding"]);
+        assert!(reported
+            .to_string()
+            .starts_with("WebMessageReceived failed: handling: decoding: "));
+
+        // The labels of handled errors are not reported with other ones.
+        run_handler("Handled", Some(&sink), || {
+            let _ = context("handled", || Err::<(), _>(crate::Error::new(-1)));
+            Err(crate::Error::new(-2))
+        });
+        assert!(rx.try_recv().unwrap().context.is_empty());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_returned_errors() {
+        let (sink, rx) = channel_sink(false);
+        let result = run_handler("WebMessageReceived", Some(&sink), || {
+            Err(crate::Error::new(-1))
+        });
+        assert_eq!(result, Some(Err(crate::Error::new(-1))));
+        let reported = rx.try_recv().unwrap();
+        assert_eq!(reported.event, "WebMessageReceived");
+        assert!(matches!(reported.failure, Failure::Error(_)));
+
+        // `HRESULT`s and successes are not reported, and neither is
+        // anything without a sink.
+        assert_eq!(run_handler("E", Some(&sink), || -1), Some(-1));
+        assert_eq!(run_handler("E", Some(&sink), || Ok(())), Some(Ok(())));
+        run_handler("E", None, || Err(crate::Error::new(-1)));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_caught_panic() {
+        let (sink, rx) = channel_sink(true);
+        let result = run_handler("ScriptDialogOpening", Some(&sink), || -> i32 {
+            let _ = context("handled", || Err::<(), _>(crate::Error::new(-1)));
+            let _ = context("showing", || {
+                context("dialog", || -> crate::Result<()> { panic!("boom {}", 1) })
+            });
+            0
+        });
+        assert_eq!(result, None);
+        let reported = rx.try_recv().unwrap();
+        assert_eq!(reported.context, vec!["showing", "dialog"]);
+        match reported.failure {
+            Failure::Panic(ref message) => assert_eq!(message, "boom 1"),
+            ref f => panic!("{:?}", f),
+        }
+
+        // The thread keeps working.
+        assert_eq!(run_handler("Next", Some(&sink), || 2), Some(2));
+    }
+
+    #[test]
+    fn test_policies() {
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let r = Rc::clone(&reported);
+        let sink = ErrorSink::new(ErrorPolicy::Callback(Rc::new(move |e: HandlerError| {
+            r.borrow_mut().push(e.to_string())
+        })));
+        assert!(!sink.catches_panics());
+        run_handler(
+            "E",
+            Some(&sink),
+            failing_handler(&sink, Synthetic("x", None)),
+        );
+        assert_eq!(*reported.borrow(), vec!["E failed: x"]);
+
+        // A closed channel falls back to logging.
+        let (tx, rx) = mpsc::channel();
+        drop(rx);
+        let sink = ErrorSink::new(ErrorPolicy::Channel(tx));
+        run_handler(
+            "E",
+            Some(&sink),
+            failing_handler(&sink, Synthetic("x", None)),
+        );
+        assert!(matches!(sink.policy(), ErrorPolicy::Channel(_)));
+    }
+}
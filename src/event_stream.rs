@@ -0,0 +1,534 @@
+// Events as streams.
+//
+// An event handler sends owned event values through an `EventSender` to the
+// matching `EventStream`, which removes the handler when it is dropped.
+// Events that support deferral yield `Deferred` values, which keep the
+// event pending until they are completed or dropped.
+
+use futures_core::Stream;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+struct Queue<T> {
+    items: VecDeque<T>,
+    waker: Option<Waker>,
+    stream_dropped: bool,
+}
+
+/// Sends events to the matching `EventStream`.
+pub struct EventSender<T> {
+    queue: Rc<RefCell<Queue<T>>>,
+}
+
+impl<T> Clone for EventSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: Rc::clone(&self.queue),
+        }
+    }
+}
+
+impl<T> Drop for EventSender<T> {
+    fn drop(&mut self) {
+        // The last sender wakes the stream, so that it ends.
+        if Rc::strong_count(&self.queue) == 2 {
+            let waker = self.queue.borrow_mut().waker.take();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for EventSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSender").finish()
+    }
+}
+
+impl<T> EventSender<T> {
+    /// Queue an event. Returns `false` if the stream was dropped.
+    pub fn send(&self, item: T) -> bool {
+        let waker = {
+            let mut queue = self.queue.borrow_mut();
+            if queue.stream_dropped {
+                return false;
+            }
+            queue.items.push_back(item);
+            queue.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        true
+    }
+}
+
+/// A stream of events. It never ends while the event source is alive.
+///
+/// Dropping it unregisters the event handler.
+pub struct EventStream<T> {
+    queue: Rc<RefCell<Queue<T>>>,
+    unregister: Option<Box<dyn FnOnce()>>,
+}
+
+/// A sender and stream pair.
+pub fn event_channel<T>() -> (EventSender<T>, EventStream<T>) {
+    let queue = Rc::new(RefCell::new(Queue {
+        items: VecDeque::new(),
+        waker: None,
+        stream_dropped: false,
+    }));
+    (
+        EventSender {
+            queue: Rc::clone(&queue),
+        },
+        EventStream {
+            queue,
+            unregister: None,
+        },
+    )
+}
+
+impl<T> EventStream<T> {
+    /// Call `unregister` when the stream is dropped.
+    pub fn with_unregister(mut self, unregister: impl FnOnce() + 'static) -> Self {
+        self.unregister = Some(Box::new(unregister));
+        self
+    }
+
+    /// The next event, if one is queued.
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.queue.borrow_mut().items.pop_front()
+    }
+
+    /// A future of the next event.
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { stream: self }
+    }
+
+    /// Number of queued events.
+    pub fn len(&self) -> usize {
+        self.queue.borrow().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.borrow().items.is_empty()
+    }
+}
+
+impl<T> Stream for EventStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut queue = self.queue.borrow_mut();
+        match queue.items.pop_front() {
+            Some(item) => Poll::Ready(Some(item)),
+            // The senders, held by the handler, are gone.
+            None if Rc::strong_count(&self.queue) == 1 => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for EventStream<T> {
+    fn drop(&mut self) {
+        let items = {
+            let mut queue = self.queue.borrow_mut();
+            queue.stream_dropped = true;
+            std::mem::take(&mut queue.items)
+        };
+        // Queued deferred events complete now, outside the borrow, since
+        // completing one may raise another event.
+        drop(items);
+        if let Some(unregister) = self.unregister.take() {
+            unregister();
+        }
+    }
+}
+
+impl<T> fmt::Debug for EventStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventStream")
+            .field("queued", &self.len())
+            .finish()
+    }
+}
+
+/// Future returned by `EventStream::recv`.
+#[derive(Debug)]
+pub struct Recv<'a, T> {
+    stream: &'a mut EventStream<T>,
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+/// Event arguments with a deferral: the event stays pending, e.g. a dialog
+/// or request waits for the host, until this is completed or dropped.
+pub struct Deferred<A> {
+    args: A,
+    complete: Option<Box<dyn FnOnce()>>,
+}
+
+impl<A> Deferred<A> {
+    /// Keep the event pending until `complete` is called.
+    pub fn new(args: A, complete: impl FnOnce() + 'static) -> Self {
+        Self {
+            args,
+            complete: Some(Box::new(complete)),
+        }
+    }
+
+    pub fn args(&self) -> &A {
+        &self.args
+    }
+
+    /// Let the event continue, with the changes made to the arguments.
+    pub fn complete(self) {
+        // Dropping completes.
+    }
+}
+
+impl<A> Deref for Deferred<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.args
+    }
+}
+
+impl<A> Drop for Deferred<A> {
+    fn drop(&mut self) {
+        if let Some(complete) = self.complete.take() {
+            complete();
+        }
+    }
+}
+
+impl<A: fmt::Debug> fmt::Debug for Deferred<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Deferred")
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::*;
+
+    // An event stream yielding a value made from the sender and arguments.
+    macro_rules! event_stream {
+        ($(#[$doc:meta])* $name:ident, $add:ident, $remove:ident, $T:ty, |$sender:ident, $args:pat| $item:expr) => {
+            $(#[$doc])*
+            pub fn $name(&self) -> Result<EventStream<$T>> {
+                let (tx, stream) = event_channel();
+                let token = self.$add(move |$sender, $args| {
+                    tx.send($item);
+                    Ok(())
+                })?;
+                let source = self.clone();
+                Ok(stream.with_unregister(move || {
+                    let _ = source.$remove(token);
+                }))
+            }
+        };
+    }
+
+    // An event stream of deferred arguments.
+    macro_rules! deferred_event_stream {
+        ($(#[$doc:meta])* $name:ident, $add:ident, $remove:ident, $Args:ty) => {
+            event_stream!(
+                $(#[$doc])*
+                $name,
+                $add,
+                $remove,
+                Deferred<$Args>,
+                |_sender, args| {
+                    let deferral = args.get_deferral()?;
+                    Deferred::new(args, move || {
+                        let _ = deferral.complete();
+                    })
+                }
+            );
+        };
+    }
+
+    // Events without arguments, yielding the sender.
+    macro_rules! sender_event_stream {
+        ($(#[$doc:meta])* $name:ident, $add:ident, $remove:ident, $Sender:ty) => {
+            $(#[$doc])*
+            pub fn $name(&self) -> Result<EventStream<$Sender>> {
+                let (tx, stream) = event_channel();
+                let token = self.$add(move |sender| {
+                    tx.send(sender);
+                    Ok(())
+                })?;
+                let source = self.clone();
+                Ok(stream.with_unregister(move || {
+                    let _ = source.$remove(token);
+                }))
+            }
+        };
+    }
+
+    impl WebView {
+        event_stream!(
+            /// `put_cancel` only has an effect in a handler registered with
+            /// `add_navigation_starting`, not on streamed events.
+            navigation_starting_events,
+            add_navigation_starting,
+            remove_navigation_starting,
+            NavigationStartingEventArgs,
+            |_sender, args| args
+        );
+        event_stream!(
+            /// See `navigation_starting_events`.
+            frame_navigation_starting_events,
+            add_frame_navigation_starting,
+            remove_frame_navigation_starting,
+            NavigationStartingEventArgs,
+            |_sender, args| args
+        );
+        event_stream!(
+            content_loading_events,
+            add_content_loading,
+            remove_content_loading,
+            ContentLoadingEventArgs,
+            |_sender, args| args
+        );
+        event_stream!(
+            source_changed_events,
+            add_source_changed,
+            remove_source_changed,
+            SourceChangedEventArgs,
+            |_sender, args| args
+        );
+        event_stream!(
+            navigation_completed_events,
+            add_navigation_completed,
+            remove_navigation_completed,
+            NavigationCompletedEventArgs,
+            |_sender, args| args
+        );
+        event_stream!(
+            process_failed_events,
+            add_process_failed,
+            remove_process_failed,
+            ProcessFailedEventArgs,
+            |_sender, args| args
+        );
+        event_stream!(
+            web_message_received_events,
+            add_web_message_received,
+            remove_web_message_received,
+            WebMessageReceivedEventArgs,
+            |_sender, args| args
+        );
+        deferred_event_stream!(
+            script_dialog_opening_events,
+            add_script_dialog_opening,
+            remove_script_dialog_opening,
+            ScriptDialogOpeningEventArgs
+        );
+        deferred_event_stream!(
+            permission_requested_events,
+            add_permission_requested,
+            remove_permission_requested,
+            PermissionRequestedEventArgs
+        );
+        deferred_event_stream!(
+            new_window_requested_events,
+            add_new_window_requested,
+            remove_new_window_requested,
+            NewWindowRequestedEventArgs
+        );
+        deferred_event_stream!(
+            /// Add filters with `add_web_resource_requested_filter`.
+            web_resource_requested_events,
+            add_web_resource_requested,
+            remove_web_resource_requested,
+            WebResourceRequestedEventArgs
+        );
+        sender_event_stream!(
+            history_changed_events,
+            add_history_changed,
+            remove_history_changed,
+            WebView
+        );
+        sender_event_stream!(
+            document_title_changed_events,
+            add_document_title_changed,
+            remove_document_title_changed,
+            WebView
+        );
+        sender_event_stream!(
+            contains_full_screen_element_changed_events,
+            add_contains_full_screen_element_changed,
+            remove_contains_full_screen_element_changed,
+            WebView
+        );
+        sender_event_stream!(
+            window_close_requested_events,
+            add_window_close_requested,
+            remove_window_close_requested,
+            WebView
+        );
+    }
+
+    impl Controller {
+        sender_event_stream!(
+            zoom_factor_changed_events,
+            add_zoom_factor_changed,
+            remove_zoom_factor_changed,
+            Controller
+        );
+        event_stream!(
+            /// `put_handled` only has an effect in a handler registered
+            /// with `add_move_focus_requested`, not on streamed events.
+            move_focus_requested_events,
+            add_move_focus_requested,
+            remove_move_focus_requested,
+            MoveFocusRequestedEventArgs,
+            |_sender, args| args
+        );
+        sender_event_stream!(
+            got_focus_events,
+            add_got_focus,
+            remove_got_focus,
+            Controller
+        );
+        sender_event_stream!(
+            lost_focus_events,
+            add_lost_focus,
+            remove_lost_focus,
+            Controller
+        );
+        event_stream!(
+            /// `put_handled` only has an effect in a handler registered
+            /// with `add_accelerator_key_pressed`, not on streamed events.
+            accelerator_key_pressed_events,
+            add_accelerator_key_pressed,
+            remove_accelerator_key_pressed,
+            AcceleratorKeyPressedEventArgs,
+            |_sender, args| args
+        );
+    }
+
+    impl Environment {
+        sender_event_stream!(
+            new_browser_version_available_events,
+            add_new_browser_version_available,
+            remove_new_browser_version_available,
+            Environment
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::LocalExecutor;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_stream() {
+        let (tx, mut stream) = event_channel();
+        assert!(tx.send(1));
+        assert!(tx.send(2));
+        assert_eq!(stream.len(), 2);
+        assert_eq!(stream.try_recv(), Some(1));
+
+        let executor = LocalExecutor::new();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let r = Rc::clone(&received);
+        executor.spawn(async move {
+            while let Some(item) = stream.recv().await {
+                r.borrow_mut().push(item);
+            }
+            r.borrow_mut().push(0);
+        });
+        executor.run_until_stalled();
+        assert_eq!(*received.borrow(), vec![2]);
+        tx.send(3);
+        executor.run_until_stalled();
+        assert_eq!(*received.borrow(), vec![2, 3]);
+
+        // The stream ends when the source is gone.
+        let tx2 = tx.clone();
+        drop(tx);
+        executor.run_until_stalled();
+        assert_eq!(*received.borrow(), vec![2, 3]);
+        drop(tx2);
+        executor.run_until_stalled();
+        assert_eq!(*received.borrow(), vec![2, 3, 0]);
+        assert_eq!(executor.task_count(), 0);
+    }
+
+    #[test]
+    fn test_unregister_on_drop() {
+        let unregistered = Rc::new(Cell::new(0));
+        let u = Rc::clone(&unregistered);
+        let (tx, stream) = event_channel::<i32>();
+        let stream = stream.with_unregister(move || u.set(u.get() + 1));
+        assert!(tx.send(1));
+        drop(stream);
+        assert_eq!(unregistered.get(), 1);
+        assert!(!tx.send(2));
+    }
+
+    #[test]
+    fn test_deferred() {
+        let completed = Rc::new(Cell::new(0));
+        let (tx, mut stream) = event_channel();
+        for i in 0..3 {
+            let c = Rc::clone(&completed);
+            tx.send(Deferred::new(i, move || c.set(c.get() + 1)));
+        }
+
+        // Kept pending while held.
+        let first = stream.try_recv().unwrap();
+        assert_eq!(*first, 0);
+        assert_eq!(completed.get(), 0);
+        first.complete();
+        assert_eq!(completed.get(), 1);
+
+        let second = stream.try_recv().unwrap();
+        // Queued events complete when the stream is dropped.
+        drop(stream);
+        assert_eq!(completed.get(), 2);
+        assert_eq!(*second.args(), 1);
+        drop(second);
+        assert_eq!(completed.get(), 3);
+    }
+
+    #[test]
+    fn test_deferred_raises_event_on_drop() {
+        // Completing a queued event raises another one, which is rejected.
+        let (tx, stream) = event_channel();
+        let tx1 = tx.clone();
+        let sent = Rc::new(Cell::new(None));
+        let s = Rc::clone(&sent);
+        tx.send(Deferred::new(0, move || {
+            s.set(Some(tx1.send(Deferred::new(1, || {}))))
+        }));
+        drop(stream);
+        assert_eq!(sent.get(), Some(false));
+    }
+}
@@ -0,0 +1,719 @@
+// Futures for completion callbacks, and a single-threaded executor to run
+// them on the UI thread.
+//
+// A completion callback completes a `Completer`, which wakes the task
+// waiting on the matching `Completion`. Wakers may be called from any
+// thread: they queue the task and call the executor's notify function, which
+// on Windows posts a message to a message-only window of the thread, so that
+// modal loops dispatch it too.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// The `Completer` of a `Completion` was dropped without completing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("completion callback dropped without being called")
+    }
+}
+
+impl std::error::Error for Canceled {}
+
+struct Slot<T> {
+    value: Option<T>,
+    completer_dropped: bool,
+    waker: Option<Waker>,
+}
+
+/// Completes the matching `Completion`.
+pub struct Completer<T> {
+    slot: Rc<RefCell<Slot<T>>>,
+}
+
+/// A future of the value passed to the matching `Completer`.
+pub struct Completion<T> {
+    slot: Rc<RefCell<Slot<T>>>,
+}
+
+/// A completer and completion pair, e.g. to turn a callback into a future.
+pub fn completion<T>() -> (Completer<T>, Completion<T>) {
+    let slot = Rc::new(RefCell::new(Slot {
+        value: None,
+        completer_dropped: false,
+        waker: None,
+    }));
+    (
+        Completer {
+            slot: Rc::clone(&slot),
+        },
+        Completion { slot },
+    )
+}
+
+impl<T> Completer<T> {
+    pub fn complete(self, value: T) {
+        self.slot.borrow_mut().value = Some(value);
+        // Dropping wakes the task.
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut slot = self.slot.borrow_mut();
+            slot.completer_dropped = true;
+            slot.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Completer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Completer").finish()
+    }
+}
+
+impl<T> Future for Completion<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.borrow_mut();
+        if let Some(value) = slot.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if slot.completer_dropped {
+            return Poll::Ready(Err(Canceled));
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> fmt::Debug for Completion<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slot = self.slot.borrow();
+        f.debug_struct("Completion")
+            .field("completed", &slot.value.is_some())
+            .field(
+                "canceled",
+                &(slot.value.is_none() && slot.completer_dropped),
+            )
+            .finish()
+    }
+}
+
+// Shared with wakers, which may be sent to other threads.
+struct ReadyQueue {
+    ids: Mutex<Vec<usize>>,
+    notify: Box<dyn Fn() + Send + Sync>,
+}
+
+impl ReadyQueue {
+    fn push(&self, id: usize) {
+        let was_empty = {
+            let mut ids = self.ids.lock().unwrap();
+            let was_empty = ids.is_empty();
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+            was_empty
+        };
+        if was_empty {
+            (self.notify)();
+        }
+    }
+}
+
+struct WakerData {
+    id: usize,
+    queue: Arc<ReadyQueue>,
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(data: *const ()) -> RawWaker {
+    let data = Arc::from_raw(data as *const WakerData);
+    let cloned = Arc::clone(&data);
+    std::mem::forget(data);
+    RawWaker::new(Arc::into_raw(cloned) as *const (), &VTABLE)
+}
+
+unsafe fn wake(data: *const ()) {
+    let data = Arc::from_raw(data as *const WakerData);
+    data.queue.push(data.id);
+}
+
+unsafe fn wake_by_ref(data: *const ()) {
+    let data = &*(data as *const WakerData);
+    data.queue.push(data.id);
+}
+
+unsafe fn drop_waker(data: *const ()) {
+    drop(Arc::from_raw(data as *const WakerData));
+}
+
+fn task_waker(id: usize, queue: &Arc<ReadyQueue>) -> Waker {
+    let data = Arc::new(WakerData {
+        id,
+        queue: Arc::clone(queue),
+    });
+    // `WakerData` is `Send` and `Sync`, and the vtable functions treat the
+    // pointer as an `Arc<WakerData>`.
+    unsafe { Waker::from_raw(RawWaker::new(Arc::into_raw(data) as *const (), &VTABLE)) }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+struct ExecutorInner {
+    tasks: RefCell<HashMap<usize, Task>>,
+    next_id: Cell<usize>,
+    ready: Arc<ReadyQueue>,
+    // Whether `run_until_stalled` is running, e.g. a task runs a nested
+    // message loop.
+    running: Cell<bool>,
+    #[cfg(windows)]
+    window: RefCell<Option<win::WakeWindow>>,
+}
+
+/// A single-threaded executor for futures that are not `Send`, such as
+/// those of this crate's `*_async` methods.
+///
+/// Tasks only run in `run_until_stalled`, which must be called when the
+/// notify function is called, e.g. from the message loop. On Windows,
+/// `for_current_thread` and `run_message_loop` do this.
+///
+/// Clones share the same tasks.
+#[derive(Clone)]
+pub struct LocalExecutor {
+    inner: Rc<ExecutorInner>,
+}
+
+impl fmt::Debug for LocalExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalExecutor")
+            .field("tasks", &self.task_count())
+            .finish()
+    }
+}
+
+impl Default for LocalExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalExecutor {
+    /// An executor without a notify function.
+    pub fn new() -> Self {
+        Self::with_notify(|| {})
+    }
+
+    /// An executor calling `notify` when a task is woken and there were no
+    /// tasks ready to run. It may be called from any thread.
+    pub fn with_notify(notify: impl Fn() + Send + Sync + 'static) -> Self {
+        Self {
+            inner: Rc::new(ExecutorInner {
+                tasks: RefCell::default(),
+                next_id: Cell::new(0),
+                ready: Arc::new(ReadyQueue {
+                    ids: Mutex::new(Vec::new()),
+                    notify: Box::new(notify),
+                }),
+                running: Cell::new(false),
+                #[cfg(windows)]
+                window: RefCell::new(None),
+            }),
+        }
+    }
+
+    /// Add a task. It first runs in the next `run_until_stalled`.
+    pub fn spawn(&self, future: impl Future<Output = ()> + 'static) {
+        let id = self.inner.next_id.get();
+        self.inner.next_id.set(id + 1);
+        self.inner.tasks.borrow_mut().insert(id, Box::pin(future));
+        self.inner.ready.push(id);
+    }
+
+    /// Run tasks until none is ready. Returns the number of tasks polled.
+    pub fn run_until_stalled(&self) -> usize {
+        let was_running = self.inner.running.replace(true);
+        let mut polled = 0;
+        loop {
+            let ids = std::mem::take(&mut *self.inner.ready.ids.lock().unwrap());
+            if ids.is_empty() {
+                self.inner.running.set(was_running);
+                return polled;
+            }
+            for id in ids {
+                // Take the task out, so that it can spawn others.
+                let mut task = match self.inner.tasks.borrow_mut().remove(&id) {
+                    Some(task) => task,
+                    // Woken after it finished.
+                    None => continue,
+                };
+                polled += 1;
+                let waker = task_waker(id, &self.inner.ready);
+                let mut cx = Context::from_waker(&waker);
+                if task.as_mut().poll(&mut cx).is_pending() {
+                    self.inner.tasks.borrow_mut().insert(id, task);
+                }
+            }
+        }
+    }
+
+    /// Number of unfinished tasks.
+    pub fn task_count(&self) -> usize {
+        self.inner.tasks.borrow().len()
+    }
+
+    /// Run a future to completion, calling `wait` whenever no task is
+    /// ready. `wait` should block until there may be, e.g. by dispatching a
+    /// message, and return `false` to give up.
+    ///
+    /// Other tasks run as well. Returns `None` if `wait` gave up.
+    pub fn block_on<F: Future + 'static>(
+        &self,
+        future: F,
+        mut wait: impl FnMut() -> bool,
+    ) -> Option<F::Output> {
+        let output = Rc::new(RefCell::new(None));
+        let o = Rc::clone(&output);
+        self.spawn(async move {
+            let value = future.await;
+            *o.borrow_mut() = Some(value);
+        });
+        loop {
+            self.run_until_stalled();
+            if let Some(value) = output.borrow_mut().take() {
+                return Some(value);
+            }
+            if !wait() {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::{
+        CapturePreviewImageFormat, Controller, Environment, EnvironmentBuilder, Error, Result,
+        Stream, WebView,
+    };
+    use once_cell::sync::OnceCell;
+    use std::ptr;
+    use std::rc::Weak;
+    use widestring::WideCString;
+    use winapi::shared::minwindef::{ATOM, LPARAM, LRESULT, UINT, WPARAM};
+    use winapi::shared::windef::HWND;
+    use winapi::shared::winerror::E_ABORT;
+    use winapi::um::libloaderapi::GetModuleHandleW;
+    use winapi::um::winuser::{
+        CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW,
+        PostMessageW, PostQuitMessage, RegisterClassW, TranslateMessage, HWND_MESSAGE, WM_APP,
+        WNDCLASSW,
+    };
+
+    impl From<Canceled> for Error {
+        fn from(_: Canceled) -> Error {
+            Error::new(E_ABORT)
+        }
+    }
+
+    // The result of an operation taking a completion callback. Errors
+    // starting it are returned by the future.
+    async fn finish<T>(started: Result<()>, pending: Completion<Result<T>>) -> Result<T> {
+        started?;
+        pending.await?
+    }
+
+    // Posted to the window of an executor when a task is woken.
+    const WM_WAKE: UINT = WM_APP;
+
+    thread_local! {
+        // The executors of the windows of this thread.
+        #[allow(clippy::missing_const_for_thread_local)]
+        static EXECUTORS: RefCell<HashMap<usize, Weak<ExecutorInner>>> = RefCell::default();
+    }
+
+    unsafe extern "system" fn wake_window_proc(
+        hwnd: HWND,
+        msg: UINT,
+        w_param: WPARAM,
+        l_param: LPARAM,
+    ) -> LRESULT {
+        if msg != WM_WAKE {
+            return DefWindowProcW(hwnd, msg, w_param, l_param);
+        }
+        let inner = EXECUTORS.with(|executors| {
+            executors
+                .borrow()
+                .get(&(hwnd as usize))
+                .and_then(Weak::upgrade)
+        });
+        if let Some(inner) = inner {
+            // Tasks woken in a nested message loop run when it returns.
+            if !inner.running.get() {
+                LocalExecutor { inner }.run_until_stalled();
+            }
+        }
+        0
+    }
+
+    fn wake_window_class() -> Result<ATOM> {
+        static CLASS: OnceCell<ATOM> = OnceCell::new();
+        let atom =
+            CLASS.get_or_try_init(|| {
+                let class_name = WideCString::from_str("webview2::LocalExecutor").unwrap();
+                let class = WNDCLASSW {
+                    lpfnWndProc: Some(wake_window_proc),
+                    hInstance: unsafe { GetModuleHandleW(ptr::null()) },
+                    lpszClassName: class_name.as_ptr(),
+                    ..unsafe { std::mem::zeroed() }
+                };
+                match unsafe { RegisterClassW(&class) } {
+                    0 => Err(Error::from(std::io::Error::last_os_error())
+                        .with_operation("RegisterClassW")),
+                    atom => Ok(atom),
+                }
+            })?;
+        Ok(*atom)
+    }
+
+    // A message-only window running the tasks of an executor, destroyed
+    // with the executor.
+    pub(super) struct WakeWindow(HWND);
+
+    impl Drop for WakeWindow {
+        fn drop(&mut self) {
+            EXECUTORS.with(|executors| executors.borrow_mut().remove(&(self.0 as usize)));
+            unsafe {
+                DestroyWindow(self.0);
+            }
+        }
+    }
+
+    impl LocalExecutor {
+        /// An executor for the current thread's message loop: waking a task
+        /// posts a message to a message-only window, which runs the tasks
+        /// when the message loop or a modal loop, e.g. of a dialog box or
+        /// while moving a window, dispatches it.
+        pub fn for_current_thread() -> Result<Self> {
+            let class = wake_window_class()?;
+            let hwnd = unsafe {
+                CreateWindowExW(
+                    0,
+                    class as usize as *const u16,
+                    ptr::null(),
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    HWND_MESSAGE,
+                    ptr::null_mut(),
+                    GetModuleHandleW(ptr::null()),
+                    ptr::null_mut(),
+                )
+            };
+            if hwnd.is_null() {
+                return Err(
+                    Error::from(std::io::Error::last_os_error()).with_operation("CreateWindowExW")
+                );
+            }
+            // `HWND` is not `Send`. Posting to a destroyed window fails.
+            let window = hwnd as usize;
+            let executor = Self::with_notify(move || unsafe {
+                PostMessageW(window as HWND, WM_WAKE, 0, 0);
+            });
+            EXECUTORS.with(|executors| {
+                executors
+                    .borrow_mut()
+                    .insert(window, Rc::downgrade(&executor.inner))
+            });
+            *executor.inner.window.borrow_mut() = Some(WakeWindow(hwnd));
+            Ok(executor)
+        }
+
+        // Dispatch a message. Returns `false` on `WM_QUIT`, which is posted
+        // again for the outer loop.
+        fn pump_message() -> bool {
+            unsafe {
+                let mut msg = std::mem::zeroed();
+                match GetMessageW(&mut msg, ptr::null_mut(), 0, 0) {
+                    0 => {
+                        PostQuitMessage(msg.wParam as i32);
+                        false
+                    }
+                    -1 => false,
+                    _ => {
+                        TranslateMessage(&msg);
+                        DispatchMessageW(&msg);
+                        true
+                    }
+                }
+            }
+        }
+
+        /// Run the thread's message loop and the tasks until `WM_QUIT`.
+        ///
+        /// The executor must have been created by `for_current_thread` on
+        /// this thread.
+        pub fn run_message_loop(&self) {
+            loop {
+                self.run_until_stalled();
+                if !Self::pump_message() {
+                    return;
+                }
+            }
+        }
+
+        /// Run the message loop until a future completes. Returns `None` on
+        /// `WM_QUIT`, which is posted again.
+        pub fn block_on_message_loop<F: Future + 'static>(&self, future: F) -> Option<F::Output> {
+            self.block_on(future, Self::pump_message)
+        }
+    }
+
+    impl<'a> EnvironmentBuilder<'a> {
+        /// `build` as a future.
+        pub fn build_async(&self) -> impl Future<Output = Result<Environment>> + 'static {
+            let (completer, pending) = completion();
+            let started = self.build(move |result| {
+                completer.complete(result);
+                Ok(())
+            });
+            finish(started, pending)
+        }
+    }
+
+    impl Environment {
+        /// `create_controller` as a future.
+        pub fn create_controller_async(
+            &self,
+            parent_window: HWND,
+        ) -> impl Future<Output = Result<Controller>> + 'static {
+            let (completer, pending) = completion();
+            let started = self.create_controller(parent_window, move |result| {
+                completer.complete(result);
+                Ok(())
+            });
+            finish(started, pending)
+        }
+    }
+
+    impl WebView {
+        /// `execute_script` as a future of the result JSON.
+        pub fn execute_script_async(
+            &self,
+            script: &str,
+        ) -> impl Future<Output = Result<String>> + 'static {
+            let (completer, pending) = completion();
+            let started = self.execute_script(script, move |json| {
+                completer.complete(Ok(json));
+                Ok(())
+            });
+            finish(started, pending)
+        }
+
+        /// `add_script_to_execute_on_document_created` as a future of the
+        /// script id.
+        pub fn add_script_to_execute_on_document_created_async(
+            &self,
+            script: &str,
+        ) -> impl Future<Output = Result<String>> + 'static {
+            let (completer, pending) = completion();
+            let started = self.add_script_to_execute_on_document_created(script, move |id| {
+                completer.complete(Ok(id));
+                Ok(())
+            });
+            finish(started, pending)
+        }
+
+        /// `capture_preview` as a future.
+        pub fn capture_preview_async(
+            &self,
+            image_format: CapturePreviewImageFormat,
+            image_stream: Stream,
+        ) -> impl Future<Output = Result<()>> + 'static {
+            let (completer, pending) = completion();
+            let started = self.capture_preview(image_format, image_stream, move |result| {
+                completer.complete(result);
+                Ok(())
+            });
+            finish(started, pending)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Stands in for COM: holds completers until `fire` calls them back.
+    #[derive(Default)]
+    struct FakeSource {
+        pending: RefCell<Vec<Completer<Result<u32, String>>>>,
+    }
+
+    impl FakeSource {
+        fn operation(&self) -> Completion<Result<u32, String>> {
+            let (completer, completion) = completion();
+            self.pending.borrow_mut().push(completer);
+            completion
+        }
+
+        fn fire(&self, result: Result<u32, String>) -> bool {
+            let completer = self.pending.borrow_mut().pop();
+            match completer {
+                Some(completer) => {
+                    completer.complete(result);
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    fn counting_executor() -> (Arc<AtomicUsize>, LocalExecutor) {
+        let notified = Arc::new(AtomicUsize::new(0));
+        let n = Arc::clone(&notified);
+        let executor = LocalExecutor::with_notify(move || {
+            n.fetch_add(1, Ordering::SeqCst);
+        });
+        (notified, executor)
+    }
+
+    #[test]
+    fn test_completion() {
+        let (notified, executor) = counting_executor();
+        let source = Rc::new(FakeSource::default());
+        let results = Rc::new(RefCell::new(Vec::new()));
+
+        let (s, r) = (Rc::clone(&source), Rc::clone(&results));
+        executor.spawn(async move {
+            // Nested callbacks, as a sequence.
+            let a = s.operation().await.unwrap().unwrap();
+            let b = s.operation().await.unwrap().unwrap();
+            r.borrow_mut().push(a + b);
+        });
+        assert_eq!(notified.load(Ordering::SeqCst), 1);
+        assert_eq!(executor.run_until_stalled(), 1);
+        assert_eq!(executor.task_count(), 1);
+        // Nothing to do until the source completes.
+        assert_eq!(executor.run_until_stalled(), 0);
+
+        assert!(source.fire(Ok(1)));
+        assert_eq!(notified.load(Ordering::SeqCst), 2);
+        executor.run_until_stalled();
+        assert!(results.borrow().is_empty());
+        assert!(source.fire(Ok(2)));
+        executor.run_until_stalled();
+        assert_eq!(*results.borrow(), vec![3]);
+        assert_eq!(executor.task_count(), 0);
+    }
+
+    #[test]
+    fn test_canceled() {
+        let executor = LocalExecutor::new();
+        let (completer, pending) = completion::<u32>();
+        let result = Rc::new(Cell::new(None));
+        let r = Rc::clone(&result);
+        executor.spawn(async move { r.set(Some(pending.await)) });
+        executor.run_until_stalled();
+        drop(completer);
+        executor.run_until_stalled();
+        assert_eq!(result.get(), Some(Err(Canceled)));
+
+        // Completed before being polled.
+        let (completer, pending) = completion();
+        completer.complete(5);
+        assert_eq!(executor.block_on(pending, || false), Some(Ok(5)));
+    }
+
+    #[test]
+    fn test_block_on() {
+        let executor = LocalExecutor::new();
+        let source = Rc::new(FakeSource::default());
+        let s = Rc::clone(&source);
+        let spawner = executor.clone();
+        let background = Rc::new(Cell::new(false));
+        let b = Rc::clone(&background);
+
+        let future = async move {
+            // Tasks can spawn tasks.
+            spawner.spawn(async move { b.set(true) });
+            s.operation().await.unwrap()
+        };
+        let mut waits = 0;
+        let output = executor.block_on(future, || {
+            waits += 1;
+            source.fire(Err("failed".into()))
+        });
+        assert_eq!(output, Some(Err("failed".to_string())));
+        assert_eq!(waits, 1);
+        assert!(background.get());
+
+        // `wait` gives up.
+        let (_completer, pending) = completion::<()>();
+        assert_eq!(executor.block_on(pending, || false), None);
+    }
+
+    #[test]
+    fn test_wake_from_other_thread() {
+        let (notified, executor) = counting_executor();
+        let waker = Rc::new(RefCell::new(None));
+        let polls = Rc::new(Cell::new(0));
+        let (w, p) = (Rc::clone(&waker), Rc::clone(&polls));
+        executor.spawn(futures_poll_fn(move |cx| {
+            p.set(p.get() + 1);
+            *w.borrow_mut() = Some(cx.waker().clone());
+            if p.get() == 2 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }));
+        executor.run_until_stalled();
+        let w = waker.borrow_mut().take().unwrap();
+        std::thread::spawn(move || {
+            w.wake_by_ref();
+            // Already queued: no second notification.
+            w.wake();
+        })
+        .join()
+        .unwrap();
+        assert_eq!(notified.load(Ordering::SeqCst), 2);
+        assert_eq!(executor.run_until_stalled(), 1);
+        assert_eq!(polls.get(), 2);
+        assert_eq!(executor.task_count(), 0);
+    }
+
+    // `std::future::poll_fn` needs Rust 1.64.
+    fn futures_poll_fn<F: FnMut(&mut Context<'_>) -> Poll<()> + Unpin>(
+        f: F,
+    ) -> impl Future<Output = ()> {
+        struct PollFn<F>(F);
+        impl<F: FnMut(&mut Context<'_>) -> Poll<()> + Unpin> Future for PollFn<F> {
+            type Output = ();
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+                (self.0)(cx)
+            }
+        }
+        PollFn(f)
+    }
+}
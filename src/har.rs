@@ -0,0 +1,1010 @@
+// Recording requests and navigations to HTTP Archive (HAR 1.2) files, and
+// replaying recorded responses.
+
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A HAR file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HarLog {
+    pub version: String,
+    pub creator: HarCreator,
+    #[serde(default)]
+    pub pages: Vec<HarPage>,
+    pub entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HarCreator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPage {
+    pub started_date_time: String,
+    pub id: String,
+    pub title: String,
+    pub page_timings: HarPageTimings,
+}
+
+/// Milliseconds since the start of the page.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPageTimings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_content_load: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_load: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pageref: Option<String>,
+    pub started_date_time: String,
+    /// Always 0 in recordings: WebView2 does not report when a request
+    /// finishes.
+    pub time: f64,
+    pub request: HarRequest,
+    pub response: HarResponse,
+    #[serde(default)]
+    pub cache: HarCache,
+    /// All 0 in recordings, like `time`.
+    pub timings: HarTimings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<HarNameValue>,
+    pub headers: Vec<HarNameValue>,
+    /// Parameters of the URL's query, percent-decoded, with `+` as a
+    /// space.
+    #[serde(default)]
+    pub query_string: Vec<HarNameValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarPostData>,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    pub status: i32,
+    pub status_text: String,
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<HarNameValue>,
+    pub headers: Vec<HarNameValue>,
+    pub content: HarContent,
+    #[serde(rename = "redirectURL", default)]
+    pub redirect_url: String,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+/// A header, cookie or query string parameter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HarNameValue {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    pub mime_type: String,
+    pub text: String,
+    /// `"base64"` for binary bodies. Not part of HAR 1.2, hence the
+    /// underscore.
+    #[serde(rename = "_encoding", default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    pub size: i64,
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+impl HarContent {
+    /// Whether the body was recorded, or is empty.
+    pub fn is_complete(&self) -> bool {
+        self.text.is_some() || self.size == 0
+    }
+
+    /// The decoded body, if it was recorded.
+    pub fn body(&self) -> Option<Vec<u8>> {
+        let text = self.text.as_ref()?;
+        match self.encoding.as_deref() {
+            Some("base64") => base64::decode(text).ok(),
+            _ => Some(text.as_bytes().to_vec()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HarCache {}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HarTimings {
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+impl Har {
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("HAR serialization can't fail")
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Self::from_json(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+/// A request as seen by a `WebResourceRequested` handler.
+#[derive(Debug, Clone, Default)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+/// A response provided by the host.
+#[derive(Debug, Clone, Default)]
+pub struct RecordedResponse {
+    pub status: i32,
+    pub reason_phrase: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Default)]
+struct RecorderState {
+    pages: Vec<HarPage>,
+    // Navigation id -> (page index, start time).
+    navigations: HashMap<u64, (usize, SystemTime)>,
+    current_page: Option<String>,
+    entries: Vec<HarEntry>,
+    record_bodies: bool,
+}
+
+/// Records requests and navigations into a `Har`.
+///
+/// Clones share the same recording. On Windows, use `attach` to record the
+/// events of a `WebView`.
+#[derive(Debug, Clone, Default)]
+pub struct HarRecorder {
+    state: Rc<RefCell<RecorderState>>,
+}
+
+impl HarRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also record request and response bodies. Bodies that are not UTF-8
+    /// are stored base64 encoded.
+    pub fn with_bodies(self, record_bodies: bool) -> Self {
+        self.state.borrow_mut().record_bodies = record_bodies;
+        self
+    }
+
+    pub fn records_bodies(&self) -> bool {
+        self.state.borrow().record_bodies
+    }
+
+    /// Start a page for a navigation. Following entries belong to it.
+    pub fn navigation_started(&self, navigation_id: u64, uri: &str, at: SystemTime) {
+        let mut state = self.state.borrow_mut();
+        let id = format!("page_{}", state.pages.len() + 1);
+        state.pages.push(HarPage {
+            started_date_time: format_date_time(at),
+            id: id.clone(),
+            title: uri.into(),
+            page_timings: HarPageTimings::default(),
+        });
+        let index = state.pages.len() - 1;
+        state.navigations.insert(navigation_id, (index, at));
+        state.current_page = Some(id);
+    }
+
+    /// Set the `onLoad` timing of the page started by the navigation.
+    pub fn navigation_completed(&self, navigation_id: u64, at: SystemTime) {
+        let mut state = self.state.borrow_mut();
+        if let Some((index, start)) = state.navigations.remove(&navigation_id) {
+            state.pages[index].page_timings.on_load = Some(millis_between(start, at));
+        }
+    }
+
+    /// Record a request, and the response if the host provided one.
+    ///
+    /// Bodies are dropped unless `with_bodies(true)` was set.
+    pub fn record(
+        &self,
+        request: RecordedRequest,
+        response: Option<RecordedResponse>,
+        at: SystemTime,
+    ) {
+        let mut state = self.state.borrow_mut();
+        let record_bodies = state.record_bodies;
+        let pageref = state.current_page.clone();
+        let entry = make_entry(request, response, at, pageref, record_bodies);
+        state.entries.push(entry);
+    }
+
+    pub fn har(&self) -> Har {
+        let state = self.state.borrow();
+        Har {
+            log: HarLog {
+                version: "1.2".into(),
+                creator: HarCreator {
+                    name: env!("CARGO_PKG_NAME").into(),
+                    version: env!("CARGO_PKG_VERSION").into(),
+                },
+                pages: state.pages.clone(),
+                entries: state.entries.clone(),
+            },
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        self.har().save(path)
+    }
+
+    /// Forget everything recorded so far.
+    pub fn clear(&self) {
+        let mut state = self.state.borrow_mut();
+        state.pages.clear();
+        state.navigations.clear();
+        state.current_page = None;
+        state.entries.clear();
+    }
+}
+
+fn name_values(pairs: Vec<(String, String)>) -> Vec<HarNameValue> {
+    pairs
+        .into_iter()
+        .map(|(name, value)| HarNameValue { name, value })
+        .collect()
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+// Text for UTF-8 bodies, base64 otherwise.
+fn encode_body(body: Vec<u8>) -> (String, Option<String>) {
+    match String::from_utf8(body) {
+        Ok(text) => (text, None),
+        Err(e) => (base64::encode(e.as_bytes()), Some("base64".into())),
+    }
+}
+
+fn query_string(uri: &str) -> Vec<HarNameValue> {
+    let uri = uri.split('#').next().unwrap_or("");
+    let query = match uri.find('?') {
+        Some(i) => &uri[i + 1..],
+        None => return Vec::new(),
+    };
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let mut kv = p.splitn(2, '=');
+            HarNameValue {
+                name: decode_query_component(kv.next().unwrap_or("")),
+                value: decode_query_component(kv.next().unwrap_or("")),
+            }
+        })
+        .collect()
+}
+
+// Decode `application/x-www-form-urlencoded` escapes. Invalid escapes are
+// kept as is, and invalid UTF-8 is replaced.
+fn decode_query_component(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes[i] {
+            b'%' => s
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match (bytes[i], escaped) {
+            (_, Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b'+', None) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (b, None) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn make_entry(
+    request: RecordedRequest,
+    response: Option<RecordedResponse>,
+    at: SystemTime,
+    pageref: Option<String>,
+    record_bodies: bool,
+) -> HarEntry {
+    let request_body_size = request.body.as_ref().map_or(0, |b| b.len() as i64);
+    let post_data = match request.body {
+        Some(body) if record_bodies => {
+            let mime_type = header(&request.headers, "Content-Type")
+                .unwrap_or("")
+                .to_string();
+            let (text, encoding) = encode_body(body);
+            Some(HarPostData {
+                mime_type,
+                text,
+                encoding,
+            })
+        }
+        _ => None,
+    };
+    let request = HarRequest {
+        method: request.method,
+        query_string: query_string(&request.uri),
+        url: request.uri,
+        http_version: "HTTP/1.1".into(),
+        cookies: Vec::new(),
+        headers: name_values(request.headers),
+        post_data,
+        headers_size: -1,
+        body_size: request_body_size,
+    };
+
+    // Status 0 is what browsers record for requests without a response.
+    let response = response.unwrap_or_default();
+    let mime_type = header(&response.headers, "Content-Type")
+        .unwrap_or("x-unknown")
+        .to_string();
+    let redirect_url = header(&response.headers, "Location")
+        .unwrap_or("")
+        .to_string();
+    let size = response.body.as_ref().map_or(0, |b| b.len() as i64);
+    let (text, encoding) = match response.body {
+        Some(body) if record_bodies => {
+            let (text, encoding) = encode_body(body);
+            (Some(text), encoding)
+        }
+        _ => (None, None),
+    };
+    let response = HarResponse {
+        status: response.status,
+        status_text: response.reason_phrase,
+        http_version: "HTTP/1.1".into(),
+        cookies: Vec::new(),
+        headers: name_values(response.headers),
+        content: HarContent {
+            size,
+            mime_type,
+            text,
+            encoding,
+        },
+        redirect_url,
+        headers_size: -1,
+        body_size: size,
+    };
+
+    HarEntry {
+        pageref,
+        started_date_time: format_date_time(at),
+        // WebView2 does not report when a request finishes.
+        time: 0.0,
+        request,
+        response,
+        cache: HarCache {},
+        timings: HarTimings::default(),
+    }
+}
+
+fn millis_between(start: SystemTime, end: SystemTime) -> f64 {
+    end.duration_since(start)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0)
+}
+
+/// Format as ISO 8601 in UTC with milliseconds, e.g.
+/// `2020-08-01T12:30:15.250Z`.
+pub fn format_date_time(t: SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs();
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        d.subsec_millis()
+    )
+}
+
+/// Serves responses from a `Har` for offline reproduction.
+///
+/// Requests are matched by method and URL, ignoring the fragment and the
+/// order of query parameters. When a request was recorded several times, the
+/// recorded responses are served in order, and the last one is repeated.
+/// Entries without a response (status 0), and those recorded without their
+/// body (see `HarRecorder::with_bodies`), are never served: those requests
+/// go to the network.
+#[derive(Debug, Clone)]
+pub struct HarReplayer {
+    entries: Rc<Vec<HarEntry>>,
+    served: Rc<RefCell<HashMap<(String, String), usize>>>,
+}
+
+impl HarReplayer {
+    pub fn new(har: Har) -> Self {
+        Self {
+            entries: Rc::new(har.log.entries),
+            served: Rc::default(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(Har::load(path)?))
+    }
+
+    /// Find the next recorded response for a request.
+    pub fn find(&self, method: &str, uri: &str) -> Option<&HarResponse> {
+        let key = (method.to_ascii_uppercase(), normalize_url(uri));
+        let candidates: Vec<&HarEntry> = self
+            .entries
+            .iter()
+            .filter(|e| e.response.status != 0 && e.response.content.is_complete())
+            .filter(|e| e.request.method.eq_ignore_ascii_case(method))
+            .filter(|e| normalize_url(&e.request.url) == key.1)
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let mut served = self.served.borrow_mut();
+        let count = served.entry(key).or_insert(0);
+        let entry = candidates[(*count).min(candidates.len() - 1)];
+        *count += 1;
+        Some(&entry.response)
+    }
+
+    /// Start serving responses from the beginning again.
+    pub fn rewind(&self) {
+        self.served.borrow_mut().clear();
+    }
+}
+
+fn normalize_url(url: &str) -> String {
+    let url = url.split('#').next().unwrap_or("");
+    match url.find('?') {
+        Some(i) => {
+            let mut params: Vec<&str> = url[i + 1..].split('&').filter(|p| !p.is_empty()).collect();
+            params.sort_unstable();
+            format!("{}?{}", &url[..i], params.join("&"))
+        }
+        None => url.to_string(),
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::{
+        EventRegistrationToken, Stream, WebResourceContext, WebResourceRequestedEventArgs, WebView,
+    };
+    use std::io::{Read, Seek, SeekFrom};
+    use std::ptr;
+    use webview2_sys::{ICoreWebView2WebResourceRequest, ICoreWebView2WebResourceResponse};
+
+    /// Handlers registered by `HarRecorder::attach`.
+    #[derive(Debug)]
+    pub struct HarRecorderRegistration {
+        web_resource_requested: EventRegistrationToken,
+        navigation_starting: EventRegistrationToken,
+        navigation_completed: EventRegistrationToken,
+        // Whether `attach` added the `"*"` filter.
+        filter: bool,
+    }
+
+    impl HarRecorderRegistration {
+        /// Stop recording, and remove the `"*"` web resource filter if
+        /// `attach` added it.
+        pub fn detach(self, webview: &WebView) -> crate::Result<()> {
+            webview.remove_web_resource_requested(self.web_resource_requested)?;
+            webview.remove_navigation_starting(self.navigation_starting)?;
+            webview.remove_navigation_completed(self.navigation_completed)?;
+            remove_filter(webview, self.filter)
+        }
+    }
+
+    /// The handler registered by `HarReplayer::attach`.
+    #[derive(Debug)]
+    pub struct HarReplayerRegistration {
+        web_resource_requested: EventRegistrationToken,
+        // Whether `attach` added the `"*"` filter.
+        filter: bool,
+    }
+
+    impl HarReplayerRegistration {
+        /// Stop replaying, and remove the `"*"` web resource filter if
+        /// `attach` added it.
+        pub fn detach(self, webview: &WebView) -> crate::Result<()> {
+            webview.remove_web_resource_requested(self.web_resource_requested)?;
+            remove_filter(webview, self.filter)
+        }
+    }
+
+    fn add_filter(webview: &WebView, filter: bool) -> crate::Result<()> {
+        if filter {
+            webview.add_web_resource_requested_filter("*", WebResourceContext::All)?;
+        }
+        Ok(())
+    }
+
+    fn remove_filter(webview: &WebView, filter: bool) -> crate::Result<()> {
+        if filter {
+            webview.remove_web_resource_requested_filter("*", WebResourceContext::All)?;
+        }
+        Ok(())
+    }
+
+    // Read a stream to the end and rewind it, so that it can still be
+    // consumed by the browser.
+    fn read_and_rewind(stream: &mut Stream) -> crate::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        stream.read_to_end(&mut body)?;
+        stream.seek(SeekFrom::Start(0))?;
+        Ok(body)
+    }
+
+    // `get_content` returns a null stream if there is no content, which the
+    // `get_content` wrappers do not expect.
+    fn request_body(request: &crate::WebResourceRequest) -> crate::Result<Option<Vec<u8>>> {
+        let mut ppv = ptr::null_mut();
+        crate::check_hresult(unsafe { request.as_inner().get_content(&mut ppv) })?;
+        if ppv.is_null() {
+            return Ok(None);
+        }
+        let mut stream = unsafe { Stream::from_raw(ppv) };
+        read_and_rewind(&mut stream).map(Some)
+    }
+
+    fn response_body(response: &crate::WebResourceResponse) -> crate::Result<Option<Vec<u8>>> {
+        let mut ppv = ptr::null_mut();
+        crate::check_hresult(unsafe { response.as_inner().get_content(&mut ppv) })?;
+        if ppv.is_null() {
+            return Ok(None);
+        }
+        let mut stream = unsafe { Stream::from_raw(ppv) };
+        read_and_rewind(&mut stream).map(Some)
+    }
+
+    impl HarRecorder {
+        /// Record the requests and navigations of a `WebView`.
+        ///
+        /// All requests are intercepted through a `"*"` web resource filter.
+        /// WebView2 does not expose network responses, so only responses
+        /// provided by the host (with `put_response` in a handler registered
+        /// before this one) are recorded.
+        ///
+        /// Web resource filters are not reference counted: `detach` removes
+        /// the `"*"` filter even if something else, e.g. a `HarReplayer` or
+        /// the app, still needs it. In that case, add the filter yourself
+        /// and use `attach_without_filter`.
+        pub fn attach(&self, webview: &WebView) -> crate::Result<HarRecorderRegistration> {
+            self.attach_impl(webview, true)
+        }
+
+        /// Like `attach`, but rely on a `"*"` filter added by the caller.
+        pub fn attach_without_filter(
+            &self,
+            webview: &WebView,
+        ) -> crate::Result<HarRecorderRegistration> {
+            self.attach_impl(webview, false)
+        }
+
+        fn attach_impl(
+            &self,
+            webview: &WebView,
+            filter: bool,
+        ) -> crate::Result<HarRecorderRegistration> {
+            add_filter(webview, filter)?;
+
+            let recorder = self.clone();
+            let web_resource_requested =
+                webview.add_web_resource_requested(move |_, args| recorder.record_args(&args))?;
+            let recorder = self.clone();
+            let navigation_starting = webview.add_navigation_starting(move |_, args| {
+                recorder.navigation_started(
+                    args.get_navigation_id()?,
+                    &args.get_uri()?,
+                    SystemTime::now(),
+                );
+                Ok(())
+            })?;
+            let recorder = self.clone();
+            let navigation_completed = webview.add_navigation_completed(move |_, args| {
+                recorder.navigation_completed(args.get_navigation_id()?, SystemTime::now());
+                Ok(())
+            })?;
+
+            Ok(HarRecorderRegistration {
+                web_resource_requested,
+                navigation_starting,
+                navigation_completed,
+                filter,
+            })
+        }
+
+        fn record_args(&self, args: &WebResourceRequestedEventArgs) -> crate::Result<()> {
+            let at = SystemTime::now();
+            let record_bodies = self.records_bodies();
+
+            let request = args.get_request()?;
+            let recorded_request = RecordedRequest {
+                method: request.get_method()?,
+                uri: request.get_uri()?,
+                headers: request.get_headers()?.get_iterator()?.collect(),
+                body: if record_bodies {
+                    request_body(&request)?
+                } else {
+                    None
+                },
+            };
+
+            let response = args.get_response()?;
+            let body = response_body(&response)?;
+            let recorded_response = if body.is_some() {
+                Some(RecordedResponse {
+                    status: response.get_status_code()?,
+                    reason_phrase: response.get_reason_phrase()?,
+                    headers: response.get_headers()?.get_iterator()?.collect(),
+                    body,
+                })
+            } else {
+                None
+            };
+
+            self.record(recorded_request, recorded_response, at);
+            Ok(())
+        }
+    }
+
+    impl HarReplayer {
+        /// Answer the requests of a `WebView` with recorded responses.
+        ///
+        /// Requests without a recorded response go to the network as usual.
+        /// Like `HarRecorder::attach`, this adds a `"*"` web resource filter
+        /// that `detach` removes, see there.
+        pub fn attach(
+            &self,
+            env: &crate::Environment,
+            webview: &WebView,
+        ) -> crate::Result<HarReplayerRegistration> {
+            self.attach_impl(env, webview, true)
+        }
+
+        /// Like `attach`, but rely on a `"*"` filter added by the caller.
+        pub fn attach_without_filter(
+            &self,
+            env: &crate::Environment,
+            webview: &WebView,
+        ) -> crate::Result<HarReplayerRegistration> {
+            self.attach_impl(env, webview, false)
+        }
+
+        fn attach_impl(
+            &self,
+            env: &crate::Environment,
+            webview: &WebView,
+            filter: bool,
+        ) -> crate::Result<HarReplayerRegistration> {
+            add_filter(webview, filter)?;
+
+            let replayer = self.clone();
+            let env = env.clone();
+            let web_resource_requested = webview.add_web_resource_requested(move |_, args| {
+                let request = args.get_request()?;
+                let response = match replayer.find(&request.get_method()?, &request.get_uri()?) {
+                    Some(response) => response,
+                    None => return Ok(()),
+                };
+                let headers = response
+                    .headers
+                    .iter()
+                    .map(|h| format!("{}: {}", h.name, h.value))
+                    .collect::<Vec<_>>()
+                    .join("\r\n");
+                // `find` only returns complete responses.
+                let body = response.content.body().unwrap_or_default();
+                let response = env.create_web_resource_response(
+                    Stream::from_bytes(&body),
+                    response.status,
+                    &response.status_text,
+                    &headers,
+                )?;
+                args.put_response(response)
+            })?;
+            Ok(HarReplayerRegistration {
+                web_resource_requested,
+                filter,
+            })
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use self::win::{HarRecorderRegistration, HarReplayerRegistration};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(millis: u64) -> SystemTime {
+        // 2020-08-01T12:30:15Z
+        UNIX_EPOCH + Duration::from_secs(1_596_285_015) + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_decode_query_component() {
+        assert_eq!(decode_query_component("a+b%20c%2Bd"), "a b c+d");
+        // Invalid escapes and UTF-8.
+        assert_eq!(decode_query_component("100%"), "100%");
+        assert_eq!(decode_query_component("%+1%zz%e"), "% 1%zz%e");
+        assert_eq!(decode_query_component("%FF%C3%A9"), "\u{fffd}é");
+        assert_eq!(decode_query_component("%é"), "%é");
+
+        let query = query_string("https://app.example/?q=a+%26+b&x#y=1");
+        assert_eq!(
+            query,
+            vec![
+                HarNameValue {
+                    name: "q".into(),
+                    value: "a & b".into()
+                },
+                HarNameValue {
+                    name: "x".into(),
+                    value: "".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_date_time() {
+        assert_eq!(format_date_time(at(250)), "2020-08-01T12:30:15.250Z");
+        assert_eq!(format_date_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_date_time(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+    }
+
+    fn record_sample(recorder: &HarRecorder) {
+        recorder.navigation_started(7, "https://app.example/", at(0));
+        recorder.record(
+            RecordedRequest {
+                method: "POST".into(),
+                uri: "https://app.example/api?b=2&a=1#x".into(),
+                headers: vec![("Content-Type".into(), "application/json".into())],
+                body: Some(b"{}".to_vec()),
+            },
+            Some(RecordedResponse {
+                status: 200,
+                reason_phrase: "OK".into(),
+                headers: vec![("Content-Type".into(), "image/png".into())],
+                body: Some(vec![0x89, b'P', b'N', b'G', 0xff]),
+            }),
+            at(10),
+        );
+        recorder.record(
+            RecordedRequest {
+                method: "GET".into(),
+                uri: "https://cdn.example/lib.js".into(),
+                ..Default::default()
+            },
+            None,
+            at(20),
+        );
+        recorder.navigation_completed(7, at(1500));
+    }
+
+    #[test]
+    fn test_recorder() {
+        let recorder = HarRecorder::new().with_bodies(true);
+        record_sample(&recorder);
+        let har = recorder.har();
+
+        assert_eq!(har.log.version, "1.2");
+        assert_eq!(har.log.pages.len(), 1);
+        assert_eq!(har.log.pages[0].page_timings.on_load, Some(1500.0));
+        assert_eq!(har.log.entries.len(), 2);
+
+        let entry = &har.log.entries[0];
+        assert_eq!(entry.pageref.as_deref(), Some("page_1"));
+        assert_eq!(entry.started_date_time, "2020-08-01T12:30:15.010Z");
+        assert_eq!(entry.request.query_string.len(), 2);
+        assert_eq!(entry.request.query_string[0].name, "b");
+        let post_data = entry.request.post_data.as_ref().unwrap();
+        assert_eq!(post_data.text, "{}");
+        assert_eq!(post_data.mime_type, "application/json");
+        assert_eq!(entry.response.content.mime_type, "image/png");
+        assert_eq!(entry.response.content.encoding.as_deref(), Some("base64"));
+        assert_eq!(
+            entry.response.content.body().unwrap(),
+            vec![0x89, b'P', b'N', b'G', 0xff]
+        );
+
+        assert_eq!(har.log.entries[1].response.status, 0);
+
+        recorder.clear();
+        assert!(recorder.har().log.entries.is_empty());
+    }
+
+    #[test]
+    fn test_without_bodies() {
+        let recorder = HarRecorder::new();
+        record_sample(&recorder);
+        let entry = &recorder.har().log.entries[0];
+        assert!(entry.request.post_data.is_none());
+        assert_eq!(entry.request.body_size, 2);
+        assert!(entry.response.content.text.is_none());
+        assert_eq!(entry.response.content.size, 5);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let recorder = HarRecorder::new().with_bodies(true);
+        record_sample(&recorder);
+        let har = recorder.har();
+        let json = har.to_json();
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let entry = &value["log"]["entries"][0];
+        assert_eq!(value["log"]["creator"]["name"], "webview2");
+        assert_eq!(entry["request"]["httpVersion"], "HTTP/1.1");
+        assert_eq!(entry["request"]["headersSize"], -1);
+        assert_eq!(entry["response"]["redirectURL"], "");
+        assert_eq!(entry["response"]["statusText"], "OK");
+        assert_eq!(entry["cache"], serde_json::json!({}));
+        assert_eq!(value["log"]["pages"][0]["pageTimings"]["onLoad"], 1500.0);
+        assert!(value["log"]["pages"][0]["pageTimings"]
+            .get("onContentLoad")
+            .is_none());
+
+        assert_eq!(Har::from_json(&json).unwrap(), har);
+    }
+
+    #[test]
+    fn test_replay() {
+        let recorder = HarRecorder::new().with_bodies(true);
+        record_sample(&recorder);
+        recorder.record(
+            RecordedRequest {
+                method: "POST".into(),
+                uri: "https://app.example/api?a=1&b=2".into(),
+                ..Default::default()
+            },
+            Some(RecordedResponse {
+                status: 500,
+                ..Default::default()
+            }),
+            at(30),
+        );
+        let replayer = HarReplayer::new(recorder.har());
+
+        // Query order and fragment don't matter, responses are served in order.
+        let uri = "https://app.example/api?a=1&b=2";
+        assert_eq!(replayer.find("post", uri).unwrap().status, 200);
+        assert_eq!(replayer.find("POST", uri).unwrap().status, 500);
+        assert_eq!(replayer.find("POST", uri).unwrap().status, 500);
+        replayer.rewind();
+        assert_eq!(replayer.find("POST", uri).unwrap().status, 200);
+
+        assert!(replayer.find("GET", uri).is_none());
+        // Recorded without a response.
+        assert!(replayer.find("GET", "https://cdn.example/lib.js").is_none());
+    }
+
+    #[test]
+    fn test_replay_without_bodies() {
+        let recorder = HarRecorder::new();
+        record_sample(&recorder);
+        recorder.record(
+            RecordedRequest {
+                method: "GET".into(),
+                uri: "https://app.example/empty".into(),
+                ..Default::default()
+            },
+            Some(RecordedResponse {
+                status: 204,
+                body: Some(Vec::new()),
+                ..Default::default()
+            }),
+            at(30),
+        );
+        let har = recorder.har();
+        let replayer = HarReplayer::new(har.clone());
+
+        // Truncated bodies are not replayed, empty ones are.
+        let mut truncated = 0;
+        for entry in &har.log.entries {
+            if entry.response.content.size > 0 {
+                truncated += 1;
+                assert!(!entry.response.content.is_complete());
+                assert!(replayer.find("GET", &entry.request.url).is_none());
+                assert!(replayer.find("POST", &entry.request.url).is_none());
+            }
+        }
+        assert!(truncated > 0);
+        let empty = replayer.find("GET", "https://app.example/empty").unwrap();
+        assert_eq!(empty.status, 204);
+        assert_eq!(empty.content.body(), None);
+        assert!(empty.content.is_complete());
+    }
+}
@@ -0,0 +1,549 @@
+// Several listeners for one event.
+//
+// A `Dispatcher` calls listeners in priority order until one stops the
+// dispatch, either by returning `Flow::Stop` or by leaving the arguments in
+// a state matched by the dispatcher's stop condition, e.g. a cancelled
+// navigation. A listener returning an error or panicking does not stop the
+// others.
+//
+// On Windows, an `EventHub` registers a single handler per event with the
+// `WebView` and feeds a dispatcher.
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+/// What a listener wants to happen next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Call the next listener.
+    Continue,
+    /// Don't call further listeners: this one's outcome stands.
+    Stop,
+}
+
+/// Identifies a listener of a `Dispatcher`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ListenerId(u64);
+
+type ListenerFn<A, E> = Rc<dyn Fn(&A) -> Result<Flow, E>>;
+
+struct Listener<A, E> {
+    id: ListenerId,
+    priority: i32,
+    f: ListenerFn<A, E>,
+}
+
+/// Result of `Dispatcher::dispatch`.
+#[derive(Debug)]
+pub struct Dispatched<E> {
+    /// Listeners called, in order.
+    pub called: Vec<ListenerId>,
+    /// The listener that stopped the dispatch.
+    pub stopped_by: Option<ListenerId>,
+    /// Errors returned by listeners.
+    pub errors: Vec<(ListenerId, E)>,
+    /// Payloads of listener panics, e.g. for `std::panic::resume_unwind`.
+    pub panics: Vec<(ListenerId, Box<dyn Any + Send>)>,
+}
+
+/// Prioritized listeners for an event with arguments `A`.
+pub struct Dispatcher<A, E> {
+    // Sorted by descending priority, then by id.
+    listeners: RefCell<Vec<Listener<A, E>>>,
+    next_id: Cell<u64>,
+    stop_when: Option<Box<dyn Fn(&A) -> bool>>,
+}
+
+impl<A, E> fmt::Debug for Dispatcher<A, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("listeners", &self.len())
+            .field("stop_when", &self.stop_when.is_some())
+            .finish()
+    }
+}
+
+impl<A, E> Default for Dispatcher<A, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A, E> Dispatcher<A, E> {
+    pub fn new() -> Self {
+        Self {
+            listeners: RefCell::new(Vec::new()),
+            next_id: Cell::new(0),
+            stop_when: None,
+        }
+    }
+
+    /// Also stop after a listener leaves the arguments matching
+    /// `stop_when`, e.g. with `put_cancel(true)`.
+    pub fn with_stop_when(mut self, stop_when: impl Fn(&A) -> bool + 'static) -> Self {
+        self.stop_when = Some(Box::new(stop_when));
+        self
+    }
+
+    /// Add a listener. Listeners with higher priorities are called first,
+    /// and listeners with the same priority in the order they were added.
+    pub fn add(
+        &self,
+        priority: i32,
+        listener: impl Fn(&A) -> Result<Flow, E> + 'static,
+    ) -> ListenerId {
+        let id = ListenerId(self.next_id.get());
+        self.next_id.set(id.0 + 1);
+        let mut listeners = self.listeners.borrow_mut();
+        // After all listeners with the same or a higher priority.
+        let index = listeners
+            .iter()
+            .position(|l| l.priority < priority)
+            .unwrap_or(listeners.len());
+        listeners.insert(
+            index,
+            Listener {
+                id,
+                priority,
+                f: Rc::new(listener),
+            },
+        );
+        id
+    }
+
+    /// Remove a listener. Returns whether it was there.
+    pub fn remove(&self, id: ListenerId) -> bool {
+        let mut listeners = self.listeners.borrow_mut();
+        match listeners.iter().position(|l| l.id == id) {
+            Some(index) => {
+                listeners.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.listeners.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.borrow().is_empty()
+    }
+
+    /// Call the listeners.
+    ///
+    /// Listeners added or removed by listeners take effect for the next
+    /// dispatch. Panics are caught, and the dispatch goes on as if the
+    /// listener returned an error.
+    pub fn dispatch(&self, args: &A) -> Dispatched<E> {
+        let listeners: Vec<(ListenerId, ListenerFn<A, E>)> = self
+            .listeners
+            .borrow()
+            .iter()
+            .map(|l| (l.id, Rc::clone(&l.f)))
+            .collect();
+        let mut dispatched = Dispatched {
+            called: Vec::new(),
+            stopped_by: None,
+            errors: Vec::new(),
+            panics: Vec::new(),
+        };
+        for (id, f) in listeners {
+            dispatched.called.push(id);
+            let flow = match panic::catch_unwind(AssertUnwindSafe(|| f(args))) {
+                Ok(Ok(flow)) => flow,
+                Ok(Err(e)) => {
+                    dispatched.errors.push((id, e));
+                    Flow::Continue
+                }
+                Err(payload) => {
+                    dispatched.panics.push((id, payload));
+                    Flow::Continue
+                }
+            };
+            let stop = match self.stop_when {
+                Some(ref stop_when) => flow == Flow::Stop || stop_when(args),
+                None => flow == Flow::Stop,
+            };
+            if stop {
+                dispatched.stopped_by = Some(id);
+                break;
+            }
+        }
+        dispatched
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::subscription::Subscription;
+    use crate::*;
+
+    // A dispatcher, and the token of its handler once registered.
+    struct Slot<A> {
+        dispatcher: Rc<Dispatcher<(WebView, A), Error>>,
+        token: Cell<Option<EventRegistrationToken>>,
+    }
+
+    impl<A: 'static> Slot<A> {
+        fn new(stop_when: Option<fn(&A) -> bool>) -> Self {
+            let dispatcher = Dispatcher::new();
+            let dispatcher = match stop_when {
+                Some(stop_when) => dispatcher.with_stop_when(move |(_, args)| stop_when(args)),
+                None => dispatcher,
+            };
+            Self {
+                dispatcher: Rc::new(dispatcher),
+                token: Cell::new(None),
+            }
+        }
+    }
+
+    struct Inner {
+        webview: WebView,
+        on_error: RefCell<Option<Rc<dyn Fn(&'static str, ListenerId, Error)>>>,
+        navigation_starting: Slot<NavigationStartingEventArgs>,
+        frame_navigation_starting: Slot<NavigationStartingEventArgs>,
+        navigation_completed: Slot<NavigationCompletedEventArgs>,
+        new_window_requested: Slot<NewWindowRequestedEventArgs>,
+        permission_requested: Slot<PermissionRequestedEventArgs>,
+        script_dialog_opening: Slot<ScriptDialogOpeningEventArgs>,
+        web_resource_requested: Slot<WebResourceRequestedEventArgs>,
+        web_message_received: Slot<WebMessageReceivedEventArgs>,
+        process_failed: Slot<ProcessFailedEventArgs>,
+    }
+
+    /// Listeners for the events of a `WebView`, dispatched by one handler
+    /// per event.
+    ///
+    /// The outcome of `NavigationStarting` events is decided by the first
+    /// listener that cancels, and that of `NewWindowRequested` events by the
+    /// first that sets `handled`: later listeners are not called. Listener
+    /// errors go to the error handler and don't stop the dispatch. Neither
+    /// do panics, but once all listeners ran the first one is resumed, and
+    /// is reported or aborts the process like a panic in any handler, see
+    /// `ErrorSink`.
+    ///
+    /// The handlers stay registered until `detach` is called.
+    #[derive(Clone)]
+    pub struct EventHub {
+        inner: Rc<Inner>,
+    }
+
+    impl fmt::Debug for EventHub {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("EventHub").finish()
+        }
+    }
+
+    fn cancelled(args: &NavigationStartingEventArgs) -> bool {
+        args.get_cancel().unwrap_or(false)
+    }
+
+    fn handled(args: &NewWindowRequestedEventArgs) -> bool {
+        args.get_handled().unwrap_or(false)
+    }
+
+    macro_rules! hub_event {
+        ($on:ident, $field:ident, $add:ident, $Args:ty) => {
+            /// Add a listener, removed when the subscription is dropped.
+            pub fn $on(
+                &self,
+                priority: i32,
+                listener: impl Fn(&WebView, &$Args) -> Result<Flow> + 'static,
+            ) -> Result<Subscription> {
+                let slot = &self.inner.$field;
+                if slot.token.get().is_none() {
+                    let dispatcher = Rc::clone(&slot.dispatcher);
+                    // Not the hub, which would keep the webview alive.
+                    let on_error = Rc::downgrade(&self.inner);
+                    let token = self.inner.webview.$add(move |webview, args| {
+                        let dispatched = dispatcher.dispatch(&(webview, args));
+                        if let Some(inner) = on_error.upgrade() {
+                            report(&inner, stringify!($field), dispatched.errors);
+                        }
+                        if let Some((_, payload)) = dispatched.panics.into_iter().next() {
+                            std::panic::resume_unwind(payload);
+                        }
+                        Ok(())
+                    })?;
+                    slot.token.set(Some(token));
+                }
+                let id = slot
+                    .dispatcher
+                    .add(priority, move |(webview, args)| listener(webview, args));
+                let dispatcher = Rc::downgrade(&slot.dispatcher);
+                Ok(Subscription::from_fn(move || match dispatcher.upgrade() {
+                    Some(dispatcher) => dispatcher.remove(id),
+                    None => false,
+                }))
+            }
+        };
+    }
+
+    fn report(inner: &Inner, event: &'static str, errors: Vec<(ListenerId, Error)>) {
+        let on_error = inner.on_error.borrow().clone();
+        for (id, error) in errors {
+            match on_error {
+                Some(ref on_error) => on_error(event, id, error),
+                None => {
+                    if let Some(error_sink) = inner.webview.error_sink() {
+                        crate::error_sink::report_error(error_sink, error);
+                    }
+                }
+            }
+        }
+    }
+
+    impl EventHub {
+        pub fn new(webview: &WebView) -> Self {
+            Self {
+                inner: Rc::new(Inner {
+                    webview: webview.clone(),
+                    on_error: RefCell::new(None),
+                    navigation_starting: Slot::new(Some(cancelled)),
+                    frame_navigation_starting: Slot::new(Some(cancelled)),
+                    navigation_completed: Slot::new(None),
+                    new_window_requested: Slot::new(Some(handled)),
+                    permission_requested: Slot::new(None),
+                    script_dialog_opening: Slot::new(None),
+                    web_resource_requested: Slot::new(None),
+                    web_message_received: Slot::new(None),
+                    process_failed: Slot::new(None),
+                }),
+            }
+        }
+
+        /// Called with the event name and listener of listener errors.
+        /// They go to the `ErrorSink` of the webview's environment by
+        /// default.
+        pub fn with_error_handler(
+            self,
+            on_error: impl Fn(&'static str, ListenerId, Error) + 'static,
+        ) -> Self {
+            *self.inner.on_error.borrow_mut() = Some(Rc::new(on_error));
+            self
+        }
+
+        hub_event!(
+            on_navigation_starting,
+            navigation_starting,
+            add_navigation_starting,
+            NavigationStartingEventArgs
+        );
+        hub_event!(
+            on_frame_navigation_starting,
+            frame_navigation_starting,
+            add_frame_navigation_starting,
+            NavigationStartingEventArgs
+        );
+        hub_event!(
+            on_navigation_completed,
+            navigation_completed,
+            add_navigation_completed,
+            NavigationCompletedEventArgs
+        );
+        hub_event!(
+            on_new_window_requested,
+            new_window_requested,
+            add_new_window_requested,
+            NewWindowRequestedEventArgs
+        );
+        hub_event!(
+            on_permission_requested,
+            permission_requested,
+            add_permission_requested,
+            PermissionRequestedEventArgs
+        );
+        hub_event!(
+            on_script_dialog_opening,
+            script_dialog_opening,
+            add_script_dialog_opening,
+            ScriptDialogOpeningEventArgs
+        );
+        hub_event!(
+            on_web_resource_requested,
+            web_resource_requested,
+            add_web_resource_requested,
+            WebResourceRequestedEventArgs
+        );
+        hub_event!(
+            on_web_message_received,
+            web_message_received,
+            add_web_message_received,
+            WebMessageReceivedEventArgs
+        );
+        hub_event!(
+            on_process_failed,
+            process_failed,
+            add_process_failed,
+            ProcessFailedEventArgs
+        );
+
+        /// Remove the handlers registered with the `WebView`. Listeners are
+        /// kept, and the handlers are registered again when one is added.
+        pub fn detach(&self) -> Result<()> {
+            let inner = &self.inner;
+            let webview = &inner.webview;
+            if let Some(token) = inner.navigation_starting.token.take() {
+                webview.remove_navigation_starting(token)?;
+            }
+            if let Some(token) = inner.frame_navigation_starting.token.take() {
+                webview.remove_frame_navigation_starting(token)?;
+            }
+            if let Some(token) = inner.navigation_completed.token.take() {
+                webview.remove_navigation_completed(token)?;
+            }
+            if let Some(token) = inner.new_window_requested.token.take() {
+                webview.remove_new_window_requested(token)?;
+            }
+            if let Some(token) = inner.permission_requested.token.take() {
+                webview.remove_permission_requested(token)?;
+            }
+            if let Some(token) = inner.script_dialog_opening.token.take() {
+                webview.remove_script_dialog_opening(token)?;
+            }
+            if let Some(token) = inner.web_resource_requested.token.take() {
+                webview.remove_web_resource_requested(token)?;
+            }
+            if let Some(token) = inner.web_message_received.token.take() {
+                webview.remove_web_message_received(token)?;
+            }
+            if let Some(token) = inner.process_failed.token.take() {
+                webview.remove_process_failed(token)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use self::win::EventHub;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stands in for `NavigationStartingEventArgs`.
+    #[derive(Default)]
+    struct FakeArgs {
+        cancel: Cell<bool>,
+        log: RefCell<Vec<&'static str>>,
+    }
+
+    fn logger(
+        name: &'static str,
+        result: Result<Flow, &'static str>,
+    ) -> impl Fn(&FakeArgs) -> Result<Flow, &'static str> {
+        move |args| {
+            args.log.borrow_mut().push(name);
+            result
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let dispatcher = Dispatcher::new();
+        dispatcher.add(0, logger("a", Ok(Flow::Continue)));
+        dispatcher.add(10, logger("high", Ok(Flow::Continue)));
+        let b = dispatcher.add(0, logger("b", Ok(Flow::Continue)));
+        dispatcher.add(-5, logger("low", Ok(Flow::Continue)));
+        dispatcher.add(10, logger("high2", Ok(Flow::Continue)));
+
+        let args = FakeArgs::default();
+        let dispatched = dispatcher.dispatch(&args);
+        assert_eq!(*args.log.borrow(), vec!["high", "high2", "a", "b", "low"]);
+        assert_eq!(dispatched.called.len(), 5);
+        assert_eq!(dispatched.stopped_by, None);
+
+        assert!(dispatcher.remove(b));
+        assert!(!dispatcher.remove(b));
+        let args = FakeArgs::default();
+        dispatcher.dispatch(&args);
+        assert_eq!(*args.log.borrow(), vec!["high", "high2", "a", "low"]);
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        // First listener that cancels wins.
+        let dispatcher = Dispatcher::new().with_stop_when(|args: &FakeArgs| args.cancel.get());
+        dispatcher.add(1, logger("allow", Ok(Flow::Continue)));
+        let cancel = dispatcher.add(0, |args: &FakeArgs| {
+            args.log.borrow_mut().push("cancel");
+            args.cancel.set(true);
+            Ok(Flow::Continue)
+        });
+        dispatcher.add(0, |args: &FakeArgs| {
+            args.log.borrow_mut().push("uncancel");
+            args.cancel.set(false);
+            Ok(Flow::Continue)
+        });
+
+        let args = FakeArgs::default();
+        let dispatched = dispatcher.dispatch(&args);
+        assert!(args.cancel.get());
+        assert_eq!(*args.log.borrow(), vec!["allow", "cancel"]);
+        assert_eq!(dispatched.stopped_by, Some(cancel));
+
+        // Explicit stop.
+        let stop = dispatcher.add(5, logger("stop", Ok(Flow::Stop)));
+        let args = FakeArgs::default();
+        let dispatched = dispatcher.dispatch(&args);
+        assert_eq!(*args.log.borrow(), vec!["stop"]);
+        assert_eq!(dispatched.stopped_by, Some(stop));
+        assert!(!args.cancel.get());
+    }
+
+    #[test]
+    fn test_error_isolation() {
+        let dispatcher = Dispatcher::new();
+        let failing = dispatcher.add(1, logger("failing", Err("oops")));
+        dispatcher.add(0, logger("next", Ok(Flow::Continue)));
+
+        let args = FakeArgs::default();
+        let dispatched = dispatcher.dispatch(&args);
+        assert_eq!(*args.log.borrow(), vec!["failing", "next"]);
+        assert_eq!(dispatched.errors, vec![(failing, "oops")]);
+    }
+
+    #[test]
+    fn test_panic_isolation() {
+        let dispatcher = Dispatcher::<FakeArgs, &'static str>::new();
+        let panicking = dispatcher.add(1, |args| {
+            args.log.borrow_mut().push("panicking");
+            panic!("boom")
+        });
+        dispatcher.add(0, logger("next", Ok(Flow::Continue)));
+
+        let args = FakeArgs::default();
+        let dispatched = dispatcher.dispatch(&args);
+        assert_eq!(*args.log.borrow(), vec!["panicking", "next"]);
+        assert!(dispatched.errors.is_empty());
+        assert_eq!(dispatched.panics.len(), 1);
+        assert_eq!(dispatched.panics[0].0, panicking);
+        assert_eq!(dispatched.panics[0].1.downcast_ref::<&str>(), Some(&"boom"));
+    }
+
+    #[test]
+    fn test_reentrant_changes() {
+        let dispatcher = Rc::new(Dispatcher::<FakeArgs, &'static str>::new());
+        let d = Rc::clone(&dispatcher);
+        dispatcher.add(0, move |args| {
+            args.log.borrow_mut().push("adder");
+            d.add(1, logger("added", Ok(Flow::Continue)));
+            Ok(Flow::Continue)
+        });
+
+        let args = FakeArgs::default();
+        dispatcher.dispatch(&args);
+        assert_eq!(*args.log.borrow(), vec!["adder"]);
+        let args = FakeArgs::default();
+        dispatcher.dispatch(&args);
+        assert_eq!(*args.log.borrow(), vec!["added", "adder"]);
+    }
+}
@@ -4,10 +4,14 @@
 #[derive(Clone)]
 pub struct WebView {
     inner: ComRc<dyn ICoreWebView2>,
+    error_sink: Option<Rc<ErrorSink>>,
 }
 impl From<ComRc<dyn ICoreWebView2>> for WebView {
     fn from(inner: ComRc<dyn ICoreWebView2>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            error_sink: None,
+        }
     }
 }
 impl fmt::Debug for WebView {
@@ -28,10 +32,14 @@ impl WebView {
 #[derive(Clone)]
 pub struct Controller {
     inner: ComRc<dyn ICoreWebView2Controller>,
+    error_sink: Option<Rc<ErrorSink>>,
 }
 impl From<ComRc<dyn ICoreWebView2Controller>> for Controller {
     fn from(inner: ComRc<dyn ICoreWebView2Controller>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            error_sink: None,
+        }
     }
 }
 impl fmt::Debug for Controller {
@@ -561,10 +569,14 @@ impl AcceleratorKeyPressedEventArgs {
 #[derive(Clone)]
 pub struct Environment {
     inner: ComRc<dyn ICoreWebView2Environment>,
+    error_sink: Option<Rc<ErrorSink>>,
 }
 impl From<ComRc<dyn ICoreWebView2Environment>> for Environment {
     fn from(inner: ComRc<dyn ICoreWebView2Environment>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            error_sink: None,
+        }
     }
 }
 impl fmt::Debug for Environment {
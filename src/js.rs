@@ -0,0 +1,343 @@
+// Building scripts with Rust values interpolated as JavaScript literals.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::{self, Write};
+
+/// Serialize a value as a JavaScript literal.
+///
+/// The literal is JSON with every non-ASCII character and `<`, `>` and `&`
+/// escaped, so it is pure ASCII. It can't end a `<script>` element or an
+/// HTML comment, contains no U+2028 or U+2029 (line terminators in older
+/// JavaScript engines) and no NUL (which `execute_script` can't pass on).
+pub fn to_js_literal<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<String> {
+    let json = serde_json::to_string(value)?;
+    let mut literal = String::with_capacity(json.len());
+    for c in json.chars() {
+        push_char(&mut literal, c);
+    }
+    Ok(literal)
+}
+
+// Push a character of JSON, which is only ever inside a string if it needs
+// escaping here.
+fn push_char(out: &mut String, c: char) {
+    match c {
+        '<' | '>' | '&' => push_unit(out, c as u16),
+        c if c.is_ascii() => out.push(c),
+        c => {
+            let mut buf = [0u16; 2];
+            for unit in c.encode_utf16(&mut buf) {
+                push_unit(out, *unit);
+            }
+        }
+    }
+}
+
+fn push_unit(out: &mut String, unit: u16) {
+    write!(out, "\\u{:04x}", unit).unwrap();
+}
+
+/// A JavaScript string literal from UTF-16, which may contain lone
+/// surrogates, e.g. text read from a Windows API.
+pub fn utf16_to_js_literal(units: &[u16]) -> String {
+    let mut literal = String::with_capacity(units.len() + 2);
+    literal.push('"');
+    for &unit in units {
+        match unit {
+            0x22 => literal.push_str("\\\""),
+            0x5c => literal.push_str("\\\\"),
+            0x20..=0x7e if !matches!(unit, 0x3c | 0x3e | 0x26) => literal.push(unit as u8 as char),
+            unit => push_unit(&mut literal, unit),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// A script assembled from trusted source text and interpolated values.
+///
+/// ```
+/// use webview2::Script;
+///
+/// let title = "</script><script>alert(1)</script>";
+/// let script = Script::new()
+///     .with_source("document.title = ")
+///     .with_value(title)
+///     .unwrap()
+///     .with_source(";");
+/// assert_eq!(
+///     script.as_str(),
+///     r#"document.title = "\u003c/script\u003e\u003cscript\u003ealert(1)\u003c/script\u003e";"#
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    source: String,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append source text as is. Never pass untrusted input here.
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source.push_str(source);
+        self
+    }
+
+    /// Append a value as a literal, see `to_js_literal`.
+    pub fn with_value<T: Serialize + ?Sized>(mut self, value: &T) -> serde_json::Result<Self> {
+        self.source.push_str(&to_js_literal(value)?);
+        Ok(self)
+    }
+
+    /// Append a string literal from UTF-16, see `utf16_to_js_literal`.
+    pub fn with_utf16(mut self, units: &[u16]) -> Self {
+        self.source.push_str(&utf16_to_js_literal(units));
+        self
+    }
+
+    /// Append a call of `function` with `args` as literals, e.g.
+    /// `setTitle("...", 2);`. The function is source text.
+    pub fn with_call(mut self, function: &str, args: &[Value]) -> Self {
+        self.source.push_str(function);
+        self.source.push('(');
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.source.push_str(", ");
+            }
+            // Serializing a `Value` can't fail.
+            self.source.push_str(&to_js_literal(arg).unwrap());
+        }
+        self.source.push_str(");");
+        self
+    }
+
+    /// Fill the `{}` placeholders of a template with literals. `{{` and
+    /// `}}` are literal braces. Used by `js!`.
+    ///
+    /// # Panics
+    ///
+    /// If the number of placeholders and literals differ.
+    pub fn from_template(template: &str, literals: &[String]) -> Self {
+        let mut source = String::with_capacity(template.len());
+        let mut literals = literals.iter();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    source.push(c);
+                    chars.next();
+                }
+                ('{', Some('}')) => {
+                    chars.next();
+                    let literal = literals
+                        .next()
+                        .expect("js!: more placeholders than arguments");
+                    source.push_str(literal);
+                }
+                _ => source.push(c),
+            }
+        }
+        assert!(
+            literals.next().is_none(),
+            "js!: more arguments than placeholders"
+        );
+        Self { source }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn into_string(self) -> String {
+        self.source
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl From<Script> for String {
+    fn from(script: Script) -> String {
+        script.source
+    }
+}
+
+/// Build a `Script` from a template, with the arguments serialized as
+/// JavaScript literals in place of `{}`.
+///
+/// Evaluates to `serde_json::Result<Script>`. Panics if the number of
+/// placeholders and arguments differ.
+///
+/// ```
+/// use webview2::js;
+///
+/// let title = "It's \"quoted\"";
+/// let script = js!("setTitle({}, {});", title, [1, 2]).unwrap();
+/// assert_eq!(script.as_str(), r#"setTitle("It's \"quoted\"", [1,2]);"#);
+/// ```
+#[macro_export]
+macro_rules! js {
+    ($template:expr) => {
+        $crate::js!($template,)
+    };
+    ($template:expr, $($arg:expr),* $(,)?) => {
+        {
+            let literals: ::std::vec::Vec<$crate::serde_json::Result<::std::string::String>> =
+                ::std::vec![$($crate::to_js_literal(&$arg)),*];
+            literals
+                .into_iter()
+                .collect::<$crate::serde_json::Result<::std::vec::Vec<_>>>()
+                .map(|literals| $crate::Script::from_template($template, &literals))
+        }
+    };
+}
+
+#[cfg(windows)]
+impl crate::WebView {
+    /// Execute a `Script`, see `execute_script`.
+    pub fn execute(
+        &self,
+        script: &Script,
+        callback: impl FnOnce(String) -> crate::Result<()> + 'static,
+    ) -> crate::Result<()> {
+        self.execute_script(script.as_str(), callback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    // Decode a JavaScript string literal as produced here into UTF-16.
+    fn decode_literal(literal: &str) -> Vec<u16> {
+        assert!(literal.starts_with('"') && literal.ends_with('"'));
+        let inner = &literal[1..literal.len() - 1];
+        let mut units = Vec::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                let mut buf = [0u16; 2];
+                units.extend_from_slice(c.encode_utf16(&mut buf));
+                continue;
+            }
+            let unit = match chars.next().unwrap() {
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    u16::from_str_radix(&hex, 16).unwrap()
+                }
+                'n' => 0x0a,
+                'r' => 0x0d,
+                't' => 0x09,
+                'b' => 0x08,
+                'f' => 0x0c,
+                c => c as u16,
+            };
+            units.push(unit);
+        }
+        units
+    }
+
+    fn assert_safe(literal: &str) {
+        assert!(literal.is_ascii(), "{}", literal);
+        assert!(!literal.contains('\0'));
+        assert!(!literal.contains('<'));
+        assert!(!literal.contains('>'));
+        // No raw line terminators inside string literals.
+        assert!(!literal.contains('\n') && !literal.contains('\r'));
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(
+            to_js_literal("</script>").unwrap(),
+            r#""\u003c/script\u003e""#
+        );
+        assert_eq!(
+            to_js_literal("a\u{2028}b\u{2029}").unwrap(),
+            r#""a\u2028b\u2029""#
+        );
+        assert_eq!(to_js_literal("\0").unwrap(), r#""\u0000""#);
+        assert_eq!(to_js_literal("\u{1f600}").unwrap(), r#""\ud83d\ude00""#);
+        assert_eq!(to_js_literal(&Some(1.5)).unwrap(), "1.5");
+        assert_eq!(to_js_literal(&()).unwrap(), "null");
+        let mut map = BTreeMap::new();
+        map.insert("<k>", vec![true]);
+        assert_eq!(to_js_literal(&map).unwrap(), r#"{"\u003ck\u003e":[true]}"#);
+        assert!(to_js_literal(&f64::NAN).unwrap() == "null");
+
+        assert_eq!(utf16_to_js_literal(&[0x61, 0xd800, 0x22]), r#""a\ud800\"""#);
+    }
+
+    #[test]
+    fn test_template() {
+        let script = js!("f({}, '{{}}', {})", "x", 2).unwrap();
+        assert_eq!(script.as_str(), r#"f("x", '{}', 2)"#);
+        assert_eq!(js!("g()").unwrap().as_str(), "g()");
+
+        let script = Script::new()
+            .with_call("f", &[json!("a"), json!(1)])
+            .with_source(" ")
+            .with_call("g", &[json!("</script>"), json!([null])])
+            .with_call("h", &[])
+            .with_utf16(&[0xdc00]);
+        assert_eq!(
+            String::from(script),
+            r#"f("a", 1); g("\u003c/script\u003e", [null]);h();"\udc00""#
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "more placeholders")]
+    fn test_missing_argument() {
+        let _ = js!("f({}, {})", 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "more arguments")]
+    fn test_extra_argument() {
+        let _ = js!("f()", 1);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_string_literal(s in any::<String>()) {
+            let literal = to_js_literal(&s).unwrap();
+            assert_safe(&literal);
+            // Still JSON, and the same string.
+            prop_assert_eq!(serde_json::from_str::<String>(&literal).unwrap(), s.clone());
+            prop_assert_eq!(decode_literal(&literal), s.encode_utf16().collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn prop_nested_literal(v in any::<Vec<(String, Option<i64>, bool)>>()) {
+            let literal = to_js_literal(&v).unwrap();
+            assert_safe(&literal);
+            let decoded: Vec<(String, Option<i64>, bool)> = serde_json::from_str(&literal).unwrap();
+            prop_assert_eq!(decoded, v);
+        }
+
+        #[test]
+        fn prop_utf16_literal(units in any::<Vec<u16>>()) {
+            let literal = utf16_to_js_literal(&units);
+            assert_safe(&literal);
+            prop_assert_eq!(decode_literal(&literal), units);
+        }
+
+        #[test]
+        fn prop_template(s in any::<String>(), n in any::<i32>()) {
+            let script = js!("f({}, {})", s, n).unwrap();
+            let expected = format!("f({}, {})", to_js_literal(&s).unwrap(), n);
+            prop_assert_eq!(script.as_str(), expected.as_str());
+        }
+    }
+}
@@ -0,0 +1,624 @@
+// A growable in-memory stream, and its `IStream` implementation.
+//
+// The stream logic is plain Rust so that it can be tested on any platform.
+// Only the COM glue is Windows specific.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+// Only used by the COM glue, and by tests on other platforms.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) const STREAM_SEEK_SET: u32 = 0;
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) const STREAM_SEEK_CUR: u32 = 1;
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) const STREAM_SEEK_END: u32 = 2;
+
+/// Convert `IStream::Seek` arguments to a `SeekFrom`.
+///
+/// For `STREAM_SEEK_SET`, the offset is interpreted as an unsigned value, as
+/// documented for `IStream::Seek`. Returns `None` for an unknown origin.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn seek_from(offset: i64, origin: u32) -> Option<SeekFrom> {
+    match origin {
+        STREAM_SEEK_SET => Some(SeekFrom::Start(offset as u64)),
+        STREAM_SEEK_CUR => Some(SeekFrom::Current(offset)),
+        STREAM_SEEK_END => Some(SeekFrom::End(offset)),
+        _ => None,
+    }
+}
+
+/// A growable in-memory stream.
+///
+/// It implements the full `IStream` interface when converted to a `Stream`,
+/// and can be used directly through `io::Read`, `io::Write` and `io::Seek`.
+///
+/// Clones copy the bytes. Use `share` for a stream sharing them, like
+/// `IStream::Clone`.
+#[derive(Default)]
+pub struct MemoryStream {
+    data: Rc<RefCell<Vec<u8>>>,
+    pos: u64,
+}
+
+impl MemoryStream {
+    /// The largest size a stream can grow to. Growing it further, with
+    /// `set_size` or by writing, fails instead of exhausting memory.
+    pub const MAX_LEN: u64 = 1 << 32;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Size of the stream in bytes.
+    pub fn len(&self) -> u64 {
+        self.data.borrow().len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.borrow().is_empty()
+    }
+
+    /// Current position. It may be past the end of the stream.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// A stream sharing the bytes of this one, with its own position
+    /// starting at the current one.
+    pub fn share(&self) -> Self {
+        Self {
+            data: Rc::clone(&self.data),
+            pos: self.pos,
+        }
+    }
+
+    /// A copy of the contents of the stream.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.data.borrow().clone()
+    }
+
+    /// Truncate or zero-extend the stream. The position is not changed.
+    pub fn set_size(&self, size: u64) -> io::Result<()> {
+        let size = checked_len(size)?;
+        self.data.borrow_mut().resize(size, 0);
+        Ok(())
+    }
+
+    /// Copy up to `n` bytes from the current position to `dest`.
+    ///
+    /// Returns the number of bytes read and written. They differ only if
+    /// `dest` accepts fewer bytes than given.
+    pub fn copy_to(&mut self, dest: &mut dyn Write, n: u64) -> io::Result<(u64, u64)> {
+        // Copy out first, `dest` might be a clone of this stream.
+        let chunk = {
+            let data = self.data.borrow();
+            let start = data
+                .len()
+                .min(usize::try_from(self.pos).unwrap_or(usize::MAX));
+            let end = start + (data.len() - start).min(usize::try_from(n).unwrap_or(usize::MAX));
+            data[start..end].to_vec()
+        };
+        self.pos += chunk.len() as u64;
+
+        let mut written = 0;
+        while written < chunk.len() {
+            match dest.write(&chunk[written..]) {
+                Ok(0) => break,
+                Ok(w) => written += w,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((chunk.len() as u64, written as u64))
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "stream too large")
+}
+
+// Check a size to grow to. `Vec::try_reserve` is too new, so sizes are bound
+// by `MAX_LEN`, and by `isize::MAX` where that is smaller.
+fn checked_len(size: u64) -> io::Result<usize> {
+    if size > MemoryStream::MAX_LEN {
+        return Err(too_large());
+    }
+    usize::try_from(size)
+        .ok()
+        .filter(|&size| size <= isize::MAX as usize)
+        .ok_or_else(too_large)
+}
+
+impl From<Vec<u8>> for MemoryStream {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            data: Rc::new(RefCell::new(data)),
+            pos: 0,
+        }
+    }
+}
+
+impl Clone for MemoryStream {
+    fn clone(&self) -> Self {
+        Self {
+            data: Rc::new(RefCell::new(self.to_vec())),
+            pos: self.pos,
+        }
+    }
+}
+
+impl std::fmt::Debug for MemoryStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryStream")
+            .field("len", &self.len())
+            .field("pos", &self.pos)
+            .finish()
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.borrow();
+        let start = match usize::try_from(self.pos) {
+            Ok(start) if start < data.len() => start,
+            _ => return Ok(0),
+        };
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self
+            .pos
+            .checked_add(buf.len() as u64)
+            .ok_or_else(too_large)?;
+        let end = checked_len(end)?;
+        let start = end - buf.len();
+        let mut data = self.data.borrow_mut();
+        // Writing past the end fills the hole with zeros.
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        self.pos = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::Current(x) => offset(self.pos, x),
+            SeekFrom::End(x) => offset(self.len(), x),
+        };
+        match new_pos {
+            Some(new_pos) => {
+                self.pos = new_pos;
+                Ok(new_pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+fn offset(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+#[cfg(windows)]
+mod com_impl {
+    // Put it in a module so that the `MemoryStreamImplClassFactory` struct
+    // does not leak into our public API.
+
+    use super::*;
+    use crate::bindings::Stream;
+    use com::interfaces::IUnknown;
+    use com::ComPtr;
+    use std::ffi::c_void;
+    use std::mem;
+    use std::slice;
+    use webview2_sys::{ISequentialStream, IStream, IStreamVTable};
+    use winapi::shared::minwindef::{DWORD, ULONG};
+    use winapi::shared::ntdef::{HRESULT, LARGE_INTEGER, ULARGE_INTEGER};
+    use winapi::shared::winerror::{
+        STG_E_INVALIDFUNCTION, STG_E_INVALIDPOINTER, STG_E_MEDIUMFULL, SUCCEEDED, S_OK,
+    };
+    use winapi::um::objidlbase::{STATSTG, STGTY_STREAM};
+
+    #[com::co_class(implements(IStream))]
+    pub struct MemoryStreamImpl {
+        inner: RefCell<MemoryStream>,
+    }
+
+    impl MemoryStreamImpl {
+        fn new() -> Box<Self> {
+            unreachable!()
+        }
+
+        fn new_ptr(stream: MemoryStream) -> *mut *mut IStreamVTable {
+            let instance = Self::allocate(RefCell::new(stream));
+            unsafe {
+                instance.add_ref();
+            }
+            Box::into_raw(instance) as _
+        }
+    }
+
+    impl From<MemoryStream> for Stream {
+        fn from(stream: MemoryStream) -> Stream {
+            unsafe { Stream::from_raw(MemoryStreamImpl::new_ptr(stream)) }
+        }
+    }
+
+    // Adapts an `IStream` pointer for `MemoryStream::copy_to`.
+    struct StreamWriter(ComPtr<dyn IStream>);
+
+    impl Write for StreamWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(ULONG::MAX as usize);
+            let mut written: ULONG = 0;
+            let hr = unsafe {
+                self.0
+                    .write(buf.as_ptr() as *const _, len as ULONG, &mut written)
+            };
+            if SUCCEEDED(hr) {
+                Ok(written as usize)
+            } else {
+                // `io::Error::other` needs Rust 1.74.
+                #[allow(clippy::io_other_error)]
+                Err(io::Error::new(io::ErrorKind::Other, crate::Error::new(hr)))
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ISequentialStream for MemoryStreamImpl {
+        unsafe fn read(&self, pv: *mut c_void, cb: ULONG, pcb_read: *mut ULONG) -> HRESULT {
+            if pv.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let buf = slice::from_raw_parts_mut(pv as *mut u8, cb as usize);
+            // Reading from memory can't fail.
+            let n = self.inner.borrow_mut().read(buf).unwrap_or(0);
+            if !pcb_read.is_null() {
+                pcb_read.write(n as ULONG);
+            }
+            S_OK
+        }
+
+        unsafe fn write(&self, pv: *const c_void, cb: ULONG, pcb_written: *mut ULONG) -> HRESULT {
+            if pv.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let buf = slice::from_raw_parts(pv as *const u8, cb as usize);
+            match self.inner.borrow_mut().write(buf) {
+                Ok(n) => {
+                    if !pcb_written.is_null() {
+                        pcb_written.write(n as ULONG);
+                    }
+                    S_OK
+                }
+                Err(_) => STG_E_MEDIUMFULL,
+            }
+        }
+    }
+
+    impl IStream for MemoryStreamImpl {
+        unsafe fn seek(
+            &self,
+            dlib_move: LARGE_INTEGER,
+            dw_origin: DWORD,
+            plib_new_position: *mut ULARGE_INTEGER,
+        ) -> HRESULT {
+            let pos = match seek_from(*dlib_move.QuadPart(), dw_origin) {
+                Some(pos) => pos,
+                None => return STG_E_INVALIDFUNCTION,
+            };
+            match self.inner.borrow_mut().seek(pos) {
+                Ok(new_pos) => {
+                    if !plib_new_position.is_null() {
+                        *(*plib_new_position).QuadPart_mut() = new_pos;
+                    }
+                    S_OK
+                }
+                Err(_) => STG_E_INVALIDFUNCTION,
+            }
+        }
+
+        unsafe fn set_size(&self, lib_new_size: ULARGE_INTEGER) -> HRESULT {
+            match self.inner.borrow().set_size(*lib_new_size.QuadPart()) {
+                Ok(()) => S_OK,
+                Err(_) => STG_E_MEDIUMFULL,
+            }
+        }
+
+        unsafe fn copy_to(
+            &self,
+            pstm: *mut *mut IStreamVTable,
+            cb: ULARGE_INTEGER,
+            pcb_read: *mut ULARGE_INTEGER,
+            pcb_written: *mut ULARGE_INTEGER,
+        ) -> HRESULT {
+            if pstm.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let mut dest = StreamWriter(ComPtr::new(pstm));
+            let (read, written) = match self.inner.borrow_mut().copy_to(&mut dest, *cb.QuadPart()) {
+                Ok(r) => r,
+                Err(e) => return crate::to_hresult::<()>(Err(e.into())),
+            };
+            if !pcb_read.is_null() {
+                *(*pcb_read).QuadPart_mut() = read;
+            }
+            if !pcb_written.is_null() {
+                *(*pcb_written).QuadPart_mut() = written;
+            }
+            S_OK
+        }
+
+        // Changes are always applied directly, as with `SHCreateMemStream`.
+        unsafe fn commit(&self, _grf_commit_flags: DWORD) -> HRESULT {
+            S_OK
+        }
+
+        unsafe fn revert(&self) -> HRESULT {
+            S_OK
+        }
+
+        unsafe fn lock_region(
+            &self,
+            _lib_offset: ULARGE_INTEGER,
+            _cb: ULARGE_INTEGER,
+            _dw_lock_type: DWORD,
+        ) -> HRESULT {
+            STG_E_INVALIDFUNCTION
+        }
+
+        unsafe fn unlock_region(
+            &self,
+            _lib_offset: ULARGE_INTEGER,
+            _cb: ULARGE_INTEGER,
+            _dw_lock_type: DWORD,
+        ) -> HRESULT {
+            STG_E_INVALIDFUNCTION
+        }
+
+        unsafe fn stat(&self, pstatstg: *mut STATSTG, _grf_stat_flag: DWORD) -> HRESULT {
+            if pstatstg.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            // Zeroed: no name, no timestamps and no locks.
+            let mut stat: STATSTG = mem::zeroed();
+            stat.type_ = STGTY_STREAM;
+            *stat.cbSize.QuadPart_mut() = self.inner.borrow().len();
+            stat.grfMode = /* STGM_READWRITE */ 2;
+            pstatstg.write(stat);
+            S_OK
+        }
+
+        unsafe fn clone(&self, ppstm: *mut *mut *mut IStreamVTable) -> HRESULT {
+            if ppstm.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            ppstm.write(Self::new_ptr(self.inner.borrow().share()));
+            S_OK
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Behaviour every `IStream` backed stream should share.
+    fn conformance<S: Read + Write + Seek>(make: impl Fn(&[u8]) -> S) {
+        // Read everything.
+        let mut s = make(b"hello, world");
+        let mut buf = Vec::new();
+        s.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello, world");
+
+        // Seeking.
+        assert_eq!(s.seek(SeekFrom::Start(7)).unwrap(), 7);
+        assert_eq!(s.seek(SeekFrom::Current(-2)).unwrap(), 5);
+        assert_eq!(s.seek(SeekFrom::End(-5)).unwrap(), 7);
+        assert!(s.seek(SeekFrom::Current(-8)).is_err());
+        assert!(s.seek(SeekFrom::End(-13)).is_err());
+
+        // Seeking and reading past the end.
+        assert_eq!(s.seek(SeekFrom::End(3)).unwrap(), 15);
+        assert_eq!(s.read(&mut [0u8; 4]).unwrap(), 0);
+        assert_eq!(s.seek(SeekFrom::Start(u64::MAX)).unwrap(), u64::MAX);
+        assert_eq!(s.read(&mut [0u8; 4]).unwrap(), 0);
+        assert!(s.write(b"x").is_err());
+
+        // Appending.
+        let mut s = make(b"hello,");
+        s.seek(SeekFrom::End(0)).unwrap();
+        s.write_all(b" world").unwrap();
+        s.seek(SeekFrom::Start(0)).unwrap();
+        let mut buf = Vec::new();
+        s.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello, world");
+
+        // Overwriting, and writing with a hole.
+        let mut s = make(b"abc");
+        s.seek(SeekFrom::Start(1)).unwrap();
+        s.write_all(b"X").unwrap();
+        s.seek(SeekFrom::Start(5)).unwrap();
+        s.write_all(b"Y").unwrap();
+        s.seek(SeekFrom::Start(0)).unwrap();
+        let mut buf = Vec::new();
+        s.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"aXc\0\0Y");
+
+        // Empty.
+        let mut s = make(b"");
+        assert_eq!(s.read(&mut [0u8; 4]).unwrap(), 0);
+        assert_eq!(s.seek(SeekFrom::End(0)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance(|b| MemoryStream::from(b.to_vec()));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_conformance_com() {
+        conformance(crate::Stream::from_bytes);
+    }
+
+    #[test]
+    fn test_seek_from() {
+        assert_eq!(seek_from(3, STREAM_SEEK_SET), Some(SeekFrom::Start(3)));
+        assert_eq!(
+            seek_from(-1, STREAM_SEEK_SET),
+            Some(SeekFrom::Start(u64::MAX))
+        );
+        assert_eq!(seek_from(-3, STREAM_SEEK_CUR), Some(SeekFrom::Current(-3)));
+        assert_eq!(seek_from(-3, STREAM_SEEK_END), Some(SeekFrom::End(-3)));
+        assert_eq!(seek_from(0, 3), None);
+    }
+
+    #[test]
+    fn test_set_size() {
+        let mut s = MemoryStream::from(b"hello".to_vec());
+        s.seek(SeekFrom::Start(4)).unwrap();
+        s.set_size(2).unwrap();
+        assert_eq!(s.to_vec(), b"he");
+        assert_eq!(s.position(), 4);
+        assert_eq!(s.read(&mut [0u8; 4]).unwrap(), 0);
+
+        s.set_size(4).unwrap();
+        assert_eq!(s.to_vec(), b"he\0\0");
+        assert_eq!(s.len(), 4);
+
+        // Too large sizes fail without allocating.
+        for &size in &[MemoryStream::MAX_LEN + 1, 1 << 62, u64::MAX] {
+            assert_eq!(
+                s.set_size(size).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+        s.seek(SeekFrom::Start(1 << 40)).unwrap();
+        assert!(s.write(b"x").is_err());
+        s.seek(SeekFrom::Start(MemoryStream::MAX_LEN)).unwrap();
+        assert!(s.write(b"x").is_err());
+        assert_eq!(s.len(), 4);
+    }
+
+    #[test]
+    fn test_copy_to() {
+        let mut s = MemoryStream::from(b"hello, world".to_vec());
+        let mut dest = MemoryStream::from(b"==".to_vec());
+        dest.seek(SeekFrom::End(0)).unwrap();
+
+        s.seek(SeekFrom::Start(7)).unwrap();
+        assert_eq!(s.copy_to(&mut dest, 3).unwrap(), (3, 3));
+        assert_eq!(s.position(), 10);
+        assert_eq!(s.copy_to(&mut dest, 100).unwrap(), (2, 2));
+        assert_eq!(s.copy_to(&mut dest, 100).unwrap(), (0, 0));
+        assert_eq!(dest.to_vec(), b"==world");
+        assert_eq!(dest.position(), 7);
+    }
+
+    #[test]
+    fn test_share() {
+        let mut s = MemoryStream::from(b"hello".to_vec());
+        s.seek(SeekFrom::Start(2)).unwrap();
+        let mut c = s.share();
+        assert_eq!(c.position(), 2);
+
+        // Shared bytes, independent positions.
+        c.seek(SeekFrom::End(0)).unwrap();
+        c.write_all(b"!").unwrap();
+        assert_eq!(s.to_vec(), b"hello!");
+        assert_eq!(s.position(), 2);
+
+        // Copying to a clone of itself.
+        s.seek(SeekFrom::Start(0)).unwrap();
+        assert_eq!(s.copy_to(&mut c, 2).unwrap(), (2, 2));
+        assert_eq!(s.to_vec(), b"hello!he");
+    }
+
+    #[test]
+    fn test_clone() {
+        let mut s = MemoryStream::from(b"hello".to_vec());
+        s.seek(SeekFrom::Start(2)).unwrap();
+        let mut c = s.clone();
+        assert_eq!(c.position(), 2);
+        c.write_all(b"LP").unwrap();
+        assert_eq!(c.to_vec(), b"heLPo");
+        assert_eq!(s.to_vec(), b"hello");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_com_stream() {
+        use crate::check_hresult;
+        use std::mem::{self, MaybeUninit};
+        use webview2_sys::IStream;
+        use winapi::shared::ntdef::ULARGE_INTEGER;
+        use winapi::um::objidlbase::STATSTG;
+
+        let stream = crate::Stream::from(MemoryStream::from(b"hello".to_vec()));
+        let inner = stream.as_inner();
+
+        unsafe {
+            let mut size: ULARGE_INTEGER = mem::zeroed();
+            *size.QuadPart_mut() = 3;
+            check_hresult(inner.set_size(size)).unwrap();
+
+            let mut stat = MaybeUninit::<STATSTG>::uninit();
+            check_hresult(inner.stat(stat.as_mut_ptr(), 0)).unwrap();
+            let stat = stat.assume_init();
+            assert_eq!(*stat.cbSize.QuadPart(), 3);
+            assert!(stat.pwcsName.is_null());
+
+            let mut clone = MaybeUninit::uninit();
+            check_hresult(IStream::clone(inner, clone.as_mut_ptr())).unwrap();
+            let mut clone = crate::Stream::from_raw(clone.assume_init());
+            let mut buf = Vec::new();
+            clone.read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, b"hel");
+
+            let mut dest = crate::Stream::from_bytes(b"");
+            let mut n = mem::zeroed::<ULARGE_INTEGER>();
+            *n.QuadPart_mut() = 100;
+            check_hresult(inner.copy_to(
+                dest.as_inner().as_raw(),
+                n,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            ))
+            .unwrap();
+            dest.seek(SeekFrom::Start(0)).unwrap();
+            let mut buf = Vec::new();
+            dest.read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, b"hel");
+        }
+    }
+}
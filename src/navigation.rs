@@ -0,0 +1,943 @@
+// Declarative allow/deny rules for navigations and new windows.
+
+use crate::origin::{default_port, split_host_port, split_scheme, Origin};
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// What is navigating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NavigationKind {
+    /// The top-level document, `NavigationStarting`.
+    TopLevel,
+    /// An iframe, `FrameNavigationStarting`.
+    Frame,
+    /// A new window, `NewWindowRequested`.
+    NewWindow,
+}
+
+impl NavigationKind {
+    fn flag(self) -> &'static str {
+        match self {
+            NavigationKind::TopLevel => "top",
+            NavigationKind::Frame => "frame",
+            NavigationKind::NewWindow => "new-window",
+        }
+    }
+}
+
+/// A navigation to decide on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Navigation<'a> {
+    pub uri: &'a str,
+    pub kind: NavigationKind,
+    pub user_initiated: bool,
+    /// Always `false` for new windows.
+    pub redirect: bool,
+}
+
+/// What a rule does with matching navigations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavigationAction {
+    Allow,
+    Deny,
+}
+
+/// What a `NavigationPolicy` decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavigationDecision {
+    Allow,
+    Deny,
+    /// Deny, and open the URI in the system browser.
+    OpenExternally,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostPattern {
+    Exact(String),
+    /// Subdomains of the domain, not the domain itself, like
+    /// `OriginRule::Subdomains`.
+    Subdomains(String),
+}
+
+/// A rule of a `NavigationPolicy`.
+///
+/// The text form is `allow|deny PATTERN FLAG*`. The pattern is one of:
+///
+/// * `*`: any URI.
+/// * `scheme:`: any URI with the scheme, e.g. `about:` or `data:`.
+/// * `scheme:path`: URIs without an authority, e.g. `about:blank`. The path
+///   is a glob, see below.
+/// * `scheme://host[:port][/path]`: the scheme can be `*`. The host can be
+///   `*`, `*.domain` for subdomains of the domain, or a host. Without a
+///   port, only the default port of the scheme matches, unless the host is
+///   `*`. The path is a glob where `*` matches within a segment and `**`
+///   across segments. Without a path, any path matches. Queries and
+///   fragments are ignored.
+///
+/// Flags restrict the rule to navigations that are `user` or `!user`
+/// initiated, `redirect`s or `!redirect`s, and to `top`, `frame` or
+/// `new-window` navigations.
+///
+/// ```
+/// use webview2::{NavigationRule, Navigation, NavigationKind};
+///
+/// let rule: NavigationRule = "allow https://*.example.com/docs/** user top".parse().unwrap();
+/// let navigation = Navigation {
+///     uri: "https://www.example.com/docs/a/b.html?q#f",
+///     kind: NavigationKind::TopLevel,
+///     user_initiated: true,
+///     redirect: false,
+/// };
+/// assert!(rule.matches(&navigation));
+/// assert!(!rule.matches(&Navigation { user_initiated: false, ..navigation }));
+/// assert!(!rule.matches(&Navigation { uri: "https://www.example.com/", ..navigation }));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NavigationRule {
+    action: NavigationAction,
+    scheme: Option<String>,
+    host: Option<HostPattern>,
+    port: Option<u16>,
+    path: Option<String>,
+    user_initiated: Option<bool>,
+    redirect: Option<bool>,
+    // Sorted, empty for all kinds.
+    kinds: Vec<NavigationKind>,
+}
+
+/// Error parsing a `NavigationRule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidNavigationRule(pub String);
+
+impl fmt::Display for InvalidNavigationRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid navigation rule: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidNavigationRule {}
+
+impl NavigationRule {
+    /// Allow URIs matching `pattern`, see the type documentation.
+    pub fn allow(pattern: &str) -> Result<Self, InvalidNavigationRule> {
+        Self::with_pattern(NavigationAction::Allow, pattern)
+    }
+
+    /// Deny URIs matching `pattern`, see the type documentation.
+    pub fn deny(pattern: &str) -> Result<Self, InvalidNavigationRule> {
+        Self::with_pattern(NavigationAction::Deny, pattern)
+    }
+
+    fn with_pattern(
+        action: NavigationAction,
+        pattern: &str,
+    ) -> Result<Self, InvalidNavigationRule> {
+        let mut rule = Self {
+            action,
+            scheme: None,
+            host: None,
+            port: None,
+            path: None,
+            user_initiated: None,
+            redirect: None,
+            kinds: Vec::new(),
+        };
+        if rule.parse_pattern(pattern).is_none() {
+            return Err(InvalidNavigationRule(pattern.into()));
+        }
+        Ok(rule)
+    }
+
+    // `strip_prefix` needs Rust 1.45.
+    #[allow(clippy::manual_strip)]
+    fn parse_pattern(&mut self, pattern: &str) -> Option<()> {
+        if pattern == "*" {
+            return Some(());
+        }
+        let rest = if pattern.starts_with("*://") {
+            &pattern[2..]
+        } else {
+            let (scheme, rest) = split_scheme(pattern)?;
+            self.scheme = Some(scheme);
+            if rest.is_empty() {
+                return Some(());
+            }
+            rest
+        };
+        let valid_path =
+            |path: &str| !path.contains(|c: char| c == '?' || c == '#' || c.is_whitespace());
+        if !rest.starts_with("//") {
+            // E.g. `about:blank`.
+            if self.scheme.is_none() || !valid_path(rest) {
+                return None;
+            }
+            self.path = Some(rest.into());
+            return Some(());
+        }
+        let rest = &rest[2..];
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if !path.is_empty() {
+            if !valid_path(path) {
+                return None;
+            }
+            self.path = Some(path.into());
+        }
+        if authority.contains(&['@', '?', '#'][..]) {
+            return None;
+        }
+        let port = if authority == "*" {
+            None
+        } else if authority.starts_with("*:") {
+            Some(&authority[2..])
+        } else {
+            let subdomains = authority.starts_with("*.");
+            let authority = if subdomains {
+                &authority[2..]
+            } else {
+                authority
+            };
+            let (host, port) = split_host_port(authority)?;
+            if host.contains('*') {
+                return None;
+            }
+            self.host = Some(if subdomains {
+                HostPattern::Subdomains(host)
+            } else {
+                HostPattern::Exact(host)
+            });
+            self.port = port;
+            None
+        };
+        if let Some(port) = port {
+            self.port = Some(port.parse().ok()?);
+        }
+        Some(())
+    }
+
+    /// Only match user initiated navigations, or only others.
+    pub fn with_user_initiated(mut self, user_initiated: bool) -> Self {
+        self.user_initiated = Some(user_initiated);
+        self
+    }
+
+    /// Only match redirects, or only others.
+    pub fn with_redirect(mut self, redirect: bool) -> Self {
+        self.redirect = Some(redirect);
+        self
+    }
+
+    /// Only match navigations of these kinds. Can be called repeatedly.
+    pub fn with_kind(mut self, kind: NavigationKind) -> Self {
+        if let Err(i) = self.kinds.binary_search(&kind) {
+            self.kinds.insert(i, kind);
+        }
+        self
+    }
+
+    pub fn action(&self) -> NavigationAction {
+        self.action
+    }
+
+    pub fn matches(&self, navigation: &Navigation<'_>) -> bool {
+        if self.user_initiated == Some(!navigation.user_initiated)
+            || self.redirect == Some(!navigation.redirect)
+            || !(self.kinds.is_empty() || self.kinds.contains(&navigation.kind))
+        {
+            return false;
+        }
+        if self.scheme.is_none()
+            && self.host.is_none()
+            && self.port.is_none()
+            && self.path.is_none()
+        {
+            return true;
+        }
+        let origin = match Origin::parse(navigation.uri) {
+            Some(origin) => origin,
+            None => return false,
+        };
+        if let Some(ref scheme) = self.scheme {
+            if *scheme != origin.scheme {
+                return false;
+            }
+        }
+        let port_matches = match self.port {
+            Some(port) => origin.port.or_else(|| default_port(&origin.scheme)) == Some(port),
+            None => self.host.is_none() || origin.port.is_none(),
+        };
+        let host_matches = match self.host {
+            None => true,
+            Some(HostPattern::Exact(ref host)) => *host == origin.host,
+            Some(HostPattern::Subdomains(ref domain)) => {
+                origin.host.len() > domain.len() + 1
+                    && origin.host.ends_with(domain.as_str())
+                    && origin.host[..origin.host.len() - domain.len()].ends_with('.')
+            }
+        };
+        let path_matches = match self.path {
+            None => true,
+            Some(ref glob) => glob_matches(glob, uri_path(navigation.uri)),
+        };
+        port_matches && host_matches && path_matches
+    }
+}
+
+impl FromStr for NavigationRule {
+    type Err = InvalidNavigationRule;
+
+    // `strip_prefix` needs Rust 1.45.
+    #[allow(clippy::manual_strip)]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidNavigationRule(s.into());
+        let mut words = s.split_whitespace();
+        let action = match words.next() {
+            Some("allow") => NavigationAction::Allow,
+            Some("deny") => NavigationAction::Deny,
+            _ => return Err(invalid()),
+        };
+        let pattern = words.next().ok_or_else(invalid)?;
+        let mut rule = Self::with_pattern(action, pattern).map_err(|_| invalid())?;
+        for flag in words {
+            let (negated, name) = if flag.starts_with('!') {
+                (true, &flag[1..])
+            } else {
+                (false, flag)
+            };
+            let kind = match name {
+                "user" if rule.user_initiated.is_none() => {
+                    rule.user_initiated = Some(!negated);
+                    continue;
+                }
+                "redirect" if rule.redirect.is_none() => {
+                    rule.redirect = Some(!negated);
+                    continue;
+                }
+                "top" => NavigationKind::TopLevel,
+                "frame" => NavigationKind::Frame,
+                "new-window" => NavigationKind::NewWindow,
+                _ => return Err(invalid()),
+            };
+            if negated || rule.kinds.contains(&kind) {
+                return Err(invalid());
+            }
+            rule = rule.with_kind(kind);
+        }
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for NavigationRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.action {
+            NavigationAction::Allow => "allow ",
+            NavigationAction::Deny => "deny ",
+        })?;
+        match (&self.scheme, &self.host, self.port, &self.path) {
+            (None, None, None, None) => f.write_str("*")?,
+            (Some(scheme), None, None, None) => write!(f, "{}:", scheme)?,
+            (Some(scheme), None, None, Some(path)) if !path.starts_with('/') => {
+                write!(f, "{}:{}", scheme, path)?
+            }
+            (scheme, host, port, path) => {
+                write!(f, "{}://", scheme.as_ref().map_or("*", |s| s.as_str()))?;
+                match host {
+                    None => f.write_str("*")?,
+                    Some(HostPattern::Exact(host)) => f.write_str(host)?,
+                    Some(HostPattern::Subdomains(domain)) => write!(f, "*.{}", domain)?,
+                }
+                if let Some(port) = port {
+                    write!(f, ":{}", port)?;
+                }
+                if let Some(path) = path {
+                    f.write_str(path)?;
+                }
+            }
+        }
+        let flag = |f: &mut fmt::Formatter<'_>, value: Option<bool>, name| match value {
+            Some(true) => write!(f, " {}", name),
+            Some(false) => write!(f, " !{}", name),
+            None => Ok(()),
+        };
+        flag(f, self.user_initiated, "user")?;
+        flag(f, self.redirect, "redirect")?;
+        for kind in &self.kinds {
+            write!(f, " {}", kind.flag())?;
+        }
+        Ok(())
+    }
+}
+
+// The path of a URI, without query and fragment. Empty if there is none,
+// e.g. `https://example.com`.
+#[allow(clippy::manual_strip)]
+fn uri_path(uri: &str) -> &str {
+    let rest = match split_scheme(uri.trim()) {
+        Some((_, rest)) => rest,
+        None => return "",
+    };
+    let rest = if rest.starts_with("//") {
+        let authority_end = rest[2..]
+            .find(&['/', '?', '#'][..])
+            .map_or(rest.len(), |i| i + 2);
+        &rest[authority_end..]
+    } else {
+        rest
+    };
+    &rest[..rest.find(&['?', '#'][..]).unwrap_or(rest.len())]
+}
+
+// Match a path glob, where `*` matches anything but `/` and `**` anything.
+fn glob_matches(glob: &str, path: &str) -> bool {
+    let path: Vec<char> = path.chars().collect();
+    // `matched[j]`: the glob so far matches `path[..j]`.
+    let mut matched = vec![false; path.len() + 1];
+    matched[0] = true;
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut next = vec![false; path.len() + 1];
+        if c == '*' {
+            let any = chars.peek() == Some(&'*');
+            if any {
+                chars.next();
+            }
+            for j in 0..=path.len() {
+                next[j] = matched[j] || (j > 0 && next[j - 1] && (any || path[j - 1] != '/'));
+            }
+        } else {
+            for j in 0..path.len() {
+                next[j + 1] = matched[j] && path[j] == c;
+            }
+        }
+        matched = next;
+    }
+    matched[path.len()]
+}
+
+/// Rules deciding which navigations and new windows are allowed.
+///
+/// The first matching rule decides, or the default if none does.
+///
+/// ```
+/// use webview2::{NavigationDecision, Navigation, NavigationKind, NavigationPolicy};
+///
+/// let policy = NavigationPolicy::new()
+///     .with_rule("allow https://app.example.com".parse().unwrap())
+///     .with_rule("allow about:blank frame".parse().unwrap())
+///     .with_open_externally(true);
+/// let navigation = Navigation {
+///     uri: "https://app.example.com/settings",
+///     kind: NavigationKind::TopLevel,
+///     user_initiated: true,
+///     redirect: false,
+/// };
+/// assert_eq!(policy.decide(&navigation), NavigationDecision::Allow);
+/// let elsewhere = Navigation { uri: "https://example.org/", ..navigation };
+/// assert_eq!(policy.decide(&elsewhere), NavigationDecision::OpenExternally);
+/// ```
+#[derive(Clone)]
+pub struct NavigationPolicy {
+    rules: Vec<NavigationRule>,
+    default: NavigationAction,
+    open_externally: bool,
+    on_denied: Option<Rc<dyn Fn(&Navigation<'_>, NavigationDecision)>>,
+}
+
+impl fmt::Debug for NavigationPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NavigationPolicy")
+            .field("rules", &self.rules)
+            .field("default", &self.default)
+            .field("open_externally", &self.open_externally)
+            .finish()
+    }
+}
+
+impl Default for NavigationPolicy {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default: NavigationAction::Deny,
+            open_externally: false,
+            on_denied: None,
+        }
+    }
+}
+
+impl NavigationPolicy {
+    /// A policy that denies everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: NavigationRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// What to do if no rule matches. `Deny` by default.
+    pub fn with_default(mut self, default: NavigationAction) -> Self {
+        self.default = default;
+        self
+    }
+
+    /// Open denied `http`, `https` and `mailto` URIs of user initiated
+    /// top-level navigations and new windows in the system browser.
+    /// Redirects, and navigations scripts start on their own, are only
+    /// denied.
+    pub fn with_open_externally(mut self, open_externally: bool) -> Self {
+        self.open_externally = open_externally;
+        self
+    }
+
+    /// Called with every navigation that is not allowed.
+    pub fn with_denial_handler(
+        mut self,
+        on_denied: impl Fn(&Navigation<'_>, NavigationDecision) + 'static,
+    ) -> Self {
+        self.on_denied = Some(Rc::new(on_denied));
+        self
+    }
+
+    pub fn rules(&self) -> &[NavigationRule] {
+        &self.rules
+    }
+
+    pub fn decide(&self, navigation: &Navigation<'_>) -> NavigationDecision {
+        let action = self
+            .rules
+            .iter()
+            .find(|r| r.matches(navigation))
+            .map_or(self.default, |r| r.action);
+        if action == NavigationAction::Allow {
+            return NavigationDecision::Allow;
+        }
+        let external = self.open_externally
+            && navigation.kind != NavigationKind::Frame
+            && navigation.user_initiated
+            && !navigation.redirect
+            && match split_scheme(navigation.uri.trim()) {
+                Some((scheme, _)) => scheme == "http" || scheme == "https" || scheme == "mailto",
+                None => false,
+            };
+        if external {
+            NavigationDecision::OpenExternally
+        } else {
+            NavigationDecision::Deny
+        }
+    }
+
+    /// Like `decide`, but report navigations that are not allowed.
+    pub fn check(&self, navigation: &Navigation<'_>) -> NavigationDecision {
+        let decision = self.decide(navigation);
+        if decision != NavigationDecision::Allow {
+            if let Some(ref on_denied) = self.on_denied {
+                on_denied(navigation, decision);
+            }
+        }
+        decision
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::*;
+    use std::ptr;
+    use widestring::WideCString;
+    use winapi::um::shellapi::ShellExecuteW;
+    use winapi::um::winuser::SW_SHOWNORMAL;
+
+    /// Open a URI with its default handler, e.g. the system browser.
+    pub fn open_externally(uri: &str) -> Result<()> {
+        let verb = WideCString::from_str("open")?;
+        let uri = WideCString::from_str(uri)?;
+        let instance = unsafe {
+            ShellExecuteW(
+                ptr::null_mut(),
+                verb.as_ptr(),
+                uri.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                SW_SHOWNORMAL,
+            )
+        };
+        // Values above 32 mean success.
+        if instance as usize > 32 {
+            Ok(())
+        } else {
+            Err(Error::new(winapi::shared::winerror::E_FAIL).with_operation("ShellExecuteW"))
+        }
+    }
+
+    // Called once the navigation is cancelled, so that it does not go
+    // through if opening it externally fails.
+    fn apply(policy: &NavigationPolicy, navigation: &Navigation<'_>) -> Result<bool> {
+        match policy.check(navigation) {
+            NavigationDecision::Allow => Ok(true),
+            NavigationDecision::Deny => Ok(false),
+            NavigationDecision::OpenExternally => open_externally(navigation.uri).map(|_| false),
+        }
+    }
+
+    impl NavigationPolicy {
+        /// Cancel navigations and new windows the policy does not allow.
+        ///
+        /// They are cancelled up front and only let through once allowed:
+        /// if reading the event fails, the navigation is denied. Navigations
+        /// an earlier handler cancelled, and new windows it handled, stay
+        /// that way and are not decided on.
+        pub fn attach(&self, webview: &WebView) -> Result<SubscriptionSet> {
+            let mut subscriptions = SubscriptionSet::new();
+            for &kind in &[NavigationKind::TopLevel, NavigationKind::Frame] {
+                let policy = self.clone();
+                let handler = move |_: WebView, args: NavigationStartingEventArgs| {
+                    let cancelled = args.get_cancel();
+                    args.put_cancel(true)?;
+                    if cancelled? {
+                        // By an earlier handler, which this must not undo.
+                        return Ok(());
+                    }
+                    let uri = args.get_uri()?;
+                    let navigation = Navigation {
+                        uri: &uri,
+                        kind,
+                        user_initiated: args.get_is_user_initiated()?,
+                        redirect: args.get_is_redirected()?,
+                    };
+                    if apply(&policy, &navigation)? {
+                        args.put_cancel(false)?;
+                    }
+                    Ok(())
+                };
+                subscriptions.add(if kind == NavigationKind::TopLevel {
+                    webview.on_navigation_starting(handler)?
+                } else {
+                    webview.on_frame_navigation_starting(handler)?
+                });
+            }
+            let policy = self.clone();
+            subscriptions.add(webview.on_new_window_requested(move |_, args| {
+                // Handled without a new window: nothing is opened.
+                let handled = args.get_handled();
+                args.put_handled(true)?;
+                if handled? {
+                    return Ok(());
+                }
+                let uri = args.get_uri()?;
+                let navigation = Navigation {
+                    uri: &uri,
+                    kind: NavigationKind::NewWindow,
+                    user_initiated: args.get_is_user_initiated()?,
+                    redirect: false,
+                };
+                if apply(&policy, &navigation)? {
+                    args.put_handled(false)?;
+                }
+                Ok(())
+            })?);
+            Ok(subscriptions)
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use self::win::open_externally;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::cell::RefCell;
+
+    fn top(uri: &str) -> Navigation<'_> {
+        Navigation {
+            uri,
+            kind: NavigationKind::TopLevel,
+            user_initiated: false,
+            redirect: false,
+        }
+    }
+
+    fn rule(s: &str) -> NavigationRule {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        for s in &[
+            "allow *",
+            "deny about:",
+            "allow about:blank",
+            "allow data:text/*",
+            "allow https://example.com",
+            "allow *://*.example.com:8080/a/**",
+            "deny https://*:8443 !user redirect new-window",
+            "allow http://[::1]/*.html top frame",
+        ] {
+            assert_eq!(rule(s).to_string(), *s);
+        }
+        // Normalized.
+        assert_eq!(
+            rule(" allow  HTTPS://*  frame top ").to_string(),
+            "allow https: top frame"
+        );
+        assert_eq!(
+            NavigationRule::deny("https://a.com/x")
+                .unwrap()
+                .with_kind(NavigationKind::NewWindow)
+                .with_kind(NavigationKind::TopLevel)
+                .with_user_initiated(true)
+                .to_string(),
+            "deny https://a.com/x user top new-window"
+        );
+
+        for s in &[
+            "",
+            "allow",
+            "block *",
+            "allow *:",
+            "allow *:x",
+            "allow about:a b",
+            "allow https://a.*.com",
+            "allow https://u@a.com",
+            "allow https://a.com/?q",
+            "allow https://a.com:http",
+            "allow * user !user",
+            "allow * !top",
+            "allow * top top",
+            "allow * sideways",
+        ] {
+            assert!(s.parse::<NavigationRule>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_matching() {
+        let r = rule("allow https://example.com");
+        assert!(r.matches(&top("https://EXAMPLE.com:443/any/path?q")));
+        assert!(!r.matches(&top("https://example.com:8443/")));
+        assert!(!r.matches(&top("http://example.com/")));
+        assert!(!r.matches(&top("not a uri")));
+
+        let r = rule("allow *://*.example.com/docs/*.html");
+        assert!(r.matches(&top("http://a.example.com/docs/x.html#top")));
+        assert!(r.matches(&top("wss://a.b.example.com/docs/.html")));
+        assert!(!r.matches(&top("https://example.com/docs/x.html")));
+        assert!(!r.matches(&top("https://a.example.com/docs/a/x.html")));
+        assert!(!r.matches(&top("https://a.example.com/docs/x.htm")));
+
+        let r = rule("allow https://*/**");
+        assert!(r.matches(&top("https://anything:1234/")));
+        assert!(!r.matches(&top("https://anything")));
+        let r = rule("allow *://localhost:8080");
+        assert!(r.matches(&top("http://localhost:8080/")));
+        assert!(!r.matches(&top("http://localhost/")));
+        assert!(rule("allow https://*:443").matches(&top("https://a.com/")));
+
+        assert!(rule("allow about:").matches(&top("about:blank")));
+        assert!(rule("allow about:blank").matches(&top("about:blank#x")));
+        assert!(!rule("allow about:blank").matches(&top("about:srcdoc")));
+        assert!(rule("allow data:").matches(&top("data:text/html,<p>")));
+        assert!(rule("allow file://*/C:/app/**").matches(&top("file:///C:/app/index.html")));
+
+        let r = rule("deny * user redirect frame");
+        let frame = Navigation {
+            kind: NavigationKind::Frame,
+            user_initiated: true,
+            redirect: true,
+            ..top("whatever")
+        };
+        assert!(r.matches(&frame));
+        assert!(!r.matches(&Navigation {
+            redirect: false,
+            ..frame
+        }));
+        assert!(!r.matches(&Navigation {
+            user_initiated: false,
+            ..frame
+        }));
+        assert!(!r.matches(&Navigation {
+            kind: NavigationKind::NewWindow,
+            ..frame
+        }));
+    }
+
+    #[test]
+    fn test_policy() {
+        let denied = Rc::new(RefCell::new(Vec::new()));
+        let d = denied.clone();
+        let policy = NavigationPolicy::new()
+            .with_rule(rule("deny https://app.example.com/admin/**"))
+            .with_rule(rule("allow https://app.example.com"))
+            .with_rule(rule("allow about:blank"))
+            .with_open_externally(true)
+            .with_denial_handler(move |n, decision| {
+                d.borrow_mut().push((n.uri.to_string(), decision))
+            });
+        assert_eq!(policy.rules().len(), 3);
+
+        let check = |uri, kind| {
+            policy.check(&Navigation {
+                kind,
+                user_initiated: true,
+                ..top(uri)
+            })
+        };
+        assert_eq!(
+            check("https://app.example.com/", NavigationKind::TopLevel),
+            NavigationDecision::Allow
+        );
+        assert_eq!(
+            check("about:blank", NavigationKind::Frame),
+            NavigationDecision::Allow
+        );
+        // First match wins.
+        assert_eq!(
+            check("https://app.example.com/admin/", NavigationKind::TopLevel),
+            NavigationDecision::OpenExternally
+        );
+        assert_eq!(
+            check("mailto:a@example.com", NavigationKind::NewWindow),
+            NavigationDecision::OpenExternally
+        );
+        // Not for frames, or other schemes.
+        assert_eq!(
+            check("https://ads.example.net/", NavigationKind::Frame),
+            NavigationDecision::Deny
+        );
+        assert_eq!(
+            check("file:///C:/Windows/", NavigationKind::NewWindow),
+            NavigationDecision::Deny
+        );
+        // Nor for navigations the user did not start, or redirects.
+        let elsewhere = Navigation {
+            user_initiated: true,
+            ..top("https://example.org/")
+        };
+        assert_eq!(
+            policy.decide(&elsewhere),
+            NavigationDecision::OpenExternally
+        );
+        let not_user = Navigation {
+            user_initiated: false,
+            ..elsewhere
+        };
+        assert_eq!(policy.decide(&not_user), NavigationDecision::Deny);
+        let redirect = Navigation {
+            redirect: true,
+            ..elsewhere
+        };
+        assert_eq!(policy.decide(&redirect), NavigationDecision::Deny);
+        assert_eq!(denied.borrow().len(), 4);
+        assert_eq!(
+            denied.borrow()[3],
+            ("file:///C:/Windows/".to_string(), NavigationDecision::Deny)
+        );
+
+        let open = NavigationPolicy::new().with_default(NavigationAction::Allow);
+        assert_eq!(open.decide(&top("anything")), NavigationDecision::Allow);
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_matches("/", "/"));
+        assert!(!glob_matches("/", ""));
+        assert!(glob_matches("/*", "/"));
+        assert!(glob_matches("/a*c", "/abbc"));
+        assert!(!glob_matches("/a*c", "/a/c"));
+        assert!(glob_matches("/a**c", "/a/c"));
+        assert!(glob_matches("/**/x", "/a/b/x"));
+        assert!(!glob_matches("/**/x", "/x"));
+        assert_eq!(uri_path("https://a.com"), "");
+        assert_eq!(uri_path("https://a.com?q=/x"), "");
+        assert_eq!(uri_path("https://a.com/p/q?x#y"), "/p/q");
+        assert_eq!(uri_path("about:blank#x"), "blank");
+    }
+
+    // The obvious exponential implementation.
+    fn naive_glob(glob: &[char], path: &[char]) -> bool {
+        match glob.split_first() {
+            None => path.is_empty(),
+            Some((&'*', rest)) => {
+                let (any, rest) = match rest.split_first() {
+                    Some((&'*', rest)) => (true, rest),
+                    _ => (false, rest),
+                };
+                (0..=path.len())
+                    .take_while(|&i| any || i == 0 || path[i - 1] != '/')
+                    .any(|i| naive_glob(rest, &path[i..]))
+            }
+            Some((c, rest)) => path.first() == Some(c) && naive_glob(rest, &path[1..]),
+        }
+    }
+
+    fn arb_rule() -> impl Strategy<Value = String> {
+        (
+            prop_oneof![Just("allow"), Just("deny")],
+            prop_oneof![
+                Just("*".to_string()),
+                "[a-z][a-z0-9+.-]{0,5}:",
+                (
+                    prop_oneof![Just("*".to_string()), "[a-z]{1,5}"],
+                    prop_oneof![
+                        Just("*".to_string()),
+                        "[a-z0-9.-]{1,8}",
+                        "\\*\\.[a-z0-9.-]{1,8}"
+                    ],
+                    proptest::option::of(any::<u16>()),
+                    proptest::option::of("/[a-z*/.]{0,8}"),
+                )
+                    .prop_map(|(scheme, host, port, path)| {
+                        let port = port.map_or(String::new(), |p| format!(":{}", p));
+                        format!("{}://{}{}{}", scheme, host, port, path.unwrap_or_default())
+                    }),
+            ],
+            proptest::sample::subsequence(
+                vec!["user", "!redirect", "top", "frame", "new-window"],
+                0..=5,
+            ),
+        )
+            .prop_map(|(action, pattern, flags)| {
+                format!("{} {} {}", action, pattern, flags.join(" "))
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_any(s in any::<String>()) {
+            if let Ok(rule) = s.parse::<NavigationRule>() {
+                prop_assert_eq!(rule.to_string().parse::<NavigationRule>().unwrap(), rule);
+            }
+        }
+
+        #[test]
+        fn prop_round_trip(s in arb_rule(), uri in "[a-z]{1,5}://[a-z0-9.]{0,8}(:[0-9]{1,5})?[/a-z.]{0,8}") {
+            // Generated rules can still be invalid, e.g. with bad ports.
+            if let Ok(rule) = s.parse::<NavigationRule>() {
+                let reparsed: NavigationRule = rule.to_string().parse().unwrap();
+                prop_assert_eq!(&reparsed, &rule);
+                let navigation = top(&uri);
+                prop_assert_eq!(reparsed.matches(&navigation), rule.matches(&navigation));
+            }
+        }
+
+        #[test]
+        fn prop_decide_any(uri in any::<String>(), user_initiated in any::<bool>()) {
+            let navigation = Navigation { user_initiated, ..top(&uri) };
+            let policy = NavigationPolicy::new()
+                .with_rule(rule("deny https://*.example.com/**/x"))
+                .with_rule(rule("allow * user"))
+                .with_open_externally(true);
+            let decision = policy.decide(&navigation);
+            prop_assert_eq!(decision == NavigationDecision::Allow, user_initiated && !rule("deny https://*.example.com/**/x").matches(&navigation));
+        }
+
+        #[test]
+        fn prop_glob(glob in "[a/*]{0,8}", path in "[ab/]{0,8}") {
+            let g: Vec<char> = glob.chars().collect();
+            let p: Vec<char> = path.chars().collect();
+            prop_assert_eq!(glob_matches(&glob, &path), naive_glob(&g, &p));
+        }
+    }
+}
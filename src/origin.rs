@@ -0,0 +1,493 @@
+// Origin allowlists for web messages.
+
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// The origin of a URI: scheme, host and port.
+///
+/// URIs without an authority, e.g. `about:blank` or `data:...`, have an
+/// empty host.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Origin {
+    pub scheme: String,
+    pub host: String,
+    /// `None` for the default port of the scheme.
+    pub port: Option<u16>,
+}
+
+pub(crate) fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        _ => None,
+    }
+}
+
+fn valid_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+// Split `scheme:rest`, with the scheme lowercased.
+pub(crate) fn split_scheme(uri: &str) -> Option<(String, &str)> {
+    let colon = uri.find(':')?;
+    let scheme = &uri[..colon];
+    if !valid_scheme(scheme) {
+        return None;
+    }
+    Some((scheme.to_ascii_lowercase(), &uri[colon + 1..]))
+}
+
+// Parse `host[:port]`, lowercasing the host. IPv6 hosts keep their brackets.
+pub(crate) fn split_host_port(authority: &str) -> Option<(String, Option<u16>)> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority.find(']')?;
+        let rest = &authority[end + 1..];
+        let port = match rest {
+            "" => None,
+            _ if rest.starts_with(':') => Some(&rest[1..]),
+            _ => return None,
+        };
+        (&authority[..=end], port)
+    } else {
+        match authority.rfind(':') {
+            Some(i) => (&authority[..i], Some(&authority[i + 1..])),
+            None => (authority, None),
+        }
+    };
+    if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c == '/' || c == '@') {
+        return None;
+    }
+    let port = match port {
+        // `http://host:/` has the default port.
+        None | Some("") => None,
+        Some(port) => Some(port.parse().ok()?),
+    };
+    Some((host.to_ascii_lowercase(), port))
+}
+
+impl Origin {
+    /// The origin of an absolute URI, or `None` if it is not one.
+    pub fn parse(uri: &str) -> Option<Self> {
+        let (scheme, rest) = split_scheme(uri.trim())?;
+        if !rest.starts_with("//") {
+            return Some(Self {
+                scheme,
+                host: String::new(),
+                port: None,
+            });
+        }
+        let rest = &rest[2..];
+        let authority = &rest[..rest.find(&['/', '?', '#'][..]).unwrap_or(rest.len())];
+        // Drop user info.
+        let authority = &authority[authority.rfind('@').map_or(0, |i| i + 1)..];
+        if authority.is_empty() {
+            // E.g. `file:///C:/index.html`.
+            return Some(Self {
+                scheme,
+                host: String::new(),
+                port: None,
+            });
+        }
+        let (host, port) = split_host_port(authority)?;
+        let port = if port == default_port(&scheme) {
+            None
+        } else {
+            port
+        };
+        Some(Self { scheme, host, port })
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.is_empty() {
+            return write!(f, "{}:", self.scheme);
+        }
+        write!(f, "{}://{}", self.scheme, self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+/// A rule of an `OriginAllowlist`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OriginRule {
+    /// Exactly this origin, e.g. `https://app.example.com`.
+    Exact(Origin),
+    /// Any subdomain of a domain, e.g. `https://*.example.com`. The domain
+    /// itself is not matched.
+    Subdomains {
+        scheme: String,
+        domain: String,
+        port: Option<u16>,
+    },
+    /// Any URI with the scheme, e.g. `app:`.
+    Scheme(String),
+}
+
+/// Error parsing an `OriginRule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidOriginRule(pub String);
+
+impl fmt::Display for InvalidOriginRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid origin rule: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidOriginRule {}
+
+impl FromStr for OriginRule {
+    type Err = InvalidOriginRule;
+
+    /// Parse `scheme:`, `scheme://*.domain[:port]` or an origin. A path is
+    /// not allowed.
+    // `strip_prefix` needs Rust 1.45.
+    #[allow(clippy::manual_strip)]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidOriginRule(s.into());
+        let (scheme, rest) = split_scheme(s).ok_or_else(invalid)?;
+        if rest.is_empty() {
+            return Ok(OriginRule::Scheme(scheme));
+        }
+        if !rest.starts_with("//") {
+            return Err(invalid());
+        }
+        let authority = rest[2..].trim_end_matches('/');
+        if authority.contains(&['/', '?', '#', '@'][..]) {
+            return Err(invalid());
+        }
+        if authority.starts_with("*.") {
+            let (domain, port) = split_host_port(&authority[2..]).ok_or_else(invalid)?;
+            if domain.contains('*') {
+                return Err(invalid());
+            }
+            let port = if port == default_port(&scheme) {
+                None
+            } else {
+                port
+            };
+            return Ok(OriginRule::Subdomains {
+                scheme,
+                domain,
+                port,
+            });
+        }
+        let origin = Origin::parse(s).ok_or_else(invalid)?;
+        if origin.host.is_empty() || origin.host.contains('*') {
+            return Err(invalid());
+        }
+        Ok(OriginRule::Exact(origin))
+    }
+}
+
+impl OriginRule {
+    pub fn matches(&self, origin: &Origin) -> bool {
+        match self {
+            OriginRule::Exact(o) => o == origin,
+            OriginRule::Subdomains {
+                scheme,
+                domain,
+                port,
+            } => {
+                *scheme == origin.scheme
+                    && *port == origin.port
+                    && origin.host.len() > domain.len() + 1
+                    && origin.host.ends_with(domain.as_str())
+                    && origin.host[..origin.host.len() - domain.len()].ends_with('.')
+            }
+            OriginRule::Scheme(scheme) => *scheme == origin.scheme,
+        }
+    }
+}
+
+/// Which way a rejected message was going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageDirection {
+    /// A message received from the page was dropped.
+    Incoming,
+    /// A message to the page was not posted.
+    Outgoing,
+}
+
+/// Origins that are trusted to exchange web messages.
+///
+/// ```
+/// use webview2::OriginAllowlist;
+///
+/// let origins = OriginAllowlist::new()
+///     .with_rule("https://app.example.com".parse().unwrap())
+///     .with_rule("https://*.cdn.example.com".parse().unwrap())
+///     .with_rule("app:".parse().unwrap());
+/// assert!(origins.is_allowed("https://APP.example.com:443/index.html"));
+/// assert!(origins.is_allowed("https://eu.cdn.example.com/frame.html"));
+/// assert!(origins.is_allowed("app://index.html"));
+/// assert!(!origins.is_allowed("http://app.example.com/"));
+/// assert!(!origins.is_allowed("https://cdn.example.com/"));
+/// ```
+#[derive(Clone, Default)]
+pub struct OriginAllowlist {
+    rules: Vec<OriginRule>,
+    on_rejected: Option<Rc<dyn Fn(&str, MessageDirection)>>,
+}
+
+impl fmt::Debug for OriginAllowlist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OriginAllowlist")
+            .field("rules", &self.rules)
+            .finish()
+    }
+}
+
+impl OriginAllowlist {
+    /// An allowlist that allows nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: OriginRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Called with the source URI of every dropped or suppressed message.
+    pub fn with_rejection_handler(
+        mut self,
+        on_rejected: impl Fn(&str, MessageDirection) + 'static,
+    ) -> Self {
+        self.on_rejected = Some(Rc::new(on_rejected));
+        self
+    }
+
+    pub fn rules(&self) -> &[OriginRule] {
+        &self.rules
+    }
+
+    /// Whether the origin of `uri` matches a rule. URIs that can't be
+    /// parsed are not allowed.
+    pub fn is_allowed(&self, uri: &str) -> bool {
+        match Origin::parse(uri) {
+            Some(origin) => self.rules.iter().any(|r| r.matches(&origin)),
+            None => false,
+        }
+    }
+
+    /// Like `is_allowed`, but report rejected URIs.
+    pub fn check(&self, uri: &str, direction: MessageDirection) -> bool {
+        let allowed = self.is_allowed(uri);
+        if !allowed {
+            if let Some(ref on_rejected) = self.on_rejected {
+                on_rejected(uri, direction);
+            }
+        }
+        allowed
+    }
+}
+
+#[cfg(windows)]
+impl OriginAllowlist {
+    /// Like `WebView::add_web_message_received`, but messages from other
+    /// origins are dropped.
+    pub fn add_web_message_received(
+        &self,
+        webview: &crate::WebView,
+        handler: impl Fn(crate::WebView, crate::WebMessageReceivedEventArgs) -> crate::Result<()>
+            + 'static,
+    ) -> crate::Result<crate::EventRegistrationToken> {
+        let origins = self.clone();
+        webview.add_web_message_received(move |webview, args| {
+            if origins.check(&args.get_source()?, MessageDirection::Incoming) {
+                handler(webview, args)
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    /// Post a JSON message if the current document is trusted. Returns
+    /// whether it was posted.
+    pub fn post_web_message_as_json(
+        &self,
+        webview: &crate::WebView,
+        json: &str,
+    ) -> crate::Result<bool> {
+        if !self.check(&webview.get_source()?, MessageDirection::Outgoing) {
+            return Ok(false);
+        }
+        webview.post_web_message_as_json(json)?;
+        Ok(true)
+    }
+
+    /// Post a string message if the current document is trusted. Returns
+    /// whether it was posted.
+    pub fn post_web_message_as_string(
+        &self,
+        webview: &crate::WebView,
+        message: &str,
+    ) -> crate::Result<bool> {
+        if !self.check(&webview.get_source()?, MessageDirection::Outgoing) {
+            return Ok(false);
+        }
+        webview.post_web_message_as_string(message)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn origin(scheme: &str, host: &str, port: Option<u16>) -> Origin {
+        Origin {
+            scheme: scheme.into(),
+            host: host.into(),
+            port,
+        }
+    }
+
+    #[test]
+    fn test_parse_origin() {
+        let cases = vec![
+            (
+                "https://Example.COM/a?b#c",
+                origin("https", "example.com", None),
+            ),
+            (
+                "https://example.com:443",
+                origin("https", "example.com", None),
+            ),
+            (
+                "http://example.com:8080/",
+                origin("http", "example.com", Some(8080)),
+            ),
+            ("http://example.com:/", origin("http", "example.com", None)),
+            (
+                "https://user:pw@example.com/",
+                origin("https", "example.com", None),
+            ),
+            ("http://[::1]:3000/x", origin("http", "[::1]", Some(3000))),
+            ("HTTP://127.0.0.1", origin("http", "127.0.0.1", None)),
+            ("app://index.html/", origin("app", "index.html", None)),
+            ("file:///C:/index.html", origin("file", "", None)),
+            ("about:blank", origin("about", "", None)),
+            ("data:text/html,<p>", origin("data", "", None)),
+        ];
+        for (uri, expected) in cases {
+            assert_eq!(Origin::parse(uri).as_ref(), Some(&expected), "{}", uri);
+        }
+        for uri in &[
+            "",
+            "example.com",
+            "/path",
+            "1http://x",
+            "http://x:99999",
+            "http://[::1",
+            "http://a b/",
+        ] {
+            assert_eq!(Origin::parse(uri), None, "{}", uri);
+        }
+        assert_eq!(
+            Origin::parse("http://[::1]:3000/").unwrap().to_string(),
+            "http://[::1]:3000"
+        );
+        assert_eq!(Origin::parse("about:blank").unwrap().to_string(), "about:");
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(
+            "https://*.example.com:443".parse(),
+            Ok(OriginRule::Subdomains {
+                scheme: "https".into(),
+                domain: "example.com".into(),
+                port: None,
+            })
+        );
+        assert_eq!("APP:".parse(), Ok(OriginRule::Scheme("app".into())));
+        assert_eq!(
+            "http://localhost:8080/".parse(),
+            Ok(OriginRule::Exact(origin("http", "localhost", Some(8080))))
+        );
+        for rule in &[
+            "example.com",
+            "https://",
+            "https://example.com/path",
+            "https://a.*.example.com",
+            "https://*",
+            "https://*.",
+            "https://ex*ample.com",
+            "about:blank",
+        ] {
+            assert!(rule.parse::<OriginRule>().is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn test_matching() {
+        let allowlist = OriginAllowlist::new()
+            .with_rule("https://app.example.com".parse().unwrap())
+            .with_rule("https://*.example.org".parse().unwrap())
+            .with_rule("http://localhost:3000".parse().unwrap())
+            .with_rule("file:".parse().unwrap());
+
+        for uri in &[
+            "https://app.example.com/",
+            "https://APP.example.com:443/x",
+            "https://a.example.org/",
+            "https://a.b.example.org/",
+            "http://localhost:3000/index.html",
+            "file:///C:/app/index.html",
+        ] {
+            assert!(allowlist.is_allowed(uri), "{}", uri);
+        }
+        for uri in &[
+            "http://app.example.com/",
+            "https://app.example.com:8443/",
+            "https://evil-app.example.com/",
+            "https://example.org/",
+            "https://badexample.org/",
+            "https://example.org.evil.com/",
+            "http://a.example.org/",
+            "http://localhost/",
+            "http://localhost:3001/",
+            "about:blank",
+            "not a uri",
+        ] {
+            assert!(!allowlist.is_allowed(uri), "{}", uri);
+        }
+        assert!(!OriginAllowlist::new().is_allowed("https://app.example.com/"));
+    }
+
+    #[test]
+    fn test_rejection_handler() {
+        let rejected = Rc::new(RefCell::new(Vec::new()));
+        let r = Rc::clone(&rejected);
+        let allowlist = OriginAllowlist::new()
+            .with_rule("https://app.example.com".parse().unwrap())
+            .with_rejection_handler(move |uri, direction| {
+                r.borrow_mut().push((uri.to_string(), direction))
+            });
+        assert!(allowlist.check("https://app.example.com/", MessageDirection::Incoming));
+        assert!(!allowlist.check("https://ads.example.net/", MessageDirection::Incoming));
+        assert!(!allowlist.check("about:blank", MessageDirection::Outgoing));
+        assert_eq!(
+            *rejected.borrow(),
+            vec![
+                (
+                    "https://ads.example.net/".to_string(),
+                    MessageDirection::Incoming
+                ),
+                ("about:blank".to_string(), MessageDirection::Outgoing),
+            ]
+        );
+    }
+}
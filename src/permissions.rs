@@ -0,0 +1,819 @@
+// Deciding permission requests, and remembering decisions per origin.
+
+use crate::origin::{Origin, OriginRule};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A permission a page can request, like `PermissionKind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    Unknown,
+    Microphone,
+    Camera,
+    Geolocation,
+    Notifications,
+    OtherSensors,
+    ClipboardRead,
+}
+
+/// An answer to a permission request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PermissionDecision {
+    Allow,
+    Deny,
+}
+
+/// A permission request, from `PermissionRequested`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionRequest {
+    pub uri: String,
+    pub permission: Permission,
+    pub user_initiated: bool,
+}
+
+/// A fixed decision for some origins and permissions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionRule {
+    /// `None` for all origins.
+    pub origin: Option<OriginRule>,
+    /// `None` for all permissions.
+    pub permission: Option<Permission>,
+    pub decision: PermissionDecision,
+}
+
+impl PermissionRule {
+    /// A rule for all origins and permissions.
+    pub fn new(decision: PermissionDecision) -> Self {
+        Self {
+            origin: None,
+            permission: None,
+            decision,
+        }
+    }
+
+    pub fn with_origin(mut self, origin: OriginRule) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    pub fn with_permission(mut self, permission: Permission) -> Self {
+        self.permission = Some(permission);
+        self
+    }
+
+    /// Whether the rule applies. Without an origin, e.g. for URIs that
+    /// can't be parsed, only rules for all origins apply.
+    pub fn matches(&self, origin: Option<&Origin>, permission: Permission) -> bool {
+        if self.permission.is_some() && self.permission != Some(permission) {
+            return false;
+        }
+        match (&self.origin, origin) {
+            (None, _) => true,
+            (Some(rule), Some(origin)) => rule.matches(origin),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+fn unix_seconds(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// A remembered decision. Times are seconds since the Unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredDecision {
+    /// Serialized, e.g. `https://example.com:8443`.
+    pub origin: String,
+    pub permission: Permission,
+    pub decision: PermissionDecision,
+    pub decided_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl StoredDecision {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        match self.expires_at {
+            Some(expires_at) => unix_seconds(now) >= expires_at,
+            None => false,
+        }
+    }
+}
+
+/// Remembered decisions, by origin and permission. Saved as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionStore {
+    decisions: Vec<StoredDecision>,
+}
+
+impl PermissionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail")
+    }
+
+    /// Load a store, or an empty one if the file does not exist.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => {
+                Self::from_json(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the store. It is written to a temporary file next to `path`
+    /// first, which then replaces the file, so that a failed save leaves the
+    /// previous decisions intact.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let result = fs::File::create(&temp)
+            .and_then(|mut file| {
+                file.write_all(self.to_json().as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    fn position(&self, origin: &str, permission: Permission) -> Option<usize> {
+        self.decisions
+            .iter()
+            .position(|d| d.origin == origin && d.permission == permission)
+    }
+
+    /// The decision for an origin, unless there is none or it has expired.
+    pub fn get(
+        &self,
+        origin: &Origin,
+        permission: Permission,
+        now: SystemTime,
+    ) -> Option<PermissionDecision> {
+        let i = self.position(&origin.to_string(), permission)?;
+        let stored = &self.decisions[i];
+        if stored.is_expired(now) {
+            None
+        } else {
+            Some(stored.decision)
+        }
+    }
+
+    /// Remember a decision, replacing any previous one.
+    pub fn insert(
+        &mut self,
+        origin: &Origin,
+        permission: Permission,
+        decision: PermissionDecision,
+        now: SystemTime,
+        expiry: Option<Duration>,
+    ) {
+        let stored = StoredDecision {
+            origin: origin.to_string(),
+            permission,
+            decision,
+            decided_at: unix_seconds(now),
+            expires_at: expiry.map(|e| unix_seconds(now + e)),
+        };
+        match self.position(&stored.origin, permission) {
+            Some(i) => self.decisions[i] = stored,
+            None => self.decisions.push(stored),
+        }
+    }
+
+    /// Forget the decision for a permission of an origin. Returns whether
+    /// there was one.
+    pub fn revoke(&mut self, origin: &Origin, permission: Permission) -> bool {
+        match self.position(&origin.to_string(), permission) {
+            Some(i) => {
+                self.decisions.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget all decisions for an origin. Returns how many there were.
+    pub fn revoke_origin(&mut self, origin: &Origin) -> usize {
+        let origin = origin.to_string();
+        let len = self.decisions.len();
+        self.decisions.retain(|d| d.origin != origin);
+        len - self.decisions.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.decisions.clear();
+    }
+
+    /// Drop expired decisions. Returns how many there were.
+    pub fn purge_expired(&mut self, now: SystemTime) -> usize {
+        let len = self.decisions.len();
+        self.decisions.retain(|d| !d.is_expired(now));
+        len - self.decisions.len()
+    }
+
+    pub fn decisions(&self) -> &[StoredDecision] {
+        &self.decisions
+    }
+}
+
+/// Answers a permission request that was passed to the prompt.
+///
+/// Dropping it without answering leaves the request to the browser.
+#[must_use = "the request is left to the browser when this is dropped"]
+pub struct PermissionResponder {
+    respond: Option<Box<dyn FnOnce(Option<PermissionDecision>, bool) -> io::Result<()>>>,
+}
+
+impl PermissionResponder {
+    /// Answer, and remember the decision for the origin if `remember`.
+    /// Fails if saving the decision does, the request is answered anyway.
+    pub fn respond(mut self, decision: PermissionDecision, remember: bool) -> io::Result<()> {
+        match self.respond.take() {
+            Some(respond) => respond(Some(decision), remember),
+            None => Ok(()),
+        }
+    }
+
+    /// Let the browser decide, usually by asking the user.
+    pub fn defer_to_browser(mut self) {
+        if let Some(respond) = self.respond.take() {
+            let _ = respond(None, false);
+        }
+    }
+}
+
+impl Drop for PermissionResponder {
+    fn drop(&mut self) {
+        if let Some(respond) = self.respond.take() {
+            let _ = respond(None, false);
+        }
+    }
+}
+
+impl fmt::Debug for PermissionResponder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PermissionResponder").finish()
+    }
+}
+
+struct Inner {
+    rules: Vec<PermissionRule>,
+    store: PermissionStore,
+    path: Option<PathBuf>,
+    expiry: Option<Duration>,
+    prompt: Option<Rc<dyn Fn(PermissionRequest, PermissionResponder)>>,
+    clock: Rc<dyn Fn() -> SystemTime>,
+}
+
+/// Decides permission requests.
+///
+/// Rules are checked first, in order, then remembered decisions. Otherwise
+/// the prompt is asked, or the browser decides. Clones share the same
+/// state. On Windows, use `attach` to handle the requests of a `WebView`.
+///
+/// ```
+/// use webview2::{Permission, PermissionDecision, PermissionManager, PermissionRule};
+///
+/// let manager = PermissionManager::new()
+///     .with_rule(
+///         PermissionRule::new(PermissionDecision::Deny).with_permission(Permission::Camera),
+///     )
+///     .with_prompt(|_request, responder| {
+///         if let Err(e) = responder.respond(PermissionDecision::Allow, true) {
+///             eprintln!("saving permission decisions: {}", e);
+///         }
+///     });
+/// assert_eq!(
+///     manager.decide("https://example.com/", Permission::Camera),
+///     Some(PermissionDecision::Deny)
+/// );
+/// assert_eq!(manager.decide("https://example.com/", Permission::Microphone), None);
+/// ```
+#[derive(Clone)]
+pub struct PermissionManager {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl fmt::Debug for PermissionManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.borrow();
+        f.debug_struct("PermissionManager")
+            .field("rules", &inner.rules)
+            .field("store", &inner.store)
+            .field("path", &inner.path)
+            .field("expiry", &inner.expiry)
+            .finish()
+    }
+}
+
+impl Default for PermissionManager {
+    fn default() -> Self {
+        Self::with_store(PermissionStore::new())
+    }
+}
+
+impl PermissionManager {
+    /// A manager without rules that does not persist decisions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_store(store: PermissionStore) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                rules: Vec::new(),
+                store,
+                path: None,
+                expiry: None,
+                prompt: None,
+                clock: Rc::new(SystemTime::now),
+            })),
+        }
+    }
+
+    /// A manager that loads decisions from and saves them to a JSON file.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let manager = Self::with_store(PermissionStore::load(&path)?);
+        manager.inner.borrow_mut().path = Some(path);
+        Ok(manager)
+    }
+
+    pub fn with_rule(self, rule: PermissionRule) -> Self {
+        self.inner.borrow_mut().rules.push(rule);
+        self
+    }
+
+    /// Forget remembered decisions after this long.
+    pub fn with_expiry(self, expiry: Duration) -> Self {
+        self.inner.borrow_mut().expiry = Some(expiry);
+        self
+    }
+
+    /// Called for requests without a rule or remembered decision. The
+    /// responder can be kept to answer later.
+    pub fn with_prompt(
+        self,
+        prompt: impl Fn(PermissionRequest, PermissionResponder) + 'static,
+    ) -> Self {
+        self.inner.borrow_mut().prompt = Some(Rc::new(prompt));
+        self
+    }
+
+    /// The current time, `SystemTime::now` by default.
+    pub fn with_clock(self, clock: impl Fn() -> SystemTime + 'static) -> Self {
+        self.inner.borrow_mut().clock = Rc::new(clock);
+        self
+    }
+
+    /// A copy of the remembered decisions.
+    pub fn store(&self) -> PermissionStore {
+        self.inner.borrow().store.clone()
+    }
+
+    /// The decision of the rules or the store, if any.
+    pub fn decide(&self, uri: &str, permission: Permission) -> Option<PermissionDecision> {
+        let inner = self.inner.borrow();
+        let origin = Origin::parse(uri);
+        if let Some(rule) = inner
+            .rules
+            .iter()
+            .find(|r| r.matches(origin.as_ref(), permission))
+        {
+            return Some(rule.decision);
+        }
+        inner.store.get(&origin?, permission, (inner.clock)())
+    }
+
+    // Change the store and save it.
+    fn update<R>(
+        &self,
+        f: impl FnOnce(&mut PermissionStore, SystemTime, Option<Duration>) -> R,
+    ) -> io::Result<R> {
+        let mut inner = self.inner.borrow_mut();
+        let now = (inner.clock)();
+        let expiry = inner.expiry;
+        let r = f(&mut inner.store, now, expiry);
+        if let Some(ref path) = inner.path {
+            inner.store.save(path)?;
+        }
+        Ok(r)
+    }
+
+    /// Remember a decision for the origin of `uri`. Returns `false` if it
+    /// has no origin.
+    pub fn remember(
+        &self,
+        uri: &str,
+        permission: Permission,
+        decision: PermissionDecision,
+    ) -> io::Result<bool> {
+        let origin = match Origin::parse(uri) {
+            Some(origin) => origin,
+            None => return Ok(false),
+        };
+        self.update(|store, now, expiry| {
+            store.insert(&origin, permission, decision, now, expiry);
+            true
+        })
+    }
+
+    /// Forget the decision for a permission of the origin of `uri`.
+    pub fn revoke(&self, uri: &str, permission: Permission) -> io::Result<bool> {
+        match Origin::parse(uri) {
+            Some(origin) => self.update(|store, _, _| store.revoke(&origin, permission)),
+            None => Ok(false),
+        }
+    }
+
+    /// Forget all decisions for the origin of `uri`.
+    pub fn revoke_origin(&self, uri: &str) -> io::Result<usize> {
+        match Origin::parse(uri) {
+            Some(origin) => self.update(|store, _, _| store.revoke_origin(&origin)),
+            None => Ok(0),
+        }
+    }
+
+    pub fn revoke_all(&self) -> io::Result<()> {
+        self.update(|store, _, _| store.clear())
+    }
+
+    /// Drop expired decisions from the store.
+    pub fn purge_expired(&self) -> io::Result<usize> {
+        self.update(|store, now, _| store.purge_expired(now))
+    }
+
+    /// Decide a request. `respond` is called with the decision, or `None`
+    /// to leave it to the browser, either right away or when the prompt
+    /// answers.
+    pub fn request(
+        &self,
+        request: PermissionRequest,
+        respond: impl FnOnce(Option<PermissionDecision>) + 'static,
+    ) {
+        if let Some(decision) = self.decide(&request.uri, request.permission) {
+            respond(Some(decision));
+            return;
+        }
+        let prompt = self.inner.borrow().prompt.clone();
+        let prompt = match prompt {
+            Some(prompt) => prompt,
+            None => {
+                respond(None);
+                return;
+            }
+        };
+        let manager = self.clone();
+        let uri = request.uri.clone();
+        let permission = request.permission;
+        let responder = PermissionResponder {
+            respond: Some(Box::new(move |decision, remember| {
+                let saved = match (decision, remember) {
+                    (Some(decision), true) => {
+                        manager.remember(&uri, permission, decision).map(drop)
+                    }
+                    _ => Ok(()),
+                };
+                respond(decision);
+                saved
+            })),
+        };
+        prompt(request, responder);
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::*;
+    use std::cell::Cell;
+
+    impl From<PermissionKind> for Permission {
+        fn from(kind: PermissionKind) -> Self {
+            match kind {
+                PermissionKind::UnknownPermission => Permission::Unknown,
+                PermissionKind::Microphone => Permission::Microphone,
+                PermissionKind::Camera => Permission::Camera,
+                PermissionKind::Geolocation => Permission::Geolocation,
+                PermissionKind::Notifications => Permission::Notifications,
+                PermissionKind::OtherSensors => Permission::OtherSensors,
+                PermissionKind::ClipboardRead => Permission::ClipboardRead,
+            }
+        }
+    }
+
+    impl PermissionManager {
+        /// Decide the permission requests of `webview`. Requests the
+        /// prompt answers later are deferred until then.
+        pub fn attach(&self, webview: &WebView) -> Result<Subscription> {
+            let manager = self.clone();
+            webview.on_permission_requested(move |_, args| {
+                let request = PermissionRequest {
+                    uri: args.get_uri()?,
+                    permission: args.get_permission_kind()?.into(),
+                    user_initiated: args.get_is_user_initiated()?,
+                };
+                let answered = Rc::new(Cell::new(false));
+                let deferral = Rc::new(RefCell::new(None::<Deferral>));
+                let (a, d, args1) = (answered.clone(), deferral.clone(), args.clone());
+                manager.request(request, move |decision| {
+                    a.set(true);
+                    let state = match decision {
+                        Some(PermissionDecision::Allow) => PermissionState::Allow,
+                        Some(PermissionDecision::Deny) => PermissionState::Deny,
+                        None => PermissionState::Default,
+                    };
+                    let _ = args1.put_state(state);
+                    if let Some(deferral) = d.borrow_mut().take() {
+                        let _ = deferral.complete();
+                    }
+                });
+                if !answered.get() {
+                    *deferral.borrow_mut() = Some(args.get_deferral()?);
+                }
+                Ok(())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn origin(uri: &str) -> Origin {
+        Origin::parse(uri).unwrap()
+    }
+
+    #[test]
+    fn test_store() {
+        let mut store = PermissionStore::new();
+        let a = origin("https://a.example.com/page");
+        store.insert(
+            &a,
+            Permission::Camera,
+            PermissionDecision::Allow,
+            at(100),
+            None,
+        );
+        store.insert(
+            &a,
+            Permission::Geolocation,
+            PermissionDecision::Deny,
+            at(100),
+            Some(Duration::from_secs(50)),
+        );
+        // Same origin.
+        let a2 = origin("https://A.example.com:443/other");
+        assert_eq!(
+            store.get(&a2, Permission::Camera, at(1000)),
+            Some(PermissionDecision::Allow)
+        );
+        assert_eq!(
+            store.get(&a, Permission::Geolocation, at(149)),
+            Some(PermissionDecision::Deny)
+        );
+        assert_eq!(store.get(&a, Permission::Geolocation, at(150)), None);
+        assert_eq!(
+            store.get(&origin("http://a.example.com"), Permission::Camera, at(100)),
+            None
+        );
+
+        // Replaced.
+        store.insert(
+            &a,
+            Permission::Camera,
+            PermissionDecision::Deny,
+            at(200),
+            None,
+        );
+        assert_eq!(store.decisions().len(), 2);
+        assert_eq!(store.decisions()[0].decided_at, 200);
+
+        let json = store.to_json();
+        assert!(json.contains(r#""permission": "camera""#), "{}", json);
+        let loaded = PermissionStore::from_json(&json).unwrap();
+        assert_eq!(loaded, store);
+
+        assert_eq!(store.purge_expired(at(150)), 1);
+        assert!(store.revoke(&a, Permission::Camera));
+        assert!(!store.revoke(&a, Permission::Camera));
+        store.insert(
+            &a,
+            Permission::Microphone,
+            PermissionDecision::Allow,
+            at(0),
+            None,
+        );
+        store.insert(
+            &origin("app://x"),
+            Permission::Microphone,
+            PermissionDecision::Allow,
+            at(0),
+            None,
+        );
+        assert_eq!(store.revoke_origin(&a), 1);
+        assert_eq!(store.decisions()[0].origin, "app://x");
+
+        assert!(PermissionStore::from_json(r#"{"decisions":[{"origin":"x"}]}"#).is_err());
+    }
+
+    #[test]
+    fn test_persistence() {
+        let path =
+            std::env::temp_dir().join(format!("webview2-permissions-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let manager = PermissionManager::open(&path).unwrap();
+        assert!(manager.store().decisions().is_empty());
+        assert!(manager
+            .remember(
+                "https://example.com/",
+                Permission::Camera,
+                PermissionDecision::Allow
+            )
+            .unwrap());
+        assert!(!manager
+            .remember("not a uri", Permission::Camera, PermissionDecision::Allow)
+            .unwrap());
+
+        assert!(!path.with_extension("json.tmp").exists());
+
+        let reopened = PermissionManager::open(&path).unwrap();
+        assert_eq!(
+            reopened.decide("https://example.com/x", Permission::Camera),
+            Some(PermissionDecision::Allow)
+        );
+        assert_eq!(reopened.revoke_origin("https://example.com").unwrap(), 1);
+        assert!(PermissionStore::load(&path).unwrap().decisions().is_empty());
+
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(
+            PermissionManager::open(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_file(&path).unwrap();
+
+        // Save failures go to whoever answers, and the request is answered.
+        let manager = PermissionManager::open(path.join("missing.json"))
+            .unwrap()
+            .with_prompt(|_, responder| {
+                assert!(responder.respond(PermissionDecision::Deny, true).is_err())
+            });
+        let answer = Rc::new(RefCell::new(None));
+        let a = answer.clone();
+        manager.request(
+            PermissionRequest {
+                uri: "https://example.com/".into(),
+                permission: Permission::Camera,
+                user_initiated: true,
+            },
+            move |decision| *a.borrow_mut() = decision,
+        );
+        assert_eq!(*answer.borrow(), Some(PermissionDecision::Deny));
+    }
+
+    #[test]
+    fn test_rules() {
+        let manager = PermissionManager::new()
+            .with_rule(
+                PermissionRule::new(PermissionDecision::Allow)
+                    .with_origin("https://*.example.com".parse().unwrap())
+                    .with_permission(Permission::Geolocation),
+            )
+            .with_rule(
+                PermissionRule::new(PermissionDecision::Deny)
+                    .with_permission(Permission::ClipboardRead),
+            );
+        manager
+            .remember(
+                "https://maps.example.com",
+                Permission::Geolocation,
+                PermissionDecision::Deny,
+            )
+            .unwrap();
+        manager
+            .remember(
+                "https://maps.example.com",
+                Permission::Camera,
+                PermissionDecision::Allow,
+            )
+            .unwrap();
+
+        // Rules first.
+        assert_eq!(
+            manager.decide("https://maps.example.com/", Permission::Geolocation),
+            Some(PermissionDecision::Allow)
+        );
+        assert_eq!(
+            manager.decide("https://maps.example.com/", Permission::Camera),
+            Some(PermissionDecision::Allow)
+        );
+        assert_eq!(
+            manager.decide("https://example.com/", Permission::Geolocation),
+            None
+        );
+        assert_eq!(
+            manager.decide("about:blank", Permission::ClipboardRead),
+            Some(PermissionDecision::Deny)
+        );
+        assert_eq!(
+            manager.decide("garbage", Permission::ClipboardRead),
+            Some(PermissionDecision::Deny)
+        );
+        assert_eq!(manager.decide("garbage", Permission::Geolocation), None);
+    }
+
+    #[test]
+    fn test_prompt() {
+        let now = Rc::new(Cell::new(1000));
+        let n = now.clone();
+        let pending = Rc::new(RefCell::new(Vec::new()));
+        let p = pending.clone();
+        let manager = PermissionManager::new()
+            .with_clock(move || at(n.get()))
+            .with_expiry(Duration::from_secs(60))
+            .with_prompt(move |request, responder| p.borrow_mut().push((request, responder)));
+
+        let answers = Rc::new(RefCell::new(Vec::new()));
+        let request = |uri: &str, permission| {
+            let answers = answers.clone();
+            manager.request(
+                PermissionRequest {
+                    uri: uri.into(),
+                    permission,
+                    user_initiated: true,
+                },
+                move |decision| answers.borrow_mut().push(decision),
+            );
+        };
+
+        // Answered later, and remembered.
+        request("https://a.com/", Permission::Camera);
+        assert!(answers.borrow().is_empty());
+        let (req, responder) = pending.borrow_mut().remove(0);
+        assert_eq!(req.permission, Permission::Camera);
+        responder.respond(PermissionDecision::Allow, true).unwrap();
+        assert_eq!(*answers.borrow(), vec![Some(PermissionDecision::Allow)]);
+        request("https://a.com/other", Permission::Camera);
+        assert_eq!(answers.borrow().len(), 2);
+        assert!(pending.borrow().is_empty());
+
+        // Not remembered, and dropped responders leave it to the browser.
+        request("https://a.com/", Permission::Microphone);
+        let (_, responder) = pending.borrow_mut().remove(0);
+        responder.respond(PermissionDecision::Deny, false).unwrap();
+        request("https://a.com/", Permission::Microphone);
+        pending.borrow_mut().clear();
+        assert_eq!(
+            answers.borrow()[2..],
+            [Some(PermissionDecision::Deny), None]
+        );
+
+        // Expired.
+        now.set(1060);
+        request("https://a.com/", Permission::Camera);
+        assert_eq!(pending.borrow().len(), 1);
+        pending.borrow_mut().remove(0).1.defer_to_browser();
+        assert_eq!(answers.borrow().last(), Some(&None));
+        assert_eq!(manager.purge_expired().unwrap(), 1);
+
+        // Without a prompt.
+        let answered = Rc::new(Cell::new(false));
+        let a = answered.clone();
+        PermissionManager::new().request(
+            PermissionRequest {
+                uri: "https://a.com".into(),
+                permission: Permission::Camera,
+                user_initiated: false,
+            },
+            move |decision| a.set(decision.is_none()),
+        );
+        assert!(answered.get());
+    }
+}
@@ -0,0 +1,825 @@
+// Opening `window.open` popups as managed child webviews.
+//
+// Creating a popup means creating a native window, then a controller in it,
+// while the `NewWindowRequested` event is deferred. `PopupManager` creates the
+// windows with a `WindowFactory`, sized according to the `WindowFeatures`,
+// keeps track of them and closes them on `WindowCloseRequested`.
+
+use crate::error::{Error, Result};
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+/// Identifies a popup of a `PopupManager`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PopupId(u64);
+
+/// The features passed to `window.open`, like `WindowFeatures`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PopupFeatures {
+    /// The requested screen position of the window, `(left, top)`.
+    pub position: Option<(i32, i32)>,
+    /// The requested size of the window, `(width, height)`.
+    pub size: Option<(u32, u32)>,
+    pub menu_bar: bool,
+    pub status: bool,
+    pub toolbar: bool,
+    pub scroll_bars: bool,
+}
+
+/// A screen rectangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PopupRect {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Where to put a popup window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PopupBounds {
+    /// `(left, top)`, or `None` to leave it to the window system, e.g. with
+    /// `CW_USEDEFAULT`.
+    pub position: Option<(i32, i32)>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How popup windows are sized and placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PopupSizing {
+    default_size: (u32, u32),
+    min_size: (u32, u32),
+    work_area: Option<PopupRect>,
+    cascade: i32,
+}
+
+impl Default for PopupSizing {
+    fn default() -> Self {
+        Self {
+            default_size: (800, 600),
+            min_size: (100, 100),
+            work_area: None,
+            cascade: 24,
+        }
+    }
+}
+
+impl PopupSizing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The size of popups that don't request one. 800x600 by default.
+    pub fn with_default_size(mut self, width: u32, height: u32) -> Self {
+        self.default_size = (width, height);
+        self
+    }
+
+    /// The smallest size a popup can request. 100x100 by default.
+    pub fn with_min_size(mut self, width: u32, height: u32) -> Self {
+        self.min_size = (width, height);
+        self
+    }
+
+    /// Keep popups inside `area`, e.g. the work area of the monitor.
+    /// Popups that don't request a position are centered in it.
+    pub fn with_work_area(mut self, area: PopupRect) -> Self {
+        self.work_area = Some(area);
+        self
+    }
+
+    /// How far each centered popup is moved right and down from the last.
+    /// 24 pixels by default.
+    pub fn with_cascade(mut self, offset: i32) -> Self {
+        self.cascade = offset;
+        self
+    }
+
+    /// The bounds of a popup requesting `features` while `open` popups are
+    /// open.
+    ///
+    /// ```
+    /// # use webview2::{PopupBounds, PopupFeatures, PopupRect, PopupSizing};
+    /// let sizing = PopupSizing::new().with_work_area(PopupRect {
+    ///     left: 0,
+    ///     top: 0,
+    ///     width: 1000,
+    ///     height: 800,
+    /// });
+    /// let features = PopupFeatures {
+    ///     position: Some((900, -50)),
+    ///     size: Some((400, 2000)),
+    ///     ..PopupFeatures::default()
+    /// };
+    /// assert_eq!(
+    ///     sizing.bounds(&features, 0),
+    ///     PopupBounds {
+    ///         position: Some((600, 0)),
+    ///         width: 400,
+    ///         height: 800,
+    ///     }
+    /// );
+    /// ```
+    pub fn bounds(&self, features: &PopupFeatures, open: usize) -> PopupBounds {
+        let (mut width, mut height) = features.size.unwrap_or(self.default_size);
+        width = width.max(self.min_size.0);
+        height = height.max(self.min_size.1);
+        let area = match self.work_area {
+            Some(area) => area,
+            None => {
+                return PopupBounds {
+                    position: features.position,
+                    width,
+                    height,
+                }
+            }
+        };
+        width = width.min(area.width);
+        height = height.min(area.height);
+        let (left, top) = match features.position {
+            Some((left, top)) => (left, top),
+            None => {
+                let offset = i64::from(self.cascade) * open as i64;
+                (
+                    centered(area.left, area.width, width, offset),
+                    centered(area.top, area.height, height, offset),
+                )
+            }
+        };
+        PopupBounds {
+            position: Some((
+                inside(left, area.left, area.width, width),
+                inside(top, area.top, area.height, height),
+            )),
+            width,
+            height,
+        }
+    }
+}
+
+// Centered in `extent` from `start`, moved by `offset` and wrapped around
+// before reaching the end.
+fn centered(start: i32, extent: u32, size: u32, offset: i64) -> i32 {
+    let free = i64::from(extent - size);
+    let room = free - free / 2 + 1;
+    saturate(i64::from(start) + free / 2 + offset.rem_euclid(room))
+}
+
+// Moved into `extent` from `start`.
+fn inside(position: i32, start: i32, extent: u32, size: u32) -> i32 {
+    let start = i64::from(start);
+    let end = start + i64::from(extent - size);
+    saturate(i64::from(position).max(start).min(end))
+}
+
+fn saturate(value: i64) -> i32 {
+    i32::try_from(value).unwrap_or(if value < 0 { i32::MIN } else { i32::MAX })
+}
+
+/// A popup the page requested.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PopupRequest {
+    pub uri: String,
+    pub user_initiated: bool,
+    pub features: PopupFeatures,
+}
+
+/// Creates the native windows of popups, see `PopupManager`.
+pub trait WindowFactory {
+    type Window;
+
+    /// Create a window for a popup. The toolbar flags etc. are in
+    /// `request.features`.
+    fn create_window(
+        &self,
+        id: PopupId,
+        request: &PopupRequest,
+        bounds: &PopupBounds,
+    ) -> Result<Self::Window>;
+
+    /// Destroy the window of a popup that was closed.
+    fn destroy_window(&self, window: Self::Window);
+}
+
+/// Why a popup was not opened.
+#[derive(Debug)]
+pub enum PopupError {
+    /// The maximum number of popups is open.
+    TooMany { max: usize },
+    /// The `WindowFactory` failed.
+    Window(Error),
+}
+
+impl fmt::Display for PopupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PopupError::TooMany { max } => {
+                write!(f, "too many popups, at most {} are allowed", max)
+            }
+            PopupError::Window(error) => write!(f, "failed to create popup window: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for PopupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PopupError::TooMany { .. } => None,
+            PopupError::Window(error) => Some(error),
+        }
+    }
+}
+
+struct Popup<W> {
+    id: PopupId,
+    request: PopupRequest,
+    bounds: PopupBounds,
+    window: W,
+    #[cfg(windows)]
+    controller: Option<crate::Controller>,
+}
+
+struct Inner<F: WindowFactory> {
+    factory: F,
+    sizing: Cell<PopupSizing>,
+    max_popups: Cell<Option<usize>>,
+    next_id: Cell<u64>,
+    // In the order they were opened.
+    popups: RefCell<Vec<Popup<F::Window>>>,
+}
+
+impl<F: WindowFactory> Inner<F> {
+    fn close(&self, id: PopupId) -> bool {
+        let popup = {
+            let mut popups = self.popups.borrow_mut();
+            match popups.iter().position(|p| p.id == id) {
+                Some(index) => popups.remove(index),
+                None => return false,
+            }
+        };
+        #[cfg(windows)]
+        {
+            if let Some(controller) = &popup.controller {
+                // Nothing to do about errors, the window goes away anyway.
+                let _ = controller.close();
+            }
+        }
+        self.factory.destroy_window(popup.window);
+        true
+    }
+}
+
+impl<F: WindowFactory> Drop for Inner<F> {
+    fn drop(&mut self) {
+        while let Some(id) = self.popups.get_mut().last().map(|p| p.id) {
+            self.close(id);
+        }
+    }
+}
+
+/// Opens popups in windows created by a `WindowFactory` and keeps track of
+/// them.
+///
+/// Clones share the popups. They are closed when the last clone is dropped.
+pub struct PopupManager<F: WindowFactory> {
+    inner: Rc<Inner<F>>,
+}
+
+impl<F: WindowFactory> Clone for PopupManager<F> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<F: WindowFactory> fmt::Debug for PopupManager<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PopupManager")
+            .field("sizing", &self.inner.sizing.get())
+            .field("max_popups", &self.inner.max_popups.get())
+            .field("popups", &self.ids())
+            .finish()
+    }
+}
+
+impl<F: WindowFactory> PopupManager<F> {
+    /// A manager with default sizing and no maximum number of popups.
+    pub fn new(factory: F) -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                factory,
+                sizing: Cell::new(PopupSizing::default()),
+                max_popups: Cell::new(None),
+                next_id: Cell::new(1),
+                popups: RefCell::new(Vec::new()),
+            }),
+        }
+    }
+
+    pub fn with_sizing(self, sizing: PopupSizing) -> Self {
+        self.inner.sizing.set(sizing);
+        self
+    }
+
+    /// Block popups while `max` are open.
+    pub fn with_max_popups(self, max: usize) -> Self {
+        self.inner.max_popups.set(Some(max));
+        self
+    }
+
+    pub fn factory(&self) -> &F {
+        &self.inner.factory
+    }
+
+    pub fn sizing(&self) -> PopupSizing {
+        self.inner.sizing.get()
+    }
+
+    pub fn max_popups(&self) -> Option<usize> {
+        self.inner.max_popups.get()
+    }
+
+    /// Create the window of a popup.
+    pub fn open(&self, request: PopupRequest) -> std::result::Result<PopupId, PopupError> {
+        let open = self.len();
+        if let Some(max) = self.max_popups() {
+            if open >= max {
+                return Err(PopupError::TooMany { max });
+            }
+        }
+        let id = PopupId(self.inner.next_id.get());
+        self.inner.next_id.set(id.0 + 1);
+        let bounds = self.sizing().bounds(&request.features, open);
+        let window = self
+            .inner
+            .factory
+            .create_window(id, &request, &bounds)
+            .map_err(PopupError::Window)?;
+        self.inner.popups.borrow_mut().push(Popup {
+            id,
+            request,
+            bounds,
+            window,
+            #[cfg(windows)]
+            controller: None,
+        });
+        Ok(id)
+    }
+
+    /// Close a popup and destroy its window. Returns whether it was open.
+    pub fn close(&self, id: PopupId) -> bool {
+        self.inner.close(id)
+    }
+
+    /// Close all popups, the most recent first.
+    pub fn close_all(&self) {
+        while let Some(id) = self.ids().pop() {
+            self.close(id);
+        }
+    }
+
+    pub fn is_open(&self, id: PopupId) -> bool {
+        self.inner.popups.borrow().iter().any(|p| p.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.popups.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The open popups, in the order they were opened.
+    pub fn ids(&self) -> Vec<PopupId> {
+        self.inner.popups.borrow().iter().map(|p| p.id).collect()
+    }
+
+    pub fn request(&self, id: PopupId) -> Option<PopupRequest> {
+        self.find(id, |p| p.request.clone())
+    }
+
+    /// The bounds the window was created with.
+    pub fn bounds(&self, id: PopupId) -> Option<PopupBounds> {
+        self.find(id, |p| p.bounds)
+    }
+
+    /// Call `f` with the window of a popup.
+    pub fn with_window<R>(&self, id: PopupId, f: impl FnOnce(&F::Window) -> R) -> Option<R> {
+        self.find(id, |p| f(&p.window))
+    }
+
+    fn find<R>(&self, id: PopupId, f: impl FnOnce(&Popup<F::Window>) -> R) -> Option<R> {
+        self.inner
+            .popups
+            .borrow()
+            .iter()
+            .find(|p| p.id == id)
+            .map(f)
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::*;
+    use std::mem;
+    use std::rc::Weak;
+    use winapi::shared::windef::HWND;
+    use winapi::shared::winerror::E_ABORT;
+    use winapi::um::winuser::GetClientRect;
+
+    /// A popup window the controller is created in.
+    pub trait PopupWindow {
+        fn hwnd(&self) -> HWND;
+    }
+
+    impl PopupFeatures {
+        pub fn from_window_features(features: &WindowFeatures) -> Result<Self> {
+            let to_i32 = |v: u32| i32::try_from(v).unwrap_or(i32::MAX);
+            Ok(Self {
+                position: if features.has_position()? {
+                    Some((to_i32(features.get_left()?), to_i32(features.get_top()?)))
+                } else {
+                    None
+                },
+                size: if features.has_size()? {
+                    Some((features.get_width()?, features.get_height()?))
+                } else {
+                    None
+                },
+                menu_bar: features.get_menu_bar()?,
+                status: features.get_status()?,
+                toolbar: features.get_toolbar()?,
+                scroll_bars: features.get_scroll_bars()?,
+            })
+        }
+    }
+
+    impl<F> PopupManager<F>
+    where
+        F: WindowFactory + 'static,
+        F::Window: PopupWindow,
+    {
+        /// Open the popups of `webview`, and of the popups themselves, with
+        /// controllers created by `environment`.
+        ///
+        /// Popups over the maximum are blocked, and new windows an earlier
+        /// handler handled, e.g. a `NavigationPolicy` attached first, are
+        /// left alone. The controller fills the client area of the window;
+        /// resizing it later is up to the window.
+        pub fn attach(&self, environment: &Environment, webview: &WebView) -> Result<Subscription> {
+            let manager = Rc::downgrade(&self.inner);
+            let environment = environment.clone();
+            webview.on_new_window_requested(move |_, args| match upgrade(&manager) {
+                Some(manager) => manager.open_requested(&environment, args),
+                None => Ok(()),
+            })
+        }
+
+        /// The controller of a popup, once it is created.
+        pub fn controller(&self, id: PopupId) -> Option<Controller> {
+            self.find(id, |p| p.controller.clone()).and_then(|c| c)
+        }
+
+        fn open_requested(
+            &self,
+            environment: &Environment,
+            args: NewWindowRequestedEventArgs,
+        ) -> Result<()> {
+            if args.get_handled()? {
+                // By an earlier handler, e.g. denied by a `NavigationPolicy`.
+                return Ok(());
+            }
+            let request = PopupRequest {
+                uri: args.get_uri()?,
+                user_initiated: args.get_is_user_initiated()?,
+                features: PopupFeatures::from_window_features(&args.get_window_features()?)?,
+            };
+            let id = match self.open(request) {
+                Ok(id) => id,
+                Err(PopupError::TooMany { .. }) => return args.put_handled(true),
+                Err(PopupError::Window(e)) => {
+                    args.put_handled(true)?;
+                    return Err(e);
+                }
+            };
+            let hwnd = self.with_window(id, |w| w.hwnd()).unwrap();
+            let deferral = match args.get_deferral() {
+                Ok(deferral) => deferral,
+                Err(e) => {
+                    self.close(id);
+                    args.put_handled(true)?;
+                    return Err(e);
+                }
+            };
+            let manager = Rc::downgrade(&self.inner);
+            let env = environment.clone();
+            let (args1, deferral1) = (args.clone(), deferral.clone());
+            let created = environment.create_controller(hwnd, move |controller| {
+                let result = match upgrade(&manager) {
+                    Some(manager) => {
+                        let result = controller.and_then(|c| manager.adopt(id, &env, c, &args));
+                        if result.is_err() {
+                            manager.close(id);
+                        }
+                        result
+                    }
+                    None => Err(Error::new(E_ABORT).with_operation("PopupManager")),
+                };
+                if result.is_err() {
+                    // Block the popup rather than open it in a default window.
+                    let _ = args.put_handled(true);
+                }
+                deferral.complete()?;
+                result
+            });
+            if created.is_err() {
+                self.close(id);
+                let _ = args1.put_handled(true);
+                let _ = deferral1.complete();
+            }
+            created
+        }
+
+        fn adopt(
+            &self,
+            id: PopupId,
+            environment: &Environment,
+            controller: Controller,
+            args: &NewWindowRequestedEventArgs,
+        ) -> Result<()> {
+            let hwnd = match self.with_window(id, |w| w.hwnd()) {
+                Some(hwnd) => hwnd,
+                // Closed while the controller was created.
+                None => {
+                    let _ = controller.close();
+                    return Err(Error::new(E_ABORT).with_operation("PopupManager"));
+                }
+            };
+            let mut rect = unsafe { mem::zeroed() };
+            unsafe { GetClientRect(hwnd, &mut rect) };
+            controller.put_bounds(rect)?;
+            let webview = controller.get_webview()?;
+            let manager = Rc::downgrade(&self.inner);
+            webview
+                .on_window_close_requested(move |_| {
+                    if let Some(manager) = upgrade(&manager) {
+                        manager.close(id);
+                    }
+                    Ok(())
+                })?
+                .detach();
+            // Closing the controller removes these.
+            self.attach(environment, &webview)?.detach();
+            args.put_new_window(webview)?;
+            let mut popups = self.inner.popups.borrow_mut();
+            if let Some(popup) = popups.iter_mut().find(|p| p.id == id) {
+                popup.controller = Some(controller);
+            }
+            Ok(())
+        }
+    }
+
+    fn upgrade<F: WindowFactory>(manager: &Weak<Inner<F>>) -> Option<PopupManager<F>> {
+        manager.upgrade().map(|inner| PopupManager { inner })
+    }
+}
+
+#[cfg(windows)]
+pub use self::win::PopupWindow;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area() -> PopupRect {
+        PopupRect {
+            left: 100,
+            top: 50,
+            width: 1000,
+            height: 700,
+        }
+    }
+
+    fn sized(width: u32, height: u32) -> PopupFeatures {
+        PopupFeatures {
+            size: Some((width, height)),
+            ..PopupFeatures::default()
+        }
+    }
+
+    #[test]
+    fn test_sizing() {
+        let sizing = PopupSizing::new();
+        let bounds = |features: PopupFeatures| sizing.bounds(&features, 3);
+        assert_eq!(
+            bounds(PopupFeatures::default()),
+            PopupBounds {
+                position: None,
+                width: 800,
+                height: 600,
+            }
+        );
+        let features = PopupFeatures {
+            position: Some((-20, 5000)),
+            ..sized(50, 300)
+        };
+        // Without a work area positions are kept.
+        assert_eq!(
+            bounds(features),
+            PopupBounds {
+                position: Some((-20, 5000)),
+                width: 100,
+                height: 300,
+            }
+        );
+
+        let sizing = sizing.with_work_area(area()).with_min_size(200, 150);
+        assert_eq!(
+            sizing.bounds(&features, 0),
+            PopupBounds {
+                position: Some((100, 450)),
+                width: 200,
+                height: 300,
+            }
+        );
+        // The work area wins over the minimum size.
+        let tiny = PopupRect {
+            width: 120,
+            height: 80,
+            ..area()
+        };
+        let bounds = PopupSizing::new()
+            .with_min_size(200, 150)
+            .with_work_area(tiny)
+            .bounds(&features, 0);
+        assert_eq!(
+            bounds,
+            PopupBounds {
+                position: Some((100, 50)),
+                width: 120,
+                height: 80,
+            }
+        );
+    }
+
+    #[test]
+    fn test_cascade() {
+        let sizing = PopupSizing::new().with_work_area(area()).with_cascade(100);
+        let position = |open| sizing.bounds(&sized(600, 500), open).position.unwrap();
+        // 400 and 200 pixels free: centered, then moved until the right or
+        // bottom edge, then wrapped.
+        assert_eq!(position(0), (300, 150));
+        assert_eq!(position(1), (400, 250));
+        assert_eq!(position(2), (500, 249));
+        assert_eq!(position(3), (399, 248));
+        for open in 0..100 {
+            let (left, top) = position(open);
+            assert!(left >= 100 && left + 600 <= 1100, "{}", open);
+            assert!(top >= 50 && top + 500 <= 750, "{}", open);
+        }
+
+        let full = sizing.bounds(&sized(5000, 5000), 7);
+        assert_eq!(
+            full,
+            PopupBounds {
+                position: Some((100, 50)),
+                width: 1000,
+                height: 700,
+            }
+        );
+        assert_eq!(
+            PopupSizing::new()
+                .with_work_area(area())
+                .with_cascade(0)
+                .bounds(&sized(600, 500), 5)
+                .position,
+            Some((300, 150))
+        );
+    }
+
+    #[derive(Default)]
+    struct FakeFactory {
+        log: RefCell<Vec<String>>,
+        fail: Cell<bool>,
+    }
+
+    impl FakeFactory {
+        fn take_log(&self) -> Vec<String> {
+            self.log.borrow_mut().drain(..).collect()
+        }
+    }
+
+    impl WindowFactory for FakeFactory {
+        type Window = String;
+
+        fn create_window(
+            &self,
+            id: PopupId,
+            request: &PopupRequest,
+            bounds: &PopupBounds,
+        ) -> Result<String> {
+            if self.fail.get() {
+                return Err(Error::new(-1));
+            }
+            let window = format!("{} {}", id.0, request.uri);
+            self.log.borrow_mut().push(format!(
+                "create {} {}x{}",
+                window, bounds.width, bounds.height
+            ));
+            Ok(window)
+        }
+
+        fn destroy_window(&self, window: String) {
+            self.log.borrow_mut().push(format!("destroy {}", window));
+        }
+    }
+
+    fn request(uri: &str) -> PopupRequest {
+        PopupRequest {
+            uri: uri.into(),
+            user_initiated: true,
+            features: PopupFeatures::default(),
+        }
+    }
+
+    #[test]
+    fn test_open_close() {
+        let manager = PopupManager::new(FakeFactory::default())
+            .with_sizing(PopupSizing::new().with_default_size(300, 200))
+            .with_max_popups(2);
+        let a = manager.open(request("a")).unwrap();
+        let b = manager.open(request("b")).unwrap();
+        assert_eq!(manager.ids(), vec![a, b]);
+        match manager.open(request("c")) {
+            Err(PopupError::TooMany { max: 2 }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            manager.factory().take_log(),
+            vec!["create 1 a 300x200", "create 2 b 300x200"]
+        );
+        assert_eq!(manager.request(b).unwrap().uri, "b");
+        assert_eq!(manager.bounds(a).unwrap().width, 300);
+        assert_eq!(manager.with_window(a, |w| w.clone()).unwrap(), "1 a");
+
+        assert!(manager.close(a));
+        assert!(!manager.close(a));
+        assert!(!manager.is_open(a));
+        assert_eq!(manager.factory().take_log(), vec!["destroy 1 a"]);
+        assert_eq!(manager.request(a), None);
+
+        // A failed window doesn't count.
+        manager.factory().fail.set(true);
+        match manager.open(request("d")) {
+            Err(PopupError::Window(e)) => assert_eq!(e.hresult(), -1),
+            other => panic!("{:?}", other),
+        }
+        manager.factory().fail.set(false);
+        let e = manager.open(request("e")).unwrap();
+        assert_eq!(manager.ids(), vec![b, e]);
+        assert_eq!(manager.len(), 2);
+
+        manager.close_all();
+        assert!(manager.is_empty());
+        assert_eq!(
+            manager.factory().take_log(),
+            vec!["create 4 e 300x200", "destroy 4 e", "destroy 2 b"]
+        );
+    }
+
+    #[test]
+    fn test_close_on_drop() {
+        struct Shared(Rc<FakeFactory>);
+        impl WindowFactory for Shared {
+            type Window = String;
+            fn create_window(
+                &self,
+                id: PopupId,
+                request: &PopupRequest,
+                bounds: &PopupBounds,
+            ) -> Result<String> {
+                self.0.create_window(id, request, bounds)
+            }
+            fn destroy_window(&self, window: String) {
+                self.0.destroy_window(window)
+            }
+        }
+
+        let factory = Rc::new(FakeFactory::default());
+        let manager = PopupManager::new(Shared(factory.clone()));
+        manager.open(request("a")).unwrap();
+        manager.open(request("b")).unwrap();
+        let clone = manager.clone();
+        drop(manager);
+        assert_eq!(clone.len(), 2);
+        factory.take_log();
+        drop(clone);
+        assert_eq!(factory.take_log(), vec!["destroy 2 b", "destroy 1 a"]);
+    }
+}
@@ -0,0 +1,729 @@
+// Topic-based publish/subscribe from the host to the page.
+//
+// The page subscribes to topics with web messages. The host remembers the
+// subscriptions across navigations: when a new document from a trusted
+// origin (by default, the origin of the previous document) finishes
+// loading, the topics are subscribed again for it and the last messages of
+// each topic are replayed. The page script buffers messages of topics it
+// has no handler for yet, so that a reloaded page gets them once it
+// subscribes.
+
+use crate::origin::{MessageDirection, Origin, OriginAllowlist};
+use crate::rpc::Transport;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+
+/// Field that marks a web message as a pub/sub message.
+pub const PUBSUB_TAG: &str = "$pubsub";
+
+/// A pub/sub protocol message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "$pubsub", rename_all = "lowercase")]
+pub enum PubSubMessage {
+    /// From the page.
+    Subscribe {
+        topic: String,
+        /// Whether to replay the last messages of the topic.
+        #[serde(default = "default_true")]
+        replay: bool,
+    },
+    /// From the page.
+    Unsubscribe { topic: String },
+    /// From the host.
+    Message {
+        topic: String,
+        /// Increases by one for each message published to the topic.
+        seq: u64,
+        data: Value,
+        #[serde(default, skip_serializing_if = "is_false")]
+        replay: bool,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+impl PubSubMessage {
+    /// Parse a web message, or `None` if it is not a pub/sub message.
+    pub fn parse(message: &str) -> Option<Result<Self, serde_json::Error>> {
+        let value: Value = serde_json::from_str(message).ok()?;
+        value.get(PUBSUB_TAG)?;
+        Some(serde_json::from_value(value))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("messages always serialize")
+    }
+}
+
+#[derive(Default)]
+struct Topic {
+    // Messages kept for replay, with their sequence numbers.
+    history: VecDeque<(u64, Value)>,
+    // `None` for the default.
+    replay: Option<usize>,
+    next_seq: u64,
+}
+
+struct Inner {
+    transport: Rc<dyn Transport>,
+    replay: Cell<usize>,
+    topics: RefCell<HashMap<String, Topic>>,
+    // Topics the page subscribed to, across navigations.
+    subscriptions: RefCell<BTreeSet<String>>,
+    // Topics subscribed for the current document.
+    active: RefCell<BTreeSet<String>>,
+    on_change: RefCell<Option<Rc<dyn Fn(&str, bool)>>>,
+    // Origins of the documents subscribed again after navigations.
+    origins: RefCell<Option<OriginAllowlist>>,
+    // Origin of the last document that finished loading.
+    document_origin: RefCell<Option<Origin>>,
+}
+
+/// Publishes messages to topics the page subscribed to.
+///
+/// Feed every received web message to `receive`, and call
+/// `navigation_starting` and `navigation_completed` on navigations, or use
+/// `attach` on Windows.
+#[derive(Clone)]
+pub struct PubSub {
+    inner: Rc<Inner>,
+}
+
+impl fmt::Debug for PubSub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PubSub")
+            .field("replay", &self.inner.replay.get())
+            .field("subscriptions", &*self.inner.subscriptions.borrow())
+            .field("active", &*self.inner.active.borrow())
+            .finish()
+    }
+}
+
+impl PubSub {
+    /// A pub/sub host replaying no messages by default.
+    pub fn new(transport: Rc<dyn Transport>) -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                transport,
+                replay: Cell::new(0),
+                topics: RefCell::default(),
+                subscriptions: RefCell::default(),
+                active: RefCell::default(),
+                on_change: RefCell::new(None),
+                origins: RefCell::new(None),
+                document_origin: RefCell::new(None),
+            }),
+        }
+    }
+
+    /// Keep the last `count` messages of each topic for replay.
+    pub fn with_replay(self, count: usize) -> Self {
+        self.inner.replay.set(count);
+        self.trim_all();
+        self
+    }
+
+    /// Keep the last `count` messages of `topic` for replay.
+    pub fn with_topic_replay(self, topic: &str, count: usize) -> Self {
+        self.inner
+            .topics
+            .borrow_mut()
+            .entry(topic.into())
+            .or_default()
+            .replay = Some(count);
+        self.trim_all();
+        self
+    }
+
+    /// Subscribe new documents from these origins to the remembered
+    /// topics, instead of those from the origin of the previous document,
+    /// see `navigation_completed`. With `attach`, messages from
+    /// other origins are also dropped, and nothing is posted to them.
+    pub fn with_origins(self, origins: OriginAllowlist) -> Self {
+        *self.inner.origins.borrow_mut() = Some(origins);
+        self
+    }
+
+    /// Called when the page subscribes to (`true`) or unsubscribes from
+    /// (`false`) a topic. Not called for automatic resubscriptions.
+    pub fn on_subscription_change(&self, handler: impl Fn(&str, bool) + 'static) {
+        *self.inner.on_change.borrow_mut() = Some(Rc::new(handler));
+    }
+
+    /// Publish a value to a topic. Returns whether it was sent to the page;
+    /// it is kept for replay either way.
+    pub fn publish<T: Serialize + ?Sized>(
+        &self,
+        topic: &str,
+        data: &T,
+    ) -> serde_json::Result<bool> {
+        Ok(self.publish_value(topic, serde_json::to_value(data)?))
+    }
+
+    /// Publish a JSON value to a topic, see `publish`.
+    pub fn publish_value(&self, topic: &str, data: Value) -> bool {
+        let seq = {
+            let mut topics = self.inner.topics.borrow_mut();
+            let t = topics.entry(topic.into()).or_default();
+            let seq = t.next_seq;
+            t.next_seq += 1;
+            let limit = t.replay.unwrap_or_else(|| self.inner.replay.get());
+            if limit > 0 {
+                t.history.push_back((seq, data.clone()));
+                while t.history.len() > limit {
+                    t.history.pop_front();
+                }
+            }
+            seq
+        };
+        if !self.inner.active.borrow().contains(topic) {
+            return false;
+        }
+        self.send(PubSubMessage::Message {
+            topic: topic.into(),
+            seq,
+            data,
+            replay: false,
+        });
+        true
+    }
+
+    /// Whether the page is subscribed to a topic, even if the current
+    /// document has not loaded yet.
+    pub fn is_subscribed(&self, topic: &str) -> bool {
+        self.inner.subscriptions.borrow().contains(topic)
+    }
+
+    /// Topics the page is subscribed to.
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.inner.subscriptions.borrow().iter().cloned().collect()
+    }
+
+    /// Messages of a topic kept for replay, oldest first.
+    pub fn history(&self, topic: &str) -> Vec<Value> {
+        match self.inner.topics.borrow().get(topic) {
+            Some(t) => t.history.iter().map(|(_, data)| data.clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Forget the messages of a topic kept for replay.
+    pub fn clear_history(&self, topic: &str) {
+        if let Some(t) = self.inner.topics.borrow_mut().get_mut(topic) {
+            t.history.clear();
+        }
+    }
+
+    /// Unsubscribe the page from a topic.
+    pub fn unsubscribe(&self, topic: &str) {
+        self.inner.subscriptions.borrow_mut().remove(topic);
+        self.inner.active.borrow_mut().remove(topic);
+    }
+
+    /// The current document is going away: stop sending messages until
+    /// the next one subscribes or finishes loading.
+    pub fn navigation_starting(&self) {
+        self.inner.active.borrow_mut().clear();
+    }
+
+    /// A new document from `source` finished loading: if its origin is
+    /// allowed by `with_origins`, subscribe it to the remembered topics it
+    /// has not subscribed to itself, and replay their messages. Without
+    /// `with_origins`, this is done if the document has the same origin as
+    /// the previous one, e.g. when reloading; documents from other origins,
+    /// and those without a host like `about:blank`, subscribe themselves.
+    pub fn navigation_completed(&self, source: &str) {
+        let origin = Origin::parse(source).filter(|o| !o.host.is_empty());
+        let previous = self.inner.document_origin.replace(origin.clone());
+        let trusted = match *self.inner.origins.borrow() {
+            Some(ref origins) => origins.check(source, MessageDirection::Outgoing),
+            None => origin.is_some() && origin == previous,
+        };
+        if !trusted {
+            return;
+        }
+        let topics: Vec<String> = {
+            let subscriptions = self.inner.subscriptions.borrow();
+            let active = self.inner.active.borrow();
+            subscriptions.difference(&active).cloned().collect()
+        };
+        for topic in topics {
+            self.inner.active.borrow_mut().insert(topic.clone());
+            self.replay(&topic);
+        }
+    }
+
+    /// Handle a received web message. Returns `false` if it is not a
+    /// pub/sub message.
+    pub fn receive(&self, message: &str) -> bool {
+        let message = match PubSubMessage::parse(message) {
+            None => return false,
+            Some(Ok(message)) => message,
+            // Malformed, or a host message echoed back.
+            Some(Err(_)) => return true,
+        };
+        match message {
+            PubSubMessage::Subscribe { topic, replay } => {
+                let new = self.inner.subscriptions.borrow_mut().insert(topic.clone());
+                // Already subscribed for this document, e.g. by
+                // `navigation_completed`, in which case it was replayed.
+                if self.inner.active.borrow_mut().insert(topic.clone()) && replay {
+                    self.replay(&topic);
+                }
+                if new {
+                    self.changed(&topic, true);
+                }
+            }
+            PubSubMessage::Unsubscribe { topic } => {
+                if self.inner.subscriptions.borrow_mut().remove(&topic) {
+                    self.inner.active.borrow_mut().remove(&topic);
+                    self.changed(&topic, false);
+                }
+            }
+            PubSubMessage::Message { .. } => {}
+        }
+        true
+    }
+
+    fn replay(&self, topic: &str) {
+        let history: Vec<(u64, Value)> = match self.inner.topics.borrow().get(topic) {
+            Some(t) => t.history.iter().cloned().collect(),
+            None => return,
+        };
+        for (seq, data) in history {
+            self.send(PubSubMessage::Message {
+                topic: topic.into(),
+                seq,
+                data,
+                replay: true,
+            });
+        }
+    }
+
+    fn changed(&self, topic: &str, subscribed: bool) {
+        let on_change = self.inner.on_change.borrow().clone();
+        if let Some(on_change) = on_change {
+            on_change(topic, subscribed);
+        }
+    }
+
+    fn trim_all(&self) {
+        let default = self.inner.replay.get();
+        for t in self.inner.topics.borrow_mut().values_mut() {
+            let limit = t.replay.unwrap_or(default);
+            while t.history.len() > limit {
+                t.history.pop_front();
+            }
+        }
+    }
+
+    fn send(&self, message: PubSubMessage) {
+        self.inner.transport.send(&message.to_json());
+    }
+
+    /// JavaScript defining the page side as `window[global]`.
+    ///
+    /// It provides `subscribe(topic, handler, {replay})`, which returns a
+    /// function that removes the handler, and `handle(message)`, which
+    /// returns whether a message was a pub/sub message. Handlers are called
+    /// with the data, and an object with the `seq` number and whether the
+    /// message is a `replay`. Up to `buffer` messages per topic without
+    /// handlers are kept for the first handler. Messages are handled
+    /// automatically unless `auto_listen` is `false`.
+    pub fn script(global: &str, buffer: usize, auto_listen: bool) -> String {
+        PUBSUB_SCRIPT
+            .replace("$GLOBAL", &Value::String(global.into()).to_string())
+            .replace("$BUFFER", &buffer.to_string())
+            .replace("$AUTO_LISTEN", if auto_listen { "true" } else { "false" })
+    }
+}
+
+const PUBSUB_SCRIPT: &str = r#"(function () {
+  var webview = window.chrome && window.chrome.webview;
+  var TAG = "$pubsub";
+  var BUFFER = $BUFFER;
+  var handlers = {};
+  var buffered = {};
+  var lastSeq = {};
+
+  function post(kind, fields) {
+    fields[TAG] = kind;
+    webview.postMessage(fields);
+  }
+
+  function dispatch(message) {
+    var list = handlers[message.topic];
+    if (!list || list.length === 0) {
+      var queue = buffered[message.topic] || (buffered[message.topic] = []);
+      queue.push(message);
+      if (queue.length > BUFFER) {
+        queue.shift();
+      }
+      return;
+    }
+    // Replays can overlap messages already seen.
+    if (message.topic in lastSeq && message.seq <= lastSeq[message.topic]) {
+      return;
+    }
+    lastSeq[message.topic] = message.seq;
+    list.slice().forEach(function (handler) {
+      handler(message.data, { seq: message.seq, replay: !!message.replay });
+    });
+  }
+
+  function handle(message) {
+    if (!message || typeof message !== "object" || typeof message[TAG] !== "string") {
+      return false;
+    }
+    if (message[TAG] === "message") {
+      dispatch(message);
+    }
+    return true;
+  }
+
+  window[$GLOBAL] = {
+    subscribe: function (topic, handler, options) {
+      var list = handlers[topic] || (handlers[topic] = []);
+      list.push(handler);
+      if (list.length === 1) {
+        post("subscribe", { topic: topic, replay: !options || options.replay !== false });
+        var queue = buffered[topic] || [];
+        delete buffered[topic];
+        queue.forEach(dispatch);
+      }
+      return function () {
+        var i = list.indexOf(handler);
+        if (i >= 0) {
+          list.splice(i, 1);
+          if (list.length === 0) {
+            delete handlers[topic];
+            delete lastSeq[topic];
+            post("unsubscribe", { topic: topic });
+          }
+        }
+      };
+    },
+    handle: handle,
+  };
+
+  if (webview && $AUTO_LISTEN) {
+    webview.addEventListener("message", function (event) {
+      handle(event.data);
+    });
+  }
+})();
+"#;
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::{EventRegistrationToken, WebView};
+
+    /// Handlers registered by `PubSub::attach`.
+    #[derive(Debug)]
+    pub struct PubSubRegistration {
+        web_message_received: EventRegistrationToken,
+        navigation_starting: EventRegistrationToken,
+        navigation_completed: EventRegistrationToken,
+    }
+
+    impl PubSubRegistration {
+        pub fn detach(self, webview: &WebView) -> crate::Result<()> {
+            webview.remove_web_message_received(self.web_message_received)?;
+            webview.remove_navigation_starting(self.navigation_starting)?;
+            webview.remove_navigation_completed(self.navigation_completed)
+        }
+    }
+
+    impl PubSub {
+        /// A pub/sub host talking to page JavaScript through web messages.
+        ///
+        /// The page side is installed as `window[global]` for new
+        /// documents, buffering up to `buffer` messages per topic. The host
+        /// and the webview reference each other until detached. New
+        /// documents are subscribed to the remembered topics if they have
+        /// the same origin as the previous document, see
+        /// `attach_with_origins` to choose the origins.
+        pub fn attach(
+            webview: &WebView,
+            global: &str,
+            buffer: usize,
+            configure: impl FnOnce(Self) -> Self,
+        ) -> crate::Result<(Self, PubSubRegistration)> {
+            Self::attach_impl(webview, global, buffer, None, configure)
+        }
+
+        /// Like `attach`, but only exchange messages with documents from
+        /// these origins, and subscribe new documents from them to the
+        /// remembered topics.
+        pub fn attach_with_origins(
+            webview: &WebView,
+            global: &str,
+            buffer: usize,
+            origins: OriginAllowlist,
+            configure: impl FnOnce(Self) -> Self,
+        ) -> crate::Result<(Self, PubSubRegistration)> {
+            Self::attach_impl(webview, global, buffer, Some(origins), configure)
+        }
+
+        fn attach_impl(
+            webview: &WebView,
+            global: &str,
+            buffer: usize,
+            origins: Option<OriginAllowlist>,
+            configure: impl FnOnce(Self) -> Self,
+        ) -> crate::Result<(Self, PubSubRegistration)> {
+            let mut transport = crate::rpc::WebViewTransport::new(webview.clone());
+            if let Some(ref origins) = origins {
+                transport = transport.with_origins(origins.clone());
+            }
+            let mut pubsub = Self::new(Rc::new(transport));
+            if let Some(ref origins) = origins {
+                pubsub = pubsub.with_origins(origins.clone());
+            }
+            let pubsub = configure(pubsub);
+            webview.add_script_to_execute_on_document_created(
+                &Self::script(global, buffer, true),
+                |_| Ok(()),
+            )?;
+            let p = pubsub.clone();
+            let web_message_received = webview.add_web_message_received(move |_, args| {
+                if let Some(ref origins) = origins {
+                    if !origins.check(&args.get_source()?, MessageDirection::Incoming) {
+                        return Ok(());
+                    }
+                }
+                p.receive(&args.get_web_message_as_json()?);
+                Ok(())
+            })?;
+            let p = pubsub.clone();
+            let navigation_starting = webview.add_navigation_starting(move |_, _| {
+                p.navigation_starting();
+                Ok(())
+            })?;
+            let p = pubsub.clone();
+            let navigation_completed = webview.add_navigation_completed(move |webview, _| {
+                p.navigation_completed(&webview.get_source()?);
+                Ok(())
+            })?;
+            Ok((
+                pubsub,
+                PubSubRegistration {
+                    web_message_received,
+                    navigation_starting,
+                    navigation_completed,
+                },
+            ))
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use self::win::PubSubRegistration;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::QueueTransport;
+    use serde_json::json;
+
+    fn pubsub(replay: usize) -> (Rc<QueueTransport>, PubSub) {
+        let queue = Rc::new(QueueTransport::default());
+        let pubsub = PubSub::new(Rc::clone(&queue) as Rc<dyn Transport>).with_replay(replay);
+        (queue, pubsub)
+    }
+
+    // The data and replay flags of the sent messages.
+    fn sent(queue: &QueueTransport) -> Vec<(String, Value, bool)> {
+        queue
+            .take()
+            .into_iter()
+            .map(|m| match PubSubMessage::parse(&m).unwrap().unwrap() {
+                PubSubMessage::Message {
+                    topic,
+                    data,
+                    replay,
+                    ..
+                } => (topic, data, replay),
+                m => panic!("{:?}", m),
+            })
+            .collect()
+    }
+
+    fn subscribe(pubsub: &PubSub, topic: &str) {
+        assert!(pubsub.receive(&format!(
+            r#"{{"$pubsub": "subscribe", "topic": "{}"}}"#,
+            topic
+        )));
+    }
+
+    #[test]
+    fn test_messages() {
+        let message = PubSubMessage::Message {
+            topic: "logs".into(),
+            seq: 3,
+            data: json!("line"),
+            replay: false,
+        };
+        let json = message.to_json();
+        assert_eq!(
+            json,
+            r#"{"$pubsub":"message","topic":"logs","seq":3,"data":"line"}"#
+        );
+        assert_eq!(PubSubMessage::parse(&json).unwrap().unwrap(), message);
+        assert_eq!(
+            PubSubMessage::parse(r#"{"$pubsub": "subscribe", "topic": "t"}"#)
+                .unwrap()
+                .unwrap(),
+            PubSubMessage::Subscribe {
+                topic: "t".into(),
+                replay: true,
+            }
+        );
+        assert!(PubSubMessage::parse(r#"{"topic": "t"}"#).is_none());
+    }
+
+    #[test]
+    fn test_publish_and_replay() {
+        let (q, pubsub) = pubsub(2);
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let c = Rc::clone(&changes);
+        pubsub
+            .on_subscription_change(move |topic, on| c.borrow_mut().push((topic.to_string(), on)));
+
+        assert!(!pubsub.publish("logs", "a").unwrap());
+        pubsub.publish("logs", "b").unwrap();
+        pubsub.publish("logs", "c").unwrap();
+        assert_eq!(pubsub.history("logs"), vec![json!("b"), json!("c")]);
+
+        subscribe(&pubsub, "logs");
+        assert_eq!(
+            sent(&q),
+            vec![
+                ("logs".to_string(), json!("b"), true),
+                ("logs".to_string(), json!("c"), true),
+            ]
+        );
+        assert!(pubsub.publish("logs", &json!({"n": 1})).unwrap());
+        assert!(!pubsub.publish("other", "x").unwrap());
+        assert_eq!(sent(&q), vec![("logs".to_string(), json!({"n": 1}), false)]);
+
+        // Subscribing again does not replay again.
+        subscribe(&pubsub, "logs");
+        assert!(sent(&q).is_empty());
+        assert_eq!(*changes.borrow(), vec![("logs".to_string(), true)]);
+
+        assert!(pubsub.receive(r#"{"$pubsub": "unsubscribe", "topic": "logs"}"#));
+        assert!(!pubsub.publish("logs", "d").unwrap());
+        assert!(!pubsub.is_subscribed("logs"));
+        assert_eq!(changes.borrow()[1], ("logs".to_string(), false));
+
+        assert!(!pubsub.receive(r#"{"type": "other"}"#));
+    }
+
+    #[test]
+    fn test_navigation() {
+        let (q, pubsub) = pubsub(1);
+        let origins = OriginAllowlist::new().with_rule("https://app.example".parse().unwrap());
+        let pubsub = pubsub.with_origins(origins);
+        subscribe(&pubsub, "a");
+        subscribe(&pubsub, "b");
+        pubsub.publish("a", "1").unwrap();
+        sent(&q);
+
+        // Messages published while navigating are only kept for replay.
+        pubsub.navigation_starting();
+        assert!(!pubsub.publish("a", "2").unwrap());
+        assert!(pubsub.is_subscribed("a"));
+
+        // The new document subscribes to `a` itself before it has loaded.
+        subscribe(&pubsub, "a");
+        assert_eq!(sent(&q), vec![("a".to_string(), json!("2"), true)]);
+        pubsub.publish("b", "3").unwrap();
+        assert!(sent(&q).is_empty());
+
+        // `b` is subscribed again once loaded, and `a` is not replayed twice.
+        pubsub.navigation_completed("https://app.example/index.html");
+        assert_eq!(sent(&q), vec![("b".to_string(), json!("3"), true)]);
+        assert!(pubsub.publish("b", "4").unwrap());
+        sent(&q);
+        assert_eq!(pubsub.subscriptions(), vec!["a", "b"]);
+
+        // Documents from other origins subscribe themselves.
+        pubsub.navigation_starting();
+        pubsub.navigation_completed("https://evil.example/");
+        assert!(!pubsub.publish("a", "5").unwrap());
+        assert!(sent(&q).is_empty());
+        assert_eq!(pubsub.subscriptions(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_navigation_without_origins() {
+        let (q, pubsub) = pubsub(1);
+        subscribe(&pubsub, "a");
+        pubsub.publish("a", "1").unwrap();
+        sent(&q);
+        pubsub.navigation_completed("https://app.example/");
+        assert!(sent(&q).is_empty());
+
+        // Reloading subscribes the new document again.
+        pubsub.navigation_starting();
+        pubsub.publish("a", "2").unwrap();
+        pubsub.navigation_completed("https://app.example/index.html");
+        assert_eq!(sent(&q), vec![("a".to_string(), json!("2"), true)]);
+
+        // Documents from other origins subscribe themselves.
+        pubsub.navigation_starting();
+        pubsub.navigation_completed("https://other.example/");
+        assert!(!pubsub.publish("a", "3").unwrap());
+        assert!(sent(&q).is_empty());
+        subscribe(&pubsub, "a");
+        assert_eq!(sent(&q), vec![("a".to_string(), json!("3"), true)]);
+
+        // As do documents without a host.
+        pubsub.navigation_starting();
+        pubsub.navigation_completed("about:blank");
+        pubsub.navigation_starting();
+        pubsub.navigation_completed("about:blank");
+        assert!(!pubsub.publish("a", "4").unwrap());
+    }
+
+    #[test]
+    fn test_replay_limits() {
+        let (q, pubsub) = pubsub(3);
+        let pubsub = pubsub
+            .with_topic_replay("none", 0)
+            .with_topic_replay("one", 1);
+        for i in 0..5 {
+            for topic in &["none", "one", "default"] {
+                pubsub.publish(topic, &i).unwrap();
+            }
+        }
+        assert!(pubsub.history("none").is_empty());
+        assert_eq!(pubsub.history("one"), vec![json!(4)]);
+        assert_eq!(
+            pubsub.history("default"),
+            vec![json!(2), json!(3), json!(4)]
+        );
+
+        assert!(pubsub.receive(r#"{"$pubsub": "subscribe", "topic": "default", "replay": false}"#));
+        assert!(sent(&q).is_empty());
+        pubsub.clear_history("default");
+        pubsub.navigation_starting();
+        subscribe(&pubsub, "default");
+        assert!(sent(&q).is_empty());
+        // Sequence numbers continue.
+        pubsub.publish("default", "x").unwrap();
+        assert!(q.take()[0].contains(r#""seq":5"#));
+    }
+}
@@ -0,0 +1,313 @@
+// Reading lazily from a Rust `Read + Seek`, for `Stream::from_reader`.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Object safe `Read + Seek`.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Read until `buf` is full or the reader is exhausted.
+///
+/// `io::Read::read` may return fewer bytes than requested at any time, but
+/// consumers of `IStream::Read` treat a short read as the end of the stream.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn read_full(reader: &mut dyn ReadSeek, mut buf: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while !buf.is_empty() {
+        match reader.read(buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                total += n;
+                buf = &mut buf[n..];
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(total)
+}
+
+/// Length of the reader, without changing the current position.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn stream_len(reader: &mut dyn ReadSeek) -> io::Result<u64> {
+    // `stream_position` needs Rust 1.51.
+    #[allow(clippy::seek_from_current)]
+    let pos = reader.seek(SeekFrom::Current(0))?;
+    let len = reader.seek(SeekFrom::End(0))?;
+    if pos != len {
+        reader.seek(SeekFrom::Start(pos))?;
+    }
+    Ok(len)
+}
+
+#[cfg(windows)]
+mod com_impl {
+    // Put it in a module so that the `ReaderStreamImplClassFactory` struct
+    // does not leak into our public API.
+
+    use super::*;
+    use crate::bindings::{to_hresult, Stream};
+    use crate::memory_stream::seek_from;
+    use com::interfaces::IUnknown;
+    use com::ComPtr;
+    use std::cell::RefCell;
+    use std::ffi::c_void;
+    use std::mem;
+    use std::slice;
+    use webview2_sys::{ISequentialStream, IStream, IStreamVTable};
+    use winapi::shared::minwindef::{DWORD, ULONG};
+    use winapi::shared::ntdef::{HRESULT, LARGE_INTEGER, ULARGE_INTEGER};
+    use winapi::shared::winerror::{
+        E_NOTIMPL, STG_E_ACCESSDENIED, STG_E_INVALIDFUNCTION, STG_E_INVALIDPOINTER, SUCCEEDED, S_OK,
+    };
+    use winapi::um::objidlbase::{STATSTG, STGTY_STREAM};
+
+    #[com::co_class(implements(IStream))]
+    pub struct ReaderStreamImpl {
+        reader: RefCell<Box<dyn ReadSeek>>,
+    }
+
+    impl ReaderStreamImpl {
+        fn new() -> Box<Self> {
+            unreachable!()
+        }
+
+        pub fn new_stream(reader: Box<dyn ReadSeek>) -> Stream {
+            let instance = Self::allocate(RefCell::new(reader));
+            unsafe {
+                instance.add_ref();
+                Stream::from_raw(Box::into_raw(instance) as _)
+            }
+        }
+    }
+
+    impl ISequentialStream for ReaderStreamImpl {
+        unsafe fn read(&self, pv: *mut c_void, cb: ULONG, pcb_read: *mut ULONG) -> HRESULT {
+            if pv.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let buf = slice::from_raw_parts_mut(pv as *mut u8, cb as usize);
+            match read_full(&mut **self.reader.borrow_mut(), buf) {
+                Ok(n) => {
+                    if !pcb_read.is_null() {
+                        pcb_read.write(n as ULONG);
+                    }
+                    S_OK
+                }
+                Err(e) => to_hresult::<()>(Err(e.into())),
+            }
+        }
+
+        unsafe fn write(
+            &self,
+            _pv: *const c_void,
+            _cb: ULONG,
+            _pcb_written: *mut ULONG,
+        ) -> HRESULT {
+            STG_E_ACCESSDENIED
+        }
+    }
+
+    impl IStream for ReaderStreamImpl {
+        unsafe fn seek(
+            &self,
+            dlib_move: LARGE_INTEGER,
+            dw_origin: DWORD,
+            plib_new_position: *mut ULARGE_INTEGER,
+        ) -> HRESULT {
+            let pos = match seek_from(*dlib_move.QuadPart(), dw_origin) {
+                Some(pos) => pos,
+                None => return STG_E_INVALIDFUNCTION,
+            };
+            match self.reader.borrow_mut().seek(pos) {
+                Ok(new_pos) => {
+                    if !plib_new_position.is_null() {
+                        *(*plib_new_position).QuadPart_mut() = new_pos;
+                    }
+                    S_OK
+                }
+                Err(e) => to_hresult::<()>(Err(e.into())),
+            }
+        }
+
+        unsafe fn set_size(&self, _lib_new_size: ULARGE_INTEGER) -> HRESULT {
+            STG_E_ACCESSDENIED
+        }
+
+        unsafe fn copy_to(
+            &self,
+            pstm: *mut *mut IStreamVTable,
+            cb: ULARGE_INTEGER,
+            pcb_read: *mut ULARGE_INTEGER,
+            pcb_written: *mut ULARGE_INTEGER,
+        ) -> HRESULT {
+            if pstm.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let target = ComPtr::<dyn IStream>::new(pstm);
+            let mut remaining = *cb.QuadPart();
+            let mut read = 0u64;
+            let mut written = 0u64;
+            let mut buf = [0u8; 8192];
+            let mut reader = self.reader.borrow_mut();
+
+            let mut result = S_OK;
+            while remaining > 0 {
+                let len = remaining.min(buf.len() as u64) as usize;
+                let n = match read_full(&mut **reader, &mut buf[..len]) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) => {
+                        result = to_hresult::<()>(Err(e.into()));
+                        break;
+                    }
+                };
+                read += n as u64;
+                remaining -= n as u64;
+
+                let mut n_written: ULONG = 0;
+                result = target.write(buf.as_ptr() as *const _, n as ULONG, &mut n_written);
+                written += u64::from(n_written);
+                if !SUCCEEDED(result) || (n_written as usize) < n {
+                    break;
+                }
+            }
+
+            if !pcb_read.is_null() {
+                *(*pcb_read).QuadPart_mut() = read;
+            }
+            if !pcb_written.is_null() {
+                *(*pcb_written).QuadPart_mut() = written;
+            }
+            result
+        }
+
+        unsafe fn commit(&self, _grf_commit_flags: DWORD) -> HRESULT {
+            S_OK
+        }
+
+        unsafe fn revert(&self) -> HRESULT {
+            S_OK
+        }
+
+        unsafe fn lock_region(
+            &self,
+            _lib_offset: ULARGE_INTEGER,
+            _cb: ULARGE_INTEGER,
+            _dw_lock_type: DWORD,
+        ) -> HRESULT {
+            STG_E_INVALIDFUNCTION
+        }
+
+        unsafe fn unlock_region(
+            &self,
+            _lib_offset: ULARGE_INTEGER,
+            _cb: ULARGE_INTEGER,
+            _dw_lock_type: DWORD,
+        ) -> HRESULT {
+            STG_E_INVALIDFUNCTION
+        }
+
+        unsafe fn stat(&self, pstatstg: *mut STATSTG, _grf_stat_flag: DWORD) -> HRESULT {
+            if pstatstg.is_null() {
+                return STG_E_INVALIDPOINTER;
+            }
+            let len = match stream_len(&mut **self.reader.borrow_mut()) {
+                Ok(len) => len,
+                Err(e) => return to_hresult::<()>(Err(e.into())),
+            };
+            // Zeroed: no name, no timestamps, `STGM_READ` mode and no locks.
+            let mut stat: STATSTG = mem::zeroed();
+            stat.type_ = STGTY_STREAM;
+            *stat.cbSize.QuadPart_mut() = len;
+            pstatstg.write(stat);
+            S_OK
+        }
+
+        unsafe fn clone(&self, _ppstm: *mut *mut *mut IStreamVTable) -> HRESULT {
+            // An arbitrary reader can't be duplicated.
+            E_NOTIMPL
+        }
+    }
+}
+
+#[cfg(windows)]
+pub(crate) use self::com_impl::ReaderStreamImpl;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Returns at most one byte per `read` call.
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    impl Seek for Trickle {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_read_full() {
+        let mut reader = Trickle(Cursor::new(b"hello, world".to_vec()));
+        let mut buf = [0u8; 5];
+        assert_eq!(read_full(&mut reader, &mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"hello");
+
+        let mut buf = [0u8; 16];
+        assert_eq!(read_full(&mut reader, &mut buf).unwrap(), 7);
+        assert_eq!(&buf[..7], b", world");
+        assert_eq!(read_full(&mut reader, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_stream_len() {
+        let mut reader = Cursor::new(b"hello, world".to_vec());
+        reader.seek(SeekFrom::Start(4)).unwrap();
+        assert_eq!(stream_len(&mut reader).unwrap(), 12);
+        assert_eq!(reader.position(), 4);
+
+        reader.seek(SeekFrom::End(0)).unwrap();
+        assert_eq!(stream_len(&mut reader).unwrap(), 12);
+        assert_eq!(reader.position(), 12);
+        let mut empty = Cursor::new(Vec::new());
+        assert_eq!(stream_len(&mut empty).unwrap(), 0);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_reader_stream() {
+        use crate::{check_hresult, Stream};
+        use std::mem::MaybeUninit;
+        use webview2_sys::IStream;
+        use winapi::um::objidlbase::STATSTG;
+
+        let reader = Trickle(Cursor::new(b"hello, world".to_vec()));
+        let mut stream = Stream::from_reader(reader);
+
+        let mut buf = Vec::new();
+        stream.seek(SeekFrom::Start(7)).unwrap();
+        stream.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"world");
+
+        assert_eq!(stream.seek(SeekFrom::End(-5)).unwrap(), 7);
+        assert!(stream.seek(SeekFrom::Current(-8)).is_err());
+
+        let mut stat = MaybeUninit::<STATSTG>::uninit();
+        check_hresult(unsafe { stream.as_inner().stat(stat.as_mut_ptr(), 0) }).unwrap();
+        let stat = unsafe { stat.assume_init() };
+        assert_eq!(unsafe { *stat.cbSize.QuadPart() }, 12);
+        assert!(stat.pwcsName.is_null());
+
+        assert!(io::Write::write(&mut stream, b"!").is_err());
+    }
+}
@@ -0,0 +1,1129 @@
+// JSON-RPC 2.0 between the host and page JavaScript over web messages.
+//
+// Both sides are clients and servers: the host calls methods registered in
+// the page and vice versa. Request ids are only unique per direction, which
+// is enough because a message is either a request or a response.
+
+use crate::typescript::{TsBindings, TypeScript};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Method of the notification cancelling a request, as in the Language
+/// Server Protocol. Its params are `{"id": <id>}`.
+pub const CANCEL_METHOD: &str = "$/cancelRequest";
+
+/// A JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+    /// The request did not complete in time.
+    pub const TIMEOUT: i64 = -32000;
+    /// The request was cancelled, or the endpoint was closed.
+    pub const REQUEST_CANCELLED: i64 = -32800;
+
+    pub fn new(code: i64, message: &str) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    fn method_not_found(method: &str) -> Self {
+        Self::new(Self::METHOD_NOT_FOUND, "Method not found").with_data(json!(method))
+    }
+
+    fn cancelled() -> Self {
+        Self::new(Self::REQUEST_CANCELLED, "Request cancelled")
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Sends encoded messages to the other side.
+pub trait Transport {
+    fn send(&self, message: &str);
+}
+
+// Collects the sent messages, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct QueueTransport(RefCell<Vec<String>>);
+
+#[cfg(test)]
+impl QueueTransport {
+    // Remove the messages sent so far.
+    pub(crate) fn take(&self) -> Vec<String> {
+        self.0.borrow_mut().drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+impl Transport for QueueTransport {
+    fn send(&self, message: &str) {
+        self.0.borrow_mut().push(message.into());
+    }
+}
+
+/// Id of a request sent by `RpcEndpoint::call`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(pub u64);
+
+type Callback = Box<dyn FnOnce(Result<Value, RpcError>)>;
+type Method = Rc<dyn Fn(Value, Responder)>;
+type Notification = Rc<dyn Fn(Value)>;
+type DeadlineHandler = Rc<dyn Fn(Instant)>;
+
+struct Pending {
+    callback: Callback,
+    deadline: Option<Instant>,
+}
+
+// Incoming request that has not been answered yet.
+struct Incoming {
+    cancelled: Cell<bool>,
+    answered: Cell<bool>,
+}
+
+struct Inner {
+    transport: Rc<dyn Transport>,
+    next_id: Cell<u64>,
+    pending: RefCell<HashMap<u64, Pending>>,
+    incoming: RefCell<HashMap<String, Rc<Incoming>>>,
+    methods: RefCell<HashMap<String, Method>>,
+    notifications: RefCell<HashMap<String, Notification>>,
+    typescript: RefCell<TsBindings>,
+    on_deadline: RefCell<Option<DeadlineHandler>>,
+}
+
+/// One side of a JSON-RPC 2.0 connection.
+///
+/// Feed received messages to `receive`. Outgoing requests time out only
+/// when `expire` is called, e.g. from a timer scheduled by `on_deadline`.
+#[derive(Clone)]
+pub struct RpcEndpoint {
+    inner: Rc<Inner>,
+}
+
+impl fmt::Debug for RpcEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut methods: Vec<String> = self.inner.methods.borrow().keys().cloned().collect();
+        methods.sort();
+        f.debug_struct("RpcEndpoint")
+            .field("methods", &methods)
+            .field("pending", &self.inner.pending.borrow().len())
+            .finish()
+    }
+}
+
+/// Answers an incoming request, possibly later.
+///
+/// Dropping a responder without answering responds with an internal error.
+pub struct Responder {
+    endpoint: RpcEndpoint,
+    id: Value,
+    state: Rc<Incoming>,
+}
+
+impl fmt::Debug for Responder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Responder").field("id", &self.id).finish()
+    }
+}
+
+impl Responder {
+    /// Whether the caller cancelled the request. The response is not sent
+    /// in this case.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.get()
+    }
+
+    pub fn respond(self, result: Result<Value, RpcError>) {
+        self.send(result);
+    }
+
+    fn send(&self, result: Result<Value, RpcError>) {
+        if self.state.answered.replace(true) {
+            return;
+        }
+        self.endpoint
+            .inner
+            .incoming
+            .borrow_mut()
+            .remove(&self.id.to_string());
+        if !self.state.cancelled.get() {
+            self.endpoint.send(response(self.id.clone(), result));
+        }
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        self.send(Err(RpcError::new(
+            RpcError::INTERNAL_ERROR,
+            "Request dropped without a response",
+        )));
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+    }
+}
+
+impl RpcEndpoint {
+    pub fn new(transport: Rc<dyn Transport>) -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                transport,
+                next_id: Cell::new(1),
+                pending: RefCell::default(),
+                incoming: RefCell::default(),
+                methods: RefCell::default(),
+                notifications: RefCell::default(),
+                typescript: RefCell::default(),
+                on_deadline: RefCell::default(),
+            }),
+        }
+    }
+
+    /// Call `f` with the deadline of every request sent with a timeout, to
+    /// schedule `expire`. Replaces the previous handler.
+    pub fn on_deadline(&self, f: impl Fn(Instant) + 'static) {
+        *self.inner.on_deadline.borrow_mut() = Some(Rc::new(f));
+    }
+
+    /// Register a method answering synchronously.
+    pub fn register(
+        &self,
+        method: &str,
+        handler: impl Fn(Value) -> Result<Value, RpcError> + 'static,
+    ) {
+        self.register_async(method, move |params, responder| {
+            responder.respond(handler(params))
+        });
+    }
+
+    /// Register a method with typed params and result. Params that don't
+    /// deserialize are answered with an invalid params error.
+    pub fn register_typed<P, R>(
+        &self,
+        method: &str,
+        handler: impl Fn(P) -> Result<R, RpcError> + 'static,
+    ) where
+        P: DeserializeOwned,
+        R: Serialize,
+    {
+        self.register(method, move |params| {
+            let params = serde_json::from_value(params).map_err(|e| {
+                RpcError::new(RpcError::INVALID_PARAMS, "Invalid params")
+                    .with_data(json!(e.to_string()))
+            })?;
+            let result = handler(params)?;
+            serde_json::to_value(result).map_err(|e| {
+                RpcError::new(RpcError::INTERNAL_ERROR, "Invalid result")
+                    .with_data(json!(e.to_string()))
+            })
+        });
+    }
+
+    /// Like `register_typed`, and include the method in
+    /// `typescript_declarations`.
+    pub fn register_typed_ts<P, R>(
+        &self,
+        method: &str,
+        handler: impl Fn(P) -> Result<R, RpcError> + 'static,
+    ) where
+        P: DeserializeOwned + TypeScript,
+        R: Serialize + TypeScript,
+    {
+        self.register_typed(method, handler);
+        self.inner
+            .typescript
+            .borrow_mut()
+            .add_method::<P, R>(method);
+    }
+
+    /// Register a method that answers through the `Responder`, possibly
+    /// after the handler has returned.
+    pub fn register_async(&self, method: &str, handler: impl Fn(Value, Responder) + 'static) {
+        self.inner
+            .methods
+            .borrow_mut()
+            .insert(method.into(), Rc::new(handler));
+    }
+
+    /// Handle notifications of a method.
+    pub fn on_notification(&self, method: &str, handler: impl Fn(Value) + 'static) {
+        self.inner
+            .notifications
+            .borrow_mut()
+            .insert(method.into(), Rc::new(handler));
+    }
+
+    /// Handle notifications with typed params. Params that don't
+    /// deserialize are ignored.
+    pub fn on_typed_notification<P>(&self, method: &str, handler: impl Fn(P) + 'static)
+    where
+        P: DeserializeOwned,
+    {
+        self.on_notification(method, move |params| {
+            if let Ok(params) = serde_json::from_value(params) {
+                handler(params);
+            }
+        });
+    }
+
+    /// Like `on_typed_notification`, and include the notification in
+    /// `typescript_declarations`.
+    pub fn on_typed_notification_ts<P>(&self, method: &str, handler: impl Fn(P) + 'static)
+    where
+        P: DeserializeOwned + TypeScript,
+    {
+        self.on_typed_notification(method, handler);
+        self.inner
+            .typescript
+            .borrow_mut()
+            .add_notification::<P>(method);
+    }
+
+    /// Declare a method the page registers, for `typescript_declarations`.
+    pub fn declare_page_method<P: TypeScript, R: TypeScript>(&self, method: &str) {
+        self.inner
+            .typescript
+            .borrow_mut()
+            .add_page_method::<P, R>(method);
+    }
+
+    /// Declare a notification the host sends, for `typescript_declarations`.
+    pub fn declare_page_notification<P: TypeScript>(&self, method: &str) {
+        self.inner
+            .typescript
+            .borrow_mut()
+            .add_page_notification::<P>(method);
+    }
+
+    pub fn unregister(&self, method: &str) {
+        self.inner.methods.borrow_mut().remove(method);
+        self.inner.notifications.borrow_mut().remove(method);
+        self.inner.typescript.borrow_mut().remove(method);
+    }
+
+    /// A `.d.ts` file declaring the client installed by `client_script`,
+    /// with the typed methods and notifications of this endpoint.
+    pub fn typescript_declarations(&self, global: &str) -> String {
+        self.inner.typescript.borrow().to_declarations(global)
+    }
+
+    /// Call a method on the other side.
+    ///
+    /// The callback is called exactly once: with the result, the error
+    /// object, or a timeout or cancellation error.
+    pub fn call(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Option<Duration>,
+        callback: impl FnOnce(Result<Value, RpcError>) + 'static,
+    ) -> RequestId {
+        let id = self.inner.next_id.get();
+        self.inner.next_id.set(id + 1);
+        let deadline = timeout.map(|t| Instant::now() + t);
+        self.inner.pending.borrow_mut().insert(
+            id,
+            Pending {
+                callback: Box::new(callback),
+                deadline,
+            },
+        );
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+        if let Some(deadline) = deadline {
+            // Clone the handler so that it can replace itself.
+            let on_deadline = self.inner.on_deadline.borrow().clone();
+            if let Some(on_deadline) = on_deadline {
+                on_deadline(deadline);
+            }
+        }
+        RequestId(id)
+    }
+
+    /// Send a notification, which has no response.
+    pub fn notify(&self, method: &str, params: Value) {
+        self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}));
+    }
+
+    /// Cancel a pending request. Its callback gets a cancellation error, and
+    /// the other side is notified. Returns whether the request was pending.
+    pub fn cancel(&self, id: RequestId) -> bool {
+        let pending = self.inner.pending.borrow_mut().remove(&id.0);
+        match pending {
+            Some(pending) => {
+                self.notify(CANCEL_METHOD, json!({ "id": id.0 }));
+                (pending.callback)(Err(RpcError::cancelled()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fail the requests whose timeout elapsed before `now`.
+    pub fn expire(&self, now: Instant) {
+        let expired: Vec<(u64, Pending)> = {
+            let mut pending = self.inner.pending.borrow_mut();
+            let mut ids: Vec<u64> = pending
+                .iter()
+                .filter(|(_, p)| match p.deadline {
+                    Some(deadline) => deadline <= now,
+                    None => false,
+                })
+                .map(|(id, _)| *id)
+                .collect();
+            ids.sort_unstable();
+            ids.into_iter()
+                .map(|id| (id, pending.remove(&id).unwrap()))
+                .collect()
+        };
+        for (id, pending) in expired {
+            self.notify(CANCEL_METHOD, json!({ "id": id }));
+            (pending.callback)(Err(RpcError::new(RpcError::TIMEOUT, "Request timed out")));
+        }
+    }
+
+    /// The earliest deadline of the pending requests, to schedule `expire`.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.inner
+            .pending
+            .borrow()
+            .values()
+            .filter_map(|p| p.deadline)
+            .min()
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.inner.pending.borrow().len()
+    }
+
+    /// Fail every pending request, e.g. when the page navigated away and
+    /// will not answer. Incoming requests are not answered anymore.
+    pub fn close(&self) {
+        let pending: Vec<(u64, Pending)> = self.inner.pending.borrow_mut().drain().collect();
+        let mut pending = pending;
+        pending.sort_by_key(|(id, _)| *id);
+        for (_, pending) in pending {
+            (pending.callback)(Err(RpcError::new(
+                RpcError::REQUEST_CANCELLED,
+                "Connection closed",
+            )));
+        }
+        for (_, incoming) in self.inner.incoming.borrow_mut().drain() {
+            incoming.cancelled.set(true);
+        }
+    }
+
+    /// Handle a received message: a request, notification, response or
+    /// batch.
+    pub fn receive(&self, message: &str) {
+        let value: Value = match serde_json::from_str(message) {
+            Ok(value) => value,
+            Err(e) => {
+                let error = RpcError::new(RpcError::PARSE_ERROR, "Parse error")
+                    .with_data(json!(e.to_string()));
+                self.send(response(Value::Null, Err(error)));
+                return;
+            }
+        };
+        match value {
+            Value::Array(batch) if batch.is_empty() => self.invalid_request(Value::Null),
+            // Responses to batch items are sent one by one rather than as a
+            // batch, because async methods may answer in any order.
+            Value::Array(batch) => batch.into_iter().for_each(|v| self.receive_one(v)),
+            value => self.receive_one(value),
+        }
+    }
+
+    fn receive_one(&self, value: Value) {
+        let mut object = match value {
+            Value::Object(object) => object,
+            _ => return self.invalid_request(Value::Null),
+        };
+        let id = object.remove("id");
+        match object.remove("method") {
+            Some(Value::String(method)) => {
+                let params = object.remove("params").unwrap_or(Value::Null);
+                match id {
+                    None => self.handle_notification(&method, params),
+                    Some(id @ Value::Number(_))
+                    | Some(id @ Value::String(_))
+                    | Some(id @ Value::Null) => self.handle_request(id, &method, params),
+                    Some(_) => self.invalid_request(Value::Null),
+                }
+            }
+            Some(_) => self.invalid_request(id.unwrap_or(Value::Null)),
+            None => self.handle_response(id, object),
+        }
+    }
+
+    fn invalid_request(&self, id: Value) {
+        let error = RpcError::new(RpcError::INVALID_REQUEST, "Invalid Request");
+        self.send(response(id, Err(error)));
+    }
+
+    fn handle_notification(&self, method: &str, params: Value) {
+        if method == CANCEL_METHOD {
+            if let Some(id) = params.get("id") {
+                let incoming = self.inner.incoming.borrow_mut().remove(&id.to_string());
+                if let Some(incoming) = incoming {
+                    incoming.cancelled.set(true);
+                }
+            }
+            return;
+        }
+        // Clone the handler so that it can register or unregister methods.
+        let handler = self.inner.notifications.borrow().get(method).cloned();
+        if let Some(handler) = handler {
+            handler(params);
+        }
+    }
+
+    fn handle_request(&self, id: Value, method: &str, params: Value) {
+        let key = id.to_string();
+        if self.inner.incoming.borrow().contains_key(&key) {
+            // Answering with the id would settle the original request.
+            let error =
+                RpcError::new(RpcError::INVALID_REQUEST, "Duplicate request id").with_data(id);
+            self.send(response(Value::Null, Err(error)));
+            return;
+        }
+        let handler = self.inner.methods.borrow().get(method).cloned();
+        let state = Rc::new(Incoming {
+            cancelled: Cell::new(false),
+            answered: Cell::new(false),
+        });
+        self.inner
+            .incoming
+            .borrow_mut()
+            .insert(key, Rc::clone(&state));
+        let responder = Responder {
+            endpoint: self.clone(),
+            id,
+            state,
+        };
+        match handler {
+            Some(handler) => handler(params, responder),
+            None => responder.respond(Err(RpcError::method_not_found(method))),
+        }
+    }
+
+    fn handle_response(&self, id: Option<Value>, mut object: Map<String, Value>) {
+        let id = match id.as_ref().and_then(Value::as_u64) {
+            Some(id) => id,
+            // Errors about our malformed messages have a null id; there is no
+            // request to fail.
+            None => return,
+        };
+        let pending = match self.inner.pending.borrow_mut().remove(&id) {
+            Some(pending) => pending,
+            // Cancelled or timed out.
+            None => return,
+        };
+        let result = match (object.remove("result"), object.remove("error")) {
+            (Some(result), None) => Ok(result),
+            (None, Some(error)) => Err(serde_json::from_value(error).unwrap_or_else(|_| {
+                RpcError::new(RpcError::INTERNAL_ERROR, "Invalid error object")
+            })),
+            _ => Err(RpcError::new(RpcError::INVALID_REQUEST, "Invalid response")),
+        };
+        (pending.callback)(result);
+    }
+
+    fn send(&self, message: Value) {
+        self.inner.transport.send(&message.to_string());
+    }
+
+    /// JavaScript defining the page side as `window[global]`.
+    ///
+    /// It provides `call(method, params, {timeout})` returning a promise,
+    /// `notify(method, params)`, `cancel(id)`, `register(method, fn)` where
+    /// `fn` may return a promise, `unregister(method)` and
+    /// `onNotification(method, fn)`. Errors thrown by registered functions
+    /// are answered with an internal error carrying the error message.
+    pub fn client_script(global: &str) -> String {
+        CLIENT_SCRIPT.replace("$GLOBAL", &Value::String(global.into()).to_string())
+    }
+}
+
+const CLIENT_SCRIPT: &str = r#"(function () {
+  var webview = window.chrome && window.chrome.webview;
+  var nextId = 1;
+  var pending = {};
+  var methods = {};
+  var notifications = {};
+  var incoming = {};
+
+  function send(message) {
+    message.jsonrpc = "2.0";
+    webview.postMessage(message);
+  }
+
+  function respond(id, error, result) {
+    if (incoming[id] === undefined) {
+      return;
+    }
+    delete incoming[id];
+    if (error) {
+      send({ id: id, error: error });
+    } else {
+      send({ id: id, result: result === undefined ? null : result });
+    }
+  }
+
+  function toError(e) {
+    if (e && typeof e.code === "number" && typeof e.message === "string") {
+      return { code: e.code, message: e.message, data: e.data };
+    }
+    return { code: -32603, message: String((e && e.message) || e) };
+  }
+
+  function settle(id, error, result) {
+    var p = pending[id];
+    if (!p) {
+      return;
+    }
+    delete pending[id];
+    clearTimeout(p.timer);
+    if (error) {
+      var e = new Error(error.message);
+      e.code = error.code;
+      e.data = error.data;
+      p.reject(e);
+    } else {
+      p.resolve(result);
+    }
+  }
+
+  function handle(message) {
+    if (!message || typeof message !== "object" || message.jsonrpc !== "2.0") {
+      return;
+    }
+    if (typeof message.method === "string") {
+      if (message.id === undefined) {
+        if (message.method === "$/cancelRequest") {
+          delete incoming[message.params && message.params.id];
+          return;
+        }
+        var n = notifications[message.method];
+        if (n) {
+          n(message.params);
+        }
+        return;
+      }
+      var id = message.id;
+      incoming[id] = true;
+      var f = methods[message.method];
+      if (!f) {
+        respond(id, { code: -32601, message: "Method not found", data: message.method });
+        return;
+      }
+      Promise.resolve()
+        .then(function () {
+          return f(message.params);
+        })
+        .then(
+          function (result) {
+            respond(id, null, result);
+          },
+          function (e) {
+            respond(id, toError(e));
+          }
+        );
+    } else if (message.id !== undefined && message.id !== null) {
+      settle(message.id, message.error, message.result);
+    }
+  }
+
+  var rpc = {
+    call: function (method, params, options) {
+      var id = nextId++;
+      var promise = new Promise(function (resolve, reject) {
+        var p = { resolve: resolve, reject: reject };
+        var timeout = options && options.timeout;
+        if (timeout) {
+          p.timer = setTimeout(function () {
+            rpc.notify("$/cancelRequest", { id: id });
+            settle(id, { code: -32000, message: "Request timed out" });
+          }, timeout);
+        }
+        pending[id] = p;
+      });
+      promise.id = id;
+      send({ id: id, method: method, params: params === undefined ? null : params });
+      return promise;
+    },
+    notify: function (method, params) {
+      send({ method: method, params: params === undefined ? null : params });
+    },
+    cancel: function (id) {
+      if (pending[id]) {
+        rpc.notify("$/cancelRequest", { id: id });
+        settle(id, { code: -32800, message: "Request cancelled" });
+      }
+    },
+    register: function (method, f) {
+      methods[method] = f;
+    },
+    unregister: function (method) {
+      delete methods[method];
+      delete notifications[method];
+    },
+    onNotification: function (method, f) {
+      notifications[method] = f;
+    },
+  };
+  window[$GLOBAL] = rpc;
+
+  if (webview) {
+    webview.addEventListener("message", function (event) {
+      var data = event.data;
+      if (Array.isArray(data)) {
+        data.forEach(handle);
+      } else {
+        handle(data);
+      }
+    });
+  }
+})();
+"#;
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::origin::{MessageDirection, OriginAllowlist};
+    use crate::timer::set_timer;
+    use crate::{EventRegistrationToken, WebView};
+
+    /// Posts messages to a `WebView` as JSON.
+    #[derive(Debug, Clone)]
+    pub struct WebViewTransport {
+        webview: WebView,
+        origins: Option<OriginAllowlist>,
+    }
+
+    impl WebViewTransport {
+        pub fn new(webview: WebView) -> Self {
+            Self {
+                webview,
+                origins: None,
+            }
+        }
+
+        /// Don't post to documents from other origins.
+        pub fn with_origins(mut self, origins: OriginAllowlist) -> Self {
+            self.origins = Some(origins);
+            self
+        }
+    }
+
+    impl Transport for WebViewTransport {
+        fn send(&self, message: &str) {
+            // Fails only when the webview is gone, and the request will then
+            // time out or be closed.
+            let _ = match self.origins {
+                Some(ref origins) => origins
+                    .post_web_message_as_json(&self.webview, message)
+                    .map(|_| ()),
+                None => self.webview.post_web_message_as_json(message),
+            };
+        }
+    }
+
+    impl RpcEndpoint {
+        /// An endpoint talking to page JavaScript through web messages.
+        ///
+        /// The client script is installed as `window[global]` for new
+        /// documents. Pending requests fail when a new document starts
+        /// loading (`ContentLoading`, so not for navigations that are
+        /// cancelled), and time out from a timer of the thread's message
+        /// loop. The endpoint and the webview reference each other until
+        /// the returned tokens are removed, the second one with
+        /// `remove_content_loading`.
+        pub fn attach(
+            webview: &WebView,
+            global: &str,
+        ) -> crate::Result<(Self, EventRegistrationToken, EventRegistrationToken)> {
+            Self::attach_impl(webview, global, None)
+        }
+
+        /// Like `attach`, but only exchange messages with documents from
+        /// these origins.
+        pub fn attach_with_origins(
+            webview: &WebView,
+            global: &str,
+            origins: OriginAllowlist,
+        ) -> crate::Result<(Self, EventRegistrationToken, EventRegistrationToken)> {
+            Self::attach_impl(webview, global, Some(origins))
+        }
+
+        fn attach_impl(
+            webview: &WebView,
+            global: &str,
+            origins: Option<OriginAllowlist>,
+        ) -> crate::Result<(Self, EventRegistrationToken, EventRegistrationToken)> {
+            let mut transport = WebViewTransport::new(webview.clone());
+            if let Some(ref origins) = origins {
+                transport = transport.with_origins(origins.clone());
+            }
+            let endpoint = Self::new(Rc::new(transport));
+            webview
+                .add_script_to_execute_on_document_created(&Self::client_script(global), |_| {
+                    Ok(())
+                })?;
+            let e = endpoint.clone();
+            let message_token = webview.add_web_message_received(move |_, args| {
+                if let Some(ref origins) = origins {
+                    if !origins.check(&args.get_source()?, MessageDirection::Incoming) {
+                        return Ok(());
+                    }
+                }
+                e.receive(&args.get_web_message_as_json()?);
+                Ok(())
+            })?;
+            let weak = Rc::downgrade(&endpoint.inner);
+            endpoint.on_deadline(move |deadline| {
+                let weak = weak.clone();
+                let delay = deadline.saturating_duration_since(Instant::now());
+                // Without a timer the request fails when the page navigates
+                // away or the endpoint is closed.
+                let _ = set_timer(delay, move || {
+                    if let Some(inner) = weak.upgrade() {
+                        // The timer may fire a little early.
+                        let now = Instant::now().max(deadline);
+                        RpcEndpoint { inner }.expire(now);
+                    }
+                });
+            });
+            let e = endpoint.clone();
+            // The current document is gone.
+            let content_loading_token = webview.add_content_loading(move |_, _| {
+                e.close();
+                Ok(())
+            })?;
+            Ok((endpoint, message_token, content_loading_token))
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use self::win::WebViewTransport;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Connects two endpoints. Messages are queued and delivered by `run`, so
+    // that nothing is reentrant.
+    #[derive(Default)]
+    struct Pipe {
+        queue: RefCell<Vec<(bool, String)>>,
+        log: RefCell<Vec<String>>,
+    }
+
+    struct End(Rc<Pipe>, bool);
+
+    impl Transport for End {
+        fn send(&self, message: &str) {
+            self.0.log.borrow_mut().push(message.into());
+            self.0.queue.borrow_mut().push((self.1, message.into()));
+        }
+    }
+
+    fn pair() -> (Rc<Pipe>, RpcEndpoint, RpcEndpoint) {
+        let pipe = Rc::new(Pipe::default());
+        let a = RpcEndpoint::new(Rc::new(End(Rc::clone(&pipe), true)));
+        let b = RpcEndpoint::new(Rc::new(End(Rc::clone(&pipe), false)));
+        (pipe, a, b)
+    }
+
+    fn run(pipe: &Pipe, a: &RpcEndpoint, b: &RpcEndpoint) {
+        loop {
+            let messages: Vec<_> = pipe.queue.borrow_mut().drain(..).collect();
+            if messages.is_empty() {
+                break;
+            }
+            for (from_a, message) in messages {
+                if from_a { b } else { a }.receive(&message);
+            }
+        }
+    }
+
+    fn result_slot() -> (
+        Rc<RefCell<Option<Result<Value, RpcError>>>>,
+        impl FnOnce(Result<Value, RpcError>),
+    ) {
+        let slot = Rc::new(RefCell::new(None));
+        let s = Rc::clone(&slot);
+        (slot, move |r| *s.borrow_mut() = Some(r))
+    }
+
+    #[test]
+    fn test_call() {
+        let (pipe, a, b) = pair();
+        b.register_typed("add", |(x, y): (i32, i32)| Ok(x + y));
+        let (slot, callback) = result_slot();
+        a.call("add", json!([1, 2]), None, callback);
+        assert_eq!(a.pending_count(), 1);
+        run(&pipe, &a, &b);
+        assert_eq!(slot.borrow_mut().take(), Some(Ok(json!(3))));
+        assert_eq!(a.pending_count(), 0);
+        assert_eq!(
+            pipe.log.borrow()[0],
+            r#"{"id":1,"jsonrpc":"2.0","method":"add","params":[1,2]}"#
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let (pipe, a, b) = pair();
+        b.register_typed("add", |(x, y): (i32, i32)| Ok(x + y));
+        b.register("fail", |_| {
+            Err(RpcError::new(7, "nope").with_data(json!({"x": 1})))
+        });
+
+        let (slot, callback) = result_slot();
+        a.call("missing", Value::Null, None, callback);
+        run(&pipe, &a, &b);
+        let error = slot.borrow_mut().take().unwrap().unwrap_err();
+        assert_eq!(error.code, RpcError::METHOD_NOT_FOUND);
+        assert_eq!(error.data, Some(json!("missing")));
+
+        let (slot, callback) = result_slot();
+        a.call("add", json!("x"), None, callback);
+        run(&pipe, &a, &b);
+        let error = slot.borrow_mut().take().unwrap().unwrap_err();
+        assert_eq!(error.code, RpcError::INVALID_PARAMS);
+
+        let (slot, callback) = result_slot();
+        a.call("fail", Value::Null, None, callback);
+        run(&pipe, &a, &b);
+        assert_eq!(
+            slot.borrow_mut().take(),
+            Some(Err(RpcError::new(7, "nope").with_data(json!({"x": 1}))))
+        );
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let (pipe, a, _) = pair();
+        a.receive("{");
+        a.receive("[]");
+        a.receive("42");
+        a.receive(r#"{"jsonrpc": "2.0", "id": 3, "method": 1}"#);
+        let log = pipe.log.borrow();
+        let codes: Vec<i64> = log
+            .iter()
+            .map(|m| {
+                serde_json::from_str::<Value>(m).unwrap()["error"]["code"]
+                    .as_i64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                RpcError::PARSE_ERROR,
+                RpcError::INVALID_REQUEST,
+                RpcError::INVALID_REQUEST,
+                RpcError::INVALID_REQUEST,
+            ]
+        );
+        assert!(log[3].contains(r#""id":3"#));
+    }
+
+    #[test]
+    fn test_duplicate_request_id() {
+        let (pipe, _, b) = pair();
+        let responders = Rc::new(RefCell::new(Vec::new()));
+        let r = Rc::clone(&responders);
+        b.register_async("slow", move |_, responder| r.borrow_mut().push(responder));
+        b.receive(r#"{"jsonrpc": "2.0", "id": 1, "method": "slow"}"#);
+        b.receive(r#"{"jsonrpc": "2.0", "id": 1, "method": "slow"}"#);
+        assert_eq!(responders.borrow().len(), 1);
+        assert_eq!(
+            pipe.log.borrow().last().unwrap(),
+            r#"{"error":{"code":-32600,"data":1,"message":"Duplicate request id"},"id":null,"jsonrpc":"2.0"}"#
+        );
+
+        // The first request is still answered, and the id can be reused.
+        responders.borrow_mut().pop().unwrap().respond(Ok(json!(1)));
+        assert_eq!(
+            pipe.log.borrow().last().unwrap(),
+            r#"{"id":1,"jsonrpc":"2.0","result":1}"#
+        );
+        b.receive(r#"{"jsonrpc": "2.0", "id": 1, "method": "slow"}"#);
+        assert_eq!(responders.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_notifications_and_batch() {
+        let (pipe, a, b) = pair();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let s = Rc::clone(&seen);
+        b.on_notification("log", move |params| s.borrow_mut().push(params));
+        b.register("echo", Ok);
+        a.notify("log", json!("hello"));
+        a.notify("unknown", json!(1));
+        run(&pipe, &a, &b);
+        assert_eq!(*seen.borrow(), vec![json!("hello")]);
+        // Notifications are never answered.
+        assert_eq!(pipe.log.borrow().len(), 2);
+
+        b.receive(
+            r#"[{"jsonrpc": "2.0", "method": "log", "params": 1},
+                {"jsonrpc": "2.0", "id": "x", "method": "echo", "params": 2}]"#,
+        );
+        assert_eq!(*seen.borrow(), vec![json!("hello"), json!(1)]);
+        assert_eq!(
+            pipe.log.borrow().last().unwrap(),
+            r#"{"id":"x","jsonrpc":"2.0","result":2}"#
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (pipe, a, b) = pair();
+        let responders = Rc::new(RefCell::new(Vec::new()));
+        let r = Rc::clone(&responders);
+        b.register_async("slow", move |_, responder| r.borrow_mut().push(responder));
+        let scheduled = Rc::new(RefCell::new(Vec::new()));
+        let s = Rc::clone(&scheduled);
+        a.on_deadline(move |deadline| s.borrow_mut().push(deadline));
+
+        let (slot, callback) = result_slot();
+        a.call("slow", Value::Null, Some(Duration::from_secs(5)), callback);
+        a.call("slow", Value::Null, None, |_| {});
+        run(&pipe, &a, &b);
+        let deadline = a.next_deadline().unwrap();
+        assert_eq!(*scheduled.borrow(), vec![deadline]);
+
+        a.expire(deadline - Duration::from_millis(1));
+        assert!(slot.borrow().is_none());
+        a.expire(deadline);
+        let error = slot.borrow_mut().take().unwrap().unwrap_err();
+        assert_eq!(error.code, RpcError::TIMEOUT);
+        assert_eq!(a.next_deadline(), None);
+
+        // The other side learns about the cancellation and does not answer.
+        run(&pipe, &a, &b);
+        let responder = responders.borrow_mut().remove(0);
+        assert!(responder.is_cancelled());
+        let sent = pipe.log.borrow().len();
+        responder.respond(Ok(json!(1)));
+        assert_eq!(pipe.log.borrow().len(), sent);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let (pipe, a, b) = pair();
+        let responders = Rc::new(RefCell::new(Vec::new()));
+        let r = Rc::clone(&responders);
+        b.register_async("slow", move |_, responder| r.borrow_mut().push(responder));
+
+        let (slot, callback) = result_slot();
+        let id = a.call("slow", Value::Null, None, callback);
+        run(&pipe, &a, &b);
+        assert!(a.cancel(id));
+        assert!(!a.cancel(id));
+        assert_eq!(
+            slot.borrow_mut().take().unwrap().unwrap_err().code,
+            RpcError::REQUEST_CANCELLED
+        );
+        run(&pipe, &a, &b);
+        assert!(responders.borrow()[0].is_cancelled());
+    }
+
+    #[test]
+    fn test_async_and_dropped_responder() {
+        let (pipe, a, b) = pair();
+        let responders = Rc::new(RefCell::new(Vec::new()));
+        let r = Rc::clone(&responders);
+        b.register_async("later", move |_, responder| r.borrow_mut().push(responder));
+
+        let (slot1, callback1) = result_slot();
+        let (slot2, callback2) = result_slot();
+        a.call("later", json!(1), None, callback1);
+        a.call("later", json!(2), None, callback2);
+        run(&pipe, &a, &b);
+
+        // Answer out of order; drop the other.
+        let second = responders.borrow_mut().pop().unwrap();
+        second.respond(Ok(json!("two")));
+        responders.borrow_mut().clear();
+        run(&pipe, &a, &b);
+
+        assert_eq!(slot2.borrow_mut().take(), Some(Ok(json!("two"))));
+        assert_eq!(
+            slot1.borrow_mut().take().unwrap().unwrap_err().code,
+            RpcError::INTERNAL_ERROR
+        );
+    }
+
+    #[test]
+    fn test_close_and_reentrancy() {
+        let (pipe, a, b) = pair();
+        b.register_async("never", |_, responder| std::mem::forget(responder));
+
+        // A callback calling into the endpoint again.
+        let (slot, callback) = result_slot();
+        let a2 = a.clone();
+        a.call("never", Value::Null, None, move |r| {
+            callback(r);
+            a2.notify("closed", Value::Null);
+        });
+        run(&pipe, &a, &b);
+        a.close();
+        assert_eq!(
+            slot.borrow_mut().take().unwrap().unwrap_err().code,
+            RpcError::REQUEST_CANCELLED
+        );
+        assert!(pipe.log.borrow().last().unwrap().contains("closed"));
+
+        // Late responses are ignored.
+        a.receive(r#"{"jsonrpc": "2.0", "id": 1, "result": 1}"#);
+    }
+
+    #[test]
+    fn test_client_script() {
+        let script = RpcEndpoint::client_script("rpc");
+        assert!(script.contains(r#"window["rpc"] = rpc;"#));
+        assert!(script.contains(CANCEL_METHOD));
+    }
+}
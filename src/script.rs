@@ -0,0 +1,231 @@
+// Typed results of `execute_script`.
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+
+/// Why a script did not produce a value of the expected type.
+#[derive(Debug)]
+pub enum ScriptError {
+    /// The script threw.
+    Exception {
+        /// `name` of the thrown error, e.g. `"TypeError"`. Empty if a
+        /// non-`Error` value was thrown.
+        name: String,
+        message: String,
+        stack: Option<String>,
+    },
+    /// The result could not be converted to the expected type.
+    Conversion {
+        json: String,
+        error: serde_json::Error,
+    },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Exception { name, message, .. } if name.is_empty() => {
+                write!(f, "script threw: {}", message)
+            }
+            ScriptError::Exception { name, message, .. } => {
+                write!(f, "script threw {}: {}", name, message)
+            }
+            ScriptError::Conversion { json, error } => {
+                write!(f, "unexpected script result {}: {}", json, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScriptError::Conversion { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Wrap a script so that its result and exceptions are both reported, as
+/// decoded by `decode_envelope`.
+///
+/// The script becomes the body of a function, so its value is what it
+/// `return`s (`undefined`, which becomes `null`, if nothing). The source is
+/// inserted as is rather than passed to `eval`, so this also works on pages
+/// whose Content Security Policy does not allow `'unsafe-eval'`, e.g.
+/// `ContentSecurityPolicy::strict()`.
+pub fn wrap_script(script: &str) -> String {
+    // The newline ends a trailing `//` comment in the script.
+    format!(
+        r#"(function () {{
+  try {{
+    var value = (function () {{
+{}
+    }})();
+    return JSON.stringify({{ ok: value === undefined ? null : value }});
+  }} catch (e) {{
+    var error = e instanceof Error
+      ? {{ name: String(e.name), message: String(e.message), stack: e.stack ? String(e.stack) : null }}
+      : {{ name: "", message: String(e), stack: null }};
+    return JSON.stringify({{ error: error }});
+  }}
+}})()"#,
+        script
+    )
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    ok: Option<Value>,
+    #[serde(default)]
+    error: Option<Exception>,
+}
+
+#[derive(Deserialize)]
+struct Exception {
+    #[serde(default)]
+    name: String,
+    message: String,
+    #[serde(default)]
+    stack: Option<String>,
+}
+
+fn conversion(json: &str, error: serde_json::Error) -> ScriptError {
+    ScriptError::Conversion {
+        json: json.into(),
+        error,
+    }
+}
+
+/// Decode a plain `execute_script` result.
+///
+/// Note that scripts that throw or return `undefined` both give `null`.
+pub fn decode_result<T: DeserializeOwned>(json: &str) -> Result<T, ScriptError> {
+    serde_json::from_str(json).map_err(|e| conversion(json, e))
+}
+
+/// Decode the `execute_script` result of a script wrapped by `wrap_script`.
+pub fn decode_envelope<T: DeserializeOwned>(json: &str) -> Result<T, ScriptError> {
+    // The envelope is returned as a string, so that serializing the value
+    // happens inside the `try`.
+    let envelope: String = decode_result(json)?;
+    let envelope: Envelope = serde_json::from_str(&envelope).map_err(|e| conversion(json, e))?;
+    match (envelope.error, envelope.ok) {
+        (Some(e), _) => Err(ScriptError::Exception {
+            name: e.name,
+            message: e.message,
+            stack: e.stack,
+        }),
+        (None, value) => {
+            let value = value.unwrap_or(Value::Null);
+            serde_json::from_value(value.clone()).map_err(|e| conversion(&value.to_string(), e))
+        }
+    }
+}
+
+#[cfg(windows)]
+impl crate::WebView {
+    /// Execute a script and deserialize its result.
+    ///
+    /// Exceptions are not reported: the result is then `null`. See
+    /// `execute_script_checked`.
+    pub fn execute_script_typed<T: DeserializeOwned + 'static>(
+        &self,
+        script: &str,
+        callback: impl FnOnce(Result<T, ScriptError>) -> crate::Result<()> + 'static,
+    ) -> crate::Result<()> {
+        self.execute_script(script, move |json| callback(decode_result(&json)))
+    }
+
+    /// Execute a script in a try/catch envelope, and deserialize its result
+    /// or report its exception.
+    pub fn execute_script_checked<T: DeserializeOwned + 'static>(
+        &self,
+        script: &str,
+        callback: impl FnOnce(Result<T, ScriptError>) -> crate::Result<()> + 'static,
+    ) -> crate::Result<()> {
+        self.execute_script(&wrap_script(script), move |json| {
+            callback(decode_envelope(&json))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What `execute_script` returns for a wrapped script: the envelope
+    // string, serialized as JSON.
+    fn returned(envelope: Value) -> String {
+        Value::String(envelope.to_string()).to_string()
+    }
+
+    #[test]
+    fn test_wrap_script() {
+        let wrapped = wrap_script("var a = 1;\nreturn a + 1; // done");
+        assert!(wrapped.contains("\nvar a = 1;\nreturn a + 1; // done\n"));
+        assert!(wrapped.starts_with("(function () {"));
+        assert!(wrapped.ends_with("})()"));
+        // Works under a policy without 'unsafe-eval'.
+        assert!(!wrapped.contains("eval"));
+        let csp = crate::ContentSecurityPolicy::strict().to_string();
+        assert!(!csp.contains("unsafe-eval"));
+    }
+
+    #[test]
+    fn test_decode_result() {
+        assert_eq!(decode_result::<Vec<i32>>("[1,2]").unwrap(), vec![1, 2]);
+        assert_eq!(decode_result::<Option<i32>>("null").unwrap(), None);
+        match decode_result::<i32>("\"x\"") {
+            Err(ScriptError::Conversion { json, .. }) => assert_eq!(json, "\"x\""),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_decode_envelope() {
+        let json = returned(serde_json::json!({"ok": {"a": [1, 2]}}));
+        let value: Value = decode_envelope(&json).unwrap();
+        assert_eq!(value, serde_json::json!({"a": [1, 2]}));
+        // `undefined`.
+        let json = returned(serde_json::json!({"ok": null}));
+        decode_envelope::<()>(&json).unwrap();
+
+        let json = returned(serde_json::json!({"error": {
+            "name": "TypeError",
+            "message": "x is not a function",
+            "stack": "TypeError: x is not a function\n    at <anonymous>:1:1",
+        }}));
+        match decode_envelope::<i32>(&json) {
+            Err(ScriptError::Exception {
+                name,
+                message,
+                stack,
+            }) => {
+                assert_eq!(name, "TypeError");
+                assert_eq!(message, "x is not a function");
+                assert!(stack.unwrap().contains("<anonymous>"));
+            }
+            r => panic!("{:?}", r),
+        }
+
+        let json =
+            returned(serde_json::json!({"error": {"name": "", "message": "oops", "stack": null}}));
+        let error = decode_envelope::<i32>(&json).unwrap_err();
+        assert_eq!(error.to_string(), "script threw: oops");
+
+        let json = returned(serde_json::json!({"ok": "x"}));
+        match decode_envelope::<i32>(&json) {
+            Err(ScriptError::Conversion { json, .. }) => assert_eq!(json, "\"x\""),
+            r => panic!("{:?}", r),
+        }
+        // Not a wrapped script.
+        assert!(matches!(
+            decode_envelope::<i32>("null"),
+            Err(ScriptError::Conversion { .. })
+        ));
+    }
+}
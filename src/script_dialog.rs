@@ -0,0 +1,374 @@
+// Replacing the browser's `alert`, `confirm`, `prompt` and `beforeunload`
+// dialogs.
+
+use crate::error::Result;
+use crate::executor::LocalExecutor;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+/// The kind of a script dialog, like `ScriptDialogKind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialogKind {
+    Alert,
+    Confirm,
+    Prompt,
+    BeforeUnload,
+}
+
+/// A script dialog the page opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptDialog {
+    pub kind: DialogKind,
+    /// The URI of the page.
+    pub uri: String,
+    pub message: String,
+    /// The default text of a prompt, empty for other dialogs.
+    pub default_text: String,
+}
+
+pub type DialogFuture<T> = Pin<Box<dyn Future<Output = T>>>;
+
+/// Shows script dialogs, see `ScriptDialogs`.
+///
+/// The page waits until the returned futures complete. The default methods
+/// answer right away, as if the user dismissed the dialog.
+pub trait ScriptDialogHandler {
+    fn alert(&self, dialog: ScriptDialog) -> DialogFuture<()> {
+        let _ = dialog;
+        Box::pin(async {})
+    }
+
+    /// Whether the user confirmed.
+    fn confirm(&self, dialog: ScriptDialog) -> DialogFuture<bool> {
+        let _ = dialog;
+        Box::pin(async { false })
+    }
+
+    /// The text the user entered, or `None` if they cancelled.
+    fn prompt(&self, dialog: ScriptDialog) -> DialogFuture<Option<String>> {
+        let _ = dialog;
+        Box::pin(async { None })
+    }
+
+    /// Whether to leave the page.
+    fn before_unload(&self, dialog: ScriptDialog) -> DialogFuture<bool> {
+        let _ = dialog;
+        Box::pin(async { true })
+    }
+}
+
+/// What `ScriptDialogs` needs of `ScriptDialogOpeningEventArgs`.
+pub trait ScriptDialogArgs {
+    fn kind(&self) -> Result<DialogKind>;
+    fn uri(&self) -> Result<String>;
+    fn message(&self) -> Result<String>;
+    fn default_text(&self) -> Result<String>;
+    fn set_result_text(&self, text: &str) -> Result<()>;
+    /// Close the dialog as if OK was clicked. Otherwise it is cancelled.
+    fn accept(&self) -> Result<()>;
+    /// Take a deferral, returning a function completing it.
+    fn defer(&self) -> Result<Box<dyn FnOnce()>>;
+}
+
+enum Answer {
+    Cancel,
+    Accept,
+    AcceptWith(String),
+}
+
+/// Answers script dialogs with a `ScriptDialogHandler`.
+///
+/// The handler's futures run on the executor. Until they complete the
+/// event is deferred.
+#[derive(Clone)]
+pub struct ScriptDialogs {
+    handler: Rc<dyn ScriptDialogHandler>,
+    executor: LocalExecutor,
+}
+
+impl fmt::Debug for ScriptDialogs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptDialogs")
+            .field("executor", &self.executor)
+            .finish()
+    }
+}
+
+impl ScriptDialogs {
+    pub fn new(handler: impl ScriptDialogHandler + 'static, executor: LocalExecutor) -> Self {
+        Self {
+            handler: Rc::new(handler),
+            executor,
+        }
+    }
+
+    /// Handle a `ScriptDialogOpening` event.
+    pub fn handle(&self, args: impl ScriptDialogArgs + 'static) -> Result<()> {
+        let kind = args.kind()?;
+        let dialog = ScriptDialog {
+            kind,
+            uri: args.uri()?,
+            message: args.message()?,
+            default_text: if kind == DialogKind::Prompt {
+                args.default_text()?
+            } else {
+                String::new()
+            },
+        };
+        let answer: DialogFuture<Answer> = match kind {
+            DialogKind::Alert => {
+                let shown = self.handler.alert(dialog);
+                Box::pin(async move {
+                    shown.await;
+                    Answer::Accept
+                })
+            }
+            DialogKind::Confirm => {
+                let confirmed = self.handler.confirm(dialog);
+                Box::pin(async move {
+                    if confirmed.await {
+                        Answer::Accept
+                    } else {
+                        Answer::Cancel
+                    }
+                })
+            }
+            DialogKind::Prompt => {
+                let text = self.handler.prompt(dialog);
+                Box::pin(async move {
+                    match text.await {
+                        Some(text) => Answer::AcceptWith(text),
+                        None => Answer::Cancel,
+                    }
+                })
+            }
+            DialogKind::BeforeUnload => {
+                let leave = self.handler.before_unload(dialog);
+                Box::pin(async move {
+                    if leave.await {
+                        Answer::Accept
+                    } else {
+                        Answer::Cancel
+                    }
+                })
+            }
+        };
+        let complete = args.defer()?;
+        self.executor.spawn(async move {
+            // Nothing to do about errors: the dialog is cancelled.
+            let _ = match answer.await {
+                Answer::Cancel => Ok(()),
+                Answer::Accept => args.accept(),
+                Answer::AcceptWith(text) => args.set_result_text(&text).and_then(|_| args.accept()),
+            };
+            complete();
+        });
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::*;
+
+    impl ScriptDialogArgs for ScriptDialogOpeningEventArgs {
+        fn kind(&self) -> Result<DialogKind> {
+            Ok(match self.get_kind()? {
+                ScriptDialogKind::Alert => DialogKind::Alert,
+                ScriptDialogKind::Confirm => DialogKind::Confirm,
+                ScriptDialogKind::Prompt => DialogKind::Prompt,
+                ScriptDialogKind::Beforeunload => DialogKind::BeforeUnload,
+            })
+        }
+
+        fn uri(&self) -> Result<String> {
+            self.get_uri()
+        }
+
+        fn message(&self) -> Result<String> {
+            self.get_message()
+        }
+
+        fn default_text(&self) -> Result<String> {
+            self.get_default_text()
+        }
+
+        fn set_result_text(&self, text: &str) -> Result<()> {
+            self.put_result_text(text)
+        }
+
+        fn accept(&self) -> Result<()> {
+            ScriptDialogOpeningEventArgs::accept(self)
+        }
+
+        fn defer(&self) -> Result<Box<dyn FnOnce()>> {
+            let deferral = self.get_deferral()?;
+            Ok(Box::new(move || {
+                let _ = deferral.complete();
+            }))
+        }
+    }
+
+    impl ScriptDialogs {
+        /// Disable the default dialogs of `webview` and handle its
+        /// `ScriptDialogOpening` events.
+        pub fn attach(&self, webview: &WebView) -> Result<Subscription> {
+            webview
+                .get_settings()?
+                .put_are_default_script_dialogs_enabled(false)?;
+            let dialogs = self.clone();
+            webview.on_script_dialog_opening(move |_, args| dialogs.handle(args))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{completion, Completer};
+    use std::cell::RefCell;
+
+    #[derive(Clone)]
+    struct FakeArgs {
+        kind: DialogKind,
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl FakeArgs {
+        fn new(kind: DialogKind) -> Self {
+            Self {
+                kind,
+                log: Rc::default(),
+            }
+        }
+
+        fn log(&self) -> Vec<String> {
+            self.log.borrow().clone()
+        }
+    }
+
+    impl ScriptDialogArgs for FakeArgs {
+        fn kind(&self) -> Result<DialogKind> {
+            Ok(self.kind)
+        }
+        fn uri(&self) -> Result<String> {
+            Ok("https://example.com/".into())
+        }
+        fn message(&self) -> Result<String> {
+            Ok("message".into())
+        }
+        fn default_text(&self) -> Result<String> {
+            Ok("default".into())
+        }
+        fn set_result_text(&self, text: &str) -> Result<()> {
+            self.log.borrow_mut().push(format!("result {}", text));
+            Ok(())
+        }
+        fn accept(&self) -> Result<()> {
+            self.log.borrow_mut().push("accept".into());
+            Ok(())
+        }
+        fn defer(&self) -> Result<Box<dyn FnOnce()>> {
+            self.log.borrow_mut().push("defer".into());
+            let log = self.log.clone();
+            Ok(Box::new(move || log.borrow_mut().push("complete".into())))
+        }
+    }
+
+    // Answers confirms and prompts through completers kept for the test.
+    #[derive(Default)]
+    struct Pending {
+        dialogs: RefCell<Vec<ScriptDialog>>,
+        confirms: RefCell<Vec<Completer<bool>>>,
+        prompts: RefCell<Vec<Completer<Option<String>>>>,
+    }
+
+    impl ScriptDialogHandler for Rc<Pending> {
+        fn confirm(&self, dialog: ScriptDialog) -> DialogFuture<bool> {
+            self.dialogs.borrow_mut().push(dialog);
+            let (completer, completion) = completion();
+            self.confirms.borrow_mut().push(completer);
+            Box::pin(async move { completion.await.unwrap_or(false) })
+        }
+
+        fn prompt(&self, dialog: ScriptDialog) -> DialogFuture<Option<String>> {
+            self.dialogs.borrow_mut().push(dialog);
+            let (completer, completion) = completion();
+            self.prompts.borrow_mut().push(completer);
+            Box::pin(async move { completion.await.unwrap_or(None) })
+        }
+    }
+
+    #[test]
+    fn test_deferred_answers() {
+        let pending = Rc::new(Pending::default());
+        let executor = LocalExecutor::new();
+        let dialogs = ScriptDialogs::new(pending.clone(), executor.clone());
+
+        let confirm = FakeArgs::new(DialogKind::Confirm);
+        dialogs.handle(confirm.clone()).unwrap();
+        executor.run_until_stalled();
+        assert_eq!(confirm.log(), vec!["defer"]);
+        pending.confirms.borrow_mut().remove(0).complete(true);
+        executor.run_until_stalled();
+        assert_eq!(confirm.log(), vec!["defer", "accept", "complete"]);
+
+        let prompt = FakeArgs::new(DialogKind::Prompt);
+        dialogs.handle(prompt.clone()).unwrap();
+        pending
+            .prompts
+            .borrow_mut()
+            .remove(0)
+            .complete(Some("typed".into()));
+        executor.run_until_stalled();
+        assert_eq!(
+            prompt.log(),
+            vec!["defer", "result typed", "accept", "complete"]
+        );
+        assert_eq!(
+            pending.dialogs.borrow()[1],
+            ScriptDialog {
+                kind: DialogKind::Prompt,
+                uri: "https://example.com/".into(),
+                message: "message".into(),
+                default_text: "default".into(),
+            }
+        );
+        assert_eq!(pending.dialogs.borrow()[0].default_text, "");
+
+        // Cancelled, also when the handler goes away.
+        let prompt = FakeArgs::new(DialogKind::Prompt);
+        dialogs.handle(prompt.clone()).unwrap();
+        pending.prompts.borrow_mut().clear();
+        executor.run_until_stalled();
+        assert_eq!(prompt.log(), vec!["defer", "complete"]);
+        assert_eq!(executor.task_count(), 0);
+    }
+
+    #[test]
+    fn test_default_answers() {
+        struct Defaults;
+        impl ScriptDialogHandler for Defaults {}
+
+        let executor = LocalExecutor::new();
+        let dialogs = ScriptDialogs::new(Defaults, executor.clone());
+        let expected = [
+            (DialogKind::Alert, vec!["defer", "accept", "complete"]),
+            (DialogKind::Confirm, vec!["defer", "complete"]),
+            (DialogKind::Prompt, vec!["defer", "complete"]),
+            (
+                DialogKind::BeforeUnload,
+                vec!["defer", "accept", "complete"],
+            ),
+        ];
+        for (kind, log) in expected.iter() {
+            let args = FakeArgs::new(*kind);
+            dialogs.handle(args.clone()).unwrap();
+            executor.run_until_stalled();
+            assert_eq!(args.log(), *log, "{:?}", kind);
+        }
+    }
+}
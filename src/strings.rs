@@ -0,0 +1,205 @@
+// Converting UTF-16 strings from WebView2.
+//
+// Strings from the browser, e.g. document titles, URIs and web messages, can
+// be ill-formed UTF-16: they can contain lone surrogates. Getters returning
+// `String` convert them according to the crate-wide `StringPolicy`, JSON
+// getters with `decode_json` and the others with `decode_text`. The `*_wide`
+// getters return the `WideCString` as is, which can be turned into an
+// `OsString` losslessly with `to_os_string`.
+
+use crate::error::{Error, Result};
+use std::char;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How ill-formed UTF-16 from the browser is converted to `String`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringPolicy {
+    /// Fail with `ErrorKind::NotUtf16`. The default.
+    Strict,
+    /// Replace lone surrogates with U+FFFD.
+    Lossy,
+    /// Write lone surrogates in JSON, i.e. `get_web_message_as_json` and
+    /// `execute_script` results, as `\uXXXX` escapes, which keeps it
+    /// lossless. Other getters fail like with `Strict`: use their `*_wide`
+    /// variants to get such strings as is.
+    Raw,
+}
+
+// `#[default]` on variants is too new.
+#[allow(clippy::derivable_impls)]
+impl Default for StringPolicy {
+    fn default() -> Self {
+        StringPolicy::Strict
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(0);
+
+/// Set the `StringPolicy` of all threads.
+pub fn set_string_policy(policy: StringPolicy) {
+    let policy = match policy {
+        StringPolicy::Strict => 0,
+        StringPolicy::Lossy => 1,
+        StringPolicy::Raw => 2,
+    };
+    POLICY.store(policy, Ordering::Relaxed);
+}
+
+pub fn string_policy() -> StringPolicy {
+    match POLICY.load(Ordering::Relaxed) {
+        0 => StringPolicy::Strict,
+        1 => StringPolicy::Lossy,
+        _ => StringPolicy::Raw,
+    }
+}
+
+/// Convert UTF-16 to a `String` according to `policy`.
+///
+/// ```
+/// # use webview2::{decode_utf16, StringPolicy};
+/// let units = [0x61, 0xd800, 0x62];
+/// assert!(decode_utf16(&units, StringPolicy::Strict).is_err());
+/// assert_eq!(decode_utf16(&units, StringPolicy::Lossy).unwrap(), "a\u{fffd}b");
+/// assert_eq!(decode_utf16(&units, StringPolicy::Raw).unwrap(), "a\\uD800b");
+/// ```
+pub fn decode_utf16(units: &[u16], policy: StringPolicy) -> Result<String> {
+    if policy == StringPolicy::Strict {
+        return String::from_utf16(units).map_err(Error::from);
+    }
+    let mut result = String::with_capacity(units.len());
+    for c in char::decode_utf16(units.iter().cloned()) {
+        match c {
+            Ok(c) => result.push(c),
+            Err(_) if policy == StringPolicy::Lossy => result.push(char::REPLACEMENT_CHARACTER),
+            Err(e) => write!(result, "\\u{:04X}", e.unpaired_surrogate()).unwrap(),
+        }
+    }
+    Ok(result)
+}
+
+// Convert a string that is not JSON, where escapes would change its meaning.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn decode_text(units: &[u16]) -> Result<String> {
+    match string_policy() {
+        StringPolicy::Raw => decode_utf16(units, StringPolicy::Strict),
+        policy => decode_utf16(units, policy),
+    }
+}
+
+// Convert JSON.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn decode_json(units: &[u16]) -> Result<String> {
+    decode_utf16(units, string_policy())
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use widestring::{WideCStr, WideCString};
+    use winapi::shared::ntdef::{LPCWSTR, LPWSTR};
+    use winapi::um::combaseapi::CoTaskMemFree;
+
+    /// Copy and free a string allocated by WebView2 with `CoTaskMemAlloc`.
+    /// Null is taken as an empty string.
+    pub(crate) unsafe fn take_co_task_mem_string(s: LPWSTR) -> WideCString {
+        if s.is_null() {
+            return WideCString::default();
+        }
+        let result = WideCStr::from_ptr_str(s).to_ucstring();
+        CoTaskMemFree(s as _);
+        result
+    }
+
+    /// `take_co_task_mem_string` and convert with `decode_text`.
+    pub(crate) unsafe fn take_string(s: LPWSTR) -> Result<String> {
+        let s = take_co_task_mem_string(s);
+        decode_text(s.as_slice())
+    }
+
+    /// Convert a string WebView2 passes to a callback with `decode_text`.
+    pub(crate) unsafe fn to_string(s: LPCWSTR) -> Result<String> {
+        if s.is_null() {
+            return Ok(String::new());
+        }
+        decode_text(WideCStr::from_ptr_str(s).as_slice())
+    }
+
+    /// Convert JSON WebView2 passes to a callback with `decode_json`.
+    pub(crate) unsafe fn to_json_string(s: LPCWSTR) -> Result<String> {
+        if s.is_null() {
+            return Ok(String::new());
+        }
+        decode_json(WideCStr::from_ptr_str(s).as_slice())
+    }
+}
+
+#[cfg(windows)]
+pub(crate) use self::win::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_decode() {
+        let valid: Vec<u16> = "héllo 😀".encode_utf16().collect();
+        for &policy in &[StringPolicy::Strict, StringPolicy::Lossy, StringPolicy::Raw] {
+            assert_eq!(decode_utf16(&valid, policy).unwrap(), "héllo 😀");
+            assert_eq!(decode_utf16(&[], policy).unwrap(), "");
+        }
+
+        // A lone low surrogate, and a high one at the end.
+        let invalid = [0xdc00, 0x41, 0xd83d];
+        assert_eq!(
+            decode_utf16(&invalid, StringPolicy::Strict)
+                .unwrap_err()
+                .kind(),
+            &ErrorKind::NotUtf16
+        );
+        assert_eq!(
+            decode_utf16(&invalid, StringPolicy::Lossy).unwrap(),
+            "\u{fffd}A\u{fffd}"
+        );
+        let raw = decode_utf16(&invalid, StringPolicy::Raw).unwrap();
+        assert_eq!(raw, "\\uDC00A\\uD83D");
+
+        // In JSON strings the escapes decode to the original code units.
+        let json: Vec<u16> = "[\"x"
+            .encode_utf16()
+            .chain(vec![0xd800])
+            .chain("\"]".encode_utf16())
+            .collect();
+        assert_eq!(
+            decode_utf16(&json, StringPolicy::Raw).unwrap(),
+            "[\"x\\uD800\"]"
+        );
+    }
+
+    #[test]
+    fn test_policy() {
+        let invalid = [0x41, 0xd800];
+        assert_eq!(string_policy(), StringPolicy::default());
+        for &policy in &[StringPolicy::Lossy, StringPolicy::Raw, StringPolicy::Strict] {
+            set_string_policy(policy);
+            assert_eq!(string_policy(), policy);
+            assert_eq!(decode_text(&[0x41]).unwrap(), "A");
+            match policy {
+                StringPolicy::Lossy => {
+                    assert_eq!(decode_text(&invalid).unwrap(), "A\u{fffd}");
+                    assert_eq!(decode_json(&invalid).unwrap(), "A\u{fffd}");
+                }
+                // Titles and URIs are never escaped.
+                StringPolicy::Raw => {
+                    assert!(decode_text(&invalid).is_err());
+                    assert_eq!(decode_json(&invalid).unwrap(), "A\\uD800");
+                }
+                StringPolicy::Strict => {
+                    assert!(decode_text(&invalid).is_err());
+                    assert!(decode_json(&invalid).is_err());
+                }
+            }
+        }
+    }
+}
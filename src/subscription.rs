@@ -0,0 +1,346 @@
+// Event handler registrations that are removed on drop.
+
+use std::fmt;
+
+/// An event handler registration, removed when this is dropped.
+///
+/// The `on_*` methods of `WebView`, `Controller` and `Environment` return
+/// these. Use `detach` to keep the handler for the sender's lifetime.
+#[must_use = "the handler is removed when the subscription is dropped"]
+pub struct Subscription {
+    // Returns whether the handler was removed.
+    remove: Option<Box<dyn FnOnce() -> bool>>,
+}
+
+impl Subscription {
+    /// A subscription calling `remove(&sender, token)` on drop.
+    pub fn new<S: 'static, T: 'static, E: 'static>(
+        sender: S,
+        token: T,
+        remove: fn(&S, T) -> Result<(), E>,
+    ) -> Self {
+        Self::from_fn(move || remove(&sender, token).is_ok())
+    }
+
+    /// A subscription calling `remove` on drop. It returns whether the
+    /// handler was removed.
+    pub fn from_fn(remove: impl FnOnce() -> bool + 'static) -> Self {
+        Self {
+            remove: Some(Box::new(remove)),
+        }
+    }
+
+    /// Remove the handler now. Returns whether that succeeded.
+    pub fn unsubscribe(mut self) -> bool {
+        match self.remove.take() {
+            Some(remove) => remove(),
+            None => false,
+        }
+    }
+
+    /// Keep the handler registered.
+    pub fn detach(mut self) {
+        self.remove = None;
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(remove) = self.remove.take() {
+            // Nothing to do about errors here, use `unsubscribe` to check.
+            remove();
+        }
+    }
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription").finish()
+    }
+}
+
+/// Subscriptions of a component, all removed when it is dropped.
+#[derive(Debug, Default)]
+#[must_use = "the handlers are removed when the set is dropped"]
+pub struct SubscriptionSet {
+    subscriptions: Vec<Subscription>,
+}
+
+impl SubscriptionSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, subscription: Subscription) {
+        self.subscriptions.push(subscription);
+    }
+
+    pub fn with(mut self, subscription: Subscription) -> Self {
+        self.add(subscription);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    /// Remove all handlers now, in reverse order of subscription. Returns
+    /// whether all were removed.
+    pub fn clear(&mut self) -> bool {
+        let mut all = true;
+        while let Some(subscription) = self.subscriptions.pop() {
+            all &= subscription.unsubscribe();
+        }
+        all
+    }
+
+    /// Keep all handlers registered.
+    pub fn detach(mut self) {
+        for subscription in self.subscriptions.drain(..) {
+            subscription.detach();
+        }
+    }
+}
+
+impl Drop for SubscriptionSet {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl Extend<Subscription> for SubscriptionSet {
+    fn extend<I: IntoIterator<Item = Subscription>>(&mut self, iter: I) {
+        self.subscriptions.extend(iter);
+    }
+}
+
+impl std::iter::FromIterator<Subscription> for SubscriptionSet {
+    fn from_iter<I: IntoIterator<Item = Subscription>>(iter: I) -> Self {
+        Self {
+            subscriptions: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::*;
+
+    // `on_*`: `add_*` returning a `Subscription` that calls `remove_*`.
+    macro_rules! subscribe {
+        ($on:ident, $add:ident, $remove:ident, $($Handler:tt)*) => {
+            pub fn $on(&self, handler: impl $($Handler)* + 'static) -> Result<Subscription> {
+                let token = self.$add(handler)?;
+                Ok(Subscription::new(self.clone(), token, Self::$remove))
+            }
+        };
+    }
+
+    impl Environment {
+        subscribe!(
+            on_new_browser_version_available,
+            add_new_browser_version_available,
+            remove_new_browser_version_available,
+            Fn(Environment) -> Result<()>
+        );
+    }
+
+    impl Controller {
+        subscribe!(
+            on_zoom_factor_changed,
+            add_zoom_factor_changed,
+            remove_zoom_factor_changed,
+            Fn(Controller) -> Result<()>
+        );
+        subscribe!(
+            on_move_focus_requested,
+            add_move_focus_requested,
+            remove_move_focus_requested,
+            Fn(Controller, MoveFocusRequestedEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_got_focus,
+            add_got_focus,
+            remove_got_focus,
+            Fn(Controller) -> Result<()>
+        );
+        subscribe!(
+            on_lost_focus,
+            add_lost_focus,
+            remove_lost_focus,
+            Fn(Controller) -> Result<()>
+        );
+        subscribe!(
+            on_accelerator_key_pressed,
+            add_accelerator_key_pressed,
+            remove_accelerator_key_pressed,
+            Fn(Controller, AcceleratorKeyPressedEventArgs) -> Result<()>
+        );
+    }
+
+    impl WebView {
+        subscribe!(
+            on_navigation_starting,
+            add_navigation_starting,
+            remove_navigation_starting,
+            Fn(WebView, NavigationStartingEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_content_loading,
+            add_content_loading,
+            remove_content_loading,
+            Fn(WebView, ContentLoadingEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_source_changed,
+            add_source_changed,
+            remove_source_changed,
+            Fn(WebView, SourceChangedEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_history_changed,
+            add_history_changed,
+            remove_history_changed,
+            Fn(WebView) -> Result<()>
+        );
+        subscribe!(
+            on_navigation_completed,
+            add_navigation_completed,
+            remove_navigation_completed,
+            Fn(WebView, NavigationCompletedEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_frame_navigation_starting,
+            add_frame_navigation_starting,
+            remove_frame_navigation_starting,
+            Fn(WebView, NavigationStartingEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_script_dialog_opening,
+            add_script_dialog_opening,
+            remove_script_dialog_opening,
+            Fn(WebView, ScriptDialogOpeningEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_permission_requested,
+            add_permission_requested,
+            remove_permission_requested,
+            Fn(WebView, PermissionRequestedEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_process_failed,
+            add_process_failed,
+            remove_process_failed,
+            Fn(WebView, ProcessFailedEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_document_title_changed,
+            add_document_title_changed,
+            remove_document_title_changed,
+            Fn(WebView) -> Result<()>
+        );
+        subscribe!(
+            on_web_message_received,
+            add_web_message_received,
+            remove_web_message_received,
+            Fn(WebView, WebMessageReceivedEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_new_window_requested,
+            add_new_window_requested,
+            remove_new_window_requested,
+            Fn(WebView, NewWindowRequestedEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_contains_full_screen_element_changed,
+            add_contains_full_screen_element_changed,
+            remove_contains_full_screen_element_changed,
+            Fn(WebView) -> Result<()>
+        );
+        subscribe!(
+            on_web_resource_requested,
+            add_web_resource_requested,
+            remove_web_resource_requested,
+            Fn(WebView, WebResourceRequestedEventArgs) -> Result<()>
+        );
+        subscribe!(
+            on_window_close_requested,
+            add_window_close_requested,
+            remove_window_close_requested,
+            Fn(WebView) -> Result<()>
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Stands in for a `WebView`: clones share the removed tokens.
+    #[derive(Clone, Default)]
+    struct FakeSender {
+        removed: Rc<RefCell<Vec<u64>>>,
+    }
+
+    impl FakeSender {
+        fn remove(&self, token: u64) -> Result<(), ()> {
+            if token == 0 {
+                return Err(());
+            }
+            self.removed.borrow_mut().push(token);
+            Ok(())
+        }
+
+        fn subscribe(&self, token: u64) -> Subscription {
+            Subscription::new(self.clone(), token, Self::remove)
+        }
+
+        fn removed(&self) -> Vec<u64> {
+            self.removed.borrow().clone()
+        }
+    }
+
+    #[test]
+    fn test_subscription() {
+        let sender = FakeSender::default();
+        let subscription = sender.subscribe(1);
+        assert!(sender.removed().is_empty());
+        drop(subscription);
+        assert_eq!(sender.removed(), vec![1]);
+
+        sender.subscribe(2).detach();
+        assert_eq!(sender.removed(), vec![1]);
+
+        assert!(sender.subscribe(3).unsubscribe());
+        assert!(!sender.subscribe(0).unsubscribe());
+        assert_eq!(sender.removed(), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_subscription_set() {
+        let sender = FakeSender::default();
+        let mut set: SubscriptionSet = (1..=2).map(|t| sender.subscribe(t)).collect();
+        set.add(sender.subscribe(3));
+        let set = set.with(sender.subscribe(4));
+        assert_eq!(set.len(), 4);
+        drop(set);
+        // In reverse order.
+        assert_eq!(sender.removed(), vec![4, 3, 2, 1]);
+
+        let mut set = SubscriptionSet::new();
+        set.extend(vec![sender.subscribe(5), sender.subscribe(0)]);
+        assert!(!set.clear());
+        assert!(set.is_empty());
+        assert_eq!(sender.removed(), vec![4, 3, 2, 1, 5]);
+
+        SubscriptionSet::new().with(sender.subscribe(6)).detach();
+        assert_eq!(sender.removed().len(), 5);
+    }
+}
@@ -0,0 +1,932 @@
+// Recovering from browser process failures.
+//
+// After `BrowserProcessExited` the webview is closed for good: the
+// environment, controller and webview have to be created again, and the
+// settings, scripts, filters and location restored. `Supervisor` records these
+// and does so, with a backoff between attempts. `RecoveryMachine` is its state
+// machine, deciding what to do about each failure.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A process failure, like `ProcessFailedKind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessFailure {
+    /// The webview is closed and has to be created again.
+    BrowserExited,
+    /// The page shows an error page, reloading may help.
+    RenderExited,
+    RenderUnresponsive,
+}
+
+/// When to give up recreating the webview and how long to wait before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: u32,
+    max_failures: usize,
+    window: Duration,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2,
+            max_failures: 5,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RecoveryPolicy {
+    /// Wait 0.5s after the first failure, doubling up to 30s, and give up
+    /// after more than 5 failures in a minute.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait `initial` before the first attempt, multiplied for each recent
+    /// failure up to `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_delay = initial;
+        self.max_delay = max;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Give up after more than `failures` failures within `window`.
+    pub fn with_crash_loop_limit(mut self, failures: usize, window: Duration) -> Self {
+        self.max_failures = failures;
+        self.window = window;
+        self
+    }
+
+    /// The delay before attempt `attempt`, counting from 1.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use webview2::RecoveryPolicy;
+    /// let policy = RecoveryPolicy::new()
+    ///     .with_backoff(Duration::from_secs(1), Duration::from_secs(5));
+    /// let delays: Vec<_> = (1..=5).map(|a| policy.delay(a).as_secs()).collect();
+    /// assert_eq!(delays, [1, 2, 4, 5, 5]);
+    /// ```
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let mut delay = self.initial_delay;
+        for _ in 1..attempt {
+            match delay.checked_mul(self.multiplier) {
+                // Does not grow, e.g. a multiplier of 1 or no delay.
+                Some(d) if d == delay => break,
+                Some(d) if d < self.max_delay => delay = d,
+                _ => return self.max_delay,
+            }
+        }
+        delay.min(self.max_delay)
+    }
+}
+
+/// What to do about a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryAction {
+    /// Nothing, e.g. a recreation is already pending.
+    None,
+    Reload,
+    /// Recreate the webview after `delay`.
+    Recreate {
+        delay: Duration,
+    },
+    /// Too many failures: tear down and stay down.
+    GiveUp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupervisorState {
+    /// The webview is being created for the first time.
+    Starting,
+    Running,
+    /// Waiting for, or doing, recreation attempt `attempt`.
+    Recovering {
+        attempt: u32,
+    },
+    /// Too many failures. `restart` starts over.
+    GaveUp,
+}
+
+/// Emitted by `RecoveryMachine` and `Supervisor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoveryEvent {
+    /// A process failed. `failures` is the number of recent failures.
+    Failed {
+        failure: ProcessFailure,
+        failures: usize,
+    },
+    /// The page is reloaded after a renderer failure.
+    Reloading,
+    /// The webview will be recreated after `delay`.
+    Recovering { attempt: u32, delay: Duration },
+    /// The webview was created, for the first time if `attempt` is 0.
+    Recovered { attempt: u32 },
+    /// Creating the webview failed.
+    RecoveryFailed { attempt: u32, error: String },
+    /// Too many failures.
+    GaveUp { failures: usize },
+}
+
+/// The state machine of `Supervisor`.
+///
+/// Failures and failed recreations both count towards the crash-loop limit
+/// and the backoff: the delay grows with the number of failures within the
+/// policy's window, not with successful recoveries.
+#[derive(Debug, Clone)]
+pub struct RecoveryMachine {
+    policy: RecoveryPolicy,
+    state: SupervisorState,
+    failures: VecDeque<Instant>,
+    events: Vec<RecoveryEvent>,
+}
+
+impl RecoveryMachine {
+    pub fn new(policy: RecoveryPolicy) -> Self {
+        Self {
+            policy,
+            state: SupervisorState::Starting,
+            failures: VecDeque::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn policy(&self) -> &RecoveryPolicy {
+        &self.policy
+    }
+
+    pub fn state(&self) -> SupervisorState {
+        self.state
+    }
+
+    /// The number of failures within the window before the last transition.
+    pub fn recent_failures(&self) -> usize {
+        self.failures.len()
+    }
+
+    /// The events since the last call.
+    pub fn take_events(&mut self) -> Vec<RecoveryEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// A process of the webview failed.
+    pub fn failed(&mut self, failure: ProcessFailure, now: Instant) -> RecoveryAction {
+        match self.state {
+            // The old webview is gone, or will be.
+            SupervisorState::Recovering { .. } | SupervisorState::GaveUp => {
+                return RecoveryAction::None
+            }
+            SupervisorState::Starting | SupervisorState::Running => {}
+        }
+        let failures = self.record_failure(now);
+        self.events
+            .push(RecoveryEvent::Failed { failure, failures });
+        if failure == ProcessFailure::BrowserExited {
+            return self.recover();
+        }
+        if let Some(action) = self.crash_loop() {
+            return action;
+        }
+        self.events.push(RecoveryEvent::Reloading);
+        RecoveryAction::Reload
+    }
+
+    /// The webview was created.
+    pub fn created(&mut self) {
+        let attempt = match self.state {
+            SupervisorState::Recovering { attempt } => attempt,
+            _ => 0,
+        };
+        self.state = SupervisorState::Running;
+        self.events.push(RecoveryEvent::Recovered { attempt });
+    }
+
+    /// Creating the webview failed.
+    pub fn creation_failed(&mut self, error: String, now: Instant) -> RecoveryAction {
+        let attempt = match self.state {
+            SupervisorState::Recovering { attempt } => attempt,
+            SupervisorState::Starting => 0,
+            // E.g. a stale attempt.
+            SupervisorState::Running | SupervisorState::GaveUp => return RecoveryAction::None,
+        };
+        self.events
+            .push(RecoveryEvent::RecoveryFailed { attempt, error });
+        self.record_failure(now);
+        self.recover()
+    }
+
+    /// Forget past failures and start over, e.g. after giving up.
+    pub fn restart(&mut self) {
+        self.failures.clear();
+        self.state = SupervisorState::Starting;
+    }
+
+    fn record_failure(&mut self, now: Instant) -> usize {
+        while let Some(&first) = self.failures.front() {
+            if now.saturating_duration_since(first) < self.policy.window {
+                break;
+            }
+            self.failures.pop_front();
+        }
+        self.failures.push_back(now);
+        self.failures.len()
+    }
+
+    fn crash_loop(&mut self) -> Option<RecoveryAction> {
+        let failures = self.failures.len();
+        if failures <= self.policy.max_failures {
+            return None;
+        }
+        self.state = SupervisorState::GaveUp;
+        self.events.push(RecoveryEvent::GaveUp { failures });
+        Some(RecoveryAction::GiveUp)
+    }
+
+    fn recover(&mut self) -> RecoveryAction {
+        if let Some(action) = self.crash_loop() {
+            return action;
+        }
+        let attempt = match self.state {
+            SupervisorState::Recovering { attempt } => attempt + 1,
+            _ => 1,
+        };
+        let delay = self.policy.delay(self.failures.len() as u32);
+        self.state = SupervisorState::Recovering { attempt };
+        self.events
+            .push(RecoveryEvent::Recovering { attempt, delay });
+        RecoveryAction::Recreate { delay }
+    }
+}
+
+/// A boolean of `Settings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    ScriptEnabled,
+    WebMessageEnabled,
+    DefaultScriptDialogsEnabled,
+    StatusBarEnabled,
+    DevToolsEnabled,
+    DefaultContextMenusEnabled,
+    HostObjectsAllowed,
+    ZoomControlEnabled,
+    BuiltInErrorPageEnabled,
+}
+
+/// What `Supervisor` restores on a recreated webview.
+#[derive(Debug, Clone, Default)]
+pub struct WebViewConfig {
+    settings: Vec<(Setting, bool)>,
+    scripts: Vec<String>,
+    #[cfg(windows)]
+    filters: Vec<(String, crate::WebResourceContext)>,
+    source: Option<String>,
+}
+
+impl WebViewConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put_setting(&mut self, setting: Setting, enabled: bool) {
+        match self.settings.iter_mut().find(|(s, _)| *s == setting) {
+            Some(entry) => entry.1 = enabled,
+            None => self.settings.push((setting, enabled)),
+        }
+    }
+
+    /// The value put, if any.
+    pub fn setting(&self, setting: Setting) -> Option<bool> {
+        self.settings
+            .iter()
+            .find(|(s, _)| *s == setting)
+            .map(|(_, enabled)| *enabled)
+    }
+
+    /// The settings put, in the order they were first put.
+    pub fn settings(&self) -> &[(Setting, bool)] {
+        &self.settings
+    }
+
+    /// Add a script to execute on document created.
+    pub fn add_script(&mut self, script: &str) {
+        self.scripts.push(script.to_owned());
+    }
+
+    /// Remove all copies of `script`. Returns whether there were any.
+    pub fn remove_script(&mut self, script: &str) -> bool {
+        let len = self.scripts.len();
+        self.scripts.retain(|s| s != script);
+        self.scripts.len() != len
+    }
+
+    pub fn scripts(&self) -> &[String] {
+        &self.scripts
+    }
+
+    /// The URI to navigate to.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn set_source(&mut self, source: &str) {
+        self.source = Some(source.to_owned());
+    }
+}
+
+#[cfg(windows)]
+mod win {
+    use super::*;
+    use crate::timer::set_timer;
+    use crate::*;
+    use std::cell::{Cell, RefCell};
+    use std::fmt;
+    use std::mem;
+    use std::rc::{Rc, Weak};
+    use winapi::shared::windef::{HWND, RECT};
+    use winapi::um::winuser::GetClientRect;
+
+    impl From<ProcessFailedKind> for ProcessFailure {
+        fn from(kind: ProcessFailedKind) -> Self {
+            match kind {
+                ProcessFailedKind::BrowserProcessExited => ProcessFailure::BrowserExited,
+                ProcessFailedKind::RenderProcessExited => ProcessFailure::RenderExited,
+                ProcessFailedKind::RenderProcessUnresponsive => ProcessFailure::RenderUnresponsive,
+            }
+        }
+    }
+
+    impl Setting {
+        fn put(self, settings: &Settings, enabled: bool) -> Result<()> {
+            match self {
+                Setting::ScriptEnabled => settings.put_is_script_enabled(enabled),
+                Setting::WebMessageEnabled => settings.put_is_web_message_enabled(enabled),
+                Setting::DefaultScriptDialogsEnabled => {
+                    settings.put_are_default_script_dialogs_enabled(enabled)
+                }
+                Setting::StatusBarEnabled => settings.put_is_status_bar_enabled(enabled),
+                Setting::DevToolsEnabled => settings.put_are_dev_tools_enabled(enabled),
+                Setting::DefaultContextMenusEnabled => {
+                    settings.put_are_default_context_menus_enabled(enabled)
+                }
+                Setting::HostObjectsAllowed => settings.put_are_host_objects_allowed(enabled),
+                Setting::ZoomControlEnabled => settings.put_is_zoom_control_enabled(enabled),
+                Setting::BuiltInErrorPageEnabled => {
+                    settings.put_is_built_in_error_page_enabled(enabled)
+                }
+            }
+        }
+    }
+
+    impl WebViewConfig {
+        pub fn add_web_resource_requested_filter(
+            &mut self,
+            uri: &str,
+            context: WebResourceContext,
+        ) {
+            self.filters.push((uri.to_owned(), context));
+        }
+
+        pub fn web_resource_requested_filters(&self) -> &[(String, WebResourceContext)] {
+            &self.filters
+        }
+
+        /// Put the settings, add the scripts and filters and navigate to the
+        /// source.
+        pub fn apply(&self, webview: &WebView) -> Result<()> {
+            let settings = webview.get_settings()?;
+            for &(setting, enabled) in &self.settings {
+                setting.put(&settings, enabled)?;
+            }
+            for script in &self.scripts {
+                webview.add_script_to_execute_on_document_created(script, |_| Ok(()))?;
+            }
+            for (uri, context) in &self.filters {
+                webview.add_web_resource_requested_filter(uri, *context)?;
+            }
+            if let Some(source) = &self.source {
+                webview.navigate(source)?;
+            }
+            Ok(())
+        }
+    }
+
+    type EnvironmentCompleted = Box<dyn FnOnce(Result<Environment>) -> Result<()>>;
+
+    struct Inner {
+        parent: HWND,
+        create_environment: Box<dyn Fn(EnvironmentCompleted) -> Result<()>>,
+        machine: RefCell<RecoveryMachine>,
+        config: RefCell<WebViewConfig>,
+        bounds: Cell<Option<RECT>>,
+        // Incremented for each creation, so that stale ones are dropped.
+        generation: Cell<u64>,
+        current: RefCell<Option<(Controller, WebView)>>,
+        on_event: RefCell<Vec<Rc<dyn Fn(&RecoveryEvent)>>>,
+        on_created: RefCell<Vec<Rc<dyn Fn(&Controller, &WebView) -> Result<()>>>>,
+    }
+
+    /// Creates a webview and recreates it when the browser process fails.
+    ///
+    /// Configure the webview through the supervisor, e.g. `put_setting` and
+    /// `navigate`, so that it is restored. Subscriptions and host objects are
+    /// added again in `on_created` handlers, which run for each webview.
+    ///
+    /// Renderer failures reload the page. Clones share the webview.
+    #[derive(Clone)]
+    pub struct Supervisor {
+        inner: Rc<Inner>,
+    }
+
+    impl fmt::Debug for Supervisor {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Supervisor")
+                .field("state", &self.state())
+                .field("config", &*self.inner.config.borrow())
+                .finish()
+        }
+    }
+
+    impl Supervisor {
+        /// A supervisor of webviews in `parent`, in environments built by
+        /// `create_environment`, e.g.
+        /// `|completed| Environment::builder().build(completed)`.
+        pub fn new(
+            parent: HWND,
+            create_environment: impl Fn(EnvironmentCompleted) -> Result<()> + 'static,
+        ) -> Self {
+            Self {
+                inner: Rc::new(Inner {
+                    parent,
+                    create_environment: Box::new(create_environment),
+                    machine: RefCell::new(RecoveryMachine::new(RecoveryPolicy::default())),
+                    config: RefCell::default(),
+                    bounds: Cell::new(None),
+                    generation: Cell::new(0),
+                    current: RefCell::new(None),
+                    on_event: RefCell::default(),
+                    on_created: RefCell::default(),
+                }),
+            }
+        }
+
+        pub fn with_policy(self, policy: RecoveryPolicy) -> Self {
+            *self.inner.machine.borrow_mut() = RecoveryMachine::new(policy);
+            self
+        }
+
+        pub fn with_config(self, config: WebViewConfig) -> Self {
+            *self.inner.config.borrow_mut() = config;
+            self
+        }
+
+        pub fn on_event(&self, handler: impl Fn(&RecoveryEvent) + 'static) {
+            self.inner.on_event.borrow_mut().push(Rc::new(handler));
+        }
+
+        /// Call `handler` with each created webview, after the configuration
+        /// is applied.
+        pub fn on_created(&self, handler: impl Fn(&Controller, &WebView) -> Result<()> + 'static) {
+            self.inner.on_created.borrow_mut().push(Rc::new(handler));
+        }
+
+        /// Create the webview. Failures are retried like process failures
+        /// and reported as `RecoveryFailed` events.
+        pub fn start(&self) {
+            self.create();
+        }
+
+        /// Start over after giving up.
+        pub fn restart(&self) {
+            self.teardown();
+            self.inner.machine.borrow_mut().restart();
+            self.create();
+        }
+
+        pub fn state(&self) -> SupervisorState {
+            self.inner.machine.borrow().state()
+        }
+
+        pub fn config(&self) -> WebViewConfig {
+            self.inner.config.borrow().clone()
+        }
+
+        pub fn controller(&self) -> Option<Controller> {
+            self.inner.current.borrow().as_ref().map(|(c, _)| c.clone())
+        }
+
+        pub fn webview(&self) -> Option<WebView> {
+            self.inner.current.borrow().as_ref().map(|(_, w)| w.clone())
+        }
+
+        /// Put and record a setting.
+        pub fn put_setting(&self, setting: Setting, enabled: bool) -> Result<()> {
+            self.inner.config.borrow_mut().put_setting(setting, enabled);
+            match self.webview() {
+                Some(webview) => setting.put(&webview.get_settings()?, enabled),
+                None => Ok(()),
+            }
+        }
+
+        /// Add and record a script to execute on document created.
+        pub fn add_script(&self, script: &str) -> Result<()> {
+            self.inner.config.borrow_mut().add_script(script);
+            match self.webview() {
+                Some(webview) => {
+                    webview.add_script_to_execute_on_document_created(script, |_| Ok(()))
+                }
+                None => Ok(()),
+            }
+        }
+
+        /// Add and record a web resource requested filter.
+        pub fn add_web_resource_requested_filter(
+            &self,
+            uri: &str,
+            context: WebResourceContext,
+        ) -> Result<()> {
+            self.inner
+                .config
+                .borrow_mut()
+                .add_web_resource_requested_filter(uri, context);
+            match self.webview() {
+                Some(webview) => webview.add_web_resource_requested_filter(uri, context),
+                None => Ok(()),
+            }
+        }
+
+        /// Navigate to `uri`. Later navigations are recorded too.
+        pub fn navigate(&self, uri: &str) -> Result<()> {
+            self.inner.config.borrow_mut().set_source(uri);
+            match self.webview() {
+                Some(webview) => webview.navigate(uri),
+                None => Ok(()),
+            }
+        }
+
+        /// Put and record the bounds of the controller. By default it fills
+        /// the client area of the parent window.
+        pub fn put_bounds(&self, bounds: RECT) -> Result<()> {
+            self.inner.bounds.set(Some(bounds));
+            match self.controller() {
+                Some(controller) => controller.put_bounds(bounds),
+                None => Ok(()),
+            }
+        }
+
+        fn emit(&self) {
+            let events = self.inner.machine.borrow_mut().take_events();
+            let handlers = self.inner.on_event.borrow().clone();
+            for event in &events {
+                for handler in &handlers {
+                    handler(event);
+                }
+            }
+        }
+
+        fn create(&self) {
+            let generation = self.inner.generation.get() + 1;
+            self.inner.generation.set(generation);
+            let supervisor = Rc::downgrade(&self.inner);
+            let parent = self.inner.parent;
+            let started = (self.inner.create_environment)(Box::new(move |environment| {
+                let s = supervisor.clone();
+                let created = environment.and_then(|environment| {
+                    environment.create_controller(parent, move |controller| {
+                        if let Some(supervisor) = upgrade(&s) {
+                            supervisor.created(generation, controller);
+                        }
+                        Ok(())
+                    })
+                });
+                if let (Err(e), Some(supervisor)) = (created, upgrade(&supervisor)) {
+                    supervisor.created(generation, Err(e));
+                }
+                Ok(())
+            }));
+            if let Err(e) = started {
+                self.created(generation, Err(e));
+            }
+        }
+
+        fn created(&self, generation: u64, controller: Result<Controller>) {
+            if generation != self.inner.generation.get() {
+                if let Ok(controller) = controller {
+                    let _ = controller.close();
+                }
+                return;
+            }
+            let result = controller.and_then(|controller| {
+                let adopted = self.adopt(&controller);
+                if adopted.is_err() {
+                    let _ = controller.close();
+                }
+                adopted
+            });
+            match result {
+                Ok(()) => {
+                    self.inner.machine.borrow_mut().created();
+                    self.emit();
+                }
+                Err(e) => {
+                    let action = self
+                        .inner
+                        .machine
+                        .borrow_mut()
+                        .creation_failed(e.to_string(), Instant::now());
+                    self.emit();
+                    self.act(action);
+                }
+            }
+        }
+
+        fn adopt(&self, controller: &Controller) -> Result<()> {
+            let bounds = match self.inner.bounds.get() {
+                Some(bounds) => bounds,
+                None => {
+                    let mut rect = unsafe { mem::zeroed() };
+                    unsafe { GetClientRect(self.inner.parent, &mut rect) };
+                    rect
+                }
+            };
+            controller.put_bounds(bounds)?;
+            let webview = controller.get_webview()?;
+            let config = self.inner.config.borrow().clone();
+            config.apply(&webview)?;
+
+            // Closing the controller removes these.
+            let supervisor = Rc::downgrade(&self.inner);
+            webview
+                .on_source_changed(move |webview, _| {
+                    if let Some(supervisor) = upgrade(&supervisor) {
+                        let source = webview.get_source()?;
+                        supervisor.inner.config.borrow_mut().set_source(&source);
+                    }
+                    Ok(())
+                })?
+                .detach();
+            let supervisor = Rc::downgrade(&self.inner);
+            webview
+                .on_process_failed(move |_, args| {
+                    let failure = args.get_process_failed_kind()?.into();
+                    if let Some(supervisor) = upgrade(&supervisor) {
+                        supervisor.failed(failure);
+                    }
+                    Ok(())
+                })?
+                .detach();
+
+            *self.inner.current.borrow_mut() = Some((controller.clone(), webview.clone()));
+            let handlers = self.inner.on_created.borrow().clone();
+            for handler in handlers {
+                handler(controller, &webview)?;
+            }
+            Ok(())
+        }
+
+        fn failed(&self, failure: ProcessFailure) {
+            let action = self
+                .inner
+                .machine
+                .borrow_mut()
+                .failed(failure, Instant::now());
+            self.emit();
+            self.act(action);
+        }
+
+        fn act(&self, action: RecoveryAction) {
+            match action {
+                RecoveryAction::None => {}
+                RecoveryAction::Reload => {
+                    if let Some(webview) = self.webview() {
+                        let _ = webview.reload();
+                    }
+                }
+                RecoveryAction::Recreate { delay } => {
+                    self.teardown();
+                    let supervisor = Rc::downgrade(&self.inner);
+                    let scheduled = set_timer(delay, move || {
+                        if let Some(supervisor) = upgrade(&supervisor) {
+                            supervisor.create();
+                        }
+                    });
+                    if let Err(e) = scheduled {
+                        self.created(self.inner.generation.get(), Err(e));
+                    }
+                }
+                RecoveryAction::GiveUp => self.teardown(),
+            }
+        }
+
+        fn teardown(&self) {
+            // Stale creations are dropped.
+            self.inner.generation.set(self.inner.generation.get() + 1);
+            if let Some((controller, _)) = self.inner.current.borrow_mut().take() {
+                let _ = controller.close();
+            }
+        }
+    }
+
+    fn upgrade(supervisor: &Weak<Inner>) -> Option<Supervisor> {
+        supervisor.upgrade().map(|inner| Supervisor { inner })
+    }
+}
+
+#[cfg(windows)]
+pub use self::win::Supervisor;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    fn policy() -> RecoveryPolicy {
+        RecoveryPolicy::new()
+            .with_backoff(secs(1), secs(10))
+            .with_crash_loop_limit(3, secs(60))
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = policy().with_multiplier(3);
+        let delays: Vec<_> = (0..6).map(|a| policy.delay(a)).collect();
+        assert_eq!(
+            delays,
+            [secs(1), secs(1), secs(3), secs(9), secs(10), secs(10)]
+        );
+        assert_eq!(policy.delay(u32::MAX), secs(10));
+        let constant = policy.with_multiplier(1);
+        assert_eq!(constant.delay(100), secs(1));
+        assert_eq!(constant.delay(u32::MAX), secs(1));
+        let none = RecoveryPolicy::new().with_backoff(Duration::from_secs(0), secs(10));
+        assert_eq!(none.delay(u32::MAX), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn test_recovery() {
+        let start = Instant::now();
+        let mut machine = RecoveryMachine::new(policy());
+        machine.created();
+        assert_eq!(machine.state(), SupervisorState::Running);
+        assert_eq!(
+            machine.take_events(),
+            vec![RecoveryEvent::Recovered { attempt: 0 }]
+        );
+
+        // Renderer failures reload.
+        assert_eq!(
+            machine.failed(ProcessFailure::RenderExited, start),
+            RecoveryAction::Reload
+        );
+        assert_eq!(
+            machine.take_events(),
+            vec![
+                RecoveryEvent::Failed {
+                    failure: ProcessFailure::RenderExited,
+                    failures: 1,
+                },
+                RecoveryEvent::Reloading,
+            ]
+        );
+        assert_eq!(machine.state(), SupervisorState::Running);
+
+        // The browser exits, recreating fails once, then works.
+        let t = start + secs(10);
+        assert_eq!(
+            machine.failed(ProcessFailure::BrowserExited, t),
+            RecoveryAction::Recreate { delay: secs(2) }
+        );
+        assert_eq!(machine.state(), SupervisorState::Recovering { attempt: 1 });
+        // Ignored while recovering.
+        assert_eq!(
+            machine.failed(ProcessFailure::RenderUnresponsive, t),
+            RecoveryAction::None
+        );
+        assert_eq!(
+            machine.creation_failed("E_FAIL".into(), t + secs(2)),
+            RecoveryAction::Recreate { delay: secs(4) }
+        );
+        machine.created();
+        assert_eq!(machine.state(), SupervisorState::Running);
+        assert_eq!(
+            machine.take_events(),
+            vec![
+                RecoveryEvent::Failed {
+                    failure: ProcessFailure::BrowserExited,
+                    failures: 2,
+                },
+                RecoveryEvent::Recovering {
+                    attempt: 1,
+                    delay: secs(2),
+                },
+                RecoveryEvent::RecoveryFailed {
+                    attempt: 1,
+                    error: "E_FAIL".into(),
+                },
+                RecoveryEvent::Recovering {
+                    attempt: 2,
+                    delay: secs(4),
+                },
+                RecoveryEvent::Recovered { attempt: 2 },
+            ]
+        );
+        assert_eq!(machine.recent_failures(), 3);
+        // Not from a recovery.
+        assert_eq!(
+            machine.creation_failed("late".into(), t),
+            RecoveryAction::None
+        );
+    }
+
+    #[test]
+    fn test_crash_loop() {
+        let start = Instant::now();
+        let mut machine = RecoveryMachine::new(policy());
+        // Failures more than a minute apart never add up.
+        for i in 0..10 {
+            let action = machine.failed(ProcessFailure::BrowserExited, start + secs(61 * i));
+            assert_eq!(action, RecoveryAction::Recreate { delay: secs(1) });
+            machine.created();
+        }
+
+        let t = start + secs(1000);
+        for i in 0..3 {
+            machine.failed(ProcessFailure::RenderExited, t + secs(i));
+        }
+        machine.take_events();
+        assert_eq!(
+            machine.failed(ProcessFailure::RenderUnresponsive, t + secs(59)),
+            RecoveryAction::GiveUp
+        );
+        assert_eq!(machine.state(), SupervisorState::GaveUp);
+        assert_eq!(
+            machine.take_events(),
+            vec![
+                RecoveryEvent::Failed {
+                    failure: ProcessFailure::RenderUnresponsive,
+                    failures: 4,
+                },
+                RecoveryEvent::GaveUp { failures: 4 },
+            ]
+        );
+        assert_eq!(
+            machine.failed(ProcessFailure::BrowserExited, t + secs(60)),
+            RecoveryAction::None
+        );
+        assert!(machine.take_events().is_empty());
+
+        machine.restart();
+        assert_eq!(machine.state(), SupervisorState::Starting);
+        assert_eq!(machine.recent_failures(), 0);
+        // Failing to start counts too.
+        for i in 1..=3 {
+            let action = machine.creation_failed("no runtime".into(), t + secs(100 + i));
+            let delay = policy().delay(i as u32);
+            assert_eq!(action, RecoveryAction::Recreate { delay });
+        }
+        assert_eq!(
+            machine.creation_failed("no runtime".into(), t + secs(104)),
+            RecoveryAction::GiveUp
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let mut config = WebViewConfig::new();
+        config.put_setting(Setting::DevToolsEnabled, false);
+        config.put_setting(Setting::ScriptEnabled, true);
+        config.put_setting(Setting::DevToolsEnabled, true);
+        assert_eq!(
+            config.settings(),
+            &[
+                (Setting::DevToolsEnabled, true),
+                (Setting::ScriptEnabled, true)
+            ]
+        );
+        assert_eq!(config.setting(Setting::StatusBarEnabled), None);
+
+        config.add_script("a()");
+        config.add_script("b()");
+        config.add_script("a()");
+        assert!(config.remove_script("a()"));
+        assert!(!config.remove_script("c()"));
+        assert_eq!(config.scripts(), &["b()".to_string()]);
+
+        assert_eq!(config.source(), None);
+        config.set_source("https://example.com/");
+        assert_eq!(config.source(), Some("https://example.com/"));
+    }
+}
@@ -0,0 +1,34 @@
+// One-shot timers run by the thread's message loop.
+
+use crate::{Error, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ptr;
+use std::time::Duration;
+use winapi::shared::basetsd::UINT_PTR;
+use winapi::shared::minwindef::{DWORD, UINT};
+use winapi::shared::windef::HWND;
+use winapi::um::winuser::{KillTimer, SetTimer};
+
+thread_local! {
+    #[allow(clippy::missing_const_for_thread_local)]
+    static TIMERS: RefCell<HashMap<UINT_PTR, Box<dyn FnOnce()>>> = RefCell::default();
+}
+
+unsafe extern "system" fn on_timer(_: HWND, _: UINT, id: UINT_PTR, _: DWORD) {
+    KillTimer(ptr::null_mut(), id);
+    if let Some(f) = TIMERS.with(|timers| timers.borrow_mut().remove(&id)) {
+        f();
+    }
+}
+
+// Call `f` from the message loop after `delay`.
+pub(crate) fn set_timer(delay: Duration, f: impl FnOnce() + 'static) -> Result<()> {
+    let millis = delay.as_millis().min(u128::from(UINT::MAX)) as UINT;
+    let id = unsafe { SetTimer(ptr::null_mut(), 0, millis, Some(on_timer)) };
+    if id == 0 {
+        return Err(Error::from(std::io::Error::last_os_error()).with_operation("SetTimer"));
+    }
+    TIMERS.with(|timers| timers.borrow_mut().insert(id, Box::new(f)));
+    Ok(())
+}
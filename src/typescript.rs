@@ -0,0 +1,671 @@
+// TypeScript declarations for the messages exchanged with the page.
+
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{self, Write};
+use std::rc::Rc;
+
+/// A TypeScript type expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TsType {
+    Boolean,
+    Number,
+    String,
+    Null,
+    Unknown,
+    /// A string, number or boolean literal type, e.g. a serde tag.
+    Literal(Value),
+    Array(Box<TsType>),
+    Tuple(Vec<TsType>),
+    /// `Record<string, T>`.
+    Record(Box<TsType>),
+    Union(Vec<TsType>),
+    /// An object type. Fields of type `Optional` are declared with `?`.
+    Object(Vec<(String, TsType)>),
+    /// A field that may be missing, e.g. with `#[serde(default)]`.
+    Optional(Box<TsType>),
+    /// A type declared in `TsDefinitions`.
+    Named(String),
+}
+
+impl TsType {
+    pub fn literal(value: impl Into<Value>) -> Self {
+        TsType::Literal(value.into())
+    }
+
+    pub fn array(item: TsType) -> Self {
+        TsType::Array(Box::new(item))
+    }
+
+    pub fn optional(ty: TsType) -> Self {
+        TsType::Optional(Box::new(ty))
+    }
+
+    pub fn object<'a>(fields: impl IntoIterator<Item = (&'a str, TsType)>) -> Self {
+        TsType::Object(
+            fields
+                .into_iter()
+                .map(|(name, ty)| (name.to_string(), ty))
+                .collect(),
+        )
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        match self {
+            TsType::Boolean => f.write_str("boolean"),
+            TsType::Number => f.write_str("number"),
+            TsType::String => f.write_str("string"),
+            TsType::Null => f.write_str("null"),
+            TsType::Unknown => f.write_str("unknown"),
+            TsType::Literal(value) => write!(f, "{}", value),
+            TsType::Array(item) => match **item {
+                TsType::Union(_) | TsType::Optional(_) => {
+                    f.write_str("(")?;
+                    item.write(f, indent)?;
+                    f.write_str(")[]")
+                }
+                _ => {
+                    item.write(f, indent)?;
+                    f.write_str("[]")
+                }
+            },
+            TsType::Tuple(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    item.write(f, indent)?;
+                }
+                f.write_str("]")
+            }
+            TsType::Record(value) => {
+                f.write_str("Record<string, ")?;
+                value.write(f, indent)?;
+                f.write_str(">")
+            }
+            TsType::Union(variants) if variants.is_empty() => f.write_str("never"),
+            TsType::Union(variants) => {
+                for (i, variant) in variants.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" | ")?;
+                    }
+                    variant.write(f, indent)?;
+                }
+                Ok(())
+            }
+            TsType::Object(fields) if fields.is_empty() => f.write_str("{}"),
+            TsType::Object(fields) => {
+                f.write_str("{\n")?;
+                for (name, ty) in fields {
+                    write!(f, "{:width$}", "", width = (indent + 1) * 2)?;
+                    write_property_name(f, name)?;
+                    let ty = match ty {
+                        TsType::Optional(ty) => {
+                            f.write_str("?")?;
+                            ty
+                        }
+                        ty => ty,
+                    };
+                    f.write_str(": ")?;
+                    ty.write(f, indent + 1)?;
+                    f.write_str(";\n")?;
+                }
+                write!(f, "{:width$}}}", "", width = indent * 2)
+            }
+            TsType::Optional(ty) => {
+                ty.write(f, indent)?;
+                f.write_str(" | undefined")
+            }
+            TsType::Named(name) => f.write_str(name),
+        }
+    }
+}
+
+impl fmt::Display for TsType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+fn write_property_name(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    if is_identifier(name) {
+        f.write_str(name)
+    } else {
+        write!(f, "{}", Value::String(name.into()))
+    }
+}
+
+/// Named type declarations, in the order they were defined.
+#[derive(Debug, Clone, Default)]
+pub struct TsDefinitions {
+    names: HashSet<String>,
+    declarations: Vec<(String, TsType)>,
+}
+
+impl TsDefinitions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a named type once, and refer to it by name.
+    ///
+    /// Dependencies declared by `declare` come first. Recursive types are
+    /// fine: a reference to a type being declared is just its name.
+    pub fn define(&mut self, name: &str, declare: impl FnOnce(&mut Self) -> TsType) -> TsType {
+        if self.names.insert(name.into()) {
+            let ty = declare(self);
+            self.declarations.push((name.into(), ty));
+        }
+        TsType::Named(name.into())
+    }
+
+    /// The type expression of `T`, declaring the named types it uses.
+    pub fn add<T: TypeScript + ?Sized>(&mut self) -> TsType {
+        T::ts_type(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.declarations.is_empty()
+    }
+}
+
+impl fmt::Display for TsDefinitions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, ty)) in self.declarations.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            match ty {
+                TsType::Object(_) => writeln!(f, "export interface {} {}", name, ty)?,
+                ty => writeln!(f, "export type {} = {};", name, ty)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Types with a TypeScript equivalent of their JSON representation.
+///
+/// Implementations must match the type's `Serialize`/`Deserialize`
+/// implementations. Named types use `TsDefinitions::define`:
+///
+/// ```
+/// use webview2::{TsDefinitions, TsType, TypeScript};
+///
+/// // #[derive(Serialize, Deserialize)]
+/// // #[serde(tag = "type")]
+/// enum Shape {
+///     Circle { radius: f64 },
+///     Label { text: String, color: Option<String> },
+/// }
+///
+/// impl TypeScript for Shape {
+///     fn ts_type(defs: &mut TsDefinitions) -> TsType {
+///         defs.define("Shape", |defs| {
+///             TsType::Union(vec![
+///                 TsType::object(vec![
+///                     ("type", TsType::literal("Circle")),
+///                     ("radius", defs.add::<f64>()),
+///                 ]),
+///                 TsType::object(vec![
+///                     ("type", TsType::literal("Label")),
+///                     ("text", defs.add::<String>()),
+///                     ("color", defs.add::<Option<String>>()),
+///                 ]),
+///             ])
+///         })
+///     }
+/// }
+///
+/// let mut defs = TsDefinitions::new();
+/// assert_eq!(defs.add::<Vec<Shape>>().to_string(), "Shape[]");
+/// assert!(defs.to_string().starts_with("export type Shape = {\n  type: \"Circle\";"));
+/// ```
+pub trait TypeScript {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType;
+}
+
+macro_rules! impl_typescript {
+    ($ts:expr; $($t:ty),*) => {
+        $(
+            impl TypeScript for $t {
+                fn ts_type(_: &mut TsDefinitions) -> TsType {
+                    $ts
+                }
+            }
+        )*
+    };
+}
+
+impl_typescript!(TsType::Boolean; bool);
+impl_typescript!(TsType::Number; i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+impl_typescript!(TsType::String; str, String, char);
+impl_typescript!(TsType::Null; ());
+impl_typescript!(TsType::Unknown; Value);
+
+impl<T: TypeScript> TypeScript for Option<T> {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType {
+        TsType::Union(vec![T::ts_type(defs), TsType::Null])
+    }
+}
+
+impl<T: TypeScript> TypeScript for Vec<T> {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType {
+        TsType::array(T::ts_type(defs))
+    }
+}
+
+impl<T: TypeScript> TypeScript for [T] {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType {
+        TsType::array(T::ts_type(defs))
+    }
+}
+
+impl<T: TypeScript> TypeScript for VecDeque<T> {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType {
+        TsType::array(T::ts_type(defs))
+    }
+}
+
+impl<V: TypeScript, S> TypeScript for HashMap<String, V, S> {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType {
+        TsType::Record(Box::new(V::ts_type(defs)))
+    }
+}
+
+impl<V: TypeScript> TypeScript for BTreeMap<String, V> {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType {
+        TsType::Record(Box::new(V::ts_type(defs)))
+    }
+}
+
+impl<T: TypeScript + ?Sized> TypeScript for &T {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType {
+        T::ts_type(defs)
+    }
+}
+
+impl<T: TypeScript + ?Sized> TypeScript for Box<T> {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType {
+        T::ts_type(defs)
+    }
+}
+
+impl<T: TypeScript + ?Sized> TypeScript for Rc<T> {
+    fn ts_type(defs: &mut TsDefinitions) -> TsType {
+        T::ts_type(defs)
+    }
+}
+
+macro_rules! impl_typescript_tuple {
+    ($($t:ident),*) => {
+        impl<$($t: TypeScript),*> TypeScript for ($($t,)*) {
+            fn ts_type(defs: &mut TsDefinitions) -> TsType {
+                TsType::Tuple(vec![$($t::ts_type(defs)),*])
+            }
+        }
+    };
+}
+
+impl_typescript_tuple!(A);
+impl_typescript_tuple!(A, B);
+impl_typescript_tuple!(A, B, C);
+impl_typescript_tuple!(A, B, C, D);
+
+/// Declarations of the commands and events of a bridge, rendered as a
+/// `.d.ts` file.
+#[derive(Debug, Clone, Default)]
+pub struct TsBindings {
+    defs: TsDefinitions,
+    methods: Vec<(String, (TsType, TsType))>,
+    host_methods: Vec<(String, (TsType, TsType))>,
+    notifications: Vec<(String, TsType)>,
+    host_notifications: Vec<(String, TsType)>,
+    messages: Option<(TsType, TsType)>,
+}
+
+impl TsBindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A method implemented by the host, called with `call`.
+    pub fn add_method<P: TypeScript, R: TypeScript>(&mut self, name: &str) {
+        let params = self.defs.add::<P>();
+        let result = self.defs.add::<R>();
+        replace(&mut self.methods, name, (params, result));
+    }
+
+    /// A method the host calls, registered with `register`.
+    pub fn add_page_method<P: TypeScript, R: TypeScript>(&mut self, name: &str) {
+        let params = self.defs.add::<P>();
+        let result = self.defs.add::<R>();
+        replace(&mut self.host_methods, name, (params, result));
+    }
+
+    /// A notification handled by the host, sent with `notify`.
+    pub fn add_notification<P: TypeScript>(&mut self, name: &str) {
+        let params = self.defs.add::<P>();
+        replace(&mut self.notifications, name, params);
+    }
+
+    /// A notification the host sends, handled with `onNotification`.
+    pub fn add_page_notification<P: TypeScript>(&mut self, name: &str) {
+        let params = self.defs.add::<P>();
+        replace(&mut self.host_notifications, name, params);
+    }
+
+    /// The message types of a `MessageBridge`: `In` is posted by the page,
+    /// `Out` by the host.
+    pub fn set_messages<In: TypeScript, Out: TypeScript>(&mut self) {
+        let incoming = self.defs.add::<In>();
+        let outgoing = self.defs.add::<Out>();
+        self.messages = Some((incoming, outgoing));
+    }
+
+    /// Forget the host methods and notifications named `name`. Their
+    /// named types stay declared.
+    pub fn remove(&mut self, name: &str) {
+        self.methods.retain(|(n, _)| n != name);
+        self.notifications.retain(|(n, _)| n != name);
+    }
+
+    pub fn definitions(&self) -> &TsDefinitions {
+        &self.defs
+    }
+
+    /// Render the declarations, with the client declared as `window[global]`.
+    ///
+    /// `global` is the name passed to `RpcEndpoint::client_script` or
+    /// `MessageBridge::with_global`.
+    pub fn to_declarations(&self, global: &str) -> String {
+        let mut out = String::new();
+        out.push_str("// Generated by webview2. Do not edit.\n\n");
+        write!(out, "{}", self.defs).unwrap();
+        if !self.defs.is_empty() {
+            out.push('\n');
+        }
+
+        let mut members = Vec::new();
+        if let Some((incoming, outgoing)) = &self.messages {
+            members.push(format!("post(message: {}): void;", incoming));
+            members.push(format!(
+                "on(type: string, callback: (message: {}) => void): void;",
+                outgoing
+            ));
+            members.push("off(type: string): void;".to_string());
+        }
+        for (name, (params, result)) in &self.methods {
+            members.push(format!(
+                "call(method: {}, params: {}, options?: {{ timeout?: number }}): Promise<{}> & {{ id: number }};",
+                Value::String(name.clone()),
+                params,
+                result
+            ));
+        }
+        for (name, params) in &self.notifications {
+            members.push(format!(
+                "notify(method: {}, params: {}): void;",
+                Value::String(name.clone()),
+                params
+            ));
+        }
+        if !self.methods.is_empty() || !self.notifications.is_empty() {
+            members.push("cancel(id: number): void;".to_string());
+        }
+        for (name, (params, result)) in &self.host_methods {
+            members.push(format!(
+                "register(method: {}, f: (params: {}) => {} | Promise<{}>): void;",
+                Value::String(name.clone()),
+                params,
+                result,
+                result
+            ));
+        }
+        for (name, params) in &self.host_notifications {
+            members.push(format!(
+                "onNotification(method: {}, f: (params: {}) => void): void;",
+                Value::String(name.clone()),
+                params
+            ));
+        }
+        if !self.host_methods.is_empty() || !self.host_notifications.is_empty() {
+            members.push("unregister(method: string): void;".to_string());
+        }
+
+        let interface = interface_name(global);
+        writeln!(out, "export interface {} {{", interface).unwrap();
+        for member in members {
+            // Long lines are left to the formatter of the front-end.
+            writeln!(out, "  {}", member).unwrap();
+        }
+        out.push_str("}\n\ndeclare global {\n  interface Window {\n    ");
+        let mut name = String::new();
+        write_name(&mut name, global);
+        writeln!(out, "{}: {};\n  }}\n}}", name, interface).unwrap();
+        out
+    }
+}
+
+fn replace<T>(list: &mut Vec<(String, T)>, name: &str, value: T) {
+    list.retain(|(n, _)| n != name);
+    list.push((name.into(), value));
+}
+
+fn write_name(out: &mut String, name: &str) {
+    if is_identifier(name) {
+        out.push_str(name);
+    } else {
+        out.push_str(&Value::String(name.into()).to_string());
+    }
+}
+
+// `hostBridge` -> `HostBridge`.
+fn interface_name(global: &str) -> String {
+    let mut name: String = global
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(chars).collect::<String>()
+        })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert_str(0, "Bridge");
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{RpcEndpoint, Transport};
+    use crate::MessageBridge;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Point {
+        x: f64,
+        y: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    }
+
+    impl TypeScript for Point {
+        fn ts_type(defs: &mut TsDefinitions) -> TsType {
+            defs.define("Point", |defs| {
+                TsType::object(vec![
+                    ("x", defs.add::<f64>()),
+                    ("y", defs.add::<f64>()),
+                    ("label", TsType::optional(defs.add::<String>())),
+                ])
+            })
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    enum Command {
+        Move { to: Point },
+        Clear,
+    }
+
+    impl TypeScript for Command {
+        fn ts_type(defs: &mut TsDefinitions) -> TsType {
+            defs.define("Command", |defs| {
+                TsType::Union(vec![
+                    TsType::object(vec![
+                        ("type", TsType::literal("Move")),
+                        ("to", defs.add::<Point>()),
+                    ]),
+                    TsType::object(vec![("type", TsType::literal("Clear"))]),
+                ])
+            })
+        }
+    }
+
+    // A recursive type.
+    #[derive(Serialize, Deserialize)]
+    struct Tree {
+        children: Vec<Tree>,
+    }
+
+    impl TypeScript for Tree {
+        fn ts_type(defs: &mut TsDefinitions) -> TsType {
+            defs.define("Tree", |defs| {
+                TsType::object(vec![("children", defs.add::<Vec<Tree>>())])
+            })
+        }
+    }
+
+    const RPC_SNAPSHOT: &str = r#"// Generated by webview2. Do not edit.
+
+export interface Point {
+  x: number;
+  y: number;
+  label?: string;
+}
+
+export type Command = {
+  type: "Move";
+  to: Point;
+} | {
+  type: "Clear";
+};
+
+export interface Tree {
+  children: Tree[];
+}
+
+export interface Rpc {
+  call(method: "distance", params: [Point, Point], options?: { timeout?: number }): Promise<number> & { id: number };
+  call(method: "draw", params: Command, options?: { timeout?: number }): Promise<null> & { id: number };
+  notify(method: "log", params: string): void;
+  cancel(id: number): void;
+  register(method: "count", f: (params: Tree) => number | Promise<number>): void;
+  onNotification(method: "cursor", f: (params: Point) => void): void;
+  unregister(method: string): void;
+}
+
+declare global {
+  interface Window {
+    rpc: Rpc;
+  }
+}
+"#;
+
+    const BRIDGE_SNAPSHOT: &str = r#"// Generated by webview2. Do not edit.
+
+export interface Point {
+  x: number;
+  y: number;
+  label?: string;
+}
+
+export type Command = {
+  type: "Move";
+  to: Point;
+} | {
+  type: "Clear";
+};
+
+export interface HostBridge {
+  post(message: Command): void;
+  on(type: string, callback: (message: Point) => void): void;
+  off(type: string): void;
+}
+
+declare global {
+  interface Window {
+    hostBridge: HostBridge;
+  }
+}
+"#;
+
+    struct Null;
+
+    impl Transport for Null {
+        fn send(&self, _: &str) {}
+    }
+
+    #[test]
+    fn test_types() {
+        let mut defs = TsDefinitions::new();
+        assert_eq!(defs.add::<Option<Vec<u8>>>().to_string(), "number[] | null");
+        assert_eq!(
+            defs.add::<Vec<Option<u8>>>().to_string(),
+            "(number | null)[]"
+        );
+        assert_eq!(
+            defs.add::<(bool, HashMap<String, Value>)>().to_string(),
+            "[boolean, Record<string, unknown>]"
+        );
+        assert_eq!(defs.add::<()>().to_string(), "null");
+        assert_eq!(
+            TsType::object(vec![("a-b", TsType::Number)]).to_string(),
+            "{\n  \"a-b\": number;\n}"
+        );
+        assert!(defs.is_empty());
+        assert_eq!(defs.add::<Tree>().to_string(), "Tree");
+        assert_eq!(
+            defs.to_string(),
+            "export interface Tree {\n  children: Tree[];\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_rpc_snapshot() {
+        let endpoint = RpcEndpoint::new(Rc::new(Null));
+        endpoint.register_typed_ts("distance", |(a, b): (Point, Point)| {
+            Ok((a.x - b.x).hypot(a.y - b.y))
+        });
+        endpoint.register_typed_ts("draw", |_: Command| Ok(()));
+        endpoint.on_typed_notification_ts("log", |_: String| {});
+        endpoint.declare_page_method::<Tree, usize>("count");
+        endpoint.declare_page_notification::<Point>("cursor");
+        // Unregistered methods are not declared.
+        endpoint.register_typed_ts("removed", |_: ()| Ok(()));
+        endpoint.unregister("removed");
+        // Neither are methods registered without the `_ts` variants.
+        endpoint.register_typed("untyped", |_: Value| Ok(()));
+        endpoint.on_typed_notification("untyped_log", |_: Value| {});
+        assert_eq!(endpoint.typescript_declarations("rpc"), RPC_SNAPSHOT);
+    }
+
+    #[test]
+    fn test_bridge_snapshot() {
+        let bridge = MessageBridge::<Command, Point>::new();
+        assert_eq!(bridge.typescript_declarations(), BRIDGE_SNAPSHOT);
+    }
+}
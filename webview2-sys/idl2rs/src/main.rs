@@ -131,7 +131,12 @@ impl<'a> Method<'a> {
         } else {
             ""
         };
-        write!(w, "    unsafe fn {}{}(&self", name_prefix, camel_to_snake(self.name))?;
+        write!(
+            w,
+            "    unsafe fn {}{}(&self",
+            name_prefix,
+            camel_to_snake(self.name)
+        )?;
         for p in &self.parameters {
             write!(w, ", ")?;
             p.render(w)?;
@@ -477,14 +482,28 @@ fn main() {
                 wrapper_name
             };
 
+            // These carry the error sink of their environment to the
+            // handlers registered on them.
+            let has_error_sink = ["WebView", "Controller", "Environment"].contains(&&*wrapper_name);
+
             println!("/// Wrapper for `{}`.", i.name);
             println!("#[derive(Clone)]");
             println!("pub struct {} {{", wrapper_name);
             println!("    inner: ComRc<dyn {}>,", i.name);
+            if has_error_sink {
+                println!("    error_sink: Option<Rc<ErrorSink>>,");
+            }
             println!("}}");
             println!("impl From<ComRc<dyn {}>> for {} {{", i.name, wrapper_name);
             println!("    fn from(inner: ComRc<dyn {}>) -> Self {{", i.name);
-            println!("        Self {{ inner }}");
+            if has_error_sink {
+                println!("        Self {{");
+                println!("            inner,");
+                println!("            error_sink: None,");
+                println!("        }}");
+            } else {
+                println!("        Self {{ inner }}");
+            }
             println!("    }}");
             println!("}}");
             println!("impl fmt::Debug for {} {{", wrapper_name);
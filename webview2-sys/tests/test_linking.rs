@@ -1,3 +1,5 @@
+#![cfg(windows)]
+
 use core::ptr::{null, null_mut};
 
 // Just make sure that we can actually call a function from the SDK, i.e. linking is successful.